@@ -0,0 +1,15 @@
+#include <stddef.h>
+
+#include "example.h"
+
+int32_t toy_a = 4;
+int32_t toy_asdf = 4;
+
+void toy_main(void) {
+    return;
+}
+
+int main(void) {
+    toy_main();
+    return 0;
+}
@@ -0,0 +1,12 @@
+#ifndef TOY_EXAMPLE_H
+#define TOY_EXAMPLE_H
+
+#include <stdbool.h>
+#include <stdint.h>
+
+extern int32_t toy_a;
+extern int32_t toy_asdf;
+
+void toy_main(void);
+
+#endif // TOY_EXAMPLE_H
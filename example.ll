@@ -1,21 +1,34 @@
-@a = global i32 4
-@asdf = global i32 4
-define void @main() {
+source_filename = "example.toy"
+target datalayout = "e-m:e-p270:32:32-p271:32:32-p272:64:64-i64:64-f80:128-n8:16:32:64-S128"
+target triple = "x86_64-unknown-linux-gnu"
+
+@toy_a = global i32 4
+@toy_asdf = global i32 4
+
+define void @toy_main() #0 {
     %a = alloca i32
-    %a = i32 2
     %b = alloca i32
-    %b = i32 1
     %d = alloca i32
-    %d = i32 1
     %c = alloca i32
-    %1 = load %a
-    %2 = load %b
-    %0 = mul i32 %1, %2
-    %5 = i32 4
-    %7 = load %b
-    %8 = load %d
-    %6 = add i32 %7, %8
-    %4 = mul i32 %5, %6
-    %c = add i32 %0, %4
+    store i32 2, ptr %a
+    store i32 1, ptr %b
+    store i32 1, ptr %d
+    %t0 = load i32, ptr %a
+    %t1 = load i32, ptr %b
+    %t2 = mul i32 %t0, %t1
+    %t3 = load i32, ptr %b
+    %t4 = load i32, ptr %d
+    %t5 = add i32 %t3, %t4
+    %t6 = mul i32 4, %t5
+    %t7 = add i32 %t2, %t6
+    store i32 %t7, ptr %c
+    ret void
 }
 
+define i32 @main() {
+    call void @toy_main()
+    ret i32 0
+}
+
+attributes #0 = { nounwind }
+
@@ -0,0 +1,69 @@
+    .text
+
+    .type toy_main, @function
+toy_main:
+    pushq %rbp
+    movq %rsp, %rbp
+    subq $96, %rsp
+    leaq -8(%rbp), %rdi
+    movq $2, %rcx
+    movl %ecx, 0(%rdi)
+    leaq -16(%rbp), %rdi
+    movq $1, %rcx
+    movl %ecx, 0(%rdi)
+    leaq -24(%rbp), %rdi
+    movq $1, %rcx
+    movl %ecx, 0(%rdi)
+    leaq -8(%rbp), %rsi
+    movl (%rsi), %eax
+    movl %eax, -40(%rbp)
+    leaq -16(%rbp), %rsi
+    movl (%rsi), %eax
+    movl %eax, -48(%rbp)
+    movl -40(%rbp), %eax
+    movl -48(%rbp), %ecx
+    imulq %rcx, %rax
+    movl %eax, -56(%rbp)
+    leaq -16(%rbp), %rsi
+    movl (%rsi), %eax
+    movl %eax, -64(%rbp)
+    leaq -24(%rbp), %rsi
+    movl (%rsi), %eax
+    movl %eax, -72(%rbp)
+    movl -64(%rbp), %eax
+    movl -72(%rbp), %ecx
+    addq %rcx, %rax
+    movl %eax, -80(%rbp)
+    movq $4, %rax
+    movl -80(%rbp), %ecx
+    imulq %rcx, %rax
+    movl %eax, -88(%rbp)
+    movl -56(%rbp), %eax
+    movl -88(%rbp), %ecx
+    addq %rcx, %rax
+    movl %eax, -96(%rbp)
+    leaq -32(%rbp), %rdi
+    movl -96(%rbp), %ecx
+    movl %ecx, 0(%rdi)
+    leave
+    ret
+
+    .globl main
+    .type main, @function
+main:
+    pushq %rbp
+    movq %rsp, %rbp
+    call toy_main
+    xorl %eax, %eax
+    popq %rbp
+    ret
+
+    .data
+    .p2align 2
+toy_a:
+    .long 4
+    .p2align 2
+toy_asdf:
+    .long 4
+
+    .section .note.GNU-stack,"",@progbits
@@ -0,0 +1,54 @@
+(module
+  (memory (export "memory") 2)
+  (global $toy.stack_pointer (mut i32) (i32.const 65552))
+  (global $a (mut i32) (i32.const 4))
+  (global $asdf (mut i32) (i32.const 4))
+
+  (func $main (export "main")
+    (local $a i32)
+    (local $b i32)
+    (local $d i32)
+    (local $t0 i32)
+    (local $t1 i32)
+    (local $t2 i32)
+    (local $t3 i32)
+    (local $t4 i32)
+    (local $t5 i32)
+    (local $t6 i32)
+    (local $t7 i32)
+    (local $c i32)
+    i32.const 2
+    local.set $a
+    i32.const 1
+    local.set $b
+    i32.const 1
+    local.set $d
+    local.get $a
+    local.set $t0
+    local.get $b
+    local.set $t1
+    local.get $t0
+    local.get $t1
+    i32.mul
+    local.set $t2
+    local.get $b
+    local.set $t3
+    local.get $d
+    local.set $t4
+    local.get $t3
+    local.get $t4
+    i32.add
+    local.set $t5
+    i32.const 4
+    local.get $t5
+    i32.mul
+    local.set $t6
+    local.get $t2
+    local.get $t6
+    i32.add
+    local.set $t7
+    local.get $t7
+    local.set $c
+    return
+  )
+)
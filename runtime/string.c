@@ -0,0 +1,37 @@
+// Runtime support for the `string` type.
+//
+// A string is a pointer to its bytes and its length, the bytes are not null
+// terminated. Link it with the generated code, e.g.
+//     llc example.ll -o example.s && cc example.s runtime/string.c
+
+#include <stdint.h>
+#include <stdlib.h>
+#include <string.h>
+
+// Returns a new buffer holding the bytes of `a` followed by those of `b`.
+// Strings are never freed.
+char *toy_string_concat(const char *a, int64_t a_len, const char *b, int64_t b_len) {
+    if (a_len + b_len == 0) {
+        return NULL;
+    }
+
+    char *result = malloc(a_len + b_len);
+    if (a_len > 0) {
+        memcpy(result, a, a_len);
+    }
+    if (b_len > 0) {
+        memcpy(result + a_len, b, b_len);
+    }
+    return result;
+}
+
+// Compares two strings byte by byte. Returns -1, 0 or 1 when `a` is less
+// than, equal to or greater than `b`.
+int32_t toy_string_compare(const char *a, int64_t a_len, const char *b, int64_t b_len) {
+    int64_t len = a_len < b_len ? a_len : b_len;
+    int result = len > 0 ? memcmp(a, b, len) : 0;
+    if (result != 0) {
+        return result < 0 ? -1 : 1;
+    }
+    return (a_len > b_len) - (a_len < b_len);
+}
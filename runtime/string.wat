@@ -0,0 +1,119 @@
+;; Runtime support for the `string` type in WebAssembly, the counterpart of
+;; `string.c`. These functions are copied into the generated modules that
+;; use them, which define the memory and `$toy.heap`, the address of the
+;; first free byte after the stack.
+
+;; Returns a new buffer holding the bytes of `a` followed by those of `b`,
+;; growing the memory as needed. Strings are never freed.
+(func $toy_string_concat (param $a i32) (param $a_len i64) (param $b i32) (param $b_len i64) (result i32)
+  (local $len i32) (local $result i32) (local $end i32)
+  local.get $a_len
+  local.get $b_len
+  i64.add
+  i32.wrap_i64
+  local.tee $len
+  i32.eqz
+  if
+    i32.const 0
+    return
+  end
+  global.get $toy.heap
+  local.tee $result
+  local.get $len
+  i32.add
+  local.tee $end
+  memory.size
+  i32.const 16
+  i32.shl
+  i32.gt_u
+  if
+    local.get $end
+    memory.size
+    i32.const 16
+    i32.shl
+    i32.sub
+    i32.const 65535
+    i32.add
+    i32.const 16
+    i32.shr_u
+    memory.grow
+    i32.const -1
+    i32.eq
+    if
+      unreachable
+    end
+  end
+  local.get $end
+  global.set $toy.heap
+  local.get $result
+  local.get $a
+  local.get $a_len
+  i32.wrap_i64
+  memory.copy
+  local.get $result
+  local.get $a_len
+  i32.wrap_i64
+  i32.add
+  local.get $b
+  local.get $b_len
+  i32.wrap_i64
+  memory.copy
+  local.get $result
+)
+
+;; Compares two strings byte by byte. Returns -1, 0 or 1 when `a` is less
+;; than, equal to or greater than `b`.
+(func $toy_string_compare (param $a i32) (param $a_len i64) (param $b i32) (param $b_len i64) (result i32)
+  (local $len i64) (local $i i64) (local $x i32) (local $y i32)
+  local.get $a_len
+  local.get $b_len
+  local.get $a_len
+  local.get $b_len
+  i64.lt_s
+  select
+  local.set $len
+  block $done
+    loop $next
+      local.get $i
+      local.get $len
+      i64.ge_s
+      br_if $done
+      local.get $a
+      local.get $i
+      i32.wrap_i64
+      i32.add
+      i32.load8_u
+      local.set $x
+      local.get $b
+      local.get $i
+      i32.wrap_i64
+      i32.add
+      i32.load8_u
+      local.set $y
+      local.get $x
+      local.get $y
+      i32.ne
+      if
+        i32.const -1
+        i32.const 1
+        local.get $x
+        local.get $y
+        i32.lt_u
+        select
+        return
+      end
+      local.get $i
+      i64.const 1
+      i64.add
+      local.set $i
+      br $next
+    end
+  end
+  local.get $a_len
+  local.get $b_len
+  i64.gt_s
+  local.get $a_len
+  local.get $b_len
+  i64.lt_s
+  i32.sub
+)
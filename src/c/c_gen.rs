@@ -0,0 +1,1149 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::Path;
+
+use crate::parser::ast::{self, BuiltInType, TranslationUnit};
+use crate::codegen::{CodegenOptions, CodegenError, lower_and_optimize, encode_identifier, symbol_name};
+use crate::mir::mir::*;
+use crate::mir::lower::lower_type;
+use crate::mir::dominators::Dominators;
+
+pub fn create_c(tu : &TranslationUnit, source_file : &str, header_file : &str) -> Result<(), CodegenError> {
+    create_c_with_options(tu, source_file, header_file, &CodegenOptions::default())
+}
+
+// Writes the definitions to `source_file` and the types, globals and
+// function prototypes to `header_file`, which the source includes. Both
+// files are generated before anything is written, and neither is left
+// behind if writing one fails.
+pub fn create_c_with_options(tu : &TranslationUnit, source_file : &str, header_file : &str, options : &CodegenOptions) -> Result<(), CodegenError> {
+    let header_name = Path::new(header_file)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(header_file);
+
+    let mut header = vec![];
+    let mut source = vec![];
+    generate_c(tu, options, header_name, &mut header, &mut source)?;
+
+    std::fs::write(header_file, header)
+        .and_then(|_| std::fs::write(source_file, source))
+        .map_err(|error| {
+            let _ = std::fs::remove_file(header_file);
+            let _ = std::fs::remove_file(source_file);
+            CodegenError::Io(error)
+        })
+}
+
+// The source includes the header as `header_name`.
+pub fn generate_c<H : Write, S : Write>(tu : &TranslationUnit, options : &CodegenOptions, header_name : &str, header_output : &mut H, source_output : &mut S) -> Result<(), CodegenError> {
+    let module = lower_and_optimize(tu, options)?;
+    let (header, source) = CGenerator::new(&module, tu).generate(header_name);
+
+    header_output.write_all(header.as_bytes())?;
+    header_output.flush()?;
+    source_output.write_all(source.as_bytes())?;
+    source_output.flush()?;
+    Ok(())
+}
+
+/****************************/
+/****************************/
+/****************************/
+
+const C_KEYWORDS : [&str; 59] = [
+    "auto", "break", "case", "char", "const", "continue", "default", "do", "double", "else", "enum",
+    "extern", "float", "for", "goto", "if", "inline", "int", "long", "register", "restrict", "return",
+    "short", "signed", "sizeof", "static", "struct", "switch", "typedef", "union", "unsigned", "void",
+    "volatile", "while", "_Bool", "_Complex", "_Imaginary",
+    // Names from the headers the generated code uses
+    "bool", "true", "false", "NULL", "NAN", "INFINITY", "abort", "fmod", "fmodf", "main",
+    "int8_t", "int16_t", "int32_t", "int64_t", "uint8_t", "uint16_t", "uint32_t", "uint64_t", "uintptr_t",
+    "INT64_C", "UINT64_C", "INT64_MIN",
+];
+
+// Identifiers of the language may contain `.` (methods and registers) and
+// may clash with C keywords. Names starting with `toy_` are left to the
+// generated code.
+fn sanitize(name : &str) -> String {
+    let mut sanitized : String = name
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '_' {c} else {'_'})
+        .collect();
+    if sanitized.is_empty() || sanitized.starts_with(|c : char| c.is_ascii_digit()) {
+        sanitized.insert(0, '_');
+    }
+    if C_KEYWORDS.contains(&sanitized.as_str()) || sanitized.starts_with("toy_") {
+        sanitized.push('_');
+    }
+    sanitized
+}
+
+// Names of classes and fields, which are not made unique like locals are
+fn type_name(name : &str) -> String {
+    sanitize(&encode_identifier(name))
+}
+
+fn unique_name(name : &str, taken : &mut HashSet<String>) -> String {
+    let mut unique = name.to_string();
+    let mut count = 0;
+    while taken.contains(&unique) {
+        count += 1;
+        unique = format!("{}_{}", name, count);
+    }
+    taken.insert(unique.clone());
+    unique
+}
+
+// `type name`, without a space after a `*`
+fn declaration(type_ : &str, name : &str) -> String {
+    if type_.ends_with('*') {
+        format!("{}{}", type_, name)
+    } else {
+        format!("{} {}", type_, name)
+    }
+}
+
+fn is_integer(c_type : &str) -> bool {
+    c_type == "bool" || (c_type.ends_with("int8_t") || c_type.ends_with("int16_t") ||
+        c_type.ends_with("int32_t") || c_type.ends_with("int64_t"))
+}
+
+// `expression` of type `from` converted to `to`. Integers of a different
+// sign or width are cast explicitly, pointers convert implicitly.
+fn convert(expression : String, from : &str, to : &str) -> String {
+    if from != to && is_integer(from) && is_integer(to) {
+        format!("({}){}", to, expression)
+    } else {
+        expression
+    }
+}
+
+fn unsigned_type(width : usize) -> String {
+    match width {
+        1 => "bool".to_string(),
+        width => format!("uint{}_t", width),
+    }
+}
+
+fn signed_type(width : usize) -> String {
+    format!("int{}_t", width)
+}
+
+// Integers are printed in the sign of the type they are stored in
+fn int_constant(value : i64, type_ : &Type, signed : bool) -> String {
+    let width = type_.width();
+    if width == 1 {
+        return (value & 1).to_string();
+    }
+
+    if signed {
+        let value = (value << (64-width)) >> (64-width);
+        return match width {
+            64 if value == i64::MIN => "INT64_MIN".to_string(),
+            64 => format!("INT64_C({})", value),
+            _ => value.to_string(),
+        };
+    }
+
+    let value = match width {
+        64 => value as u64,
+        _ => value as u64 & ((1 << width) - 1),
+    };
+    match width {
+        64 => format!("UINT64_C({})", value),
+        32 => format!("{}u", value),
+        _ => value.to_string(),
+    }
+}
+
+// Bytes other than printable ASCII, `"`, `\` and `?` (trigraphs) are
+// written as octal escapes.
+fn escape_string(text : &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b' '..=b'~' if byte != b'"' && byte != b'\\' && byte != b'?' => (byte as char).to_string(),
+            _ => format!("\\{:03o}", byte),
+        })
+        .collect()
+}
+
+/****************************/
+/****************************/
+/****************************/
+
+// Operations written as calls to static functions of the generated source.
+// Division and shifts give a value where C leaves them undefined, e.g.
+// `INT32_MIN / -1` or a shift by the width of the type, and compilers do not
+// warn about constant operands of a call, e.g. a division by zero in a
+// branch that is never taken. The same goes for comparisons of pointers
+// that are addresses of variables. Their names start with `toy_int_` or
+// `toy_ptr_`, which no symbol of the program does.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Helper {
+    // On unsigned integers of the given width
+    Binary(BinaryOp, usize),
+    PointerEquals,
+}
+
+impl Helper {
+    fn name(&self) -> String {
+        match self {
+            Helper::Binary(op, width) => format!("toy_int_{}{}", op.to_string(), width),
+            Helper::PointerEquals => "toy_ptr_eq".to_string(),
+        }
+    }
+
+    fn definition(&self) -> String {
+        let (op, width) = match self {
+            Helper::Binary(op, width) => (*op, *width),
+            Helper::PointerEquals => return "static inline bool toy_ptr_eq(void *a, void *b) {\n    return a == b;\n}\n\n".to_string(),
+        };
+
+        let (unsigned, signed) = (unsigned_type(width), signed_type(width));
+        // Narrow integers are promoted to `int` before being shifted
+        let shifted = if width < 32 {"(uint32_t)a"} else {"a"};
+        let body = match op {
+            BinaryOp::SDiv => format!("b == UINT{}_MAX ? ({})(0u - a) : ({})(({})a / ({})b)", width, unsigned, unsigned, signed, signed),
+            BinaryOp::SRem => format!("b == UINT{}_MAX ? 0 : ({})(({})a % ({})b)", width, unsigned, signed, signed),
+            BinaryOp::UDiv => "a / b".to_string(),
+            BinaryOp::URem => "a % b".to_string(),
+            BinaryOp::Shl => format!("b < {} ? ({})({} << b) : 0", width, unsigned, shifted),
+            BinaryOp::AShr => format!("b < {} ? ({})(({})a >> b) : 0", width, unsigned, signed),
+            _ => format!("b < {} ? a >> b : 0", width),
+        };
+        format!("static inline {} {}({} a, {} b) {{\n    return {};\n}}\n\n", unsigned, self.name(), unsigned, unsigned, body)
+    }
+}
+
+/****************************/
+/****************************/
+/****************************/
+
+// Registers and blocks of the function being generated
+struct FunctionContext<'f> {
+    function : &'f Function,
+    // C name of the registers that are declared
+    names : HashMap<Register, String>,
+    // C type of the parameters, if it differs from that of their MIR type
+    parameter_types : HashMap<Register, String>,
+    // Allocas become local variables of the type they hold
+    slots : HashMap<Register, Type>,
+    // Allocas that are never read, stores to them are left out
+    write_only : HashSet<Register>,
+    labels : HashMap<BlockId, String>,
+    // Blocks reached by a `goto`, the others need no label
+    targets : HashSet<BlockId>,
+    // Block printed after the current one, which needs no `goto`
+    next_block : Option<BlockId>,
+    taken : HashSet<String>,
+}
+
+// Prints a MIR module as C99. Values are computed on the unsigned integer
+// types matching the MIR types, and converted to the types of the source
+// language at the boundaries: fields, globals, parameters and return values.
+struct CGenerator<'a> {
+    module : &'a Module,
+    tu : &'a TranslationUnit,
+    // C names of the functions and globals
+    names : HashMap<String, String>,
+    type_definitions : Vec<String>,
+    defined_types : HashSet<String>,
+    used_strings : Vec<bool>,
+    uses_math : bool,
+    uses_abort : bool,
+    // Operations computed by helpers, in order of first use
+    helpers : Vec<Helper>,
+}
+
+impl<'a> CGenerator<'a> {
+    fn new(module : &'a Module, tu : &'a TranslationUnit) -> CGenerator<'a> {
+        let mut names = HashMap::new();
+        for function in &module.functions {
+            names.insert(function.name.clone(), symbol_name(&function.name));
+        }
+        for global in &module.globals {
+            names.insert(global.name.clone(), symbol_name(&global.name));
+        }
+
+        CGenerator {
+            module,
+            tu,
+            names,
+            type_definitions : vec![],
+            defined_types : HashSet::new(),
+            used_strings : vec![false; module.strings.len()],
+            uses_math : false,
+            uses_abort : false,
+            helpers : vec![],
+        }
+    }
+
+    fn generate(mut self, header_name : &str) -> (String, String) {
+        self.define_types();
+
+        let mut definitions = String::new();
+        for global in &self.module.globals {
+            let type_ = self.global_type(&global.name);
+            let init_value = self.initializer(&global.init_value, &global.type_, &type_);
+            definitions += &format!("{} = {};\n", declaration(&type_, &self.names[&global.name]), init_value);
+        }
+        if !self.module.globals.is_empty() {
+            definitions += "\n";
+        }
+
+        for function in &self.module.functions {
+            definitions += &self.generate_function(function);
+        }
+        definitions += &self.generate_main();
+
+        (self.generate_header(header_name), self.generate_source(header_name, definitions))
+    }
+
+    fn generate_header(&self, header_name : &str) -> String {
+        let guard : String = header_name
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() {c.to_ascii_uppercase()} else {'_'})
+            .collect();
+        let guard = format!("TOY_{}", guard);
+
+        let mut header = format!("#ifndef {}\n#define {}\n\n#include <stdbool.h>\n#include <stdint.h>\n\n", guard, guard);
+
+        for definition in &self.type_definitions {
+            header += definition;
+            header += "\n";
+        }
+
+        for global in &self.module.globals {
+            header += &format!("extern {};\n", declaration(&self.global_type(&global.name), &self.names[&global.name]));
+        }
+        if !self.module.globals.is_empty() {
+            header += "\n";
+        }
+
+        for function in &self.module.functions {
+            header += &format!("{};\n", self.prototype(function, &self.header_parameter_names(function)));
+        }
+        if !self.module.functions.is_empty() {
+            header += "\n";
+        }
+
+        header += &format!("#endif // {}\n", guard);
+        header
+    }
+
+    fn generate_source(&self, header_name : &str, definitions : String) -> String {
+        let mut source = "#include <stddef.h>\n".to_string();
+        if self.uses_abort {
+            source += "#include <stdlib.h>\n";
+        }
+        if self.uses_math {
+            source += "#include <math.h>\n";
+        }
+        source += &format!("\n#include \"{}\"\n\n", header_name);
+
+        // Functions of the runtime, with the types of `runtime/string.c`
+        for function in &self.module.externs {
+            let return_type = function.return_type.as_ref().map_or("void".to_string(), CGenerator::extern_type);
+            let parameters : Vec<String> = function.parameters.iter().map(CGenerator::extern_type).collect();
+            let parameters = if parameters.is_empty() {"void".to_string()} else {parameters.join(", ")};
+            source += &format!("{}({});\n", declaration(&return_type, &function.name), parameters);
+        }
+        if !self.module.externs.is_empty() {
+            source += "\n";
+        }
+
+        for helper in &self.helpers {
+            source += &helper.definition();
+        }
+
+        let mut any_string = false;
+        for (i, text) in self.module.strings.iter().enumerate() {
+            if self.used_strings[i] {
+                source += &format!("static char toy_str_{}[] = \"{}\";\n", i, escape_string(text));
+                any_string = true;
+            }
+        }
+        if any_string {
+            source += "\n";
+        }
+
+        source + &definitions
+    }
+
+    fn generate_main(&self) -> String {
+        let main = match self.tu.functions.iter().find(|function| function.identifier == "main") {
+            Some(main) if main.arguments.is_empty() => main,
+            _ => return String::new(),
+        };
+
+        match &main.return_type {
+            Some(type_) if lower_type(type_, &self.module.target).is_int() => {
+                format!("int main(void) {{\n    return (int){}();\n}}\n", symbol_name("main"))
+            },
+            _ => format!("int main(void) {{\n    {}();\n    return 0;\n}}\n", symbol_name("main")),
+        }
+    }
+
+    /*******************/
+    /*******************/
+    /*******************/
+
+    // Types of the values in the generated code
+    fn c_type(&self, type_ : &Type) -> String {
+        match type_ {
+            Type::I1 | Type::I8 | Type::I16 | Type::I32 | Type::I64 => unsigned_type(type_.width()),
+            Type::F32 => "float".to_string(),
+            Type::F64 => "double".to_string(),
+            Type::Ptr => "void *".to_string(),
+            Type::String => "toy_string".to_string(),
+            Type::Struct(name) => format!("struct {}", type_name(name)),
+            Type::Array(..) => format!("toy_{}", CGenerator::type_tag(type_)),
+        }
+    }
+
+    // Arrays are wrapped in structs so they can be assigned, passed and
+    // returned by value like in the MIR.
+    fn type_tag(type_ : &Type) -> String {
+        match type_ {
+            Type::I1 => "bool".to_string(),
+            Type::I8 | Type::I16 | Type::I32 | Type::I64 => format!("u{}", type_.width()),
+            Type::F32 => "f32".to_string(),
+            Type::F64 => "f64".to_string(),
+            Type::Ptr => "ptr".to_string(),
+            Type::String => "string".to_string(),
+            Type::Struct(name) => type_name(name),
+            Type::Array(element_type, len) => format!("array_{}_{}", len, CGenerator::type_tag(element_type)),
+        }
+    }
+
+    // Types of the source language, used in the public declarations
+    fn api_type(&self, type_ : &ast::Type) -> String {
+        match type_ {
+            ast::Type::BuiltIn(BuiltInType::Bool) => "bool".to_string(),
+            ast::Type::BuiltIn(built_in_type) if built_in_type.is_integer() => {
+                let width = lower_type(type_, &self.module.target).width();
+                if built_in_type.is_signed() {signed_type(width)} else {unsigned_type(width)}
+            },
+            ast::Type::Enum(_) => "int32_t".to_string(),
+            ast::Type::Pointer(pointee_type) => format!("{} *", self.api_type(pointee_type)),
+            _ => self.c_type(&lower_type(type_, &self.module.target)),
+        }
+    }
+
+    fn extern_type(type_ : &Type) -> String {
+        match type_ {
+            Type::Ptr => "char *".to_string(),
+            type_ if type_.is_int() && *type_ != Type::I1 => signed_type(type_.width()),
+            Type::F32 => "float".to_string(),
+            Type::F64 => "double".to_string(),
+            _ => "bool".to_string(),
+        }
+    }
+
+    fn custom_type(&self, name : &str) -> Option<&'a ast::CustomType> {
+        self.tu.custom_types
+            .iter()
+            .find(|custom_type| custom_type.identifier == name)
+            .map(|custom_type| custom_type.as_ref())
+    }
+
+    fn field_name(&self, type_ : &Type, index : usize) -> String {
+        match type_ {
+            Type::String => ["ptr", "len"][index].to_string(),
+            Type::Struct(name) => match self.custom_type(name) {
+                Some(custom_type) => type_name(&custom_type.attributes[index].identifier),
+                None => format!("field{}", index),
+            },
+            _ => format!("elements[{}]", index),
+        }
+    }
+
+    // C type of a field or element, which differs from the type of the
+    // value in the MIR for signed integers and booleans.
+    fn field_type(&self, type_ : &Type, index : usize) -> String {
+        match type_ {
+            Type::String => ["char *", "int64_t"][index].to_string(),
+            Type::Struct(name) => match self.custom_type(name) {
+                Some(custom_type) => self.api_type(&custom_type.attributes[index].type_),
+                None => self.c_type(&self.module.field_type(type_, index).unwrap()),
+            },
+            _ => self.c_type(&self.module.field_type(type_, index).unwrap()),
+        }
+    }
+
+    fn global_type(&self, name : &str) -> String {
+        match self.tu.global_variables.iter().find(|global| global.identifier == name) {
+            Some(global) => self.api_type(&global.type_),
+            None => self.c_type(&self.module.global(name).unwrap().type_),
+        }
+    }
+
+    /*******************/
+    /*******************/
+    /*******************/
+
+    // Type definitions go before their first use
+    fn define_types(&mut self) {
+        let module = self.module;
+        for struct_type in &module.structs {
+            self.define_type(&Type::Struct(struct_type.name.clone()));
+        }
+        for global in &module.globals {
+            self.define_type(&global.type_);
+        }
+        for function in &module.functions {
+            for register in &function.registers {
+                self.define_type(&register.type_);
+            }
+            for instruction in function.blocks.iter().flat_map(|block| &block.instructions) {
+                match &instruction.kind {
+                    InstructionKind::Alloca(type_) |
+                    InstructionKind::Load{type_, ..} |
+                    InstructionKind::Store{type_, ..} |
+                    InstructionKind::FieldAddress{type_, ..} |
+                    InstructionKind::ElementAddress{type_, ..} |
+                    InstructionKind::Offset{type_, ..} |
+                    InstructionKind::ExtractValue{type_, ..} |
+                    InstructionKind::InsertValue{type_, ..} => self.define_type(type_),
+                    _ => {},
+                }
+            }
+        }
+    }
+
+    // Pointers to structs only need the struct to be declared
+    fn define_api_type(&mut self, type_ : &ast::Type) {
+        match type_ {
+            ast::Type::Pointer(pointee_type) => match pointee_type.as_ref() {
+                ast::Type::Custom(_) => {},
+                pointee_type => self.define_api_type(pointee_type),
+            },
+            type_ => self.define_type(&lower_type(type_, &self.module.target)),
+        }
+    }
+
+    fn define_type(&mut self, type_ : &Type) {
+        let name = self.c_type(type_);
+        match type_ {
+            Type::String if self.defined_types.insert(name.clone()) => {
+                self.type_definitions.push("typedef struct {\n    char *ptr;\n    int64_t len;\n} toy_string;\n".to_string());
+            },
+            Type::Struct(struct_name) => {
+                if !self.defined_types.insert(name.clone()) {
+                    return;
+                }
+
+                let mut fields = vec![];
+                match self.custom_type(struct_name) {
+                    Some(custom_type) => {
+                        for attribute in &custom_type.attributes {
+                            self.define_api_type(&attribute.type_);
+                            fields.push(declaration(&self.api_type(&attribute.type_), &type_name(&attribute.identifier)));
+                        }
+                    },
+                    None => {
+                        let field_types = self.module.struct_type(struct_name).unwrap().fields.clone();
+                        for (i, field_type) in field_types.iter().enumerate() {
+                            self.define_type(field_type);
+                            fields.push(declaration(&self.c_type(field_type), &format!("field{}", i)));
+                        }
+                    },
+                }
+
+                let fields : String = fields.iter().map(|field| format!("    {};\n", field)).collect();
+                self.type_definitions.push(format!("{} {{\n{}}};\n", name, fields));
+            },
+            Type::Array(element_type, len) => {
+                self.define_type(element_type);
+                if self.defined_types.insert(name.clone()) {
+                    let elements = declaration(&self.c_type(element_type), &format!("elements[{}]", len));
+                    self.type_definitions.push(format!("typedef struct {{\n    {};\n}} {};\n", elements, name));
+                }
+            },
+            _ => {},
+        }
+    }
+
+    /*******************/
+    /*******************/
+    /*******************/
+
+    fn ast_function(&self, function : &Function) -> Option<&'a ast::FunctionBlock> {
+        self.tu.functions.iter().find(|ast_function| ast_function.identifier == function.name)
+    }
+
+    // C types of the parameters and return value
+    fn signature(&self, function : &Function) -> (Vec<String>, String) {
+        let mut parameters : Vec<String> = function.parameter_types().iter().map(|type_| self.c_type(type_)).collect();
+        let mut return_type = function.return_type.as_ref().map_or("void".to_string(), |type_| self.c_type(type_));
+
+        if let Some(ast_function) = self.ast_function(function) {
+            let first_argument = parameters.len() - ast_function.arguments.len();
+            if let Some(receiver) = &ast_function.receiver {
+                parameters[0] = format!("struct {} *", type_name(&receiver.identifier));
+            }
+            for (i, argument) in ast_function.arguments.iter().enumerate() {
+                parameters[first_argument + i] = self.api_type(&argument.type_);
+            }
+            if let Some(type_) = &ast_function.return_type {
+                return_type = self.api_type(type_);
+            }
+        }
+
+        (parameters, return_type)
+    }
+
+    // The header names the parameters as in the source, the definition as
+    // the registers holding them.
+    fn prototype(&self, function : &Function, names : &[String]) -> String {
+        let (types, return_type) = self.signature(function);
+        let parameters : Vec<String> = types
+            .iter()
+            .zip(names)
+            .map(|(type_, name)| declaration(type_, name))
+            .collect();
+        let parameters = if parameters.is_empty() {"void".to_string()} else {parameters.join(", ")};
+
+        format!("{}({})", declaration(&return_type, &self.names[&function.name]), parameters)
+    }
+
+    fn header_parameter_names(&self, function : &Function) -> Vec<String> {
+        let mut taken = HashSet::new();
+        match self.ast_function(function) {
+            Some(ast_function) => {
+                let mut names = vec![];
+                if ast_function.receiver.is_some() {
+                    names.push(unique_name("self", &mut taken));
+                }
+                for argument in &ast_function.arguments {
+                    names.push(unique_name(&sanitize(&argument.identifier), &mut taken));
+                }
+                names
+            },
+            None => function.parameters
+                .iter()
+                .map(|parameter| unique_name(&sanitize(&function.register(*parameter).name), &mut taken))
+                .collect(),
+        }
+    }
+
+    // Registers whose value is needed by a terminator, a call or a store,
+    // other than a store to an alloca that is itself not needed.
+    fn live_registers(function : &Function, blocks : &[BlockId]) -> HashSet<Register> {
+        let registers = |values : Vec<&Value>| values.into_iter().filter_map(|value| value.as_register()).collect::<Vec<_>>();
+
+        let mut definitions = HashMap::new();
+        let mut stored_values : HashMap<Register, Vec<&Value>> = HashMap::new();
+        let mut worklist = vec![];
+        for block in blocks {
+            let block = function.block(*block);
+            for instruction in &block.instructions {
+                if let Some(result) = instruction.result {
+                    definitions.insert(result, instruction);
+                }
+                match &instruction.kind {
+                    InstructionKind::Store{value, pointer : Value::Register(pointer), ..} => {
+                        stored_values.entry(*pointer).or_default().push(value);
+                    },
+                    kind if kind.has_side_effects() => worklist.extend(registers(kind.operands())),
+                    _ => {},
+                }
+            }
+            worklist.extend(registers(block.terminator.as_ref().unwrap().operands()));
+        }
+
+        // Stores through pointers other than allocas are always needed
+        for pointer in stored_values.keys() {
+            if !definitions.get(pointer).is_some_and(|instruction| matches!(instruction.kind, InstructionKind::Alloca(_))) {
+                worklist.push(*pointer);
+            }
+        }
+
+        let mut live = HashSet::new();
+        while let Some(register) = worklist.pop() {
+            if live.insert(register) {
+                if let Some(instruction) = definitions.get(&register) {
+                    worklist.extend(registers(instruction.kind.operands()));
+                }
+                if let Some(values) = stored_values.get(&register) {
+                    worklist.extend(registers(values.clone()));
+                }
+            }
+        }
+        live
+    }
+
+    fn generate_function(&mut self, function : &Function) -> String {
+        let mut context = FunctionContext {
+            function,
+            names : HashMap::new(),
+            parameter_types : HashMap::new(),
+            slots : HashMap::new(),
+            write_only : HashSet::new(),
+            labels : HashMap::new(),
+            targets : HashSet::new(),
+            next_block : None,
+            taken : self.names.values().cloned().collect(),
+        };
+        context.taken.extend(C_KEYWORDS.iter().map(|keyword| keyword.to_string()));
+
+        let dominators = Dominators::new(function);
+        let blocks : Vec<BlockId> = (0..function.blocks.len())
+            .map(BlockId)
+            .filter(|block| dominators.is_reachable(*block))
+            .collect();
+
+        for block in &blocks {
+            for successor in function.block(*block).successors() {
+                if !context.labels.contains_key(&successor) {
+                    let label = unique_name(&sanitize(&function.block(successor).name), &mut context.taken);
+                    context.labels.insert(successor, label);
+                }
+            }
+        }
+        let live = CGenerator::live_registers(function, &blocks);
+
+        let (parameter_types, _) = self.signature(function);
+        for (parameter, type_) in function.parameters.iter().zip(parameter_types) {
+            let name = unique_name(&sanitize(&function.register(*parameter).name), &mut context.taken);
+            context.names.insert(*parameter, name);
+            if type_ != self.c_type(&function.register(*parameter).type_) {
+                context.parameter_types.insert(*parameter, type_);
+            }
+        }
+
+        // Locals are declared at the top of the function. Allocas that are
+        // only written to are left out, along with the stores, as C
+        // compilers warn about variables that are set but never read.
+        let mut locals = String::new();
+        for block in &blocks {
+            for instruction in &function.block(*block).instructions {
+                let result = match instruction.result {
+                    Some(result) => result,
+                    None => continue,
+                };
+                let read = live.contains(&result);
+                let type_ = match &instruction.kind {
+                    InstructionKind::Alloca(type_) if read => {
+                        context.slots.insert(result, type_.clone());
+                        type_
+                    },
+                    InstructionKind::Alloca(_) => {
+                        context.write_only.insert(result);
+                        continue;
+                    },
+                    _ if read => &function.register(result).type_,
+                    _ => continue,
+                };
+                let name = unique_name(&sanitize(&function.register(result).name), &mut context.taken);
+                locals += &format!("    {};\n", declaration(&self.c_type(type_), &name));
+                context.names.insert(result, name);
+            }
+        }
+
+        let mut texts = vec![];
+        for (i, block) in blocks.iter().enumerate() {
+            context.next_block = blocks.get(i+1).copied();
+            let block_ = function.block(*block);
+            let mut text = String::new();
+            for instruction in &block_.instructions {
+                text += &self.generate_instruction(&context, instruction);
+            }
+            text += &self.generate_terminator(&mut context, *block, block_.terminator.as_ref().unwrap());
+            texts.push(text);
+        }
+
+        let mut body = String::new();
+        for (block, text) in blocks.iter().zip(texts) {
+            if context.targets.contains(block) {
+                body += &format!("{}:\n", context.labels[block]);
+            }
+            body += &text;
+        }
+
+        let separator = if locals.is_empty() {""} else {"\n"};
+        let parameters : Vec<String> = function.parameters.iter().map(|parameter| context.names[parameter].clone()).collect();
+        format!("{} {{\n{}{}{}}}\n\n", self.prototype(function, &parameters), locals, separator, body)
+    }
+
+    /*******************/
+    /*******************/
+    /*******************/
+
+    // Constants in initializer form, e.g. `{ 1, 2 }` for aggregates
+    fn initializer(&mut self, constant : &Constant, type_ : &Type, c_type : &str) -> String {
+        match constant {
+            Constant::Int(value) => int_constant(*value, type_, c_type.starts_with("int")),
+            Constant::Float(value) => self.float_constant(*value, type_),
+            Constant::Null => "NULL".to_string(),
+            Constant::Zero | Constant::Undef => match type_ {
+                type_ if type_.is_aggregate() => "{0}".to_string(),
+                Type::Ptr => "NULL".to_string(),
+                type_ if type_.is_float() => self.float_constant(0.0, type_),
+                type_ => int_constant(0, type_, false),
+            },
+            Constant::String(index) => {
+                self.used_strings[*index] = true;
+                format!("{{ toy_str_{}, {} }}", index, self.module.strings[*index].len())
+            },
+            Constant::Global(name) => format!("(void *)&{}", self.names[name]),
+            Constant::Aggregate(elements) if elements.is_empty() => "{0}".to_string(),
+            Constant::Aggregate(elements) => {
+                let elements : Vec<String> = elements
+                    .iter()
+                    .enumerate()
+                    .map(|(i, element)| {
+                        let element_type = self.module.field_type(type_, i).unwrap();
+                        let element_c_type = self.field_type(type_, i);
+                        self.initializer(element, &element_type, &element_c_type)
+                    })
+                    .collect();
+
+                match type_ {
+                    Type::Array(..) => format!("{{ {{ {} }} }}", elements.join(", ")),
+                    _ => format!("{{ {} }}", elements.join(", ")),
+                }
+            },
+        }
+    }
+
+    fn float_constant(&mut self, value : f64, type_ : &Type) -> String {
+        if value.is_nan() || value.is_infinite() {
+            self.uses_math = true;
+            return match value {
+                value if value.is_nan() => "NAN".to_string(),
+                value if value > 0.0 => "INFINITY".to_string(),
+                _ => "-INFINITY".to_string(),
+            };
+        }
+
+        match type_ {
+            Type::F32 => format!("{:?}f", value as f32),
+            _ => format!("{:?}", value),
+        }
+    }
+
+    // `value` of MIR type `type_` as a value of C type `c_type`
+    fn value_as(&mut self, context : &FunctionContext, value : &Value, type_ : &Type, c_type : &str) -> String {
+        match value {
+            Value::Constant(constant) if !type_.is_aggregate() => {
+                self.initializer(constant, type_, c_type)
+            },
+            Value::Constant(constant) => {
+                let initializer = self.initializer(constant, type_, c_type);
+                format!("({}){}", self.c_type(type_), initializer)
+            },
+            Value::Register(register) => {
+                let name = context.names[register].clone();
+                if context.slots.contains_key(register) {
+                    return format!("(void *)&{}", name);
+                }
+                let from = context.parameter_types
+                    .get(register)
+                    .cloned()
+                    .unwrap_or_else(|| self.c_type(type_));
+                convert(name, &from, c_type)
+            },
+        }
+    }
+
+    fn value(&mut self, context : &FunctionContext, value : &Value, type_ : &Type) -> String {
+        let c_type = self.c_type(type_);
+        self.value_as(context, value, type_, &c_type)
+    }
+
+    // The memory holding a `type_` at `pointer`, as an lvalue
+    fn dereference(&mut self, context : &FunctionContext, pointer : &Value, type_ : &Type) -> String {
+        let c_type = self.c_type(type_);
+        match pointer {
+            Value::Register(register) if context.slots.get(register) == Some(type_) => context.names[register].clone(),
+            Value::Constant(Constant::Global(name)) if self.global_type(name) == c_type => self.names[name].clone(),
+            _ => {
+                let pointer = self.value(context, pointer, &Type::Ptr);
+                format!("*({} *){}", c_type, pointer)
+            },
+        }
+    }
+
+    // `(T *)pointer`, or the local variable the pointer is the address of
+    fn aggregate_at(&mut self, context : &FunctionContext, pointer : &Value, type_ : &Type) -> String {
+        match pointer {
+            Value::Register(register) if context.slots.get(register) == Some(type_) => context.names[register].clone(),
+            _ => {
+                let pointer = self.value(context, pointer, &Type::Ptr);
+                format!("(({} *){})->", self.c_type(type_), pointer)
+            },
+        }
+    }
+
+    fn binary(&mut self, context : &FunctionContext, op : BinaryOp, type_ : &Type, left : &Value, right : &Value) -> String {
+        let left = self.value(context, left, type_);
+        let right = self.value(context, right, type_);
+        let width = type_.width();
+
+        let operator = match op {
+            BinaryOp::Add | BinaryOp::FAdd => "+",
+            BinaryOp::Sub | BinaryOp::FSub => "-",
+            BinaryOp::Mul | BinaryOp::FMul => "*",
+            BinaryOp::SDiv | BinaryOp::UDiv | BinaryOp::FDiv => "/",
+            BinaryOp::SRem | BinaryOp::URem => "%",
+            BinaryOp::And => "&",
+            BinaryOp::Or => "|",
+            BinaryOp::Xor => "^",
+            BinaryOp::Shl => "<<",
+            BinaryOp::AShr | BinaryOp::LShr => ">>",
+            BinaryOp::FRem => {
+                self.uses_math = true;
+                let function = if *type_ == Type::F32 {"fmodf"} else {"fmod"};
+                return format!("{}({}, {})", function, left, right);
+            },
+        };
+
+        if op.is_float() {
+            return format!("{} {} {}", left, operator, right);
+        }
+
+        match op {
+            BinaryOp::SDiv | BinaryOp::UDiv | BinaryOp::SRem | BinaryOp::URem |
+            BinaryOp::Shl | BinaryOp::AShr | BinaryOp::LShr if width > 1 => {
+                let helper = self.helper(Helper::Binary(op, width));
+                format!("{}({}, {})", helper, left, right)
+            },
+            // Narrow integers are promoted to `int`, in which they could
+            // overflow.
+            _ if width == 1 => format!("({} {} {}) & 1", left, operator, right),
+            _ if width < 32 => format!("({})((uint32_t){} {} {})", self.c_type(type_), left, operator, right),
+            _ => format!("{} {} {}", left, operator, right),
+        }
+    }
+
+    fn helper(&mut self, helper : Helper) -> String {
+        if !self.helpers.contains(&helper) {
+            self.helpers.push(helper);
+        }
+        helper.name()
+    }
+
+    fn compare(&mut self, context : &FunctionContext, comparison : Comparison, type_ : &Type, left : &Value, right : &Value) -> String {
+        let mut left = self.value(context, left, type_);
+        let mut right = self.value(context, right, type_);
+
+        let signed = matches!(comparison, Comparison::SLt | Comparison::SLe | Comparison::SGt | Comparison::SGe);
+        if *type_ == Type::Ptr && matches!(comparison, Comparison::Eq | Comparison::Ne) {
+            let helper = self.helper(Helper::PointerEquals);
+            let negation = if comparison == Comparison::Ne {"!"} else {""};
+            return format!("{}{}({}, {})", negation, helper, left, right);
+        }
+        if signed {
+            let signed_type = signed_type(type_.width().max(8));
+            left = format!("({}){}", signed_type, left);
+            right = format!("({}){}", signed_type, right);
+        } else if *type_ == Type::Ptr {
+            left = format!("(uintptr_t){}", left);
+            right = format!("(uintptr_t){}", right);
+        }
+
+        let operator = match comparison {
+            Comparison::Eq | Comparison::FOeq => "==",
+            Comparison::Ne | Comparison::FUne => "!=",
+            Comparison::SLt | Comparison::ULt | Comparison::FOlt => "<",
+            Comparison::SLe | Comparison::ULe | Comparison::FOle => "<=",
+            Comparison::SGt | Comparison::UGt | Comparison::FOgt => ">",
+            Comparison::SGe | Comparison::UGe | Comparison::FOge => ">=",
+        };
+        format!("{} {} {}", left, operator, right)
+    }
+
+    fn cast(&mut self, context : &FunctionContext, op : CastOp, from : &Type, value : &Value, to : &Type) -> String {
+        let value = self.value(context, value, from);
+        let to_type = self.c_type(to);
+        match op {
+            CastOp::Trunc if *to == Type::I1 => format!("{} & 1", value),
+            CastOp::SExt if *from == Type::I1 => format!("({})-(int64_t){}", to_type, value),
+            CastOp::SExt => format!("({})({}){}", to_type, signed_type(from.width()), value),
+            CastOp::FPToSI => format!("({})({}){}", to_type, signed_type(to.width().max(8)), value),
+            CastOp::SIToFP => format!("({})({}){}", to_type, signed_type(from.width().max(8)), value),
+            _ => format!("({}){}", to_type, value),
+        }
+    }
+
+    fn generate_instruction(&mut self, context : &FunctionContext, instruction : &Instruction) -> String {
+        let module = self.module;
+        let result = instruction.result.and_then(|result| context.names.get(&result).cloned());
+
+        let expression = match &instruction.kind {
+            InstructionKind::Alloca(_) | InstructionKind::Phi{..} => return String::new(),
+            InstructionKind::Store{type_, value, pointer} => {
+                if pointer.as_register().is_some_and(|pointer| context.write_only.contains(&pointer)) {
+                    return String::new();
+                }
+
+                let destination = self.dereference(context, pointer, type_);
+                let value = self.value(context, value, type_);
+                return format!("    {} = {};\n", destination, value);
+            },
+            InstructionKind::Call{callee, return_type, arguments} => {
+                let function = match callee {
+                    Callee::Function(name) => module.function(name),
+                    Callee::Extern(_) => None,
+                };
+                let (parameter_types, name) = match function {
+                    Some(function) => (self.signature(function).0, self.names[callee.name()].clone()),
+                    None => (arguments.iter().map(|(type_, _)| CGenerator::extern_type(type_)).collect(), callee.name().to_string()),
+                };
+                let arguments : Vec<String> = arguments
+                    .iter()
+                    .zip(&parameter_types)
+                    .map(|((type_, argument), parameter_type)| self.value_as(context, argument, type_, parameter_type))
+                    .collect();
+                let call = format!("{}({})", name, arguments.join(", "));
+
+                let return_c_type = match function {
+                    Some(function) => self.signature(function).1,
+                    None => return_type.as_ref().map_or("void".to_string(), CGenerator::extern_type),
+                };
+                return match (result, return_type) {
+                    (Some(result), Some(type_)) => format!("    {} = {};\n", result, convert(call, &return_c_type, &self.c_type(type_))),
+                    _ => format!("    {};\n", call),
+                };
+            },
+            // Pure instructions whose result is unused are left out
+            _ if result.is_none() => return String::new(),
+            InstructionKind::Load{type_, pointer} => self.dereference(context, pointer, type_),
+            InstructionKind::Binary{op, type_, left, right} => self.binary(context, *op, type_, left, right),
+            InstructionKind::Negate{type_, value} => format!("-{}", self.value(context, value, type_)),
+            InstructionKind::Compare{comparison, type_, left, right} => self.compare(context, *comparison, type_, left, right),
+            InstructionKind::Cast{op, from, value, to} => self.cast(context, *op, from, value, to),
+            InstructionKind::FieldAddress{type_, pointer, index} => {
+                let aggregate = self.aggregate_at(context, pointer, type_);
+                let separator = if aggregate.ends_with("->") {""} else {"."};
+                format!("(void *)&{}{}{}", aggregate, separator, self.field_name(type_, *index))
+            },
+            InstructionKind::ElementAddress{type_, pointer, index} => {
+                let aggregate = self.aggregate_at(context, pointer, type_);
+                let separator = if aggregate.ends_with("->") {""} else {"."};
+                let index = self.value(context, index, &Type::I64);
+                format!("(void *)&{}{}elements[{}]", aggregate, separator, index)
+            },
+            InstructionKind::Offset{type_, pointer, index} => {
+                let pointer = self.value(context, pointer, &Type::Ptr);
+                let index = self.value(context, index, &Type::I64);
+                format!("(void *)(({} *){} + (int64_t){})", self.c_type(type_), pointer, index)
+            },
+            InstructionKind::ExtractValue{type_, aggregate, index} => {
+                let field_type = module.field_type(type_, *index).unwrap();
+                let aggregate = self.value(context, aggregate, type_);
+                let field = format!("{}.{}", aggregate, self.field_name(type_, *index));
+                convert(field, &self.field_type(type_, *index), &self.c_type(&field_type))
+            },
+            InstructionKind::InsertValue{type_, aggregate, value, index} => {
+                let result = result.unwrap();
+                let field_type = module.field_type(type_, *index).unwrap();
+                let field_c_type = self.field_type(type_, *index);
+                let aggregate = self.value(context, aggregate, type_);
+                let value = self.value_as(context, value, &field_type, &field_c_type);
+                let copy = if aggregate == result {String::new()} else {format!("    {} = {};\n", result, aggregate)};
+                return format!("{}    {}.{} = {};\n", copy, result, self.field_name(type_, *index), value);
+            },
+        };
+
+        format!("    {} = {};\n", result.unwrap(), expression)
+    }
+
+    /*******************/
+    /*******************/
+    /*******************/
+
+    // Phis are assignments on the edges that lead to their block. When a
+    // phi reads another phi of the same block, all the values are read
+    // before any is assigned.
+    fn phi_copies(&mut self, context : &mut FunctionContext, from : BlockId, to : BlockId, indent : &str) -> String {
+        let function = context.function;
+        let mut copies = vec![];
+        let mut phis = HashSet::new();
+        for instruction in &function.block(to).instructions {
+            let (type_, incoming) = match &instruction.kind {
+                InstructionKind::Phi{type_, incoming} => (type_, incoming),
+                _ => continue,
+            };
+            let result = instruction.result.unwrap();
+            phis.insert(result);
+            let name = match context.names.get(&result) {
+                Some(name) => name.clone(),
+                None => continue,
+            };
+            let value = match incoming.iter().find(|(_, block)| *block == from) {
+                Some((value, _)) => value,
+                None => continue,
+            };
+            if *value == Value::Constant(Constant::Undef) || value.as_register() == Some(result) {
+                continue;
+            }
+            copies.push((name, type_, value));
+        }
+
+        let reads_phi = copies.iter().any(|(_, _, value)| value.as_register().is_some_and(|register| phis.contains(&register)));
+        if !reads_phi {
+            return copies
+                .iter()
+                .map(|(name, type_, value)| format!("{}{} = {};\n", indent, name, self.value(context, value, type_)))
+                .collect();
+        }
+
+        let mut text = format!("{}{{\n", indent);
+        let mut assignments = String::new();
+        for (name, type_, value) in &copies {
+            let temporary = unique_name(&format!("{}_next", name), &mut context.taken);
+            let value = self.value(context, value, type_);
+            text += &format!("{}    {} = {};\n", indent, declaration(&self.c_type(type_), &temporary), value);
+            assignments += &format!("{}    {} = {};\n", indent, name, temporary);
+        }
+        text + &assignments + &format!("{}}}\n", indent)
+    }
+
+    fn jump(&mut self, context : &mut FunctionContext, from : BlockId, to : BlockId, indent : &str) -> String {
+        let copies = self.phi_copies(context, from, to, indent);
+        context.targets.insert(to);
+        format!("{}{}goto {};\n", copies, indent, context.labels[&to])
+    }
+
+    // A jump that may fall through to the next block
+    fn jump_last(&mut self, context : &mut FunctionContext, from : BlockId, to : BlockId) -> String {
+        if context.next_block == Some(to) {
+            return self.phi_copies(context, from, to, "    ");
+        }
+        self.jump(context, from, to, "    ")
+    }
+
+    fn generate_terminator(&mut self, context : &mut FunctionContext, block : BlockId, terminator : &Terminator) -> String {
+        match terminator {
+            Terminator::Return(None) => "    return;\n".to_string(),
+            Terminator::Return(Some((type_, value))) => {
+                let (_, return_type) = self.signature(context.function);
+                format!("    return {};\n", self.value_as(context, value, type_, &return_type))
+            },
+            Terminator::Branch(target) => self.jump_last(context, block, *target),
+            Terminator::CondBranch{condition, then_block, else_block} => {
+                let condition = self.value(context, condition, &Type::I1);
+                let then_jump = self.jump(context, block, *then_block, "        ");
+                let else_jump = self.jump_last(context, block, *else_block);
+                format!("    if ({}) {{\n{}    }}\n{}", condition, then_jump, else_jump)
+            },
+            Terminator::Switch{type_, value, default, cases} => {
+                let mut text = format!("    switch ({}) {{\n", self.value(context, value, type_));
+                for (case, target) in cases {
+                    text += &format!("    case {}:\n", int_constant(*case, type_, false));
+                    text += &self.jump(context, block, *target, "        ");
+                }
+                text += "    default:\n";
+                text += &self.jump(context, block, *default, "        ");
+                text + "    }\n"
+            },
+            Terminator::Trap | Terminator::Unreachable => {
+                self.uses_abort = true;
+                "    abort();\n".to_string()
+            },
+        }
+    }
+}
@@ -0,0 +1,2 @@
+pub mod c_gen;
+pub mod prelude;
\ No newline at end of file
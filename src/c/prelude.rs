@@ -0,0 +1 @@
+pub use super::c_gen::{create_c, create_c_with_options, generate_c};
\ No newline at end of file
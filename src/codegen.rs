@@ -0,0 +1,110 @@
+use crate::parser::ast::TranslationUnit;
+use crate::target::Target;
+use crate::llvm::attributes::Attributes;
+use crate::mir::mir::Module;
+use crate::mir::lower::lower_to_mir;
+use crate::mir::verifier::verify_module;
+use crate::mir::optimize::optimize_module;
+
+// Options shared by the lowering to MIR and by every backend.
+#[derive(Debug, Clone, Default)]
+pub struct CodegenOptions {
+    // Trap on out of range array indices instead of reading or writing
+    // past the end of the array.
+    pub bounds_check : bool,
+    pub target : Target,
+    pub opt_level : OptLevel,
+    // Printed as the `source_filename` of the LLVM module, and needed for
+    // debug info
+    pub source_filename : Option<String>,
+    // DWARF metadata in the LLVM IR, for `-g`
+    pub debug_info : bool,
+    pub attributes : Attributes,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OptLevel {
+    // The MIR as lowered
+    #[default]
+    O0,
+    O1,
+}
+
+impl OptLevel {
+    // `-O0` or `-O1`
+    pub fn from_flag(flag : &str) -> Option<OptLevel> {
+        match flag {
+            "-O0" => Some(OptLevel::O0),
+            "-O1" => Some(OptLevel::O1),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CodegenError {
+    // The program is rejected by the lowering to MIR
+    Lowering(String),
+    // The lowering or the optimizations produced invalid MIR
+    InvalidMir(String),
+    InvalidOptimizedMir(String),
+    Io(std::io::Error),
+}
+
+impl std::fmt::Display for CodegenError {
+    fn fmt(&self, f : &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CodegenError::Lowering(error) => write!(f, "{}", error),
+            CodegenError::InvalidMir(error) => write!(f, "Invalid MIR: {}", error),
+            CodegenError::InvalidOptimizedMir(error) => write!(f, "Invalid MIR after optimization: {}", error),
+            CodegenError::Io(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for CodegenError {}
+
+impl From<std::io::Error> for CodegenError {
+    fn from(error : std::io::Error) -> Self {
+        CodegenError::Io(error)
+    }
+}
+
+// The verified MIR of a program, optimized as the options ask, that the
+// backends generate code from.
+pub fn lower_and_optimize(tu : &TranslationUnit, options : &CodegenOptions) -> Result<Module, CodegenError> {
+    let mut module = lower_to_mir(tu, options).map_err(CodegenError::Lowering)?;
+    verify_module(&module).map_err(CodegenError::InvalidMir)?;
+
+    if options.opt_level != OptLevel::O0 {
+        optimize_module(&mut module, options.opt_level);
+        verify_module(&module).map_err(CodegenError::InvalidOptimizedMir)?;
+    }
+
+    Ok(module)
+}
+
+/****************************/
+/****************************/
+/****************************/
+
+// A name of the program as an identifier of ASCII letters, digits and `_`,
+// where `_` is written `__` and any other character as its code point in
+// hex between two `_`. Different names never give the same identifier.
+pub fn encode_identifier(name : &str) -> String {
+    name.chars()
+        .map(|c| match c {
+            '_' => "__".to_string(),
+            c if c.is_ascii_alphanumeric() => c.to_string(),
+            c => format!("_{:x}_", c as u32),
+        })
+        .collect()
+}
+
+// Symbol of a function or global of the program in the generated code,
+// e.g. `toy_Point_2e_norm` for the method `Point.norm`. The encoding never
+// gives the names of the C library, nor the ones of the runtime such as
+// `toy_string_concat`, so programs may define `abs` or `malloc`.
+pub fn symbol_name(name : &str) -> String {
+    format!("toy_{}", encode_identifier(name))
+}
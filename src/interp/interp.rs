@@ -0,0 +1,671 @@
+use std::collections::HashMap;
+
+use crate::parser::ast::*;
+use crate::parser::types::{check_types, field_of, Types};
+use crate::target::Target;
+use super::value::{Value, Place};
+
+// Calls nested deeper than this are reported as a stack overflow, like in
+// the VM
+pub(crate) const MAX_CALL_DEPTH : usize = 100000;
+
+// The interpreter recurses on the native stack, so it runs on a thread with
+// room for `MAX_CALL_DEPTH` calls. A call of a toy function takes up to
+// about 24KB of it in debug builds. The memory is only used by deep
+// recursion.
+const STACK_SIZE : usize = MAX_CALL_DEPTH * 32 * 1024;
+
+// Runs `main` and returns its result, or `None` if it returns `void`.
+pub fn interpret(tu : &TranslationUnit) -> Result<Option<Value>, String> {
+    interpret_with_target(tu, &Target::default())
+}
+
+// `isize` and `usize` take the pointer width of `target`.
+pub fn interpret_with_target(tu : &TranslationUnit, target : &Target) -> Result<Option<Value>, String> {
+    let input = Unshared((tu, target));
+    std::thread::scope(|scope| {
+        let thread = std::thread::Builder::new()
+            .stack_size(STACK_SIZE)
+            .spawn_scoped(scope, move || {
+                let (tu, target) = input.into_inner();
+                let result = check_types(tu).and_then(|types| Interpreter::new(tu, &types, target).run());
+                Unshared(result)
+            })
+            .map_err(|error| format!("Cannot start the interpreter: {}", error))?;
+
+        match thread.join() {
+            Ok(result) => result.into_inner(),
+            Err(panic) => std::panic::resume_unwind(panic),
+        }
+    })
+}
+
+// The AST and the values share `Rc`s, so they are not `Send`. They are
+// only used by one thread at a time: the caller waits for the interpreter
+// thread while it runs.
+struct Unshared<T>(T);
+
+unsafe impl<T> Send for Unshared<T> {}
+
+impl<T> Unshared<T> {
+    fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+/****************************/
+/****************************/
+/****************************/
+
+type Scope = HashMap<String, Place>;
+
+struct Frame {
+    scopes : Vec<Scope>,
+    return_type : Option<Type>,
+}
+
+enum Flow {
+    Next,
+    Return(Option<Value>),
+}
+
+struct Interpreter<'a> {
+    tu : &'a TranslationUnit,
+    types : &'a Types<'a>,
+    pointer_width : usize,
+    functions : HashMap<&'a str, &'a FunctionBlock>,
+    global_variables : Scope,
+    frames : Vec<Frame>,
+}
+
+impl<'a> Interpreter<'a> {
+    fn new(tu : &'a TranslationUnit, types : &'a Types<'a>, target : &Target) -> Interpreter<'a> {
+        Interpreter {
+            tu,
+            types,
+            pointer_width : target.pointer_width,
+            functions : tu.functions
+                .iter()
+                .map(|fun| (fun.identifier.as_str(), fun))
+                .collect(),
+            global_variables : Scope::new(),
+            frames : vec![],
+        }
+    }
+
+    fn run(&mut self) -> Result<Option<Value>, String> {
+        for var in &self.tu.global_variables {
+            let value = match &var.init_value {
+                Some(init_value) => self.eval_value(init_value, &var.type_)?,
+                None => self.zero_value(&var.type_),
+            };
+            self.global_variables.insert(var.identifier.clone(), Place::new(value));
+        }
+
+        let main = *self.functions
+            .get("main")
+            .ok_or_else(|| "Function main not defined".to_string())?;
+        if !main.arguments.is_empty() {
+            return Err("Function main cannot take arguments".to_string());
+        }
+
+        self.call_function(main, Scope::new())
+    }
+
+    /*******************/
+    /*******************/
+    /*******************/
+
+    fn int_width(&self, type_ : &BuiltInType) -> usize {
+        match type_ {
+            BuiltInType::ISize | BuiltInType::USize => self.pointer_width,
+            BuiltInType::I8 | BuiltInType::U8 | BuiltInType::Bool => 8,
+            BuiltInType::I16 | BuiltInType::U16 => 16,
+            BuiltInType::I32 | BuiltInType::U32 | BuiltInType::F32 => 32,
+            _ => 64,
+        }
+    }
+
+    // Truncates an integer to the width of `type_` and extends it back
+    // according to its signedness.
+    fn wrap(&self, value : i64, type_ : &BuiltInType) -> i64 {
+        let width = self.int_width(type_);
+        if width >= 64 {
+            return value;
+        }
+
+        let shift = 64 - width;
+        if type_.is_signed() {
+            (value << shift) >> shift
+        } else {
+            (((value as u64) << shift) >> shift) as i64
+        }
+    }
+
+    fn round_float(value : f64, type_ : &BuiltInType) -> f64 {
+        if *type_ == BuiltInType::F32 { value as f32 as f64 } else { value }
+    }
+
+    fn zero_value(&self, type_ : &Type) -> Value {
+        match type_ {
+            Type::BuiltIn(t) if t.is_float() => Value::Float(0.0),
+            Type::BuiltIn(BuiltInType::String) => Value::String(vec![]),
+            Type::BuiltIn(_) | Type::Enum(_) => Value::Int(0),
+            Type::Pointer(_) => Value::Pointer(None),
+            Type::Custom(custom_type) => Value::Aggregate(
+                custom_type.attributes
+                    .iter()
+                    .map(|attr| self.zero_value(&attr.type_))
+                    .collect()
+            ),
+            Type::Array(element_type, len) => Value::Aggregate(vec![self.zero_value(element_type); *len]),
+        }
+    }
+
+    fn literal_to_value(lit : &Literal) -> Result<Value, String> {
+        match lit {
+            Literal::Integer(v) => {
+                // Literals above `i64::MAX` are only valid as `uint64`
+                let value = v.parse::<i64>()
+                    .or_else(|_| v.parse::<u64>().map(|v| v as i64))
+                    .map_err(|_| format!("Invalid integer literal {}", v))?;
+                Ok(Value::Int(value))
+            },
+            Literal::Float(v) => {
+                let value = v.parse::<f64>().map_err(|_| format!("Invalid float literal {}", v))?;
+                Ok(Value::Float(value))
+            },
+            Literal::Boolean(v) => Ok(Value::Int(*v as i64)),
+            Literal::String(text) => Ok(Value::String(text.as_bytes().to_vec())),
+            Literal::Null => Ok(Value::Pointer(None)),
+        }
+    }
+
+    // Same conversions as `write_conversion` in the LLVM backend. Which
+    // conversions are valid is checked by `check_types`.
+    fn convert(&self, value : Value, from : &Type, to : &Type) -> Value {
+        if from == to {
+            return value;
+        }
+
+        let (from, to) = match (from, to) {
+            (Type::BuiltIn(from), Type::BuiltIn(to)) => (from, to),
+            // `null` to any pointer
+            _ => return value,
+        };
+
+        match value {
+            Value::Float(v) if to.is_float() => Value::Float(Interpreter::round_float(v, to)),
+            Value::Float(v) if to.is_signed() => Value::Int(self.wrap(v as i64, to)),
+            Value::Float(v) => Value::Int(self.wrap(v as u64 as i64, to)),
+            Value::Int(v) if to.is_float() => {
+                let v = if from.is_signed() { v as f64 } else { v as u64 as f64 };
+                Value::Float(Interpreter::round_float(v, to))
+            },
+            // Values are already extended according to their signedness
+            Value::Int(v) => Value::Int(self.wrap(v, to)),
+            _ => unreachable!("only numbers are converted"),
+        }
+    }
+
+    /*******************/
+    /*******************/
+    /*******************/
+
+    fn find_variable(&self, id : &str) -> Place {
+        let scopes = self.frames.last().map(|frame| frame.scopes.as_slice()).unwrap_or_default();
+        scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(id))
+            .or_else(|| self.global_variables.get(id))
+            .cloned()
+            .expect("variables are declared before they are used")
+    }
+
+    fn declare_local(&mut self, id : &str, value : Value) {
+        let frame = self.frames.last_mut().expect("locals are declared inside a function");
+        frame.scopes.last_mut().unwrap().insert(id.to_string(), Place::new(value));
+    }
+
+    // Evaluates an operation as a place in memory, if it is one.
+    fn eval_place(&mut self, op : &OperationResult) -> Result<Option<Place>, String> {
+        match op {
+            OperationResult::Identifier(id) => Ok(Some(self.find_variable(id))),
+            OperationResult::MemberAccess(access) => {
+                let object = match self.eval_place(&access.object)? {
+                    Some(object) => object,
+                    None => return Ok(None),
+                };
+                let (index, _) = field_of(self.types.type_of(&access.object), &access.field)?;
+                Ok(Some(object.element(index)))
+            },
+            OperationResult::UnaryOpResult(UnaryOperation{operator : UnaryOperator::Deref, operand, ..}) => {
+                match self.eval(operand)? {
+                    Value::Pointer(Some(place)) => Ok(Some(place)),
+                    _ => Err("Null pointer dereference".to_string()),
+                }
+            },
+            OperationResult::Index(index) => {
+                let array = match self.eval_place(&index.object)? {
+                    Some(array) => array,
+                    None => return Ok(None),
+                };
+                let array_type = self.types.type_of(&index.object);
+                self.eval_element_place(&array, array_type, &index.index).map(Some)
+            },
+            _ => Ok(None),
+        }
+    }
+
+    // Like `eval_place`, but values that do not live in memory are copied
+    // to a temporary place.
+    fn eval_object_place(&mut self, op : &OperationResult) -> Result<Place, String> {
+        if let Some(place) = self.eval_place(op)? {
+            return Ok(place);
+        }
+
+        Ok(Place::new(self.eval(op)?))
+    }
+
+    // Out of range indices are always an error, as with `--bounds-check`.
+    fn eval_element_place(&mut self, array : &Place, array_type : &Type, index : &OperationResult) -> Result<Place, String> {
+        let len = match array_type {
+            Type::Array(_, len) => *len,
+            _ => match array.read()? {
+                Value::String(bytes) => bytes.len(),
+                _ => return Err("Invalid string value".to_string()),
+            },
+        };
+
+        let index = match self.eval_value(index, &Type::BuiltIn(BuiltInType::I64))? {
+            Value::Int(index) if index >= 0 && (index as usize) < len => index as usize,
+            Value::Int(index) => return Err(format!("Index {} out of bounds for length {}", index, len)),
+            _ => return Err("Invalid index value".to_string()),
+        };
+
+        Ok(array.element(index))
+    }
+
+    // Evaluates an operation as a value of type `type_`.
+    fn eval_value(&mut self, op : &OperationResult, type_ : &Type) -> Result<Value, String> {
+        let value = self.eval(op)?;
+        Ok(self.convert(value, self.types.type_of(op), type_))
+    }
+
+    fn eval(&mut self, op : &OperationResult) -> Result<Value, String> {
+        if let Some(place) = self.eval_place(op)? {
+            return place.read();
+        }
+
+        match op {
+            OperationResult::BinOpResult(bin_op) => self.eval_binary_operation(bin_op),
+            OperationResult::UnaryOpResult(unary) => self.eval_unary_operation(unary),
+            OperationResult::Literal(lit) => Interpreter::literal_to_value(lit),
+            OperationResult::EnumValue(value) => {
+                let variant = value.enum_type
+                    .variant(&value.variant)
+                    .expect("enum values are checked by the parser");
+                Ok(Value::Int(variant.value))
+            },
+            OperationResult::FuncResult(fun) => Ok(self.call(fun)?.expect("calls used as values return one")),
+            OperationResult::MemberAccess(access) => {
+                // The object is not in memory (e.g. a returned struct)
+                let (index, _) = field_of(self.types.type_of(&access.object), &access.field)?;
+                match self.eval(&access.object)? {
+                    Value::Aggregate(mut fields) if index < fields.len() => Ok(fields.swap_remove(index)),
+                    _ => Err(format!("Invalid value of type {}", self.types.type_of(&access.object))),
+                }
+            },
+            OperationResult::StructLiteral(literal) => self.eval_struct_literal(op, literal),
+            OperationResult::Index(index) => {
+                // The array is not in memory (e.g. a returned array)
+                let array = self.eval_object_place(&index.object)?;
+                let array_type = self.types.type_of(&index.object);
+                self.eval_element_place(&array, array_type, &index.index)?.read()
+            },
+            OperationResult::ArrayLiteral(literal) => {
+                let element_type = match self.types.type_of(op) {
+                    Type::Array(element_type, _) => element_type.as_ref(),
+                    _ => unreachable!("array literals have an array type"),
+                };
+                let values = literal.elements
+                    .iter()
+                    .map(|element| self.eval_value(element, element_type))
+                    .collect::<Result<Vec<Value>, String>>()?;
+                Ok(Value::Aggregate(values))
+            },
+            OperationResult::Identifier(_) => unreachable!("identifiers are always places"),
+        }
+    }
+
+    fn eval_struct_literal(&mut self, op : &OperationResult, literal : &StructLiteral) -> Result<Value, String> {
+        let type_ = self.types.type_of(op);
+
+        // Fields are evaluated in source order, missing ones are zero
+        let mut fields = match self.zero_value(type_) {
+            Value::Aggregate(fields) => fields,
+            _ => unreachable!("classes are aggregates"),
+        };
+        for init in &literal.fields {
+            let (index, field_type) = field_of(type_, &init.field)?;
+            fields[index] = self.eval_value(&init.value, field_type)?;
+        }
+
+        Ok(Value::Aggregate(fields))
+    }
+
+    fn eval_binary_operation(&mut self, bin_op : &BinaryOperation) -> Result<Value, String> {
+        if let BinaryOperator::Assign = bin_op.operator {
+            let target = self.eval_place(&bin_op.left)?.expect("only places are assigned");
+            let value = self.eval_value(&bin_op.right, self.types.type_of(&bin_op.left))?;
+            target.write(value.clone())?;
+            return Ok(value);
+        }
+
+        // The right operand is only evaluated when the left one does not
+        // decide the value
+        if let BinaryOperator::And | BinaryOperator::Or = bin_op.operator {
+            let left = self.eval_condition(&bin_op.left)?;
+            let decided = matches!(bin_op.operator, BinaryOperator::Or) == left;
+            let value = if decided { left } else { self.eval_condition(&bin_op.right)? };
+            return Ok(Value::Int(value as i64));
+        }
+
+        let type_ = self.types.operand_type(bin_op);
+        let built_in_type = match type_ {
+            Type::BuiltIn(t) => t.clone(),
+            Type::Pointer(_) => BuiltInType::USize,
+            Type::Enum(_) => BuiltInType::I32,
+            _ => unreachable!("binary operators are checked by `check_types`"),
+        };
+        let left = self.eval_value(&bin_op.left, type_)?;
+        let right = self.eval_value(&bin_op.right, type_)?;
+
+        if bin_op.operator.is_comparison() {
+            let ordering = match (&left, &right) {
+                (Value::Int(l), Value::Int(r)) if built_in_type.is_signed() => l.partial_cmp(r),
+                (Value::Int(l), Value::Int(r)) => (*l as u64).partial_cmp(&(*r as u64)),
+                (Value::Float(l), Value::Float(r)) => l.partial_cmp(r),
+                (Value::String(l), Value::String(r)) => l.partial_cmp(r),
+                (Value::Pointer(l), Value::Pointer(r)) => Interpreter::compare_pointers(l, r),
+                _ => unreachable!("operands are converted to the same type"),
+            };
+
+            // Comparisons with NaN are unordered, only `!=` holds
+            let result = match (&bin_op.operator, ordering) {
+                (BinaryOperator::NotEquals, None) => true,
+                (_, None) => false,
+                (BinaryOperator::Equals, Some(o)) => o.is_eq(),
+                (BinaryOperator::NotEquals, Some(o)) => o.is_ne(),
+                (BinaryOperator::Less, Some(o)) => o.is_lt(),
+                (BinaryOperator::LessEq, Some(o)) => o.is_le(),
+                (BinaryOperator::Greater, Some(o)) => o.is_gt(),
+                (BinaryOperator::GreaterEq, Some(o)) => o.is_ge(),
+                _ => unreachable!("not a comparison"),
+            };
+
+            return Ok(Value::Int(result as i64));
+        }
+
+        match (left, right) {
+            (Value::String(mut l), Value::String(r)) => {
+                l.extend(r);
+                Ok(Value::String(l))
+            },
+            (Value::Float(l), Value::Float(r)) => {
+                let value = match bin_op.operator {
+                    BinaryOperator::Plus => l + r,
+                    BinaryOperator::Minus => l - r,
+                    BinaryOperator::Times => l * r,
+                    BinaryOperator::Div => l / r,
+                    BinaryOperator::Mod => l % r,
+                    _ => unreachable!("unsupported binary operator for floating point values"),
+                };
+                Ok(Value::Float(Interpreter::round_float(value, &built_in_type)))
+            },
+            (Value::Int(l), Value::Int(r)) => Ok(Value::Int(self.wrap(self.int_operation(&bin_op.operator, l, r, &built_in_type)?, &built_in_type))),
+            _ => unreachable!("operands are converted to the same type"),
+        }
+    }
+
+    // Pointers into the same variable are ordered by their path. Pointers
+    // into different variables are unordered, so only `!=` holds.
+    fn compare_pointers(left : &Option<Place>, right : &Option<Place>) -> Option<std::cmp::Ordering> {
+        match (left, right) {
+            (Some(l), Some(r)) if l.same_object(r) => l.path().partial_cmp(r.path()),
+            (None, None) => Some(std::cmp::Ordering::Equal),
+            _ => None,
+        }
+    }
+
+    fn int_operation(&self, op : &BinaryOperator, l : i64, r : i64, type_ : &BuiltInType) -> Result<i64, String> {
+        let signed = type_.is_signed();
+        match op {
+            BinaryOperator::Plus => Ok(l.wrapping_add(r)),
+            BinaryOperator::Minus => Ok(l.wrapping_sub(r)),
+            BinaryOperator::Times => Ok(l.wrapping_mul(r)),
+            BinaryOperator::Div | BinaryOperator::Mod if r == 0 => Err("Division by zero".to_string()),
+            BinaryOperator::Div if signed => Ok(l.wrapping_div(r)),
+            BinaryOperator::Div => Ok(((l as u64) / (r as u64)) as i64),
+            BinaryOperator::Mod if signed => Ok(l.wrapping_rem(r)),
+            BinaryOperator::Mod => Ok(((l as u64) % (r as u64)) as i64),
+            BinaryOperator::BitAnd => Ok(l & r),
+            BinaryOperator::BitOr => Ok(l | r),
+            BinaryOperator::Xor | BinaryOperator::BitXor => Ok(l ^ r),
+            BinaryOperator::LShift | BinaryOperator::RShift => {
+                let width = self.int_width(type_);
+                if r < 0 || r as usize >= width {
+                    return Err(format!("Shift amount {} out of range for {}", r, type_.to_string()));
+                }
+                match op {
+                    BinaryOperator::LShift => Ok(l << r),
+                    _ if signed => Ok(l >> r),
+                    _ => Ok(((l as u64) >> r) as i64),
+                }
+            },
+            _ => unreachable!("comparisons are evaluated apart"),
+        }
+    }
+
+    fn eval_unary_operation(&mut self, unary : &UnaryOperation) -> Result<Value, String> {
+        if let UnaryOperator::AddressOf = unary.operator {
+            let place = self.eval_place(&unary.operand)?.expect("only places have an address");
+            return Ok(Value::Pointer(Some(place)));
+        }
+
+        let built_in_type = match self.types.type_of(&unary.operand) {
+            Type::BuiltIn(t) => t.clone(),
+            _ => unreachable!("unary operators are checked by `check_types`"),
+        };
+
+        match (&unary.operator, self.eval(&unary.operand)?) {
+            (UnaryOperator::Negate, Value::Float(v)) => Ok(Value::Float(-v)),
+            (UnaryOperator::Negate, Value::Int(v)) => Ok(Value::Int(self.wrap(v.wrapping_neg(), &built_in_type))),
+            (UnaryOperator::Not, Value::Int(v)) if built_in_type == BuiltInType::Bool => Ok(Value::Int(v ^ 1)),
+            (UnaryOperator::Not, Value::Int(v)) => Ok(Value::Int(self.wrap(!v, &built_in_type))),
+            _ => unreachable!("unary operators are checked by `check_types`"),
+        }
+    }
+
+    /*******************/
+    /*******************/
+    /*******************/
+
+    fn call(&mut self, fun : &ApplyFunction) -> Result<Option<Value>, String> {
+        if let (Some(receiver), true) = (&fun.receiver, fun.function_id == method_name("string", "len")) {
+            return match self.eval(receiver)? {
+                Value::String(bytes) => Ok(Some(Value::Int(bytes.len() as i64))),
+                _ => Err("Invalid string value".to_string()),
+            };
+        }
+
+        let function = self.functions[fun.function_id.as_str()];
+
+        // Methods get the object they are called on as `self`
+        let mut scope = Scope::new();
+        if let Some(receiver) = &fun.receiver {
+            let object = self.eval_object_place(receiver)?;
+            scope.insert("self".to_string(), object);
+        }
+        for (arg, param) in fun.arguments.iter().zip(function.arguments.iter()) {
+            let value = self.eval_value(arg, &param.type_)?;
+            scope.insert(param.identifier.clone(), Place::new(value));
+        }
+
+        self.call_function(function, scope)
+    }
+
+    fn call_function(&mut self, function : &FunctionBlock, arguments : Scope) -> Result<Option<Value>, String> {
+        if self.frames.len() >= MAX_CALL_DEPTH {
+            return Err("Stack overflow".to_string());
+        }
+
+        self.frames.push(Frame {
+            scopes : vec![arguments],
+            return_type : function.return_type.clone(),
+        });
+        let flow = self.exec_body(&function.body);
+        self.frames.pop();
+
+        match (flow?, &function.return_type) {
+            (_, None) => Ok(None),
+            (Flow::Return(value), Some(_)) => Ok(value),
+            (Flow::Next, Some(_)) => Err(format!("Function {} ended without returning a value", function.identifier)),
+        }
+    }
+
+    fn exec_body(&mut self, body : &Body) -> Result<Flow, String> {
+        for stm in &body.statements {
+            if let Flow::Return(value) = self.exec_statement(stm)? {
+                return Ok(Flow::Return(value));
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    fn exec_scoped_body(&mut self, body : &Body) -> Result<Flow, String> {
+        self.push_scope();
+        let flow = self.exec_body(body);
+        self.pop_scope();
+        flow
+    }
+
+    fn push_scope(&mut self) {
+        self.frames.last_mut().unwrap().scopes.push(Scope::new());
+    }
+
+    fn pop_scope(&mut self) {
+        self.frames.last_mut().unwrap().scopes.pop();
+    }
+
+    fn eval_condition(&mut self, condition : &OperationResult) -> Result<bool, String> {
+        match self.eval(condition)? {
+            Value::Int(v) => Ok(v != 0),
+            _ => unreachable!("conditions are bools"),
+        }
+    }
+
+    fn exec_operation(&mut self, op : &OperationResult) -> Result<(), String> {
+        match op {
+            OperationResult::FuncResult(fun) => {self.call(fun)?;},
+            op => {self.eval(op)?;},
+        }
+        Ok(())
+    }
+
+    fn exec_statement(&mut self, stm : &Statement) -> Result<Flow, String> {
+        match stm {
+            Statement::Declaration(decl) => self.exec_declaration(decl)?,
+            Statement::Operation(op) => self.exec_operation(op)?,
+            Statement::Return(ret, _) => return self.exec_return(ret),
+            Statement::Body(body) => return self.exec_scoped_body(body),
+            Statement::If(if_block) => {
+                if self.eval_condition(&if_block.condition)? {
+                    return self.exec_scoped_body(&if_block.body);
+                }
+                if let Some(else_body) = &if_block.else_body {
+                    return self.exec_scoped_body(else_body);
+                }
+            },
+            Statement::While(while_block) => {
+                while self.eval_condition(&while_block.condition)? {
+                    if let Flow::Return(value) = self.exec_scoped_body(&while_block.body)? {
+                        return Ok(Flow::Return(value));
+                    }
+                }
+            },
+            Statement::For(for_block) => {
+                self.push_scope();
+                let flow = self.exec_for(for_block);
+                self.pop_scope();
+                return flow;
+            },
+            Statement::Match(match_block) => {
+                let body = self.find_match_arm(match_block)?;
+                return self.exec_scoped_body(body);
+            },
+            _ => return Err("Unsupported statement type".to_string()),
+        }
+        Ok(Flow::Next)
+    }
+
+    fn exec_declaration(&mut self, decl : &VariableDeclaration) -> Result<(), String> {
+        let value = match &decl.init_value {
+            Some(init_value) => self.eval_value(init_value, &decl.type_)?,
+            None => self.zero_value(&decl.type_),
+        };
+        self.declare_local(&decl.identifier, value);
+        Ok(())
+    }
+
+    fn exec_return(&mut self, ret : &Option<OperationResult>) -> Result<Flow, String> {
+        let return_type = self.frames.last().unwrap().return_type.clone();
+        let value = match (ret, return_type) {
+            (None, None) => None,
+            (Some(op), Some(type_)) => Some(self.eval_value(op, &type_)?),
+            _ => unreachable!("return values are checked against the return type"),
+        };
+        Ok(Flow::Return(value))
+    }
+
+    fn find_match_arm<'b>(&mut self, match_block : &'b MatchBlock) -> Result<&'b Body, String> {
+        let enum_type = match self.types.type_of(&match_block.value) {
+            Type::Enum(enum_type) => enum_type.clone(),
+            _ => unreachable!("only enums are matched"),
+        };
+        let discriminant = match self.eval(&match_block.value)? {
+            Value::Int(v) => v,
+            _ => return Err(format!("Invalid value of type {}", enum_type.identifier)),
+        };
+
+        let arm = match_block.arms
+            .iter()
+            .find(|arm| arm.variants
+                .iter()
+                .any(|variant| enum_type.variant(variant).map(|v| v.value) == Some(discriminant))
+            );
+
+        match (arm, &match_block.default) {
+            (Some(arm), _) => Ok(&arm.body),
+            (None, Some(default)) => Ok(default),
+            (None, None) => Err(format!("No arm of match for {} value {}", enum_type.identifier, discriminant)),
+        }
+    }
+
+    fn exec_for(&mut self, for_block : &ForBlock) -> Result<Flow, String> {
+        if let Some(init_statement) = &for_block.init_statement {
+            self.exec_statement(init_statement)?;
+        }
+
+        while self.eval_condition(&for_block.condition)? {
+            if let Flow::Return(value) = self.exec_scoped_body(&for_block.body)? {
+                return Ok(Flow::Return(value));
+            }
+            if let Some(end_statement) = &for_block.end_statement {
+                self.exec_operation(end_statement)?;
+            }
+        }
+        Ok(Flow::Next)
+    }
+}
@@ -0,0 +1,3 @@
+pub mod interp;
+pub mod value;
+pub mod prelude;
\ No newline at end of file
@@ -0,0 +1,2 @@
+pub use super::interp::{interpret, interpret_with_target};
+pub use super::value::{Value, Place};
\ No newline at end of file
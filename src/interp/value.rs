@@ -0,0 +1,115 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Integers, bools and enum values are stored as `Int`, wrapped to the width
+// of their type. Signed values are sign extended and unsigned ones zero
+// extended, so `uint64` values above `i64::MAX` are negative here.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int(i64),
+    Float(f64),
+    String(Vec<u8>),
+    // Fields of a class or elements of an array
+    Aggregate(Vec<Value>),
+    Pointer(Option<Place>),
+}
+
+impl Value {
+    pub fn as_int(&self) -> Option<i64> {
+        match self {
+            Value::Int(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    pub fn as_float(&self) -> Option<f64> {
+        match self {
+            Value::Float(value) => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+/*******************/
+/*******************/
+/*******************/
+
+// A location in memory: a variable and the path of field and element
+// indices inside it. Pointers are places, so two pointers are equal when
+// they point into the same variable with the same path.
+#[derive(Clone)]
+pub struct Place {
+    slot : Rc<RefCell<Value>>,
+    path : Vec<usize>,
+}
+
+impl Place {
+    pub fn new(value : Value) -> Place {
+        Place {
+            slot : Rc::new(RefCell::new(value)),
+            path : vec![],
+        }
+    }
+
+    pub fn element(&self, index : usize) -> Place {
+        let mut path = self.path.clone();
+        path.push(index);
+
+        Place {
+            slot : self.slot.clone(),
+            path,
+        }
+    }
+
+    pub fn read(&self) -> Result<Value, String> {
+        let slot = self.slot.borrow();
+        let mut value : &Value = &slot;
+        for index in &self.path {
+            value = match value {
+                Value::Aggregate(elements) => elements.get(*index),
+                // Characters of a string are read as `uint8`
+                Value::String(bytes) => return bytes
+                    .get(*index)
+                    .map(|byte| Value::Int(*byte as i64))
+                    .ok_or_else(|| format!("Index {} out of bounds for length {}", index, bytes.len())),
+                _ => None,
+            }.ok_or_else(|| "Invalid place".to_string())?;
+        }
+        Ok(value.clone())
+    }
+
+    pub fn write(&self, new_value : Value) -> Result<(), String> {
+        let mut slot = self.slot.borrow_mut();
+        let mut value : &mut Value = &mut slot;
+        for index in &self.path {
+            value = match value {
+                Value::Aggregate(elements) => elements.get_mut(*index),
+                Value::String(_) => return Err("Cannot modify a character of a string, strings are immutable".to_string()),
+                _ => None,
+            }.ok_or_else(|| "Invalid place".to_string())?;
+        }
+        *value = new_value;
+        Ok(())
+    }
+
+    pub fn same_object(&self, other : &Place) -> bool {
+        Rc::ptr_eq(&self.slot, &other.slot)
+    }
+
+    pub fn path(&self) -> &[usize] {
+        &self.path
+    }
+}
+
+impl PartialEq for Place {
+    fn eq(&self, other : &Place) -> bool {
+        self.same_object(other) && self.path == other.path
+    }
+}
+
+// The pointee is not printed, it may contain the pointer itself.
+impl std::fmt::Debug for Place {
+    fn fmt(&self, f : &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Place({:p}, {:?})", Rc::as_ptr(&self.slot), self.path)
+    }
+}
@@ -1,24 +1,69 @@
 use super::tokens::Token;
-use super::lexer::lexer;
+use super::lexer::{lexer, lex};
+use super::token_cursor::TokenCursor;
 
-#[derive(Debug)]
-pub struct Range {
+// `offset` is the byte offset in the source. `column` counts UTF-8 bytes
+// from the start of the line and `column_utf16` counts UTF-16 code units,
+// which is what most editors expect.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Position {
+    pub offset : usize,
     pub line : usize,
-    pub char_begin : usize,
-    pub char_end : usize,
+    pub column : usize,
+    pub column_utf16 : usize,
+}
+
+impl std::fmt::Display for Position {
+    fn fmt(&self, f : &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line+1, self.column+1)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Range {
+    pub start : Position,
+    pub end : Position,
 }
 
-#[derive(Debug)]
+impl Range {
+    pub fn bytes(&self) -> std::ops::Range<usize> {
+        self.start.offset..self.end.offset
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Trivia {
+    Whitespace(String),
+    Comment(String),
+}
+
+impl Trivia {
+    pub fn text(&self) -> &str {
+        match self {
+            Trivia::Whitespace(s) => s,
+            Trivia::Comment(s) => s,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct ContextedToken {
     pub token : Token,
     pub range : Range,
+    pub leading_trivia : Vec<Trivia>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Lexeme {
     tokens : Vec<ContextedToken>
 }
 
+impl Default for Lexeme {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Lexeme {
     pub fn new () -> Lexeme {
         Lexeme{tokens : vec![]}
@@ -44,7 +89,94 @@ impl Lexeme {
         &mut self.tokens[index]
     }
 
+    pub fn iter(&self) -> std::slice::Iter<'_, ContextedToken> {
+        self.tokens.iter()
+    }
+
+    pub fn cursor(&self) -> TokenCursor<'_> {
+        TokenCursor::new(&self.tokens)
+    }
+
     pub fn from_file(file : &str) -> Lexeme {
-        lexer(file)
+        lexer(file, false)
+    }
+
+    pub fn from_source(source : &str) -> Lexeme {
+        lex(source, false)
+    }
+
+    pub fn from_file_lossless(file : &str) -> Lexeme {
+        lexer(file, true)
+    }
+
+    pub fn from_source_lossless(source : &str) -> Lexeme {
+        lex(source, true)
+    }
+}
+
+impl<'a> IntoIterator for &'a Lexeme {
+    type Item = &'a ContextedToken;
+    type IntoIter = std::slice::Iter<'a, ContextedToken>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.tokens.iter()
+    }
+}
+
+impl IntoIterator for Lexeme {
+    type Item = ContextedToken;
+    type IntoIter = std::vec::IntoIter<ContextedToken>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.tokens.into_iter()
+    }
+}
+
+impl std::ops::Index<usize> for Lexeme {
+    type Output = ContextedToken;
+
+    fn index(&self, index : usize) -> &ContextedToken {
+        &self.tokens[index]
     }
-}
\ No newline at end of file
+}
+
+impl std::ops::Index<std::ops::Range<usize>> for Lexeme {
+    type Output = [ContextedToken];
+
+    fn index(&self, range : std::ops::Range<usize>) -> &[ContextedToken] {
+        &self.tokens[range]
+    }
+}
+/*******************/
+/*******************/
+/*******************/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn iterates_and_indexes_tokens() {
+        let lexeme = Lexeme::from_source("x = 1;");
+        let tokens : Vec<Token> = lexeme.iter().map(|tok| tok.token.clone()).collect();
+        assert_eq!(tokens, (&lexeme).into_iter().map(|tok| tok.token.clone()).collect::<Vec<Token>>());
+        assert_eq!(tokens.len(), lexeme.len());
+
+        assert_eq!(lexeme[0].token, Token::Identifier(String::from("x")));
+        assert_eq!(lexeme[2], *lexeme.token_at(2));
+        assert_eq!(lexeme[1..3].iter().map(|tok| &tok.token).collect::<Vec<&Token>>(), [&tokens[1], &tokens[2]]);
+        assert_eq!(lexeme[4].token, Token::EndOfFile);
+
+        let owned : Vec<Token> = lexeme.clone().into_iter().map(|tok| tok.token).collect();
+        assert_eq!(owned, tokens);
+    }
+
+    #[test]
+    fn compares_tokens_and_ranges() {
+        let lexeme = Lexeme::from_source("x = 1;");
+        assert_eq!(lexeme.clone(), lexeme);
+        assert_eq!(Lexeme::from_source("x = 1;"), lexeme);
+        assert_ne!(Lexeme::from_source("x  = 1;"), lexeme);
+        assert_ne!(Lexeme::from_source_lossless("x = 1;"), lexeme);
+    }
+}
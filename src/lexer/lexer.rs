@@ -1,17 +1,24 @@
 use super::lexeme::*;
 use super::tokens::*;
 use std::fs;
-use std::io::{BufReader, BufRead};
 
-fn open_file(file_path : &str) -> fs::File {
-    match fs::File::open(file_path) {
-        Ok(f) => f,
+fn read_file(file_path : &str) -> String {
+    match fs::read_to_string(file_path) {
+        Ok(s) => s,
         Err(e) => {
             panic!("{}",e);
         } 
     }
 }
 
+fn is_whitespace(ch : char) -> bool {
+    return
+        ch == ' ' ||
+        ch == '\n' ||
+        ch == '\r' ||
+        ch == '\t';
+}
+
 fn is_identifier_init(ch : char) -> bool {
     return
         ch.is_alphabetic() ||
@@ -31,6 +38,20 @@ fn is_number_init(ch : char) -> bool {
         ch == '.';
 }
 
+fn find_line_comment(source : &str) -> &str {
+    match source.find('\n') {
+        Some(n) => source[..n].trim_end_matches('\r'),
+        None => source,
+    }
+}
+
+fn find_block_comment(source : &str) -> &str {
+    match source[2..].find("*/") {
+        Some(n) => &source[..n+4],
+        None => panic!("Unterminated block comment"),
+    }
+}
+
 fn find_token(line : &str) -> Token {
     let mut chars = line.chars();
     let char_0 = chars.next().unwrap();
@@ -46,6 +67,7 @@ fn find_token(line : &str) -> Token {
         '}' => {return Token::Delimiter(Delimiter::BracketsClose)},
         '[' => {return Token::Delimiter(Delimiter::SquareBracketsOpen)},
         ']' => {return Token::Delimiter(Delimiter::SquareBracketsClose)},
+        ':' => {return Token::Delimiter(Delimiter::Colon)},
         ',' => {return Token::Operator(Operator::Comma)},
 
         '+' => match char_1 {
@@ -64,7 +86,8 @@ fn find_token(line : &str) -> Token {
         }},
 
         '/' => {match char_1 {
-            Some('/') => {return Token::Comment(String::from(&line[2..]))},
+            Some('/') => {return Token::Comment(String::from(find_line_comment(line)))},
+            Some('*') => {return Token::Comment(String::from(find_block_comment(line)))},
             Some('=') => {return Token::Operator(Operator::DivEq)},
             _ => {return Token::Operator(Operator::Div)}
         }},
@@ -76,6 +99,7 @@ fn find_token(line : &str) -> Token {
 
         '=' => {match char_1 {
             Some('=') => {return Token::Operator(Operator::Equals)},
+            Some('>') => {return Token::Operator(Operator::Arrow)},
             _ => {return Token::Operator(Operator::Assign)}
         }},
 
@@ -141,14 +165,19 @@ fn find_token(line : &str) -> Token {
         let mut n : usize = 0;
         for ch in line.chars() {
             if !is_identifier_continuation(ch) {break}
-            n += 1;
+            n += ch.len_utf8();
         }
 
         let s = line[..n].trim();
 
-        match Keyword::from_string(s) {
-            Some(kw) => return Token::Keyword(kw),
-            None => {},
+        if let Some(kw) = Keyword::from_string(s) {
+            return Token::Keyword(kw);
+        }
+
+        match s {
+            "true" => return Token::Boolean(true),
+            "false" => return Token::Boolean(false),
+            _ => {},
         }
 
         return Token::Identifier(String::from(s));
@@ -164,20 +193,20 @@ fn find_token(line : &str) -> Token {
         for ch in line.chars() {
             if !point_found && ch == '.' {
                 point_found = true;
-                n += 1;
+                n += ch.len_utf8();
                 continue;
             }
             if !e_found && (ch == 'e' || ch == 'E') {
                 e_found = true;
                 e_just_found = true;
-                n += 1;
+                n += ch.len_utf8();
                 continue;
             }
             if e_just_found {
                 e_just_found = false;
                 if ch == '+' || ch == '-' {
                     e_pm_just_found = true;
-                    n += 1;
+                    n += ch.len_utf8();
                     continue;
                 }
                 if !ch.is_numeric() {
@@ -192,7 +221,7 @@ fn find_token(line : &str) -> Token {
             }
 
             if !ch.is_numeric() {break};
-            n += 1;
+            n += ch.len_utf8();
         }
 
         let s = line[..n].trim();
@@ -204,10 +233,18 @@ fn find_token(line : &str) -> Token {
     }
 
     if char_0 == '"' {
+        // The token keeps the escape sequences as written in the source
         let mut n : usize = 0;
+        let mut closed = false;
+        let mut escaped = false;
         for ch in line[1..].chars() {
-            if ch == '"' {break}
-            n += 1;
+            if ch == '"' && !escaped {closed = true; break}
+            escaped = ch == '\\' && !escaped;
+            n += ch.len_utf8();
+        }
+
+        if !closed {
+            panic!("Unterminated string literal");
         }
 
         let s = &line[1..n+1];
@@ -217,74 +254,228 @@ fn find_token(line : &str) -> Token {
     panic!("Invalid character found: {}", char_0);
 }
 
-fn find_first_not_of(s : &str, chars : Vec<char>) -> Option<usize> {
-    let mut n : usize = 0;
-    for ch in s.chars() {
-        let mut some_equal = false;
-        for ch2 in chars.iter() {
-            if ch == *ch2 {
-                some_equal = true;
-                break;
+
+/*******************/
+/*******************/
+/*******************/
+
+struct Cursor<'a> {
+    source : &'a str,
+    offset : usize,
+    position : Position,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(source : &'a str) -> Cursor<'a> {
+        Cursor {
+            source,
+            offset : 0,
+            position : Position { offset : 0, line : 0, column : 0, column_utf16 : 0 },
+        }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.source[self.offset..]
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.offset >= self.source.len()
+    }
+
+    fn advance(&mut self, n_bytes : usize) {
+        for ch in self.source[self.offset..self.offset+n_bytes].chars() {
+            if ch == '\n' {
+                self.position.line += 1;
+                self.position.column = 0;
+                self.position.column_utf16 = 0;
+            } else {
+                self.position.column += ch.len_utf8();
+                self.position.column_utf16 += ch.len_utf16();
             }
         }
+        self.offset += n_bytes;
+        self.position.offset = self.offset;
+    }
 
-        if !some_equal {return Some(n);}
-        
-        n += 1;
+    fn skip_whitespace(&mut self) -> &'a str {
+        let begin = self.offset;
+        let n_bytes = match self.rest().find(|ch| !is_whitespace(ch)) {
+            Some(n) => n,
+            None => self.rest().len(),
+        };
+        self.advance(n_bytes);
+        &self.source[begin..self.offset]
     }
-    return None;
 }
 
-fn read_tokens_in_line<'a,'b>(lexeme: &'a mut Lexeme, mut line : &'b str, n_line : usize) {
-    let mut ch : usize = match find_first_not_of(line, vec![' ', '\n', '\r', '\t']) {
-        Some(n) => n,
-        None => return,
-    };
+fn push_whitespace(trivia : &mut Vec<Trivia>, whitespace : &str) {
+    if !whitespace.is_empty() {
+        trivia.push(Trivia::Whitespace(String::from(whitespace)));
+    }
+}
 
-    line = &line[ch..];
+// In lossless mode whitespace and comments are kept as leading trivia of the
+// next token, so the source can be rebuilt from the lexeme byte for byte.
+// The lexeme always ends with `Token::EndOfFile`, which carries whatever
+// trivia follows the last real token.
+pub fn lex(source : &str, lossless : bool) -> Lexeme {
+    let mut lexeme = Lexeme::new();
+    let mut cursor = Cursor::new(source);
+    let mut trivia : Vec<Trivia> = vec![];
+
+    let whitespace = cursor.skip_whitespace();
+    if lossless {
+        push_whitespace(&mut trivia, whitespace);
+    }
+
+    while !cursor.is_at_end() {
+        let token = find_token(cursor.rest());
+        let start = cursor.position;
+
+        cursor.advance(token.len());
 
-    while line.len() > 0 {
-        let token = find_token(&line);
-        let token_len = token.len();
-        
         match token {
-            Token::Comment(_) => {},
+            Token::Comment(comment) => {
+                if lossless {
+                    trivia.push(Trivia::Comment(comment));
+                }
+            },
             _ => {
                 lexeme.push(ContextedToken{
-                    token : token,
+                    token,
                     range : Range {
-                        line : n_line,
-                        char_begin : ch,
-                        char_end : ch+token_len,
-                    }
+                        start,
+                        end : cursor.position,
+                    },
+                    leading_trivia : std::mem::take(&mut trivia),
                 });
             }
         }
-        
-        line = &line[token_len..];
-        
-        let spaces_len = match find_first_not_of(line, vec![' ', '\n', '\r', '\t']) {
-            Some(n) => n,
-            None => return,
-        };
-        line = &line[spaces_len..];
-        
-        ch += token_len + spaces_len;
-        
+
+        let whitespace = cursor.skip_whitespace();
+        if lossless {
+            push_whitespace(&mut trivia, whitespace);
+        }
     }
+
+    lexeme.push(ContextedToken{
+        token : Token::EndOfFile,
+        range : Range {
+            start : cursor.position,
+            end : cursor.position,
+        },
+        leading_trivia : trivia,
+    });
+
+    return lexeme;
 }
 
-pub fn lexer(file_path : &str) -> Lexeme {
-    let mut lexeme = Lexeme::new();
+pub fn lexer(file_path : &str, lossless : bool) -> Lexeme {
+    lex(&read_file(file_path), lossless)
+}
 
-    let file : fs::File = open_file(file_path);
-    let reader = BufReader::new(file);
+/*******************/
+/*******************/
+/*******************/
 
-    for (n_line, line) in reader.lines().enumerate() {
-        let line = line.unwrap();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        read_tokens_in_line(&mut lexeme, &line, n_line);
+    fn position(offset : usize, line : usize, column : usize) -> Position {
+        Position { offset, line, column, column_utf16 : column }
     }
 
-    return lexeme;
+    #[test]
+    fn ranges_span_lines() {
+        let lexeme = lex("int32 x;\n  x = \"a\nb\";\n", false);
+        let tokens : Vec<&Token> = lexeme.iter().map(|tok| &tok.token).collect();
+        assert_eq!(tokens, [
+            &Token::Identifier(String::from("int32")),
+            &Token::Identifier(String::from("x")),
+            &Token::EndOfStatement,
+            &Token::Identifier(String::from("x")),
+            &Token::Operator(Operator::Assign),
+            &Token::String(String::from("a\nb")),
+            &Token::EndOfStatement,
+            &Token::EndOfFile,
+        ]);
+
+        assert_eq!(lexeme[1].range, Range { start : position(6, 0, 6), end : position(7, 0, 7) });
+        assert_eq!(lexeme[3].range, Range { start : position(11, 1, 2), end : position(12, 1, 3) });
+        assert_eq!(lexeme[5].range, Range { start : position(15, 1, 6), end : position(20, 2, 2) });
+        assert_eq!(lexeme[7].range.start, position(22, 3, 0));
+    }
+
+    #[test]
+    fn block_comments_span_lines() {
+        let source = "a /* one\r\ntwo */ b\r\n";
+        let lexeme = lex(source, true);
+        assert_eq!(lexeme[1].leading_trivia, [
+            Trivia::Whitespace(String::from(" ")),
+            Trivia::Comment(String::from("/* one\r\ntwo */")),
+            Trivia::Whitespace(String::from(" ")),
+        ]);
+        assert_eq!(lexeme[1].range.start, position(17, 1, 7));
+        assert_eq!(lexeme[2].leading_trivia, [Trivia::Whitespace(String::from("\r\n"))]);
+        assert_eq!(&source[lexeme[1].range.bytes()], "b");
+    }
+
+    #[test]
+    fn line_comments_end_before_the_line_ending() {
+        let lexeme = lex("a // comment\r\nb", true);
+        assert_eq!(lexeme[1].leading_trivia, [
+            Trivia::Whitespace(String::from(" ")),
+            Trivia::Comment(String::from("// comment")),
+            Trivia::Whitespace(String::from("\r\n")),
+        ]);
+        assert_eq!(lexeme[1].range.start, position(14, 1, 0));
+    }
+
+    #[test]
+    fn columns_count_utf8_bytes_and_utf16_units() {
+        let source = "s = \"é😀\"; ñame;";
+        let lexeme = lex(source, false);
+
+        // `é` is 2 bytes and 1 UTF-16 unit, `😀` is 4 bytes and 2 units
+        assert_eq!(lexeme[2].range.end, Position { offset : 12, line : 0, column : 12, column_utf16 : 9 });
+        assert_eq!(lexeme[4].token, Token::Identifier(String::from("ñame")));
+        assert_eq!(lexeme[4].range.start, Position { offset : 14, line : 0, column : 14, column_utf16 : 11 });
+        assert_eq!(lexeme[4].range.end, Position { offset : 19, line : 0, column : 19, column_utf16 : 15 });
+        assert_eq!(&source[lexeme[4].range.bytes()], "ñame");
+    }
+
+    #[test]
+    fn columns_restart_after_non_ascii_lines() {
+        let lexeme = lex("\"😀😀\"\nx", false);
+        assert_eq!(lexeme[1].range.start, Position { offset : 11, line : 1, column : 0, column_utf16 : 0 });
+        assert_eq!(lexeme[1].range.start.to_string(), "2:1");
+    }
+
+    #[test]
+    fn ends_with_end_of_file() {
+        let lexeme = lex("", true);
+        assert_eq!(lexeme.len(), 1);
+        assert_eq!(lexeme[0].token, Token::EndOfFile);
+
+        let lexeme = lex("x; // last\n", true);
+        let end = &lexeme[lexeme.len()-1];
+        assert_eq!(end.token, Token::EndOfFile);
+        assert_eq!(end.range.start, end.range.end);
+        assert_eq!(end.range.start, position(11, 1, 0));
+        assert_eq!(end.leading_trivia, [
+            Trivia::Whitespace(String::from(" ")),
+            Trivia::Comment(String::from("// last")),
+            Trivia::Whitespace(String::from("\n")),
+        ]);
+
+        let lexeme = lex("x; // last\n", false);
+        assert!(lexeme[lexeme.len()-1].leading_trivia.is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "Unterminated block comment")]
+    fn unterminated_block_comment() {
+        lex("a /* b", false);
+    }
 }
@@ -1,5 +1,6 @@
 pub mod tokens;
 pub mod lexeme;
+pub mod token_cursor;
 pub mod prelude;
 
 mod lexer;
\ No newline at end of file
@@ -1,2 +1,3 @@
 pub use super::lexeme::Lexeme;
+pub use super::token_cursor::TokenCursor;
 pub use super::tokens::*;
\ No newline at end of file
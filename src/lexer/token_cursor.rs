@@ -0,0 +1,206 @@
+use super::lexeme::{ContextedToken, Position};
+use super::tokens::Token;
+
+// Cursor over a slice of tokens. It never moves past a `Token::EndOfFile`,
+// so on a full lexeme the current token is always available. On a slice
+// without one, `current` returns `None` once every token has been consumed.
+#[derive(Debug, Clone, Copy)]
+pub struct TokenCursor<'a> {
+    tokens : &'a [ContextedToken],
+    position : usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Checkpoint(usize);
+
+impl<'a> TokenCursor<'a> {
+    pub fn new(tokens : &'a [ContextedToken]) -> TokenCursor<'a> {
+        TokenCursor {
+            tokens,
+            position : 0,
+        }
+    }
+
+    pub fn tokens(&self) -> &'a [ContextedToken] {
+        self.tokens
+    }
+
+    pub fn index(&self) -> usize {
+        self.position
+    }
+
+    pub fn current(&self) -> Option<&'a ContextedToken> {
+        self.tokens.get(self.position)
+    }
+
+    pub fn peek(&self) -> Option<&'a Token> {
+        self.peek_nth(0)
+    }
+
+    pub fn peek_nth(&self, n : usize) -> Option<&'a Token> {
+        let mut index = self.position;
+        for _ in 0..n {
+            match self.tokens.get(index) {
+                Some(ContextedToken{token : Token::EndOfFile, ..}) => break,
+                Some(_) => index += 1,
+                None => return None,
+            }
+        }
+        self.tokens.get(index).map(|tok| &tok.token)
+    }
+
+    pub fn is_at_end(&self) -> bool {
+        matches!(self.peek(), None | Some(Token::EndOfFile))
+    }
+
+    // Position of the current token, or the end of the last one when the
+    // cursor is past the end of the slice.
+    pub fn position(&self) -> Position {
+        match self.current() {
+            Some(tok) => tok.range.start,
+            None => match self.tokens.last() {
+                Some(tok) => tok.range.end,
+                None => Position { offset : 0, line : 0, column : 0, column_utf16 : 0 },
+            },
+        }
+    }
+
+    pub fn advance(&mut self) -> Option<&'a ContextedToken> {
+        let token = self.current()?;
+        if token.token != Token::EndOfFile {
+            self.position += 1;
+        }
+        Some(token)
+    }
+
+    pub fn check(&self, token : &Token) -> bool {
+        self.peek() == Some(token)
+    }
+
+    pub fn eat(&mut self, token : &Token) -> bool {
+        if self.check(token) {
+            self.advance();
+            return true;
+        }
+        return false;
+    }
+
+    pub fn expect(&mut self, token : Token) -> &'a ContextedToken {
+        match self.current() {
+            Some(tok) if tok.token == token => {
+                self.advance();
+                tok
+            },
+            Some(ContextedToken{token : Token::EndOfFile, ..}) | None => {
+                panic!("Unexpected end of file: expected `{}` at {}", token.to_source(), self.position())
+            },
+            Some(tok) => {
+                panic!("Expected `{}`, found `{}` at {}", token.to_source(), tok.token.to_source(), tok.range.start)
+            },
+        }
+    }
+
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint(self.position)
+    }
+
+    pub fn rewind(&mut self, checkpoint : Checkpoint) {
+        self.position = checkpoint.0;
+    }
+}
+
+/*******************/
+/*******************/
+/*******************/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::lexeme::Lexeme;
+    use crate::lexer::tokens::{Delimiter, Operator};
+
+    fn identifier(id : &str) -> Token {
+        Token::Identifier(String::from(id))
+    }
+
+    #[test]
+    fn peeks_without_moving() {
+        let lexeme = Lexeme::from_source("a + b");
+        let cursor = lexeme.cursor();
+        assert_eq!(cursor.peek(), Some(&identifier("a")));
+        assert_eq!(cursor.peek_nth(1), Some(&Token::Operator(Operator::Plus)));
+        assert_eq!(cursor.peek_nth(3), Some(&Token::EndOfFile));
+        assert_eq!(cursor.peek_nth(10), Some(&Token::EndOfFile));
+        assert_eq!(cursor.index(), 0);
+    }
+
+    #[test]
+    fn stops_at_end_of_file() {
+        let lexeme = Lexeme::from_source("a b");
+        let mut cursor = lexeme.cursor();
+        assert_eq!(cursor.advance().map(|tok| &tok.token), Some(&identifier("a")));
+        assert_eq!(cursor.advance().map(|tok| &tok.token), Some(&identifier("b")));
+        assert!(cursor.is_at_end());
+        assert_eq!(cursor.advance().map(|tok| &tok.token), Some(&Token::EndOfFile));
+        assert_eq!(cursor.advance().map(|tok| &tok.token), Some(&Token::EndOfFile));
+        assert_eq!(cursor.index(), 2);
+        assert_eq!(cursor.position().to_string(), "1:4");
+    }
+
+    #[test]
+    fn runs_out_on_slices_without_end_of_file() {
+        let lexeme = Lexeme::from_source("a b;");
+        let mut cursor = TokenCursor::new(&lexeme[0..2]);
+        cursor.advance();
+        cursor.advance();
+        assert!(cursor.is_at_end());
+        assert_eq!(cursor.peek(), None);
+        assert!(cursor.advance().is_none());
+        assert_eq!(cursor.position(), lexeme[1].range.end);
+    }
+
+    #[test]
+    fn checks_eats_and_expects() {
+        let lexeme = Lexeme::from_source("( a )");
+        let mut cursor = lexeme.cursor();
+        assert!(cursor.check(&Token::Delimiter(Delimiter::ParenthesisOpen)));
+        assert!(!cursor.eat(&identifier("a")));
+        assert!(cursor.eat(&Token::Delimiter(Delimiter::ParenthesisOpen)));
+        assert_eq!(cursor.expect(identifier("a")).range.start.to_string(), "1:3");
+        assert!(cursor.eat(&Token::Delimiter(Delimiter::ParenthesisClose)));
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected `)`, found `a` at 1:3")]
+    fn expect_reports_the_found_token() {
+        let lexeme = Lexeme::from_source("( a )");
+        let mut cursor = lexeme.cursor();
+        cursor.advance();
+        cursor.expect(Token::Delimiter(Delimiter::ParenthesisClose));
+    }
+
+    #[test]
+    #[should_panic(expected = "Unexpected end of file: expected `;` at 1:2")]
+    fn expect_reports_end_of_file() {
+        let lexeme = Lexeme::from_source("a");
+        let mut cursor = lexeme.cursor();
+        cursor.advance();
+        cursor.expect(Token::EndOfStatement);
+    }
+
+    #[test]
+    fn rewinds_to_checkpoints() {
+        let lexeme = Lexeme::from_source("a b c");
+        let mut cursor = lexeme.cursor();
+        cursor.advance();
+        let checkpoint = cursor.checkpoint();
+        cursor.advance();
+        cursor.advance();
+        assert!(cursor.is_at_end());
+
+        cursor.rewind(checkpoint);
+        assert_eq!(cursor.index(), 1);
+        assert_eq!(cursor.peek(), Some(&identifier("b")));
+        assert_eq!(cursor.checkpoint(), checkpoint);
+    }
+}
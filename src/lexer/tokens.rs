@@ -7,11 +7,14 @@ pub enum Keyword {
     While,
     Return,
     Class,
-    Void
+    Enum,
+    Match,
+    Void,
+    Null,
 }
 
 impl Keyword {
-    pub fn to_string<'a,'b>(&'a self) -> &'b str {
+    pub fn to_string<'b>(&self) -> &'b str {
         match self {
             Keyword::Def => return "def",
             Keyword::If => return "if",
@@ -20,7 +23,10 @@ impl Keyword {
             Keyword::While => return "while",
             Keyword::Return => return "return",
             Keyword::Class => return "class",
+            Keyword::Enum => return "enum",
+            Keyword::Match => return "match",
             Keyword::Void => return "void",
+            Keyword::Null => return "null",
         }
     }
 
@@ -33,7 +39,10 @@ impl Keyword {
             "while" => return Some(Keyword::While),
             "return" => return Some(Keyword::Return),
             "class" => return Some(Keyword::Class),
+            "enum" => return Some(Keyword::Enum),
+            "match" => return Some(Keyword::Match),
             "void" => return Some(Keyword::Void),
+            "null" => return Some(Keyword::Null),
             _ => None,
         }
     }
@@ -90,10 +99,11 @@ pub enum Operator {
 
     Comma,
     Point,
+    Arrow,
 }
 
 impl Operator {
-    pub fn to_string<'a,'b>(&'a self) -> &'b str {
+    pub fn to_string<'b>(&self) -> &'b str {
         match self {
             Operator::Assign => return "=",
 
@@ -132,14 +142,15 @@ impl Operator {
             Operator::BitwiseXor => return "^",
 
             Operator::AndEq => return "&&=",
-            Operator::OrEq => return "||&",
-            Operator::XorEq => return "^^&",
+            Operator::OrEq => return "||=",
+            Operator::XorEq => return "^^=",
             Operator::BitwiseAndEq => return "&=",
             Operator::BitwiseOrEq => return "|=",
             Operator::BitwiseXorEq => return "^=",
 
             Operator::Comma => return ",",
             Operator::Point => return ".",
+            Operator::Arrow => return "=>",
         }
     }
 
@@ -174,13 +185,14 @@ impl Operator {
             "|" => return Some(Operator::BitwiseOr),
             "^" => return Some(Operator::BitwiseXor),
             "&&=" => return Some(Operator::AndEq),
-            "||&" => return Some(Operator::OrEq),
-            "^^&" => return Some(Operator::XorEq),
+            "||=" => return Some(Operator::OrEq),
+            "^^=" => return Some(Operator::XorEq),
             "&=" => return Some(Operator::BitwiseAndEq),
             "|=" => return Some(Operator::BitwiseOrEq),
             "^=" => return Some(Operator::BitwiseXorEq),
             "," => return Some(Operator::Comma),
             "." => return Some(Operator::Point),
+            "=>" => return Some(Operator::Arrow),
             _ => return None,
         }
     }
@@ -198,9 +210,22 @@ pub enum Delimiter {
     BracketsClose,
     SquareBracketsOpen,
     SquareBracketsClose,
+    Colon,
 }
 
 impl Delimiter {
+    pub fn to_string<'b>(&self) -> &'b str {
+        match self {
+            Delimiter::ParenthesisOpen => return "(",
+            Delimiter::ParenthesisClose => return ")",
+            Delimiter::BracketsOpen => return "{",
+            Delimiter::BracketsClose => return "}",
+            Delimiter::SquareBracketsOpen => return "[",
+            Delimiter::SquareBracketsClose => return "]",
+            Delimiter::Colon => return ":",
+        }
+    }
+
     pub fn len(&self) -> usize {
         return 1;
     }
@@ -222,6 +247,22 @@ pub enum Token {
 }
 
 impl Token {
+    pub fn to_source(&self) -> String {
+        match self {
+            Token::EndOfFile => return String::new(),
+            Token::EndOfStatement => return String::from(";"),
+            Token::Keyword(kw) => return String::from(kw.to_string()),
+            Token::Operator(op) => return String::from(op.to_string()),
+            Token::Integer(s) => return s.clone(),
+            Token::Float(s) => return s.clone(),
+            Token::String(s) => return format!("\"{}\"", s),
+            Token::Boolean(b) => return b.to_string(),
+            Token::Identifier(s) => return s.clone(),
+            Token::Delimiter(d) => return String::from(d.to_string()),
+            Token::Comment(s) => return s.clone(),
+        }
+    }
+
     pub fn len(&self) -> usize {
         match self {
             Token::EndOfFile => return 0,
@@ -231,10 +272,10 @@ impl Token {
             Token::Integer(s) => return s.len(),
             Token::Float(s) => return s.len(),
             Token::String(s) => return s.len()+2,
-            Token::Boolean(_) => return 1,
+            Token::Boolean(b) => return b.to_string().len(),
             Token::Identifier(s) => return s.len(),
             Token::Delimiter(d) => return d.len(),
-            Token::Comment(s) => return s.len()+2,
+            Token::Comment(s) => return s.len(),
         }
     }
 }
\ No newline at end of file
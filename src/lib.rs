@@ -1,7 +1,25 @@
+#![allow(clippy::needless_return, clippy::module_inception, clippy::len_without_is_empty)]
+
+pub mod target;
+pub mod codegen;
 pub mod lexer;
 pub mod parser;
 pub mod llvm;
+pub mod mir;
+pub mod interp;
+pub mod vm;
+pub mod c;
+pub mod wasm;
+pub mod x86;
 
+pub use target::Target;
+pub use codegen::{CodegenOptions, CodegenError, OptLevel, lower_and_optimize};
 pub use lexer::prelude::*;
 pub use parser::prelude::*;
 pub use llvm::prelude::*;
+pub use mir::prelude::*;
+pub use interp::prelude::*;
+pub use vm::prelude::*;
+pub use c::prelude::*;
+pub use wasm::prelude::*;
+pub use x86::prelude::*;
@@ -0,0 +1,57 @@
+// Attributes of the generated LLVM module and of its functions.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Attributes {
+    // Attributes of every defined function, printed as the group #0
+    pub function : Vec<String>,
+    pub visibility : Visibility,
+    pub module_flags : Vec<ModuleFlag>,
+}
+
+impl Default for Attributes {
+    // Toy code never unwinds, runtime errors trap or exit
+    fn default() -> Self {
+        Attributes {
+            function : vec!["nounwind".to_string()],
+            visibility : Visibility::Default,
+            module_flags : vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Visibility {
+    #[default]
+    Default,
+    Hidden,
+    Protected,
+}
+
+impl std::fmt::Display for Visibility {
+    fn fmt(&self, f : &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Visibility::Default => write!(f, "default"),
+            Visibility::Hidden => write!(f, "hidden"),
+            Visibility::Protected => write!(f, "protected"),
+        }
+    }
+}
+
+// Entry of `!llvm.module.flags`, such as `!{i32 8, !"PIC Level", i32 2}`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModuleFlag {
+    // How the flag is merged when linking modules: 1 for error, 2 for
+    // warning, 7 for max, 8 for min...
+    pub behavior : u32,
+    pub name : String,
+    pub value : u32,
+}
+
+impl ModuleFlag {
+    pub fn new(behavior : u32, name : &str, value : u32) -> ModuleFlag {
+        ModuleFlag {
+            behavior,
+            name : name.to_string(),
+            value,
+        }
+    }
+}
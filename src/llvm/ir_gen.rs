@@ -1,313 +1,577 @@
-use std::hash::Hash;
 use std::io::Write;
-use std::collections::HashMap;
-use std::fs::File;
 
-use crate::parser::ast::*;
+use crate::parser::ast::TranslationUnit;
+use crate::mir::mir::*;
+use crate::mir::debug_info::Location;
+use crate::codegen::{CodegenOptions, CodegenError, lower_and_optimize, symbol_name};
+use super::attributes::{Attributes, Visibility};
+use super::metadata::{ModuleMetadata, escape_string};
+
+// Writes the file only once the whole IR is generated, and removes what
+// was written if writing fails.
+pub fn create_llvm_ir(tu : &TranslationUnit, file_name : &str) -> Result<(), CodegenError> {
+    create_llvm_ir_with_options(tu, file_name, &CodegenOptions::default())
+}
+
+pub fn create_llvm_ir_with_options(tu : &TranslationUnit, file_name : &str, options : &CodegenOptions) -> Result<(), CodegenError> {
+    let mut ir = vec![];
+    generate_llvm_ir(tu, options, &mut ir)?;
+    std::fs::write(file_name, ir).map_err(|error| {
+        let _ = std::fs::remove_file(file_name);
+        CodegenError::Io(error)
+    })
+}
 
-pub fn create_llvm_ir(tu : &TranslationUnit, file_name : &str) {
-    LLVMIRGenerator::new(tu, file_name).generate_ir();
+pub fn generate_llvm_ir<W : Write>(tu : &TranslationUnit, options : &CodegenOptions, output : &mut W) -> Result<(), CodegenError> {
+    let module = lower_and_optimize(tu, options)?;
+    LLVMIRGenerator::with_options(&module, options).write(output)
 }
 
 /****************************/
 /****************************/
 /****************************/
 
-type LLVMVariables = HashMap<String, Type>;
-type LLVMFunctions = HashMap<String, Option<Type>>;
+// `%name` or `@name`, quoted if the name has characters LLVM does not
+// accept unquoted, e.g. `%"é"`
+fn identifier(sigil : char, name : &str) -> String {
+    let bare = name.starts_with(|c : char| !c.is_ascii_digit()) &&
+        name.chars().all(|c| c.is_ascii_alphanumeric() || "-$._".contains(c));
+    match bare {
+        true => format!("{}{}", sigil, name),
+        false => format!("{}\"{}\"", sigil, escape_string(name)),
+    }
+}
 
-#[derive(Debug, Hash, PartialEq, Eq)]
-struct LLVMFunction {
-    name : String,
-    return_type : Option<Type>,
+// Prints a MIR module as LLVM textual IR, also through `to_string()`.
+pub struct LLVMIRGenerator<'a> {
+    module : &'a Module,
+    source_filename : Option<String>,
+    attributes : Attributes,
+    metadata : ModuleMetadata,
 }
 
-struct LLVMIRGenerator<'a> {
-    tu : &'a TranslationUnit,
-    global_variables : LLVMVariables,
-    functions : LLVMFunctions,
-    output_file : &'a str,
-    tmp_variable_count : usize
+impl std::fmt::Display for LLVMIRGenerator<'_> {
+    fn fmt(&self, f : &mut std::fmt::Formatter) -> std::fmt::Result {
+        let ir = self.generate_ir().map_err(|_| std::fmt::Error)?;
+        f.write_str(&String::from_utf8_lossy(&ir))
+    }
 }
 
 impl<'a> LLVMIRGenerator<'a> {
-    fn new(tu : &'a TranslationUnit, output_file : &'a str) -> LLVMIRGenerator<'a> {
+    pub fn new(module : &'a Module) -> LLVMIRGenerator<'a> {
+        LLVMIRGenerator::with_options(module, &CodegenOptions::default())
+    }
+
+    pub fn with_options(module : &'a Module, options : &CodegenOptions) -> LLVMIRGenerator<'a> {
         LLVMIRGenerator {
-            tu,
-            global_variables : LLVMVariables::new(),
-            functions : LLVMFunctions::new(),
-            output_file,
-            tmp_variable_count : 0
+            module,
+            source_filename : options.source_filename.clone(),
+            attributes : options.attributes.clone(),
+            metadata : ModuleMetadata::new(module, &options.attributes.module_flags),
         }
     }
 
-    fn generate_ir(&mut self) {
-        let mut file = std::fs::File::create(self.output_file).unwrap();
-
-        self.generate_global_variables(&mut file).unwrap_or_else(|e| {
-            self.delete_file();
-            panic!("Error while generating global variables: {}", e);
-        });
+    // The IR is written with a single call, nothing is written if its
+    // generation fails.
+    pub fn write<W : Write>(&self, output : &mut W) -> Result<(), CodegenError> {
+        let ir = self.generate_ir()?;
+        output.write_all(&ir)?;
+        output.flush()?;
+        Ok(())
+    }
 
-        self.generate_functions(&mut file).unwrap_or_else(|e| {
-            self.delete_file();
-            panic!("Error while generating global variables: {}", e);
-        });
+    fn generate_ir(&self) -> std::io::Result<Vec<u8>> {
+        let mut output = vec![];
+        self.generate_header(&mut output)?;
+        self.generate_custom_types(&mut output)?;
+        self.generate_global_variables(&mut output)?;
+        self.generate_functions(&mut output)?;
+        self.generate_main(&mut output)?;
+        self.generate_declarations(&mut output)?;
+        self.generate_attributes(&mut output)?;
+        self.generate_metadata(&mut output)?;
+        Ok(output)
     }
 
-    fn delete_file(&self) {
-        std::fs::remove_file(self.output_file).unwrap_or_else(|e| {
-            panic!("Error while deleting file {}: {}", self.output_file, e);
-        });
+    fn return_type_to_llvm_ir(type_ : &Option<Type>) -> String {
+        match type_ {
+            None => "void".to_string(),
+            Some(type_) => LLVMIRGenerator::type_to_llvm_ir(type_),
+        }
     }
 
-    fn type_to_llvm_ir(type_ : &Type) -> Result<String, String> {
+    fn type_to_llvm_ir(type_ : &Type) -> String {
         match type_ {
-            Type::BuiltIn(built_in_type) => {
-                match built_in_type {
-                    BuiltInType::I8 => Ok("i8".to_string()),
-                    BuiltInType::I16 => Ok("i16".to_string()),
-                    BuiltInType::I32 => Ok("i32".to_string()),
-                    BuiltInType::I64 => Ok("i64".to_string()),
-                    BuiltInType::U8 => Ok("i8".to_string()),
-                    BuiltInType::U16 => Ok("i16".to_string()),
-                    BuiltInType::U32 => Ok("i32".to_string()),
-                    BuiltInType::U64 => Ok("i64".to_string()),
-                    BuiltInType::F32 => Ok("float".to_string()),
-                    BuiltInType::F64 => Ok("double".to_string()),
-                    BuiltInType::Bool => Ok("i8".to_string()),
-                    _ => Err("Unsupported built-in type!".to_string())
+            Type::I1 => "i1".to_string(),
+            Type::I8 => "i8".to_string(),
+            Type::I16 => "i16".to_string(),
+            Type::I32 => "i32".to_string(),
+            Type::I64 => "i64".to_string(),
+            Type::F32 => "float".to_string(),
+            Type::F64 => "double".to_string(),
+            Type::Ptr => "ptr".to_string(),
+            Type::String => "{ ptr, i64 }".to_string(),
+            Type::Struct(id) => identifier('%', id),
+            Type::Array(element_type, len) => format!("[{} x {}]", len, LLVMIRGenerator::type_to_llvm_ir(element_type)),
+        }
+    }
+
+    // LLVM only accepts decimal floating point constants in `1.5` form that
+    // are exactly representable in the target type, so values that would
+    // need an exponent are written in hex. Constants of type `float` are
+    // already rounded by the lowering.
+    fn float_constant(value : f64) -> String {
+        let decimal = format!("{:?}", value);
+        if decimal.contains('.') && !decimal.contains('e') {
+            return decimal;
+        }
+        format!("0x{:016X}", value.to_bits())
+    }
+
+    // Functions of the runtime and intrinsics, in order of first use
+    fn declarations(&self) -> Vec<String> {
+        let mut declarations = vec![];
+        let mut declare = |declaration : String| {
+            if !declarations.contains(&declaration) {
+                declarations.push(declaration);
+            }
+        };
+
+        for block in self.module.functions.iter().flat_map(|function| &function.blocks) {
+            for instruction in &block.instructions {
+                if let InstructionKind::Call{callee : Callee::Extern(callee), return_type, arguments} = &instruction.kind {
+                    let parameters : Vec<String> = arguments
+                        .iter()
+                        .map(|(type_, _)| LLVMIRGenerator::type_to_llvm_ir(type_))
+                        .collect();
+                    declare(format!("declare {} {}({})", LLVMIRGenerator::return_type_to_llvm_ir(return_type), identifier('@', callee), parameters.join(", ")));
+                }
+            }
+            if let Some(Terminator::Trap) = block.terminator {
+                declare("declare void @llvm.trap()".to_string());
+            }
+        }
+
+        for function in &self.module.functions {
+            for variable in function.debug_info.iter().flat_map(|debug_info| &debug_info.variables) {
+                if self.metadata.variable(function, variable.register).is_none() {
+                    continue;
+                }
+                match function.parameters.contains(&variable.register) {
+                    true => declare("declare void @llvm.dbg.value(metadata, metadata, metadata)".to_string()),
+                    false => declare("declare void @llvm.dbg.declare(metadata, metadata, metadata)".to_string()),
+                }
+            }
+        }
+
+        declarations
+    }
+
+    fn constant_to_llvm_ir(&self, constant : &Constant, type_ : &Type) -> String {
+        match constant {
+            Constant::Int(value) => value.to_string(),
+            Constant::Float(value) => LLVMIRGenerator::float_constant(*value),
+            Constant::Null => "null".to_string(),
+            Constant::Zero => "zeroinitializer".to_string(),
+            Constant::Undef => "undef".to_string(),
+            Constant::String(index) => format!("{{ ptr @.str.{}, i64 {} }}", index, self.module.strings[*index].len()),
+            Constant::Global(id) => format!("@{}", symbol_name(id)),
+            Constant::Aggregate(elements) if elements.is_empty() => "zeroinitializer".to_string(),
+            Constant::Aggregate(elements) => {
+                let elements : Vec<String> = elements
+                    .iter()
+                    .enumerate()
+                    .map(|(i, element)| {
+                        let element_type = self.module.field_type(type_, i).unwrap();
+                        format!(
+                            "{} {}",
+                            LLVMIRGenerator::type_to_llvm_ir(&element_type),
+                            self.constant_to_llvm_ir(element, &element_type)
+                        )
+                    })
+                    .collect();
+
+                match type_ {
+                    Type::Array(..) => format!("[{}]", elements.join(", ")),
+                    _ => format!("{{ {} }}", elements.join(", ")),
                 }
-            },
-            Type::Custom(_) => {
-                panic!("Custom types not supported yet")
             },
         }
     }
 
-    fn operator_to_llvm_ir(op : &BinaryOperator) -> Result<&'static str, String> {
-        match op {
-            BinaryOperator::Plus => Ok("add"),
-            BinaryOperator::Minus => Ok("sub"),
-            BinaryOperator::Times => Ok("mul"),
-            BinaryOperator::Div => Ok("div"),
-            BinaryOperator::Mod => Ok("rem"),
-            BinaryOperator::And => Ok("and"),
-            BinaryOperator::Or => Ok("or"),
-            BinaryOperator::Xor => Ok("xor"),
-            _ => Err("Unsupported binary operator".to_string())
+    fn value_to_llvm_ir(&self, function : &Function, value : &Value, type_ : &Type) -> String {
+        match value {
+            Value::Register(register) => identifier('%', &function.register(*register).name),
+            Value::Constant(constant) => self.constant_to_llvm_ir(constant, type_),
         }
     }
 
-    fn generate_global_variables(&mut self, file : &mut File) -> Result<(), String> {
-        for var in &self.tu.global_variables {
-            if self.global_variables.contains_key(&var.identifier) {
-                return Err(format!("Global variable {} already defined", var.identifier));
-            }
+    // `type value`, e.g. `i32 %t0`
+    fn typed_value(&self, function : &Function, value : &Value, type_ : &Type) -> String {
+        format!("{} {}", LLVMIRGenerator::type_to_llvm_ir(type_), self.value_to_llvm_ir(function, value, type_))
+    }
 
-            let type_llvm = LLVMIRGenerator::type_to_llvm_ir(&var.type_)?;
-            let init_value = match &var.init_value {
-                None => None,
-                Some(OperationResult::Literal(lit)) => match lit {
-                    Literal::Integer(i) => Some(i.clone()),
-                    Literal::Float(f) => Some(f.clone()),
-                    Literal::Boolean(b) => Some(if *b { "1" } else { "0" }.to_string()),
-                    Literal::String(_) => panic!("Unsupported literal type: String")
-                },
-                _ => return Err("Unsupported operation result type".to_string())
-            };
+    fn generate_header(&self, output : &mut Vec<u8>) -> std::io::Result<()> {
+        if let Some(source_filename) = &self.source_filename {
+            writeln!(output, "source_filename = \"{}\"", escape_string(source_filename))?;
+        }
+        writeln!(output, "target datalayout = \"{}\"", self.module.target.datalayout)?;
+        writeln!(output, "target triple = \"{}\"", self.module.target.triple)?;
+        writeln!(output)
+    }
 
-            file.write_all(format!(
-                "@{} = global {} {}\n",
-                var.identifier,
-                type_llvm,
-                init_value.unwrap_or("0".to_string())
-            ).as_bytes()).map_err(|e| e.to_string())?;
+    fn generate_custom_types(&self, output : &mut Vec<u8>) -> std::io::Result<()> {
+        for struct_type in &self.module.structs {
+            let fields : Vec<String> = struct_type.fields
+                .iter()
+                .map(LLVMIRGenerator::type_to_llvm_ir)
+                .collect();
+
+            writeln!(
+                output,
+                "{} = type {{ {} }}",
+                identifier('%', &struct_type.name),
+                fields.join(", ")
+            )?;
+        }
 
-            self.global_variables.insert(var.identifier.clone(), var.type_.clone());
+        if !self.module.structs.is_empty() {
+            writeln!(output)?;
         }
 
         Ok(())
     }
 
-    fn generate_functions(&mut self, file : &mut File) -> Result<(), String> {
-        for fun in &self.tu.functions {
-            let mut local_variables : LLVMVariables = LLVMVariables::new();
-            self.tmp_variable_count = 0;
-    
-            if self.functions.contains_key(&fun.identifier) {
-                return Err(format!("Function {} already defined", fun.identifier));
-            }
-    
-            let return_type_llvm = match &fun.return_type {
-                None => "void".to_string(),
-                Some(type_) => LLVMIRGenerator::type_to_llvm_ir(type_)?
+    fn generate_global_variables(&self, output : &mut Vec<u8>) -> std::io::Result<()> {
+        for global in &self.module.globals {
+            let dbg = self.metadata.globals
+                .get(&global.name)
+                .map_or(String::new(), |expression| format!(", !dbg !{}", expression));
+            writeln!(
+                output,
+                "@{} = global {} {}{}",
+                symbol_name(&global.name),
+                LLVMIRGenerator::type_to_llvm_ir(&global.type_),
+                self.constant_to_llvm_ir(&global.init_value, &global.type_),
+                dbg
+            )?;
+        }
+
+        if !self.module.globals.is_empty() {
+            writeln!(output)?;
+        }
+
+        Ok(())
+    }
+
+    // String constants and external functions used by the generated code.
+    fn generate_declarations(&self, output : &mut Vec<u8>) -> std::io::Result<()> {
+        for (i, text) in self.module.strings.iter().enumerate() {
+            writeln!(
+                output,
+                "@.str.{} = private unnamed_addr constant [{} x i8] c\"{}\"",
+                i,
+                text.len(),
+                escape_string(text)
+            )?;
+        }
+
+        if !self.module.strings.is_empty() {
+            writeln!(output)?;
+        }
+
+        let declarations = self.declarations();
+        for declaration in &declarations {
+            writeln!(output, "{}", declaration)?;
+        }
+
+        if !declarations.is_empty() {
+            writeln!(output)?;
+        }
+
+        Ok(())
+    }
+
+    fn generate_attributes(&self, output : &mut Vec<u8>) -> std::io::Result<()> {
+        if !self.attributes.function.is_empty() && !self.module.functions.is_empty() {
+            writeln!(output, "attributes #0 = {{ {} }}\n", self.attributes.function.join(" "))?;
+        }
+
+        Ok(())
+    }
+
+    fn generate_metadata(&self, output : &mut Vec<u8>) -> std::io::Result<()> {
+        if let Some(compile_unit) = self.metadata.compile_unit {
+            writeln!(output, "!llvm.dbg.cu = !{{!{}}}", compile_unit)?;
+        }
+
+        if !self.metadata.module_flags.is_empty() {
+            let references : Vec<String> = self.metadata.module_flags.iter().map(|i| format!("!{}", i)).collect();
+            writeln!(output, "!llvm.module.flags = !{{{}}}", references.join(", "))?;
+        }
+
+        for (i, node) in self.metadata.metadata.nodes().iter().enumerate() {
+            writeln!(output, "!{} = {}", i, node)?;
+        }
+
+        Ok(())
+    }
+
+    // `, !dbg !N` for instructions with a location in functions with debug
+    // info
+    fn debug_location(&self, function : &Function, location : Option<Location>) -> String {
+        self.metadata
+            .location(function, location)
+            .map_or(String::new(), |location| format!(", !dbg !{}", location))
+    }
+
+    fn generate_functions(&self, output : &mut Vec<u8>) -> std::io::Result<()> {
+        let module = self.module;
+        for function in &module.functions {
+            let return_type_llvm = LLVMIRGenerator::return_type_to_llvm_ir(&function.return_type);
+
+            let params : Vec<String> = function.parameters
+                .iter()
+                .map(|param| {
+                    let param = function.register(*param);
+                    format!("{} {}", LLVMIRGenerator::type_to_llvm_ir(&param.type_), identifier('%', &param.name))
+                })
+                .collect();
+
+            let visibility = match self.attributes.visibility {
+                Visibility::Default => String::new(),
+                visibility => format!("{} ", visibility),
             };
-    
-            file.write_all(format!(
-                "define {} @{}(",
+            let attributes = if self.attributes.function.is_empty() {""} else {" #0"};
+            let subprogram = self.metadata
+                .subprogram(function)
+                .map_or(String::new(), |subprogram| format!(" !dbg !{}", subprogram));
+
+            writeln!(
+                output,
+                "define {}{} @{}({}){}{} {{",
+                visibility,
                 return_type_llvm,
-                fun.identifier
-            ).as_bytes()).map_err(|e| e.to_string())?;
-    
-            let mut first = true;
-            for param in &fun.arguments {
-                if !first {
-                    file.write_all(", ".as_bytes()).map_err(|e| e.to_string())?;
+                symbol_name(&function.name),
+                params.join(", "),
+                attributes,
+                subprogram
+            )?;
+
+            // The entry block only needs a label if a phi refers to it
+            let entry_referenced = function.blocks
+                .iter()
+                .flat_map(|block| &block.instructions)
+                .any(|instruction| match &instruction.kind {
+                    InstructionKind::Phi{incoming, ..} => incoming.iter().any(|(_, block)| block.0 == 0),
+                    _ => false,
+                });
+
+            for (i, block) in function.blocks.iter().enumerate() {
+                if i > 0 || entry_referenced {
+                    writeln!(output, "{}:", &identifier('%', &block.name)[1..])?;
                 }
-                first = false;
-    
-                let type_llvm = LLVMIRGenerator::type_to_llvm_ir(&param.type_)?;
-                file.write_all(format!(
-                    "{} %{}",
-                    type_llvm,
-                    param.identifier
-                ).as_bytes()).map_err(|e| e.to_string())?;
-            }
-    
-            file.write_all(") {\n".as_bytes()).map_err(|e| e.to_string())?;
-    
-            for stm in &fun.body.statements {
-                match stm {
-                    Statement::Declaration(decl) => {
-                        let type_llvm = LLVMIRGenerator::type_to_llvm_ir(&decl.type_)?;
-    
-                        file.write_all(format!(
-                            "    %{} = alloca {}\n",
-                            decl.identifier,
-                            type_llvm
-                        ).as_bytes()).map_err(|e| e.to_string())?;
-                        
-                        local_variables.insert(decl.identifier.clone(), decl.type_.clone());
-                        
-                        if let None = decl.init_value {
-                            continue;
-                        };
-
-                        let assign_var = format!("%{}", decl.identifier);
-
-                        self.write_operation(Some(&assign_var), &decl.init_value.as_ref().unwrap(), &local_variables, file)?;
-                    },
-
-                    Statement::Operation(op) => {
-                        match op {
-                            OperationResult::BinOpResult(bin_op) => {
-                                match bin_op.operator {
-                                    BinaryOperator::Assign => {
-                                        let assign_var = match &*bin_op.left {
-                                            OperationResult::Identifier(id) => {
-                                                id.to_string()
-                                            },
-                                            _ => return Err("Left hand in assignment must be an identifier".to_string())
-                                        };
-
-                                        let assign_var = if local_variables.contains_key(&assign_var) {
-                                            format!("%{}", assign_var)
-                                        }
-                                        else if self.global_variables.contains_key(&assign_var) {
-                                            format!("@{}", assign_var)
-                                        }
-                                        else {
-                                            return Err(format!("Variable {} not defined", assign_var));
-                                        };
-
-                                        self.write_operation(Some(&assign_var), &bin_op.right, &local_variables, file)?;
-                                    },
-                                    _ => {
-                                        return Err("Unsupported binary operator".to_string());
-                                    }
-                                }
-                            },
-                            _ => return Err("Unsupported operation result type".to_string())
-                        }
-                    },
-                    Statement::Return(_ret) => {
-                        return Err("Return not supported yet".to_string());
-                    },
-                    _ => return Err("Unsupported statement type".to_string())
+
+                // Parameters are described before any instruction
+                if i == 0 {
+                    for parameter in &function.parameters {
+                        self.generate_debug_variable(function, *parameter, output)?;
+                    }
                 }
+
+                for instruction in &block.instructions {
+                    self.generate_instruction(function, instruction, output)?;
+                }
+                self.generate_terminator(function, block.terminator.as_ref().unwrap(), block.terminator_location, output)?;
             }
 
-            file.write("}\n\n".as_bytes()).map_err(|e| e.to_string())?;
-    
-            self.functions.insert(fun.identifier.clone(), fun.return_type.clone());
+            writeln!(output, "}}\n")?;
         }
-    
+
         Ok(())
     }
 
-    fn write_operation(
-        &mut self,
-        var : Option<&str>,
-        op : &OperationResult,
-        local_variables : &LLVMVariables,
-        file : &mut File
-    ) -> Result<Option<usize>, String> {
-        let (variable_llvm, ret) = if let Some(var) = var {
-            (var.to_string(), None)
-        }
-        else {
-            self.tmp_variable_count += 1;
-            (format!("%{}", self.tmp_variable_count-1), Some(self.tmp_variable_count-1))
+    // The entry point the C runtime calls returns the result of the `main`
+    // of the program as exit code, or 0 if it returns nothing else
+    fn generate_main(&self, output : &mut Vec<u8>) -> std::io::Result<()> {
+        let main = match self.module.function("main") {
+            Some(main) if main.parameters.is_empty() => main,
+            _ => return Ok(()),
         };
 
-        match op {
-            OperationResult::BinOpResult(bin_op) => {
-                let left = self.write_operation(None, &bin_op.left, local_variables, file)?.unwrap();
-                let right = self.write_operation(None, &bin_op.right, local_variables, file)?.unwrap();
-
-                let operator = LLVMIRGenerator::operator_to_llvm_ir(&bin_op.operator)?;
-
-                let type_llvm = "i32".to_string(); // TODO: Get type from left and right
-
-                file.write_all(format!(
-                    "    {} = {} {} %{}, %{}\n",
-                    variable_llvm,
-                    operator,
-                    type_llvm,
-                    left,
-                    right
-                ).as_bytes()).map_err(|e| e.to_string())?;
-
-                self.tmp_variable_count += 1;
+        let callee = identifier('@', &symbol_name("main"));
+        let return_type_llvm = LLVMIRGenerator::return_type_to_llvm_ir(&main.return_type);
+        writeln!(output, "define i32 @main() {{")?;
+        match &main.return_type {
+            Some(type_) if type_.is_int() => {
+                writeln!(output, "    %result = call {} {}()", return_type_llvm, callee)?;
+                match type_ {
+                    Type::I32 => writeln!(output, "    ret i32 %result")?,
+                    Type::I64 => writeln!(output, "    %code = trunc i64 %result to i32\n    ret i32 %code")?,
+                    _ => writeln!(output, "    %code = zext {} %result to i32\n    ret i32 %code", return_type_llvm)?,
+                }
             },
-            OperationResult::Literal(lit) => {
-                let (lit_val, type_llvm) = match lit {
-                    Literal::Integer(v) => (v.clone(),"i32"),
-                    Literal::Float(v) => (v.clone(),"double"),
-                    Literal::Boolean(v) => (if *v {"1".to_string()} else {"0".to_string()} ,"i8"),
-                    Literal::String(_) => return Err("Unsupported literal type: String".to_string())
-                };
+            _ => writeln!(output, "    call {} {}()\n    ret i32 0", return_type_llvm, callee)?,
+        }
+        writeln!(output, "}}\n")
+    }
 
-                file.write_all(format!(
-                    "    {} = {} {}\n",
-                    variable_llvm,
-                    type_llvm,
-                    lit_val
-                ).as_bytes()).map_err(|e| e.to_string())?;
+    fn generate_instruction(&self, function : &Function, instruction : &Instruction, output : &mut Vec<u8>) -> std::io::Result<()> {
+        let result = instruction.result.map(|result| format!("{} = ", identifier('%', &function.register(result).name)));
+        let result = result.unwrap_or_default();
+
+        let text = match &instruction.kind {
+            InstructionKind::Alloca(type_) => format!("alloca {}", LLVMIRGenerator::type_to_llvm_ir(type_)),
+            InstructionKind::Load{type_, pointer} => format!(
+                "load {}, {}",
+                LLVMIRGenerator::type_to_llvm_ir(type_),
+                self.typed_value(function, pointer, &Type::Ptr)
+            ),
+            InstructionKind::Store{type_, value, pointer} => format!(
+                "store {}, {}",
+                self.typed_value(function, value, type_),
+                self.typed_value(function, pointer, &Type::Ptr)
+            ),
+            InstructionKind::Binary{op, type_, left, right} => format!(
+                "{} {}, {}",
+                op.to_string(),
+                self.typed_value(function, left, type_),
+                self.value_to_llvm_ir(function, right, type_)
+            ),
+            InstructionKind::Negate{type_, value} => format!("fneg {}", self.typed_value(function, value, type_)),
+            InstructionKind::Compare{comparison, type_, left, right} => format!(
+                "{} {} {}, {}",
+                if comparison.is_float() {"fcmp"} else {"icmp"},
+                comparison.to_string(),
+                self.typed_value(function, left, type_),
+                self.value_to_llvm_ir(function, right, type_)
+            ),
+            InstructionKind::Cast{op, from, value, to} => format!(
+                "{} {} to {}",
+                op.to_string(),
+                self.typed_value(function, value, from),
+                LLVMIRGenerator::type_to_llvm_ir(to)
+            ),
+            InstructionKind::FieldAddress{type_, pointer, index} => format!(
+                "getelementptr {}, {}, i32 0, i32 {}",
+                LLVMIRGenerator::type_to_llvm_ir(type_),
+                self.typed_value(function, pointer, &Type::Ptr),
+                index
+            ),
+            InstructionKind::ElementAddress{type_, pointer, index} => format!(
+                "getelementptr {}, {}, i64 0, {}",
+                LLVMIRGenerator::type_to_llvm_ir(type_),
+                self.typed_value(function, pointer, &Type::Ptr),
+                self.typed_value(function, index, &Type::I64)
+            ),
+            InstructionKind::Offset{type_, pointer, index} => format!(
+                "getelementptr {}, {}, {}",
+                LLVMIRGenerator::type_to_llvm_ir(type_),
+                self.typed_value(function, pointer, &Type::Ptr),
+                self.typed_value(function, index, &Type::I64)
+            ),
+            InstructionKind::ExtractValue{type_, aggregate, index} => format!(
+                "extractvalue {}, {}",
+                self.typed_value(function, aggregate, type_),
+                index
+            ),
+            InstructionKind::InsertValue{type_, aggregate, value, index} => {
+                let field_type = self.module.field_type(type_, *index).unwrap();
+                format!(
+                    "insertvalue {}, {}, {}",
+                    self.typed_value(function, aggregate, type_),
+                    self.typed_value(function, value, &field_type),
+                    index
+                )
             },
-            OperationResult::Identifier(id) => {
-                let id = if local_variables.contains_key(id) {
-                    format!("%{}", id)
-                }
-                else if self.global_variables.contains_key(id) {
-                    format!("@{}", id)
-                }
-                else {
-                    return Err(format!("Variable {} not defined", id));
+            InstructionKind::Call{callee, return_type, arguments} => {
+                let return_type = LLVMIRGenerator::return_type_to_llvm_ir(return_type);
+
+                let arguments : Vec<String> = arguments
+                    .iter()
+                    .map(|(type_, argument)| self.typed_value(function, argument, type_))
+                    .collect();
+                let callee = match callee {
+                    Callee::Function(name) => format!("@{}", symbol_name(name)),
+                    Callee::Extern(name) => identifier('@', name),
                 };
-
-                file.write_all(format!(
-                    "    {} = load {}\n",
-                    variable_llvm,
-                    id
-                ).as_bytes()).map_err(|e| e.to_string())?;
+                format!("call {} {}({})", return_type, callee, arguments.join(", "))
             },
-            _ => return Err("Unsupported operation result type".to_string())
+            InstructionKind::Phi{type_, incoming} => {
+                let incoming : Vec<String> = incoming
+                    .iter()
+                    .map(|(value, block)| format!(
+                        "[ {}, {} ]",
+                        self.value_to_llvm_ir(function, value, type_),
+                        identifier('%', &function.block(*block).name)
+                    ))
+                    .collect();
+                format!("phi {} {}", LLVMIRGenerator::type_to_llvm_ir(type_), incoming.join(", "))
+            },
+        };
 
+        writeln!(output, "    {}{}{}", result, text, self.debug_location(function, instruction.location))?;
+
+        match instruction.result {
+            Some(result) => self.generate_debug_variable(function, result, output),
+            None => Ok(()),
         }
-        Ok(ret)
     }
 
-    
+    // Allocas of variables are declared and parameters get a value
+    fn generate_debug_variable(&self, function : &Function, register : Register, output : &mut Vec<u8>) -> std::io::Result<()> {
+        let (variable, location) = match self.metadata.variable(function, register) {
+            Some(variable) => variable,
+            None => return Ok(()),
+        };
+
+        let intrinsic = if function.parameters.contains(&register) {"value"} else {"declare"};
+        writeln!(
+            output,
+            "    call void @llvm.dbg.{}(metadata {} {}, metadata !{}, metadata !DIExpression()), !dbg !{}",
+            intrinsic,
+            LLVMIRGenerator::type_to_llvm_ir(&function.register(register).type_),
+            identifier('%', &function.register(register).name),
+            variable,
+            location
+        )
+    }
+
+    fn generate_terminator(&self, function : &Function, terminator : &Terminator, location : Option<Location>, output : &mut Vec<u8>) -> std::io::Result<()> {
+        let dbg = self.debug_location(function, location);
+        let label = |block : BlockId| identifier('%', &function.block(block).name);
+        match terminator {
+            Terminator::Return(None) => writeln!(output, "    ret void{}", dbg),
+            Terminator::Return(Some((type_, value))) => writeln!(output, "    ret {}{}", self.typed_value(function, value, type_), dbg),
+            Terminator::Branch(target) => writeln!(output, "    br label {}{}", label(*target), dbg),
+            Terminator::CondBranch{condition, then_block, else_block} => writeln!(
+                output,
+                "    br {}, label {}, label {}{}",
+                self.typed_value(function, condition, &Type::I1),
+                label(*then_block),
+                label(*else_block),
+                dbg
+            ),
+            Terminator::Switch{type_, value, default, cases} => {
+                let type_llvm = LLVMIRGenerator::type_to_llvm_ir(type_);
+                let cases : Vec<String> = cases
+                    .iter()
+                    .map(|(case, target)| format!("{} {}, label {}", type_llvm, case, label(*target)))
+                    .collect();
+                writeln!(
+                    output,
+                    "    switch {}, label {} [ {} ]{}",
+                    self.typed_value(function, value, type_),
+                    label(*default),
+                    cases.join(" "),
+                    dbg
+                )
+            },
+            Terminator::Trap => {
+                writeln!(output, "    call void @llvm.trap(){}", dbg)?;
+                writeln!(output, "    unreachable{}", dbg)
+            },
+            Terminator::Unreachable => writeln!(output, "    unreachable{}", dbg),
+        }
+    }
 }
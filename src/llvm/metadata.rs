@@ -0,0 +1,369 @@
+use std::collections::HashMap;
+
+use crate::mir::mir::*;
+use crate::mir::debug_info::*;
+use crate::mir::layout::{size_of, align_of, field_offset};
+use crate::codegen::symbol_name;
+use super::attributes::ModuleFlag;
+
+// Numbered metadata nodes of a module, `!0`, `!1`... Identical nodes get
+// the same number.
+#[derive(Debug, Default)]
+pub struct Metadata {
+    nodes : Vec<String>,
+    numbers : HashMap<String, usize>,
+}
+
+impl Metadata {
+    pub fn add(&mut self, node : String) -> usize {
+        if let Some(number) = self.numbers.get(&node) {
+            return *number;
+        }
+
+        self.nodes.push(node.clone());
+        self.numbers.insert(node, self.nodes.len()-1);
+        self.nodes.len()-1
+    }
+
+    // Number of a node that is referred to before it is complete, e.g. by
+    // its own members. It is given with `set`.
+    pub fn reserve(&mut self) -> usize {
+        self.nodes.push(String::new());
+        self.nodes.len()-1
+    }
+
+    pub fn set(&mut self, number : usize, node : String) {
+        self.nodes[number] = node;
+    }
+
+    pub fn tuple(&mut self, numbers : &[usize]) -> usize {
+        let elements : Vec<String> = numbers.iter().map(|number| format!("!{}", number)).collect();
+        self.add(format!("!{{{}}}", elements.join(", ")))
+    }
+
+    pub fn nodes(&self) -> &[String] {
+        &self.nodes
+    }
+}
+
+/****************************/
+/****************************/
+/****************************/
+
+// Metadata of a module: its flags and, if the module has debug info, the
+// DWARF description of its source.
+#[derive(Debug, Default)]
+pub struct ModuleMetadata {
+    pub metadata : Metadata,
+    pub module_flags : Vec<usize>,
+    pub compile_unit : Option<usize>,
+    // By function name
+    pub subprograms : HashMap<String, usize>,
+    // By global name, the `DIGlobalVariableExpression`
+    pub globals : HashMap<String, usize>,
+    // By subprogram
+    pub locations : HashMap<(usize, Location), usize>,
+    // By subprogram and register, the variable and the location of its
+    // declaration
+    pub variables : HashMap<(usize, Register), (usize, usize)>,
+}
+
+impl ModuleMetadata {
+    pub fn new(module : &Module, module_flags : &[ModuleFlag]) -> ModuleMetadata {
+        let mut metadata = ModuleMetadata::default();
+
+        let mut flags = module_flags.to_vec();
+        if module.debug_info.is_some() {
+            flags.push(ModuleFlag::new(7, "Dwarf Version", 4));
+            flags.push(ModuleFlag::new(2, "Debug Info Version", 3));
+        }
+        for flag in &flags {
+            let node = format!("!{{i32 {}, !\"{}\", i32 {}}}", flag.behavior, escape_string(&flag.name), flag.value);
+            let number = metadata.metadata.add(node);
+            metadata.module_flags.push(number);
+        }
+
+        if let Some(debug_info) = &module.debug_info {
+            DebugInfoBuilder::new(module, debug_info, &mut metadata).build();
+        }
+
+        metadata
+    }
+
+    pub fn subprogram(&self, function : &Function) -> Option<usize> {
+        self.subprograms.get(&function.name).copied()
+    }
+
+    pub fn location(&self, function : &Function, location : Option<Location>) -> Option<usize> {
+        let subprogram = self.subprogram(function)?;
+        self.locations.get(&(subprogram, location?)).copied()
+    }
+
+    pub fn variable(&self, function : &Function, register : Register) -> Option<(usize, usize)> {
+        let subprogram = self.subprogram(function)?;
+        self.variables.get(&(subprogram, register)).copied()
+    }
+}
+
+// Bytes other than printable ASCII, `"` and `\` are written as `\XX`.
+pub fn escape_string(text : &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b' '..=b'~' if byte != b'"' && byte != b'\\' => (byte as char).to_string(),
+            _ => format!("\\{:02X}", byte),
+        })
+        .collect()
+}
+
+/****************************/
+/****************************/
+/****************************/
+
+struct DebugInfoBuilder<'a> {
+    module : &'a Module,
+    debug_info : &'a DebugInfo,
+    metadata : &'a mut ModuleMetadata,
+    file : usize,
+    // Classes and `string`, which may be referred to before they are
+    // complete
+    structs : HashMap<String, usize>,
+}
+
+impl<'a> DebugInfoBuilder<'a> {
+    fn new(module : &'a Module, debug_info : &'a DebugInfo, metadata : &'a mut ModuleMetadata) -> DebugInfoBuilder<'a> {
+        let file = metadata.metadata.add(format!(
+            "!DIFile(filename: \"{}\", directory: \"{}\")",
+            escape_string(&debug_info.file),
+            escape_string(&debug_info.directory)
+        ));
+
+        DebugInfoBuilder {
+            module,
+            debug_info,
+            metadata,
+            file,
+            structs : HashMap::new(),
+        }
+    }
+
+    fn add(&mut self, node : String) -> usize {
+        self.metadata.metadata.add(node)
+    }
+
+    // The language is reported as C, which debuggers know how to display
+    fn build(mut self) {
+        let compile_unit = self.metadata.metadata.reserve();
+        self.metadata.compile_unit = Some(compile_unit);
+
+        let mut globals = vec![];
+        for global in &self.debug_info.globals {
+            let type_ = self.type_(&global.type_);
+            let variable = self.add(format!(
+                "distinct !DIGlobalVariable(name: \"{}\", linkageName: \"{}\", scope: !{}, file: !{}, line: {}, type: !{}, isLocal: false, isDefinition: true)",
+                escape_string(&global.name),
+                symbol_name(&global.name),
+                compile_unit,
+                self.file,
+                global.location.line,
+                type_
+            ));
+            let expression = self.add(format!("!DIGlobalVariableExpression(var: !{}, expr: !DIExpression())", variable));
+            self.metadata.globals.insert(global.name.clone(), expression);
+            globals.push(expression);
+        }
+
+        let globals = match globals.is_empty() {
+            true => String::new(),
+            false => format!(", globals: !{}", self.metadata.metadata.tuple(&globals)),
+        };
+        self.metadata.metadata.set(compile_unit, format!(
+            "distinct !DICompileUnit(language: DW_LANG_C99, file: !{}, producer: \"toy_lang\", isOptimized: {}, runtimeVersion: 0, emissionKind: FullDebug{})",
+            self.file,
+            self.debug_info.optimized,
+            globals
+        ));
+
+        for function in &self.module.functions {
+            if let Some(function_debug_info) = &function.debug_info {
+                self.build_function(function, function_debug_info, compile_unit);
+            }
+        }
+    }
+
+    fn build_function(&mut self, function : &Function, debug_info : &FunctionDebugInfo, compile_unit : usize) {
+        let mut types = vec![match &debug_info.return_type {
+            Some(type_) => format!("!{}", self.type_(type_)),
+            None => "null".to_string(),
+        }];
+        let mut parameters : Vec<&DebugVariable> = debug_info.variables
+            .iter()
+            .filter(|variable| variable.argument.is_some())
+            .collect();
+        parameters.sort_by_key(|variable| variable.argument);
+        for parameter in parameters {
+            types.push(format!("!{}", self.type_(&parameter.type_)));
+        }
+        let types = self.add(format!("!{{{}}}", types.join(", ")));
+        let subroutine_type = self.add(format!("!DISubroutineType(types: !{})", types));
+
+        let flags = match self.debug_info.optimized {
+            true => "DISPFlagDefinition | DISPFlagOptimized",
+            false => "DISPFlagDefinition",
+        };
+        let subprogram = self.add(format!(
+            "distinct !DISubprogram(name: \"{}\", linkageName: \"{}\", scope: !{}, file: !{}, line: {}, type: !{}, scopeLine: {}, flags: DIFlagPrototyped, spFlags: {}, unit: !{})",
+            escape_string(&function.name),
+            symbol_name(&function.name),
+            self.file,
+            self.file,
+            debug_info.location.line,
+            subroutine_type,
+            debug_info.location.line,
+            flags,
+            compile_unit
+        ));
+        self.metadata.subprograms.insert(function.name.clone(), subprogram);
+
+        let locations = function.blocks
+            .iter()
+            .flat_map(|block| block.instructions.iter().map(|instruction| instruction.location).chain([block.terminator_location]))
+            .flatten();
+        for location in locations {
+            self.location(subprogram, location);
+        }
+
+        for variable in &debug_info.variables {
+            let type_ = self.type_(&variable.type_);
+            let argument = variable.argument.map_or(String::new(), |argument| format!(", arg: {}", argument));
+            let node = self.add(format!(
+                "!DILocalVariable(name: \"{}\"{}, scope: !{}, file: !{}, line: {}, type: !{})",
+                escape_string(&variable.name),
+                argument,
+                subprogram,
+                self.file,
+                variable.location.line,
+                type_
+            ));
+            let location = self.location(subprogram, variable.location);
+            self.metadata.variables.insert((subprogram, variable.register), (node, location));
+        }
+    }
+
+    fn location(&mut self, subprogram : usize, location : Location) -> usize {
+        let number = self.add(format!(
+            "!DILocation(line: {}, column: {}, scope: !{})",
+            location.line,
+            location.column,
+            subprogram
+        ));
+        self.metadata.locations.insert((subprogram, location), number);
+        number
+    }
+
+    fn bits(&self, type_ : &DebugType) -> (usize, usize) {
+        let type_ = type_.mir_type();
+        (size_of(self.module, &type_) * 8, align_of(self.module, &type_) * 8)
+    }
+
+    fn type_(&mut self, type_ : &DebugType) -> usize {
+        let (size, align) = self.bits(type_);
+        match type_ {
+            DebugType::Basic{name, encoding, ..} => {
+                let encoding = match encoding {
+                    Encoding::Signed => "DW_ATE_signed",
+                    Encoding::Unsigned => "DW_ATE_unsigned",
+                    Encoding::Float => "DW_ATE_float",
+                    Encoding::Boolean => "DW_ATE_boolean",
+                };
+                self.add(format!("!DIBasicType(name: \"{}\", size: {}, encoding: {})", name, size, encoding))
+            },
+            DebugType::Pointer(pointee_type) => {
+                let pointee_type = self.type_(pointee_type);
+                self.add(format!("!DIDerivedType(tag: DW_TAG_pointer_type, baseType: !{}, size: {})", pointee_type, size))
+            },
+            DebugType::Array(element_type, len) => {
+                let element_type = self.type_(element_type);
+                let subrange = self.add(format!("!DISubrange(count: {})", len));
+                let elements = self.metadata.metadata.tuple(&[subrange]);
+                self.add(format!(
+                    "!DICompositeType(tag: DW_TAG_array_type, baseType: !{}, size: {}, align: {}, elements: !{})",
+                    element_type,
+                    size,
+                    align,
+                    elements
+                ))
+            },
+            DebugType::Enum{name, variants} => {
+                let enumerators : Vec<usize> = variants
+                    .iter()
+                    .map(|(variant, value)| self.add(format!("!DIEnumerator(name: \"{}\", value: {})", escape_string(variant), value)))
+                    .collect();
+                let elements = self.metadata.metadata.tuple(&enumerators);
+                self.add(format!(
+                    "!DICompositeType(tag: DW_TAG_enumeration_type, name: \"{}\", file: !{}, size: {}, align: {}, elements: !{})",
+                    escape_string(name),
+                    self.file,
+                    size,
+                    align,
+                    elements
+                ))
+            },
+            DebugType::String => {
+                let byte = DebugType::Basic {
+                    name : "uint8".to_string(),
+                    size : 8,
+                    encoding : Encoding::Unsigned,
+                };
+                let length = DebugType::Basic {
+                    name : "int64".to_string(),
+                    size : 64,
+                    encoding : Encoding::Signed,
+                };
+                let fields = [("data".to_string(), DebugType::Pointer(Box::new(byte))), ("length".to_string(), length)];
+                self.struct_type("string", &fields, type_)
+            },
+            DebugType::Struct(name) => {
+                let debug_info = self.debug_info;
+                let fields = debug_info.struct_type(name).map_or(&[][..], |struct_type| &struct_type.fields);
+                self.struct_type(name, fields, type_)
+            },
+        }
+    }
+
+    fn struct_type(&mut self, name : &str, fields : &[(String, DebugType)], type_ : &DebugType) -> usize {
+        if let Some(number) = self.structs.get(name) {
+            return *number;
+        }
+
+        let number = self.metadata.metadata.reserve();
+        self.structs.insert(name.to_string(), number);
+
+        let mir_type = type_.mir_type();
+        let mut members = vec![];
+        for (i, (field, field_type)) in fields.iter().enumerate() {
+            let base_type = self.type_(field_type);
+            let (size, _) = self.bits(field_type);
+            members.push(self.add(format!(
+                "!DIDerivedType(tag: DW_TAG_member, name: \"{}\", scope: !{}, file: !{}, baseType: !{}, size: {}, offset: {})",
+                escape_string(field),
+                number,
+                self.file,
+                base_type,
+                size,
+                field_offset(self.module, &mir_type, i) * 8
+            )));
+        }
+        let elements = self.metadata.metadata.tuple(&members);
+
+        let (size, align) = self.bits(type_);
+        self.metadata.metadata.set(number, format!(
+            "distinct !DICompositeType(tag: DW_TAG_structure_type, name: \"{}\", file: !{}, size: {}, align: {}, elements: !{})",
+            escape_string(name),
+            self.file,
+            size,
+            align,
+            elements
+        ));
+        number
+    }
+}
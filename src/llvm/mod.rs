@@ -1,2 +1,4 @@
 pub mod ir_gen;
+pub mod attributes;
+pub mod metadata;
 pub mod prelude;
\ No newline at end of file
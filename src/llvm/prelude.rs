@@ -1 +1,2 @@
-pub use super::ir_gen::create_llvm_ir;
+pub use super::ir_gen::{create_llvm_ir, create_llvm_ir_with_options, generate_llvm_ir, LLVMIRGenerator};
+pub use super::attributes::{Attributes, ModuleFlag, Visibility};
\ No newline at end of file
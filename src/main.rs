@@ -1,4 +1,21 @@
 fn main() {
+    let opt_level = std::env::args()
+        .filter_map(|arg| toy_lang::OptLevel::from_flag(&arg))
+        .next_back()
+        .unwrap_or_default();
+    let target = std::env::args()
+        .filter_map(|arg| toy_lang::Target::from_flag(&arg))
+        .next_back()
+        .unwrap_or_default();
+    let options = toy_lang::CodegenOptions {
+        opt_level,
+        target,
+        source_filename : Some("example.toy".to_string()),
+        debug_info : std::env::args().any(|arg| arg == "-g"),
+        bounds_check : std::env::args().any(|arg| arg == "--bounds-check"),
+        ..toy_lang::CodegenOptions::default()
+    };
+
     let lexeme = toy_lang::Lexeme::from_file("example.toy");
 
     println!("{:#?}", lexeme);
@@ -7,5 +24,34 @@ fn main() {
 
     println!("{:#?}", tu);
 
-    toy_lang::create_llvm_ir(&tu, "example.ll");
+    match toy_lang::lower_to_mir(&tu, &options) {
+        Ok(mut module) => {
+            toy_lang::optimize_module(&mut module, opt_level);
+            println!("{}", module);
+        },
+        Err(error) => println!("{}", error),
+    }
+
+    if let Err(error) = toy_lang::create_llvm_ir_with_options(&tu, "example.ll", &options) {
+        println!("{}", error);
+    }
+    if let Err(error) = toy_lang::create_c_with_options(&tu, "example.c", "example.h", &options) {
+        println!("{}", error);
+    }
+    if let Err(error) = toy_lang::create_wat_with_options(&tu, "example.wat", &options) {
+        println!("{}", error);
+    }
+    if let Err(error) = toy_lang::create_asm_with_options(&tu, "example.s", &options) {
+        println!("{}", error);
+    }
+
+    println!("{:?}", toy_lang::interpret(&tu));
+
+    match toy_lang::compile_bytecode(&tu) {
+        Ok(program) => {
+            println!("{}", program.disassemble());
+            println!("{:?}", toy_lang::run_bytecode(&program));
+        },
+        Err(error) => println!("{}", error),
+    }
 }
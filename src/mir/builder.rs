@@ -0,0 +1,296 @@
+use std::collections::HashSet;
+
+use super::mir::*;
+use super::debug_info::Location;
+
+// Builds a function one instruction at a time, appending to the current
+// block. Register and block names are made unique within the function:
+// temporaries are `t0`, `t1`... and blocks get a numbered suffix.
+//
+// Blocks are laid out in the order they are first switched to, not in the
+// order they are created. Instructions and terminators get the location set
+// last.
+pub struct Builder {
+    function : Function,
+    current : BlockId,
+    location : Option<Location>,
+    layout : Vec<BlockId>,
+    names : HashSet<String>,
+    tmp_count : usize,
+    label_count : usize,
+}
+
+impl Builder {
+    pub fn new(name : &str, parameters : Vec<(String, Type)>, return_type : Option<Type>) -> Builder {
+        let mut builder = Builder {
+            function : Function {
+                name : name.to_string(),
+                parameters : vec![],
+                return_type,
+                registers : vec![],
+                blocks : vec![Block {
+                    name : "entry".to_string(),
+                    instructions : vec![],
+                    terminator : None,
+                    terminator_location : None,
+                }],
+                debug_info : None,
+            },
+            current : BlockId(0),
+            location : None,
+            layout : vec![BlockId(0)],
+            names : HashSet::new(),
+            tmp_count : 0,
+            label_count : 0,
+        };
+
+        for (name, type_) in parameters {
+            let register = builder.new_register(type_, Some(&name));
+            builder.function.parameters.push(register);
+        }
+        builder
+    }
+
+    pub fn finish(mut self) -> Function {
+        for i in 0..self.function.blocks.len() {
+            if !self.layout.contains(&BlockId(i)) {
+                self.layout.push(BlockId(i));
+            }
+        }
+
+        let mut new_ids = vec![BlockId(0); self.function.blocks.len()];
+        for (new_id, old_id) in self.layout.iter().enumerate() {
+            new_ids[old_id.0] = BlockId(new_id);
+        }
+
+        let mut blocks : Vec<Option<Block>> = self.function.blocks.drain(..).map(Some).collect();
+        for old_id in &self.layout {
+            let mut block = blocks[old_id.0].take().unwrap();
+            block.map_blocks(|block| new_ids[block.0]);
+            self.function.blocks.push(block);
+        }
+
+        self.function
+    }
+
+    pub fn parameter(&self, index : usize) -> Value {
+        Value::Register(self.function.parameters[index])
+    }
+
+    pub fn register_type(&self, register : Register) -> &Type {
+        &self.function.register(register).type_
+    }
+
+    // Named registers get a numbered suffix when the name is taken, e.g.
+    // for shadowed variables.
+    pub fn new_register(&mut self, type_ : Type, name : Option<&str>) -> Register {
+        let name = match name {
+            Some(name) => {
+                let mut unique = name.to_string();
+                let mut count = 0;
+                while self.names.contains(&unique) {
+                    count += 1;
+                    unique = format!("{}.{}", name, count);
+                }
+                unique
+            },
+            None => loop {
+                self.tmp_count += 1;
+                let name = format!("t{}", self.tmp_count-1);
+                if !self.names.contains(&name) {
+                    break name;
+                }
+            },
+        };
+
+        self.names.insert(name.clone());
+        self.function.registers.push(RegisterInfo {
+            name,
+            type_,
+        });
+        Register(self.function.registers.len()-1)
+    }
+
+    pub fn create_block(&mut self, name : &str) -> BlockId {
+        let name = loop {
+            self.label_count += 1;
+            let label = format!("{}.{}", name, self.label_count-1);
+            if !self.names.contains(&label) {
+                break label;
+            }
+        };
+
+        self.names.insert(name.clone());
+        self.function.blocks.push(Block {
+            name,
+            instructions : vec![],
+            terminator : None,
+            terminator_location : None,
+        });
+        BlockId(self.function.blocks.len()-1)
+    }
+
+    pub fn switch_to(&mut self, block : BlockId) {
+        if !self.layout.contains(&block) {
+            self.layout.push(block);
+        }
+        self.current = block;
+    }
+
+    pub fn current_block(&self) -> BlockId {
+        self.current
+    }
+
+    pub fn location(&self) -> Option<Location> {
+        self.location
+    }
+
+    pub fn set_location(&mut self, location : Option<Location>) {
+        self.location = location;
+    }
+
+    pub fn is_terminated(&self) -> bool {
+        self.function.block(self.current).terminator.is_some()
+    }
+
+    fn push(&mut self, kind : InstructionKind, result_type : Option<Type>) -> Option<Register> {
+        let result = result_type.map(|type_| self.new_register(type_, None));
+        self.push_with_result(kind, result);
+        result
+    }
+
+    fn push_with_result(&mut self, kind : InstructionKind, result : Option<Register>) {
+        let block = &mut self.function.blocks[self.current.0];
+        if block.terminator.is_some() {
+            panic!("Instruction added to the terminated block {}", block.name);
+        }
+        block.instructions.push(Instruction {
+            result,
+            kind,
+            location : self.location,
+        });
+    }
+
+    fn push_value(&mut self, kind : InstructionKind, result_type : Type) -> Value {
+        Value::Register(self.push(kind, Some(result_type)).unwrap())
+    }
+
+    fn terminate(&mut self, terminator : Terminator) {
+        let block = &mut self.function.blocks[self.current.0];
+        if block.terminator.is_some() {
+            panic!("Block {} terminated twice", block.name);
+        }
+        block.terminator = Some(terminator);
+        block.terminator_location = self.location;
+    }
+
+    /*******************/
+    /*******************/
+    /*******************/
+
+    // `name` is the name of the variable the slot holds, if any. Slots are
+    // put after the other ones at the start of the entry block, so that they
+    // are allocated once per call even when declared in a loop.
+    pub fn alloca(&mut self, type_ : Type, name : Option<&str>) -> Value {
+        let result = self.new_register(Type::Ptr, name);
+        let entry = &mut self.function.blocks[0].instructions;
+        let position = entry
+            .iter()
+            .position(|instruction| !matches!(instruction.kind, InstructionKind::Alloca(_)))
+            .unwrap_or(entry.len());
+        entry.insert(position, Instruction {
+            result : Some(result),
+            kind : InstructionKind::Alloca(type_),
+            location : self.location,
+        });
+        Value::Register(result)
+    }
+
+    pub fn load(&mut self, type_ : Type, pointer : Value) -> Value {
+        self.push_value(InstructionKind::Load{type_ : type_.clone(), pointer}, type_)
+    }
+
+    pub fn store(&mut self, type_ : Type, value : Value, pointer : Value) {
+        self.push(InstructionKind::Store{type_, value, pointer}, None);
+    }
+
+    pub fn binary(&mut self, op : BinaryOp, type_ : Type, left : Value, right : Value) -> Value {
+        self.push_value(InstructionKind::Binary{op, type_ : type_.clone(), left, right}, type_)
+    }
+
+    pub fn negate(&mut self, type_ : Type, value : Value) -> Value {
+        self.push_value(InstructionKind::Negate{type_ : type_.clone(), value}, type_)
+    }
+
+    pub fn compare(&mut self, comparison : Comparison, type_ : Type, left : Value, right : Value) -> Value {
+        self.push_value(InstructionKind::Compare{comparison, type_, left, right}, Type::I1)
+    }
+
+    pub fn cast(&mut self, op : CastOp, from : Type, value : Value, to : Type) -> Value {
+        self.push_value(InstructionKind::Cast{op, from, value, to : to.clone()}, to)
+    }
+
+    pub fn field_address(&mut self, type_ : Type, pointer : Value, index : usize) -> Value {
+        self.push_value(InstructionKind::FieldAddress{type_, pointer, index}, Type::Ptr)
+    }
+
+    pub fn element_address(&mut self, type_ : Type, pointer : Value, index : Value) -> Value {
+        self.push_value(InstructionKind::ElementAddress{type_, pointer, index}, Type::Ptr)
+    }
+
+    pub fn offset(&mut self, type_ : Type, pointer : Value, index : Value) -> Value {
+        self.push_value(InstructionKind::Offset{type_, pointer, index}, Type::Ptr)
+    }
+
+    // `field_type` is the type of the extracted value
+    pub fn extract_value(&mut self, type_ : Type, aggregate : Value, index : usize, field_type : Type) -> Value {
+        self.push_value(InstructionKind::ExtractValue{type_, aggregate, index}, field_type)
+    }
+
+    pub fn insert_value(&mut self, type_ : Type, aggregate : Value, value : Value, index : usize) -> Value {
+        self.push_value(InstructionKind::InsertValue{type_ : type_.clone(), aggregate, value, index}, type_)
+    }
+
+    pub fn call(&mut self, callee : Callee, return_type : Option<Type>, arguments : Vec<(Type, Value)>) -> Option<Value> {
+        self.push(
+            InstructionKind::Call {
+                callee,
+                return_type : return_type.clone(),
+                arguments,
+            },
+            return_type
+        ).map(Value::Register)
+    }
+
+    pub fn phi(&mut self, type_ : Type, incoming : Vec<(Value, BlockId)>) -> Value {
+        self.push_value(InstructionKind::Phi{type_ : type_.clone(), incoming}, type_)
+    }
+
+    /*******************/
+    /*******************/
+    /*******************/
+
+    pub fn ret(&mut self, value : Option<(Type, Value)>) {
+        self.terminate(Terminator::Return(value));
+    }
+
+    pub fn branch(&mut self, target : BlockId) {
+        self.terminate(Terminator::Branch(target));
+    }
+
+    pub fn cond_branch(&mut self, condition : Value, then_block : BlockId, else_block : BlockId) {
+        self.terminate(Terminator::CondBranch{condition, then_block, else_block});
+    }
+
+    pub fn switch(&mut self, type_ : Type, value : Value, default : BlockId, cases : Vec<(i64, BlockId)>) {
+        self.terminate(Terminator::Switch{type_, value, default, cases});
+    }
+
+    pub fn trap(&mut self) {
+        self.terminate(Terminator::Trap);
+    }
+
+    pub fn unreachable(&mut self) {
+        self.terminate(Terminator::Unreachable);
+    }
+}
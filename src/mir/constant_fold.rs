@@ -0,0 +1,302 @@
+use std::collections::HashMap;
+
+use super::mir::*;
+
+// Evaluates instructions whose operands are all constants and replaces
+// their uses with the result, until nothing changes. Branches on constants
+// become unconditional, which leaves the other targets to dead code
+// elimination. Operations with undefined results, like a division by zero
+// or a shift by more than the width, are left as they are.
+pub fn fold_constants(function : &mut Function) -> bool {
+    let mut changed = false;
+    loop {
+        let mut replacements = HashMap::new();
+        for block in &mut function.blocks {
+            block.instructions.retain(|instruction| {
+                let folded = instruction.result.and_then(|result| {
+                    let type_ = &function.registers[result.0].type_;
+                    fold_instruction(&instruction.kind, type_, result, &replacements)
+                });
+                match folded {
+                    Some(constant) => {
+                        replacements.insert(instruction.result.unwrap(), Value::Constant(constant));
+                        false
+                    },
+                    None => true,
+                }
+            });
+        }
+        function.replace_uses(&replacements);
+
+        let branches_folded = fold_branches(function);
+        if replacements.is_empty() && !branches_folded {
+            return changed;
+        }
+        changed = true;
+    }
+}
+
+// `value` with the replacements made so far
+fn constant<'b>(value : &'b Value, replacements : &'b HashMap<Register, Value>) -> Option<&'b Constant> {
+    let mut value = value;
+    while let Value::Register(register) = value {
+        value = replacements.get(register)?;
+    }
+    match value {
+        Value::Constant(constant) => Some(constant),
+        Value::Register(_) => None,
+    }
+}
+
+fn fold_instruction(kind : &InstructionKind, result_type : &Type, result : Register, replacements : &HashMap<Register, Value>) -> Option<Constant> {
+    match kind {
+        InstructionKind::Binary{op, type_, left, right} => {
+            fold_binary(*op, type_, constant(left, replacements)?, constant(right, replacements)?)
+        },
+        InstructionKind::Negate{type_, value} => {
+            let value = as_float(constant(value, replacements)?)?;
+            Some(Constant::Float(round(-value, type_)))
+        },
+        InstructionKind::Compare{comparison, type_, left, right} => {
+            fold_compare(*comparison, type_, constant(left, replacements)?, constant(right, replacements)?)
+                .map(|result| Constant::Int(result as i64))
+        },
+        InstructionKind::Cast{op, from, value, to} => fold_cast(*op, from, constant(value, replacements)?, to),
+        InstructionKind::ExtractValue{aggregate, index, ..} => match constant(aggregate, replacements)? {
+            Constant::Aggregate(elements) => elements.get(*index).cloned(),
+            Constant::Zero => Some(zero(result_type)),
+            Constant::Undef => Some(Constant::Undef),
+            _ => None,
+        },
+        // Undefined values may be anything, so they match any other value
+        InstructionKind::Phi{incoming, ..} => {
+            let mut folded = Constant::Undef;
+            for (value, _) in incoming {
+                if value.as_register() == Some(result) {
+                    continue;
+                }
+                match constant(value, replacements)? {
+                    Constant::Undef => {},
+                    value if folded == Constant::Undef => folded = value.clone(),
+                    value if *value == folded => {},
+                    _ => return None,
+                }
+            }
+            Some(folded)
+        },
+        _ => None,
+    }
+}
+
+fn fold_branches(function : &mut Function) -> bool {
+    let mut changed = false;
+    for i in 0..function.blocks.len() {
+        let target = match &function.blocks[i].terminator {
+            Some(Terminator::CondBranch{condition : Value::Constant(Constant::Int(condition)), then_block, else_block}) => {
+                if *condition != 0 {*then_block} else {*else_block}
+            },
+            Some(Terminator::Switch{type_, value : Value::Constant(value), default, cases}) => {
+                let value = match as_int(value, type_) {
+                    Some(value) => value,
+                    None => continue,
+                };
+                cases
+                    .iter()
+                    .find(|(case, _)| normalize(*case, type_) == value)
+                    .map_or(*default, |(_, target)| *target)
+            },
+            _ => continue,
+        };
+
+        let block = &mut function.blocks[i];
+        let mut dropped = block.successors();
+        dropped.retain(|successor| *successor != target);
+        block.terminator = Some(Terminator::Branch(target));
+
+        for successor in dropped {
+            for instruction in &mut function.blocks[successor.0].instructions {
+                if let InstructionKind::Phi{incoming, ..} = &mut instruction.kind {
+                    incoming.retain(|(_, from)| from.0 != i);
+                }
+            }
+        }
+        changed = true;
+    }
+    changed
+}
+
+/****************************/
+/****************************/
+/****************************/
+
+fn zero(type_ : &Type) -> Constant {
+    match type_ {
+        Type::Ptr => Constant::Null,
+        type_ if type_.is_int() => Constant::Int(0),
+        type_ if type_.is_float() => Constant::Float(0.0),
+        _ => Constant::Zero,
+    }
+}
+
+// Integer constants are kept sign extended from their width, except `i1`
+// which is 0 or 1.
+fn normalize(value : i64, type_ : &Type) -> i64 {
+    match type_.width() {
+        1 => value & 1,
+        64 => value,
+        width => (value << (64-width)) >> (64-width),
+    }
+}
+
+fn as_int(constant : &Constant, type_ : &Type) -> Option<i64> {
+    match constant {
+        Constant::Int(value) => Some(normalize(*value, type_)),
+        Constant::Zero => Some(0),
+        _ => None,
+    }
+}
+
+fn as_signed(constant : &Constant, type_ : &Type) -> Option<i64> {
+    let width = type_.width();
+    as_int(constant, type_).map(|value| (value << (64-width)) >> (64-width))
+}
+
+fn as_unsigned(constant : &Constant, type_ : &Type) -> Option<u64> {
+    let width = type_.width();
+    as_int(constant, type_).map(|value| match width {
+        64 => value as u64,
+        _ => value as u64 & ((1 << width) - 1),
+    })
+}
+
+fn as_float(constant : &Constant) -> Option<f64> {
+    match constant {
+        Constant::Float(value) => Some(*value),
+        Constant::Zero => Some(0.0),
+        _ => None,
+    }
+}
+
+// Floats of type `f32` are computed in single precision
+fn round(value : f64, type_ : &Type) -> f64 {
+    match type_ {
+        Type::F32 => value as f32 as f64,
+        _ => value,
+    }
+}
+
+fn fold_binary(op : BinaryOp, type_ : &Type, left : &Constant, right : &Constant) -> Option<Constant> {
+    if op.is_float() {
+        let (left, right) = (round(as_float(left)?, type_), round(as_float(right)?, type_));
+        let result = match op {
+            BinaryOp::FAdd => left + right,
+            BinaryOp::FSub => left - right,
+            BinaryOp::FMul => left * right,
+            BinaryOp::FDiv => left / right,
+            _ => left % right,
+        };
+        return Some(Constant::Float(round(result, type_)));
+    }
+
+    let width = type_.width() as u64;
+    let (a, b) = (as_int(left, type_)?, as_int(right, type_)?);
+    let (signed_a, signed_b) = (as_signed(left, type_)?, as_signed(right, type_)?);
+    let (unsigned_a, unsigned_b) = (as_unsigned(left, type_)?, as_unsigned(right, type_)?);
+    let overflows = signed_b == -1 && signed_a == as_signed(&Constant::Int(1 << (width-1)), type_)?;
+
+    let result = match op {
+        BinaryOp::Add => a.wrapping_add(b),
+        BinaryOp::Sub => a.wrapping_sub(b),
+        BinaryOp::Mul => a.wrapping_mul(b),
+        BinaryOp::SDiv if signed_b != 0 && !overflows => signed_a / signed_b,
+        BinaryOp::SRem if signed_b != 0 && !overflows => signed_a % signed_b,
+        BinaryOp::UDiv if unsigned_b != 0 => (unsigned_a / unsigned_b) as i64,
+        BinaryOp::URem if unsigned_b != 0 => (unsigned_a % unsigned_b) as i64,
+        BinaryOp::And => a & b,
+        BinaryOp::Or => a | b,
+        BinaryOp::Xor => a ^ b,
+        BinaryOp::Shl if unsigned_b < width => a << unsigned_b,
+        BinaryOp::AShr if unsigned_b < width => signed_a >> unsigned_b,
+        BinaryOp::LShr if unsigned_b < width => (unsigned_a >> unsigned_b) as i64,
+        _ => return None,
+    };
+    Some(Constant::Int(normalize(result, type_)))
+}
+
+fn fold_compare(comparison : Comparison, type_ : &Type, left : &Constant, right : &Constant) -> Option<bool> {
+    if comparison.is_float() {
+        let (left, right) = (as_float(left)?, as_float(right)?);
+        return Some(match comparison {
+            Comparison::FOeq => left == right,
+            Comparison::FUne => left != right,
+            Comparison::FOlt => left < right,
+            Comparison::FOle => left <= right,
+            Comparison::FOgt => left > right,
+            _ => left >= right,
+        });
+    }
+
+    // Globals are never null and have different addresses
+    if *type_ == Type::Ptr {
+        let equal = match (left, right) {
+            (Constant::Null | Constant::Zero, Constant::Null | Constant::Zero) => true,
+            (Constant::Global(left), Constant::Global(right)) => left == right,
+            (Constant::Global(_), Constant::Null | Constant::Zero) | (Constant::Null | Constant::Zero, Constant::Global(_)) => false,
+            _ => return None,
+        };
+        return match comparison {
+            Comparison::Eq => Some(equal),
+            Comparison::Ne => Some(!equal),
+            _ => None,
+        };
+    }
+
+    let (signed_a, signed_b) = (as_signed(left, type_)?, as_signed(right, type_)?);
+    let (unsigned_a, unsigned_b) = (as_unsigned(left, type_)?, as_unsigned(right, type_)?);
+    Some(match comparison {
+        Comparison::Eq => unsigned_a == unsigned_b,
+        Comparison::Ne => unsigned_a != unsigned_b,
+        Comparison::SLt => signed_a < signed_b,
+        Comparison::SLe => signed_a <= signed_b,
+        Comparison::SGt => signed_a > signed_b,
+        Comparison::SGe => signed_a >= signed_b,
+        Comparison::ULt => unsigned_a < unsigned_b,
+        Comparison::ULe => unsigned_a <= unsigned_b,
+        Comparison::UGt => unsigned_a > unsigned_b,
+        _ => unsigned_a >= unsigned_b,
+    })
+}
+
+fn fold_cast(op : CastOp, from : &Type, value : &Constant, to : &Type) -> Option<Constant> {
+    let result = match op {
+        CastOp::Trunc => Constant::Int(normalize(as_int(value, from)?, to)),
+        CastOp::ZExt => Constant::Int(normalize(as_unsigned(value, from)? as i64, to)),
+        CastOp::SExt => Constant::Int(normalize(as_signed(value, from)?, to)),
+        CastOp::FPTrunc | CastOp::FPExt => Constant::Float(round(as_float(value)?, to)),
+        // Out of range values give poison, so they are not folded
+        CastOp::FPToSI => {
+            let value = as_float(value)?.trunc();
+            let limit = 2f64.powi(to.width() as i32 - 1);
+            if !(value >= -limit && value < limit) {
+                return None;
+            }
+            Constant::Int(normalize(value as i64, to))
+        },
+        CastOp::FPToUI => {
+            let value = as_float(value)?.trunc();
+            if !(value >= 0.0 && value < 2f64.powi(to.width() as i32)) {
+                return None;
+            }
+            Constant::Int(normalize(value as u64 as i64, to))
+        },
+        CastOp::SIToFP => {
+            let value = as_signed(value, from)?;
+            Constant::Float(if *to == Type::F32 {value as f32 as f64} else {value as f64})
+        },
+        CastOp::UIToFP => {
+            let value = as_unsigned(value, from)?;
+            Constant::Float(if *to == Type::F32 {value as f32 as f64} else {value as f64})
+        },
+    };
+    Some(result)
+}
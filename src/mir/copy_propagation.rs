@@ -0,0 +1,96 @@
+use std::collections::HashMap;
+
+use super::mir::*;
+
+// Replaces copies of values by the values themselves: phis whose incoming
+// values are all the same, and loads of a value that was just stored to or
+// loaded from the same pointer in the block. Without alias analysis, any
+// other store or call may change any memory and ends the forwarding.
+pub fn propagate_copies(function : &mut Function) -> bool {
+    let mut replacements = HashMap::new();
+    loop {
+        let mut found = false;
+        for block in &mut function.blocks {
+            block.instructions.retain(|instruction| {
+                let result = match instruction.result {
+                    Some(result) => result,
+                    None => return true,
+                };
+                match trivial_phi(&instruction.kind, result, &replacements) {
+                    Some(value) => {
+                        replacements.insert(result, value);
+                        found = true;
+                        false
+                    },
+                    None => true,
+                }
+            });
+        }
+        if !found {
+            break;
+        }
+    }
+
+    for block in &mut function.blocks {
+        // Pointer and type of the load or store, and the value in memory
+        let mut memory : Vec<(Value, Type, Value)> = vec![];
+        block.instructions.retain(|instruction| {
+            match &instruction.kind {
+                InstructionKind::Load{type_, pointer} => {
+                    let pointer = resolve(pointer, &replacements);
+                    let result = instruction.result.unwrap();
+                    let known = memory
+                        .iter()
+                        .find(|(other, other_type, _)| *other == pointer && other_type == type_);
+                    match known {
+                        Some((_, _, value)) => {
+                            replacements.insert(result, value.clone());
+                            return false;
+                        },
+                        None => memory.push((pointer, type_.clone(), Value::Register(result))),
+                    }
+                },
+                InstructionKind::Store{type_, value, pointer} => {
+                    memory.clear();
+                    memory.push((resolve(pointer, &replacements), type_.clone(), resolve(value, &replacements)));
+                },
+                InstructionKind::Call{..} => memory.clear(),
+                _ => {},
+            }
+            true
+        });
+    }
+
+    function.replace_uses(&replacements);
+    !replacements.is_empty()
+}
+
+fn resolve(value : &Value, replacements : &HashMap<Register, Value>) -> Value {
+    let mut value = value;
+    while let Some(replacement) = value.as_register().and_then(|register| replacements.get(&register)) {
+        value = replacement;
+    }
+    value.clone()
+}
+
+// The single value of a phi, ignoring the phi itself
+fn trivial_phi(kind : &InstructionKind, result : Register, replacements : &HashMap<Register, Value>) -> Option<Value> {
+    let incoming = match kind {
+        InstructionKind::Phi{incoming, ..} => incoming,
+        _ => return None,
+    };
+
+    let mut single = None;
+    for (value, _) in incoming {
+        let value = resolve(value, replacements);
+        if value.as_register() == Some(result) {
+            continue;
+        }
+        match &single {
+            None => single = Some(value),
+            Some(other) if *other == value => {},
+            Some(_) => return None,
+        }
+    }
+    single
+}
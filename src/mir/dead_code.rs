@@ -0,0 +1,183 @@
+use std::collections::{HashMap, HashSet};
+
+use super::mir::*;
+use super::dominators::Dominators;
+
+// Removes unreachable blocks, merges blocks into their only predecessor,
+// and removes instructions whose results are never used along with local
+// variables that are written but never read.
+pub fn eliminate_dead_code(function : &mut Function) -> bool {
+    let mut changed = remove_unreachable_blocks(function);
+    changed |= merge_blocks(function);
+    while remove_write_only_allocas(function) | remove_dead_instructions(function) {
+        changed = true;
+    }
+    function.remove_dead_variables();
+    changed
+}
+
+pub(super) fn remove_unreachable_blocks(function : &mut Function) -> bool {
+    let dominators = Dominators::new(function);
+    let reachable : Vec<bool> = (0..function.blocks.len())
+        .map(|block| dominators.is_reachable(BlockId(block)))
+        .collect();
+    if reachable.iter().all(|reachable| *reachable) {
+        return false;
+    }
+
+    let mut new_ids = vec![BlockId(0); function.blocks.len()];
+    let mut count = 0;
+    for (block, reachable) in reachable.iter().enumerate() {
+        if *reachable {
+            new_ids[block] = BlockId(count);
+            count += 1;
+        }
+    }
+
+    let blocks = std::mem::take(&mut function.blocks);
+    for (i, mut block) in blocks.into_iter().enumerate() {
+        if !reachable[i] {
+            continue;
+        }
+
+        for instruction in &mut block.instructions {
+            if let InstructionKind::Phi{incoming, ..} = &mut instruction.kind {
+                incoming.retain(|(_, from)| reachable[from.0]);
+            }
+        }
+        block.map_blocks(|block| new_ids[block.0]);
+        function.blocks.push(block);
+    }
+
+    true
+}
+
+// A block that is the only successor of its only predecessor is appended
+// to it.
+fn merge_blocks(function : &mut Function) -> bool {
+    let mut changed = false;
+    loop {
+        let predecessors = function.predecessors();
+        let merge = (0..function.blocks.len()).find_map(|block| match function.blocks[block].terminator {
+            Some(Terminator::Branch(target)) if target.0 != block && predecessors[target.0] == [BlockId(block)] => {
+                Some((BlockId(block), target))
+            },
+            _ => None,
+        });
+        let (block, successor) = match merge {
+            Some(merge) => merge,
+            None => break,
+        };
+
+        // The phis of the successor have a single value, from `block`
+        let mut replacements = HashMap::new();
+        let instructions = std::mem::take(&mut function.blocks[successor.0].instructions);
+        for instruction in instructions {
+            match instruction.kind {
+                InstructionKind::Phi{mut incoming, ..} => {
+                    replacements.insert(instruction.result.unwrap(), incoming.remove(0).0);
+                },
+                _ => function.blocks[block.0].instructions.push(instruction),
+            }
+        }
+
+        let terminator = function.blocks[successor.0].terminator.replace(Terminator::Unreachable);
+        for target in terminator.as_ref().unwrap().successors() {
+            function.blocks[target.0].map_blocks(|from| if from == successor {block} else {from});
+        }
+        function.blocks[block.0].terminator = terminator;
+        function.replace_uses(&replacements);
+
+        remove_unreachable_blocks(function);
+        changed = true;
+    }
+    changed
+}
+
+// Allocas only used as the destination of stores
+fn remove_write_only_allocas(function : &mut Function) -> bool {
+    let mut allocas = HashSet::new();
+    for block in &function.blocks {
+        for instruction in &block.instructions {
+            if let InstructionKind::Alloca(_) = instruction.kind {
+                allocas.insert(instruction.result.unwrap());
+            }
+        }
+    }
+
+    for block in &function.blocks {
+        for instruction in &block.instructions {
+            let operands = match &instruction.kind {
+                InstructionKind::Store{value, ..} => vec![value],
+                kind => kind.operands(),
+            };
+            for operand in operands {
+                if let Some(register) = operand.as_register() {
+                    allocas.remove(&register);
+                }
+            }
+        }
+        if let Some(terminator) = &block.terminator {
+            for operand in terminator.operands() {
+                if let Some(register) = operand.as_register() {
+                    allocas.remove(&register);
+                }
+            }
+        }
+    }
+
+    if allocas.is_empty() {
+        return false;
+    }
+
+    for block in &mut function.blocks {
+        block.instructions.retain(|instruction| match &instruction.kind {
+            InstructionKind::Alloca(_) => !allocas.contains(&instruction.result.unwrap()),
+            InstructionKind::Store{pointer, ..} => !pointer.as_register().is_some_and(|pointer| allocas.contains(&pointer)),
+            _ => true,
+        });
+    }
+    true
+}
+
+// Instructions are live if they have side effects or if a live instruction
+// or a terminator uses their result. Unlike counting uses, this also
+// removes cycles of phis that nothing else uses.
+fn remove_dead_instructions(function : &mut Function) -> bool {
+    let mut definitions = HashMap::new();
+    let mut worklist = vec![];
+    for block in &function.blocks {
+        for instruction in &block.instructions {
+            if let Some(result) = instruction.result {
+                definitions.insert(result, instruction);
+            }
+            if instruction.kind.has_side_effects() {
+                worklist.extend(instruction.kind.operands());
+            }
+        }
+        if let Some(terminator) = &block.terminator {
+            worklist.extend(terminator.operands());
+        }
+    }
+
+    let mut live = HashSet::new();
+    while let Some(value) = worklist.pop() {
+        if let Some(register) = value.as_register() {
+            if live.insert(register) {
+                if let Some(instruction) = definitions.get(&register) {
+                    worklist.extend(instruction.kind.operands());
+                }
+            }
+        }
+    }
+
+    let mut changed = false;
+    for block in &mut function.blocks {
+        let len = block.instructions.len();
+        block.instructions.retain(|instruction| {
+            instruction.kind.has_side_effects() || instruction.result.is_some_and(|result| live.contains(&result))
+        });
+        changed |= block.instructions.len() != len;
+    }
+    changed
+}
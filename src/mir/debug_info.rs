@@ -0,0 +1,100 @@
+use super::mir::{Type, Register};
+
+// Source level description of a module for debuggers, only built when
+// debug info is requested. Lines and columns start at 1.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Location {
+    pub line : usize,
+    pub column : usize,
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f : &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Signed,
+    Unsigned,
+    Float,
+    Boolean,
+}
+
+// Types of the language, as opposed to their representation in MIR.
+// Classes are referred to by name since they may point to themselves.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DebugType {
+    // `size` is in bits
+    Basic{name : String, size : usize, encoding : Encoding},
+    String,
+    Pointer(Box<DebugType>),
+    Array(Box<DebugType>, usize),
+    Struct(String),
+    Enum{name : String, variants : Vec<(String, i64)>},
+}
+
+impl DebugType {
+    // Type of the values in MIR, to compute sizes and offsets
+    pub fn mir_type(&self) -> Type {
+        match self {
+            DebugType::Basic{size : 32, encoding : Encoding::Float, ..} => Type::F32,
+            DebugType::Basic{encoding : Encoding::Float, ..} => Type::F64,
+            DebugType::Basic{size, ..} => Type::int(*size).unwrap(),
+            DebugType::String => Type::String,
+            DebugType::Pointer(_) => Type::Ptr,
+            DebugType::Array(element_type, len) => Type::Array(Box::new(element_type.mir_type()), *len),
+            DebugType::Struct(name) => Type::Struct(name.clone()),
+            DebugType::Enum{..} => Type::I32,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DebugStruct {
+    pub name : String,
+    pub fields : Vec<(String, DebugType)>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DebugGlobal {
+    pub name : String,
+    pub type_ : DebugType,
+    pub location : Location,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DebugInfo {
+    pub file : String,
+    pub directory : String,
+    pub optimized : bool,
+    pub structs : Vec<DebugStruct>,
+    pub globals : Vec<DebugGlobal>,
+}
+
+impl DebugInfo {
+    pub fn struct_type(&self, name : &str) -> Option<&DebugStruct> {
+        self.structs.iter().find(|struct_type| struct_type.name == name)
+    }
+}
+
+// A local variable lives in the alloca `register`. `self` is the only one
+// held by a parameter, the pointer to the object. `argument` is the
+// position of parameters, starting at 1.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DebugVariable {
+    pub name : String,
+    pub register : Register,
+    pub type_ : DebugType,
+    pub location : Location,
+    pub argument : Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionDebugInfo {
+    pub location : Location,
+    pub return_type : Option<DebugType>,
+    pub variables : Vec<DebugVariable>,
+}
@@ -0,0 +1,159 @@
+use super::mir::*;
+
+// Dominator tree of the reachable blocks of a function, computed with the
+// algorithm of Cooper, Harvey and Kennedy.
+pub struct Dominators {
+    // Reachable blocks in reverse postorder, starting with the entry block
+    order : Vec<BlockId>,
+    // Position of each block in `order`, `None` if it is unreachable
+    positions : Vec<Option<usize>>,
+    // The entry block is its own immediate dominator
+    idoms : Vec<Option<BlockId>>,
+}
+
+impl Dominators {
+    pub fn new(function : &Function) -> Dominators {
+        let len = function.blocks.len();
+
+        let mut visited = vec![false; len];
+        let mut postorder = vec![];
+        let mut stack = vec![(BlockId(0), 0)];
+        visited[0] = true;
+        while let Some((block, next)) = stack.pop() {
+            let successors = function.block(block).successors();
+            match successors.get(next) {
+                Some(successor) => {
+                    stack.push((block, next+1));
+                    if !visited[successor.0] {
+                        visited[successor.0] = true;
+                        stack.push((*successor, 0));
+                    }
+                },
+                None => postorder.push(block),
+            }
+        }
+
+        let order : Vec<BlockId> = postorder.into_iter().rev().collect();
+        let mut positions = vec![None; len];
+        for (i, block) in order.iter().enumerate() {
+            positions[block.0] = Some(i);
+        }
+
+        let predecessors = function.predecessors();
+        let mut idoms = vec![None; len];
+        idoms[0] = Some(BlockId(0));
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for block in order.iter().skip(1) {
+                let mut new_idom = None;
+                for predecessor in &predecessors[block.0] {
+                    if idoms[predecessor.0].is_none() {
+                        continue;
+                    }
+                    new_idom = match new_idom {
+                        None => Some(*predecessor),
+                        Some(other) => Some(Dominators::intersect(&idoms, &positions, *predecessor, other)),
+                    };
+                }
+
+                if idoms[block.0] != new_idom {
+                    idoms[block.0] = new_idom;
+                    changed = true;
+                }
+            }
+        }
+
+        Dominators {
+            order,
+            positions,
+            idoms,
+        }
+    }
+
+    fn intersect(idoms : &[Option<BlockId>], positions : &[Option<usize>], mut a : BlockId, mut b : BlockId) -> BlockId {
+        while a != b {
+            while positions[a.0] > positions[b.0] {
+                a = idoms[a.0].unwrap();
+            }
+            while positions[b.0] > positions[a.0] {
+                b = idoms[b.0].unwrap();
+            }
+        }
+        a
+    }
+
+    pub fn is_reachable(&self, block : BlockId) -> bool {
+        self.positions[block.0].is_some()
+    }
+
+    pub fn reverse_postorder(&self) -> &[BlockId] {
+        &self.order
+    }
+
+    // `None` for the entry block and unreachable blocks
+    pub fn immediate_dominator(&self, block : BlockId) -> Option<BlockId> {
+        match self.idoms[block.0] {
+            Some(idom) if idom != block => Some(idom),
+            _ => None,
+        }
+    }
+
+    // Whether every path from the entry block to `b` goes through `a`. A
+    // block dominates itself.
+    pub fn dominates(&self, a : BlockId, mut b : BlockId) -> bool {
+        if !self.is_reachable(a) || !self.is_reachable(b) {
+            return false;
+        }
+        loop {
+            if a == b {
+                return true;
+            }
+            match self.immediate_dominator(b) {
+                Some(idom) => b = idom,
+                None => return false,
+            }
+        }
+    }
+
+    // Blocks immediately dominated by each block, in reverse postorder
+    pub fn children(&self) -> Vec<Vec<BlockId>> {
+        let mut children = vec![vec![]; self.idoms.len()];
+        for block in &self.order {
+            if let Some(idom) = self.immediate_dominator(*block) {
+                children[idom.0].push(*block);
+            }
+        }
+        children
+    }
+
+    // The dominance frontier of a block is where its dominance ends: the
+    // blocks it does not strictly dominate but that have a predecessor it
+    // dominates.
+    pub fn frontiers(&self, function : &Function) -> Vec<Vec<BlockId>> {
+        let mut frontiers = vec![vec![]; self.idoms.len()];
+        let predecessors = function.predecessors();
+        for block in &self.order {
+            let reachable_predecessors : Vec<&BlockId> = predecessors[block.0]
+                .iter()
+                .filter(|predecessor| self.is_reachable(**predecessor))
+                .collect();
+            if reachable_predecessors.len() < 2 {
+                continue;
+            }
+
+            let idom = self.idoms[block.0].unwrap();
+            for predecessor in reachable_predecessors {
+                let mut runner = *predecessor;
+                while runner != idom {
+                    if !frontiers[runner.0].contains(block) {
+                        frontiers[runner.0].push(*block);
+                    }
+                    runner = self.idoms[runner.0].unwrap();
+                }
+            }
+        }
+        frontiers
+    }
+}
@@ -0,0 +1,66 @@
+use super::mir::*;
+
+// Sizes and alignments of the types in memory, following the C rules:
+// fields are placed in order at the next multiple of their alignment, and
+// the size of a struct is a multiple of its largest alignment. Alignments
+// of scalars come from the datalayout of the target.
+
+pub fn size_of(module : &Module, type_ : &Type) -> usize {
+    match type_ {
+        Type::I1 | Type::I8 => 1,
+        Type::I16 => 2,
+        Type::I32 | Type::F32 => 4,
+        Type::I64 | Type::F64 => 8,
+        Type::Ptr => module.target.pointer_width / 8,
+        Type::Array(element_type, len) => size_of(module, element_type) * len,
+        Type::String | Type::Struct(_) => {
+            let fields = fields(module, type_);
+            let end = match fields.last() {
+                Some(last) => field_offset(module, type_, fields.len()-1) + size_of(module, last),
+                None => 0,
+            };
+            align_to(end, align_of(module, type_))
+        },
+    }
+}
+
+pub fn align_of(module : &Module, type_ : &Type) -> usize {
+    match type_ {
+        Type::Array(element_type, _) => align_of(module, element_type),
+        Type::String | Type::Struct(_) => fields(module, type_)
+            .iter()
+            .map(|field| align_of(module, field))
+            .max()
+            .unwrap_or(1),
+        Type::Ptr => module.target.pointer_alignment / 8,
+        Type::F32 | Type::F64 => module.target.float_alignment(type_.width()),
+        type_ => module.target.int_alignment(type_.width()),
+    }
+}
+
+// Offset of the field or element `index` of an aggregate
+pub fn field_offset(module : &Module, type_ : &Type, index : usize) -> usize {
+    match type_ {
+        Type::Array(element_type, _) => size_of(module, element_type) * index,
+        _ => {
+            let fields = fields(module, type_);
+            let mut offset = 0;
+            for field in &fields[..index] {
+                offset = align_to(offset, align_of(module, field)) + size_of(module, field);
+            }
+            align_to(offset, align_of(module, &fields[index]))
+        },
+    }
+}
+
+pub fn align_to(offset : usize, align : usize) -> usize {
+    offset.div_ceil(align) * align
+}
+
+fn fields(module : &Module, type_ : &Type) -> Vec<Type> {
+    match type_ {
+        Type::String => vec![Type::Ptr, Type::I64],
+        Type::Struct(name) => module.struct_type(name).unwrap().fields.clone(),
+        _ => vec![],
+    }
+}
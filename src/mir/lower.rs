@@ -0,0 +1,1187 @@
+use std::collections::HashMap;
+
+use crate::parser::ast::*;
+use crate::parser::types::{check_types, field_of, Types};
+use crate::lexer::lexeme::Range;
+use crate::codegen::{CodegenOptions, OptLevel};
+use crate::target::Target;
+use super::mir::{self, Module, Value, Constant, BinaryOp, Comparison, CastOp, BlockId, Callee};
+use super::debug_info::*;
+use super::builder::Builder;
+
+pub fn lower_to_mir(tu : &TranslationUnit, options : &CodegenOptions) -> Result<Module, String> {
+    let types = check_types(tu)?;
+    Lowerer::new(tu, &types, options).lower()
+}
+
+// MIR type of the values of a type of the language
+pub fn lower_type(type_ : &Type, target : &Target) -> mir::Type {
+    match type_ {
+        Type::BuiltIn(built_in_type) => match built_in_type {
+            BuiltInType::I8 | BuiltInType::U8 | BuiltInType::Bool => mir::Type::I8,
+            BuiltInType::I16 | BuiltInType::U16 => mir::Type::I16,
+            BuiltInType::I32 | BuiltInType::U32 => mir::Type::I32,
+            BuiltInType::I64 | BuiltInType::U64 => mir::Type::I64,
+            BuiltInType::F32 => mir::Type::F32,
+            BuiltInType::F64 => mir::Type::F64,
+            BuiltInType::ISize | BuiltInType::USize => mir::Type::int(target.pointer_width).unwrap(),
+            BuiltInType::String => mir::Type::String,
+        },
+        Type::Custom(custom_type) => mir::Type::Struct(custom_type.identifier.clone()),
+        Type::Array(element_type, len) => mir::Type::Array(Box::new(lower_type(element_type, target)), *len),
+        Type::Pointer(_) => mir::Type::Ptr,
+        Type::Enum(_) => mir::Type::I32,
+    }
+}
+
+/****************************/
+/****************************/
+/****************************/
+
+#[derive(Debug, Clone)]
+struct Variable {
+    pointer : Value,
+    type_ : Type,
+}
+
+// Constants are retyped freely when they meet a value of another type.
+#[derive(Debug, Clone)]
+struct Operand {
+    value : Value,
+    type_ : Type,
+    is_constant : bool,
+}
+
+type Variables = HashMap<String, Variable>;
+type Functions = HashMap<String, (Vec<Type>, Option<Type>)>;
+
+struct Lowerer<'a> {
+    tu : &'a TranslationUnit,
+    types : &'a Types<'a>,
+    options : &'a CodegenOptions,
+    module : Module,
+    global_variables : Variables,
+    functions : Functions,
+    builder : Option<Builder>,
+    scopes : Vec<Variables>,
+    return_type : Option<Type>,
+    // Locals of the current function, with debug info only
+    debug_variables : Vec<DebugVariable>,
+}
+
+impl<'a> Lowerer<'a> {
+    fn new(tu : &'a TranslationUnit, types : &'a Types<'a>, options : &'a CodegenOptions) -> Lowerer<'a> {
+        Lowerer {
+            tu,
+            types,
+            options,
+            module : Module::new(options.target.clone()),
+            global_variables : Variables::new(),
+            functions : Functions::new(),
+            builder : None,
+            scopes : vec![],
+            return_type : None,
+            debug_variables : vec![],
+        }
+    }
+
+    fn lower(mut self) -> Result<Module, String> {
+        self.lower_custom_types();
+
+        self.lower_global_variables()
+            .map_err(|e| format!("Error while generating global variables: {}", e))?;
+
+        self.lower_functions()
+            .map_err(|e| format!("Error while generating functions: {}", e))?;
+
+        if self.options.debug_info {
+            self.module.debug_info = Some(self.lower_debug_info()?);
+        }
+
+        Ok(self.module)
+    }
+
+    fn builder(&mut self) -> &mut Builder {
+        self.builder.as_mut().unwrap()
+    }
+
+    fn lower_type(&self, type_ : &Type) -> mir::Type {
+        lower_type(type_, &self.options.target)
+    }
+
+    fn int_width(&self, type_ : &BuiltInType) -> usize {
+        match type_ {
+            BuiltInType::ISize | BuiltInType::USize => self.options.target.pointer_width,
+            BuiltInType::I8 | BuiltInType::U8 | BuiltInType::Bool => 8,
+            BuiltInType::I16 | BuiltInType::U16 => 16,
+            BuiltInType::I32 | BuiltInType::U32 | BuiltInType::F32 => 32,
+            _ => 64,
+        }
+    }
+
+    fn location(range : &Range) -> Location {
+        Location {
+            line : range.start.line + 1,
+            column : range.start.column + 1,
+        }
+    }
+
+    fn set_location(&mut self, range : Option<Range>) {
+        if let Some(range) = range {
+            self.builder().set_location(Some(Lowerer::location(&range)));
+        }
+    }
+
+    fn debug_type(&self, type_ : &Type) -> DebugType {
+        match type_ {
+            Type::BuiltIn(BuiltInType::String) => DebugType::String,
+            Type::BuiltIn(built_in_type) => {
+                let encoding = match built_in_type {
+                    BuiltInType::Bool => Encoding::Boolean,
+                    t if t.is_float() => Encoding::Float,
+                    t if t.is_signed() => Encoding::Signed,
+                    _ => Encoding::Unsigned,
+                };
+                DebugType::Basic {
+                    name : built_in_type.to_string().to_string(),
+                    size : self.int_width(built_in_type),
+                    encoding,
+                }
+            },
+            Type::Custom(custom_type) => DebugType::Struct(custom_type.identifier.clone()),
+            Type::Array(element_type, len) => DebugType::Array(Box::new(self.debug_type(element_type)), *len),
+            Type::Pointer(pointee_type) => DebugType::Pointer(Box::new(self.debug_type(pointee_type))),
+            Type::Enum(enum_type) => DebugType::Enum {
+                name : enum_type.identifier.clone(),
+                variants : enum_type.variants
+                    .iter()
+                    .map(|variant| (variant.identifier.clone(), variant.value))
+                    .collect(),
+            },
+        }
+    }
+
+    // The source file is split in a name and an absolute directory so that
+    // debuggers find it from anywhere.
+    fn lower_debug_info(&self) -> Result<DebugInfo, String> {
+        let source = self.options.source_filename
+            .as_ref()
+            .ok_or_else(|| "Debug info requires the name of the source file".to_string())?;
+        let path = std::path::Path::new(source);
+        let file = path.file_name()
+            .ok_or_else(|| format!("Invalid source file name {}", source))?
+            .to_string_lossy()
+            .to_string();
+        let mut directory = path.parent().map(|parent| parent.to_path_buf()).unwrap_or_default();
+        if directory.is_relative() {
+            let current = std::env::current_dir().map_err(|e| e.to_string())?;
+            directory = current.join(directory);
+        }
+        let directory = directory.to_string_lossy().trim_end_matches('/').to_string();
+
+        let structs = self.tu.custom_types
+            .iter()
+            .map(|custom_type| DebugStruct {
+                name : custom_type.identifier.clone(),
+                fields : custom_type.attributes
+                    .iter()
+                    .map(|attr| (attr.identifier.clone(), self.debug_type(&attr.type_)))
+                    .collect(),
+            })
+            .collect();
+
+        let globals = self.tu.global_variables
+            .iter()
+            .map(|var| DebugGlobal {
+                name : var.identifier.clone(),
+                type_ : self.debug_type(&var.type_),
+                location : Lowerer::location(&var.range),
+            })
+            .collect();
+
+        Ok(DebugInfo {
+            file,
+            directory,
+            optimized : self.options.opt_level != OptLevel::O0,
+            structs,
+            globals,
+        })
+    }
+
+    fn zero_value(type_ : &Type) -> Constant {
+        match type_ {
+            Type::BuiltIn(t) if t.is_float() => Constant::Float(0.0),
+            Type::BuiltIn(BuiltInType::String) => Constant::Zero,
+            Type::Pointer(_) => Constant::Null,
+            Type::BuiltIn(_) | Type::Enum(_) => Constant::Int(0),
+            Type::Custom(_) | Type::Array(..) => Constant::Zero,
+        }
+    }
+
+    // Operators are checked against their operand types by `check_types`
+    fn operator(op : &BinaryOperator, type_ : &BuiltInType) -> BinaryOp {
+        if type_.is_float() {
+            return match op {
+                BinaryOperator::Plus => BinaryOp::FAdd,
+                BinaryOperator::Minus => BinaryOp::FSub,
+                BinaryOperator::Times => BinaryOp::FMul,
+                BinaryOperator::Div => BinaryOp::FDiv,
+                BinaryOperator::Mod => BinaryOp::FRem,
+                _ => unreachable!("unsupported binary operator for floating point values"),
+            };
+        }
+
+        let signed = type_.is_signed();
+        match op {
+            BinaryOperator::Plus => BinaryOp::Add,
+            BinaryOperator::Minus => BinaryOp::Sub,
+            BinaryOperator::Times => BinaryOp::Mul,
+            BinaryOperator::Div => if signed {BinaryOp::SDiv} else {BinaryOp::UDiv},
+            BinaryOperator::Mod => if signed {BinaryOp::SRem} else {BinaryOp::URem},
+            BinaryOperator::BitAnd => BinaryOp::And,
+            BinaryOperator::BitOr => BinaryOp::Or,
+            BinaryOperator::Xor | BinaryOperator::BitXor => BinaryOp::Xor,
+            BinaryOperator::LShift => BinaryOp::Shl,
+            BinaryOperator::RShift => if signed {BinaryOp::AShr} else {BinaryOp::LShr},
+            _ => unreachable!("comparisons are lowered by `comparison`"),
+        }
+    }
+
+    fn comparison(op : &BinaryOperator, type_ : &BuiltInType) -> Comparison {
+        if type_.is_float() {
+            return match op {
+                BinaryOperator::Equals => Comparison::FOeq,
+                BinaryOperator::NotEquals => Comparison::FUne,
+                BinaryOperator::Less => Comparison::FOlt,
+                BinaryOperator::LessEq => Comparison::FOle,
+                BinaryOperator::Greater => Comparison::FOgt,
+                BinaryOperator::GreaterEq => Comparison::FOge,
+                _ => unreachable!("not a comparison"),
+            };
+        }
+
+        let signed = type_.is_signed();
+        match op {
+            BinaryOperator::Equals => Comparison::Eq,
+            BinaryOperator::NotEquals => Comparison::Ne,
+            BinaryOperator::Less => if signed {Comparison::SLt} else {Comparison::ULt},
+            BinaryOperator::LessEq => if signed {Comparison::SLe} else {Comparison::ULe},
+            BinaryOperator::Greater => if signed {Comparison::SGt} else {Comparison::UGt},
+            BinaryOperator::GreaterEq => if signed {Comparison::SGe} else {Comparison::UGe},
+            _ => unreachable!("not a comparison"),
+        }
+    }
+
+    // Literals above `i64::MAX` are kept as their `uint64` bit pattern
+    fn parse_integer(text : &str) -> Result<i64, String> {
+        text.parse::<i64>()
+            .or_else(|_| text.parse::<u64>().map(|value| value as i64))
+            .map_err(|_| format!("Invalid integer literal {}", text))
+    }
+
+    fn literal_to_operand(lit : &Literal) -> Result<Operand, String> {
+        let (value, type_) = match lit {
+            Literal::Integer(v) => (Constant::Int(Lowerer::parse_integer(v)?), BuiltInType::I32),
+            Literal::Float(v) => {
+                let value = v.parse().map_err(|_| format!("Invalid float literal {}", v))?;
+                (Constant::Float(value), BuiltInType::F64)
+            },
+            Literal::Boolean(v) => (Constant::Int(*v as i64), BuiltInType::Bool),
+            Literal::String(_) => return Err("Unsupported literal type: String".to_string()),
+            // `null` converts to a pointer to any type
+            Literal::Null => return Ok(Operand {
+                value : Value::Constant(Constant::Null),
+                type_ : Type::Pointer(Box::new(Type::BuiltIn(BuiltInType::U8))),
+                is_constant : true,
+            }),
+        };
+
+        Ok(Operand {
+            value : Value::Constant(value),
+            type_ : Type::BuiltIn(type_),
+            is_constant : true,
+        })
+    }
+
+    fn enum_value_to_operand(value : &EnumValue) -> Operand {
+        let variant = value.enum_type
+            .variant(&value.variant)
+            .expect("enum values are checked by the parser");
+
+        Operand {
+            value : Value::int(variant.value),
+            type_ : Type::Enum(value.enum_type.clone()),
+            is_constant : true,
+        }
+    }
+
+    fn lower_constant(&mut self, op : &OperationResult, type_ : &Type) -> Result<Constant, String> {
+        match op {
+            OperationResult::EnumValue(value) => match Lowerer::enum_value_to_operand(value).value {
+                Value::Constant(constant) => Ok(constant),
+                Value::Register(_) => unreachable!("enum values are constants"),
+            },
+            OperationResult::Literal(Literal::String(text)) => Ok(Constant::String(self.module.string(text))),
+            OperationResult::Literal(lit) => {
+                let value = Lowerer::literal_to_operand(lit)?;
+                Ok(Lowerer::convert_constant(&value, type_))
+            },
+            OperationResult::UnaryOpResult(UnaryOperation{operator : UnaryOperator::AddressOf, operand, ..}) => match operand.as_ref() {
+                OperationResult::Identifier(id) => Ok(Constant::Global(id.clone())),
+                _ => Err("Global initializers must be constant".to_string()),
+            },
+            OperationResult::StructLiteral(literal) => {
+                let custom_type = match type_ {
+                    Type::Custom(custom_type) => custom_type,
+                    _ => unreachable!("class literals have the type of their class"),
+                };
+
+                let mut fields : Vec<Constant> = vec![];
+                for attr in &custom_type.attributes {
+                    let init = literal.fields
+                        .iter()
+                        .find(|init| init.field == attr.identifier)
+                        .ok_or_else(|| format!("Field {} of {} not initialized", attr.identifier, literal.type_id))?;
+
+                    fields.push(self.lower_constant(&init.value, &attr.type_)?);
+                }
+
+                if fields.is_empty() {
+                    return Ok(Constant::Zero);
+                }
+                Ok(Constant::Aggregate(fields))
+            },
+            OperationResult::ArrayLiteral(literal) => {
+                let element_type = match type_ {
+                    Type::Array(element_type, _) => element_type,
+                    _ => unreachable!("array literals have an array type"),
+                };
+
+                let elements : Vec<Constant> = literal.elements
+                    .iter()
+                    .map(|element| self.lower_constant(element, element_type))
+                    .collect::<Result<_, String>>()?;
+
+                if elements.is_empty() {
+                    return Ok(Constant::Zero);
+                }
+                Ok(Constant::Aggregate(elements))
+            },
+            _ => Err("Global initializers must be constant".to_string())
+        }
+    }
+
+    fn lower_custom_types(&mut self) {
+        for custom_type in &self.tu.custom_types {
+            let fields : Vec<mir::Type> = custom_type.attributes
+                .iter()
+                .map(|attr| self.lower_type(&attr.type_))
+                .collect();
+
+            self.module.structs.push(mir::StructType {
+                name : custom_type.identifier.clone(),
+                fields,
+            });
+        }
+    }
+
+    fn lower_global_variables(&mut self) -> Result<(), String> {
+        let tu = self.tu;
+        for var in &tu.global_variables {
+            let init_value = match &var.init_value {
+                None => Lowerer::zero_value(&var.type_),
+                Some(op) => self.lower_constant(op, &var.type_)?,
+            };
+
+            self.module.globals.push(mir::Global {
+                name : var.identifier.clone(),
+                type_ : self.lower_type(&var.type_),
+                init_value,
+            });
+
+            self.global_variables.insert(var.identifier.clone(), Variable {
+                pointer : Value::Constant(Constant::Global(var.identifier.clone())),
+                type_ : var.type_.clone(),
+            });
+        }
+
+        Ok(())
+    }
+
+    fn lower_functions(&mut self) -> Result<(), String> {
+        let tu = self.tu;
+
+        // Register every signature first so functions can call each other
+        // regardless of their order in the source.
+        for fun in &tu.functions {
+            let arguments : Vec<Type> = fun.arguments.iter().map(|arg| arg.type_.clone()).collect();
+            self.functions.insert(fun.identifier.clone(), (arguments, fun.return_type.clone()));
+        }
+
+        for fun in &tu.functions {
+            self.scopes = vec![Variables::new()];
+            self.return_type = fun.return_type.clone();
+
+            // Methods get a pointer to the object they are called on as
+            // their first argument.
+            let mut params : Vec<(String, mir::Type)> = vec![];
+            if fun.receiver.is_some() {
+                params.push(("self".to_string(), mir::Type::Ptr));
+            }
+            for param in &fun.arguments {
+                params.push((format!("{}.arg", param.identifier), self.lower_type(&param.type_)));
+            }
+            let return_type = fun.return_type.as_ref().map(|type_| self.lower_type(type_));
+            self.builder = Some(Builder::new(&fun.identifier, params, return_type));
+            self.set_location(Some(fun.range));
+
+            let mut first_argument = 0;
+            if let Some(custom_type) = &fun.receiver {
+                let pointer = self.builder().parameter(0);
+                let type_ = Type::Custom(custom_type.clone());
+                if self.options.debug_info {
+                    self.debug_variables.push(DebugVariable {
+                        name : "self".to_string(),
+                        register : pointer.as_register().unwrap(),
+                        type_ : DebugType::Pointer(Box::new(self.debug_type(&type_))),
+                        location : Lowerer::location(&fun.range),
+                        argument : Some(1),
+                    });
+                }
+                self.scopes.last_mut().unwrap().insert("self".to_string(), Variable {
+                    pointer,
+                    type_,
+                });
+                first_argument = 1;
+            }
+
+            // Arguments are copied to the stack so they can be assigned and
+            // have their fields addressed like any other local.
+            for (i, param) in fun.arguments.iter().enumerate() {
+                let var = self.declare_local(&param.identifier, &param.type_, Some(first_argument + i + 1));
+                let argument = self.builder().parameter(first_argument + i);
+                let type_ = self.lower_type(&param.type_);
+                self.builder().store(type_, argument, var.pointer);
+            }
+
+            let terminated = self.lower_body(&fun.body)?;
+
+            if !terminated {
+                match fun.return_type {
+                    None => self.builder().ret(None),
+                    Some(_) => self.builder().unreachable(),
+                }
+            }
+
+            let mut function = self.builder.take().unwrap().finish();
+            if self.options.debug_info {
+                function.debug_info = Some(FunctionDebugInfo {
+                    location : Lowerer::location(&fun.range),
+                    return_type : fun.return_type.as_ref().map(|type_| self.debug_type(type_)),
+                    variables : std::mem::take(&mut self.debug_variables),
+                });
+            }
+            self.module.functions.push(function);
+        }
+
+        Ok(())
+    }
+
+    // `argument` is the position of the parameter the local is copied from
+    fn declare_local(&mut self, id : &str, type_ : &Type, argument : Option<usize>) -> Variable {
+        let type_mir = self.lower_type(type_);
+        let var = Variable {
+            pointer : self.builder().alloca(type_mir, Some(id)),
+            type_ : type_.clone(),
+        };
+
+        if let (true, Some(location)) = (self.options.debug_info, self.builder().location()) {
+            self.debug_variables.push(DebugVariable {
+                name : id.to_string(),
+                register : var.pointer.as_register().unwrap(),
+                type_ : self.debug_type(type_),
+                location,
+                argument,
+            });
+        }
+
+        self.scopes.last_mut().unwrap().insert(id.to_string(), var.clone());
+
+        var
+    }
+
+    fn find_variable(&self, id : &str) -> Variable {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(id))
+            .or_else(|| self.global_variables.get(id))
+            .cloned()
+            .expect("variables are declared before they are used")
+    }
+
+    // Returns whether the body ended with a terminator.
+    fn lower_body(&mut self, body : &Body) -> Result<bool, String> {
+        let mut terminated = false;
+        for stm in &body.statements {
+            if terminated {
+                // Code after a return still needs a block to live in.
+                let block = self.builder().create_block("dead");
+                self.builder().switch_to(block);
+            }
+            terminated = self.lower_statement(stm)?;
+        }
+        Ok(terminated)
+    }
+
+    fn lower_scoped_body(&mut self, body : &Body) -> Result<bool, String> {
+        self.scopes.push(Variables::new());
+        let terminated = self.lower_body(body)?;
+        self.scopes.pop();
+        Ok(terminated)
+    }
+
+    fn lower_statement(&mut self, stm : &Statement) -> Result<bool, String> {
+        let range = match stm {
+            Statement::Declaration(decl) => Some(decl.range),
+            Statement::Operation(op) => op.range(),
+            Statement::Return(_, range) => Some(*range),
+            Statement::If(if_block) => Some(if_block.range),
+            Statement::For(for_block) => Some(for_block.range),
+            Statement::While(while_block) => Some(while_block.range),
+            Statement::Match(match_block) => Some(match_block.range),
+            _ => None,
+        };
+        self.set_location(range);
+
+        match stm {
+            Statement::Declaration(decl) => {
+                // The initial value cannot use the variable it initializes
+                let value = match &decl.init_value {
+                    Some(init_value) => Some(self.lower_value(init_value, &decl.type_)?),
+                    None => None,
+                };
+
+                // Variables declared without a value are zero every time
+                // their declaration runs, e.g. in each iteration of a loop
+                let var = self.declare_local(&decl.identifier, &decl.type_, None);
+                let value = match value {
+                    Some(value) => value.value,
+                    None => Value::Constant(Lowerer::zero_value(&decl.type_)),
+                };
+                let type_mir = self.lower_type(&decl.type_);
+                self.builder().store(type_mir, value, var.pointer);
+            },
+            Statement::Operation(OperationResult::FuncResult(fun)) => {
+                self.lower_call(fun)?;
+            },
+            Statement::Operation(op) => {
+                self.lower_operation(op)?;
+            },
+            Statement::Return(ret, _) => {
+                match (ret, self.return_type.clone()) {
+                    (None, None) => self.builder().ret(None),
+                    (Some(op), Some(type_)) => {
+                        let value = self.lower_value(op, &type_)?;
+                        let type_mir = self.lower_type(&type_);
+                        self.builder().ret(Some((type_mir, value.value)));
+                    },
+                    _ => unreachable!("return values are checked against the return type"),
+                }
+                return Ok(true);
+            },
+            Statement::Body(body) => return self.lower_scoped_body(body),
+            Statement::If(if_block) => return self.lower_if(if_block),
+            Statement::While(while_block) => {
+                self.lower_loop(&while_block.condition, None, &while_block.body)?;
+            },
+            Statement::For(for_block) => {
+                self.scopes.push(Variables::new());
+                if let Some(init_statement) = &for_block.init_statement {
+                    self.lower_statement(init_statement)?;
+                    self.set_location(Some(for_block.range));
+                }
+                self.lower_loop(&for_block.condition, for_block.end_statement.as_ref(), &for_block.body)?;
+                self.scopes.pop();
+            },
+            Statement::Match(match_block) => return self.lower_match(match_block),
+            _ => return Err("Unsupported statement type".to_string())
+        }
+        Ok(false)
+    }
+
+    // Evaluates a bool as an `i1` to branch on.
+    fn lower_condition(&mut self, condition : &OperationResult) -> Result<Value, String> {
+        let value = self.lower_operation(condition)?;
+        Ok(self.builder().compare(Comparison::Ne, mir::Type::I8, value.value, Value::int(0)))
+    }
+
+    // The end block is only created if a branch reaches it.
+    fn lower_if(&mut self, if_block : &IfBlock) -> Result<bool, String> {
+        let condition = self.lower_condition(&if_block.condition)?;
+
+        let then_block = self.builder().create_block("if.then");
+        let mut end_block : Option<BlockId> = None;
+        let false_block = match if_block.else_body {
+            Some(_) => self.builder().create_block("if.else"),
+            None => *end_block.insert(self.builder().create_block("if.end")),
+        };
+        self.builder().cond_branch(condition, then_block, false_block);
+
+        self.builder().switch_to(then_block);
+        if !self.lower_scoped_body(&if_block.body)? {
+            let end = *end_block.get_or_insert_with(|| self.builder().create_block("if.end"));
+            self.builder().branch(end);
+        }
+
+        if let Some(else_body) = &if_block.else_body {
+            self.builder().switch_to(false_block);
+            if !self.lower_scoped_body(else_body)? {
+                let end = *end_block.get_or_insert_with(|| self.builder().create_block("if.end"));
+                self.builder().branch(end);
+            }
+        }
+
+        match end_block {
+            Some(end) => {
+                self.builder().switch_to(end);
+                Ok(false)
+            },
+            None => Ok(true),
+        }
+    }
+
+    // The condition is checked before every iteration, `end_statement`
+    // runs after each one. Both are at the location of the loop keyword.
+    fn lower_loop(&mut self, condition : &OperationResult, end_statement : Option<&OperationResult>, body : &Body) -> Result<(), String> {
+        let location = self.builder().location();
+        let cond_block = self.builder().create_block("loop.cond");
+        let body_block = self.builder().create_block("loop.body");
+        let end_block = self.builder().create_block("loop.end");
+
+        self.builder().branch(cond_block);
+        self.builder().switch_to(cond_block);
+        let condition = self.lower_condition(condition)?;
+        self.builder().cond_branch(condition, body_block, end_block);
+
+        self.builder().switch_to(body_block);
+        if !self.lower_scoped_body(body)? {
+            self.builder().set_location(location);
+            match end_statement {
+                Some(OperationResult::FuncResult(fun)) => {self.lower_call(fun)?;},
+                Some(op) => {self.lower_operation(op)?;},
+                None => {},
+            }
+            self.builder().branch(cond_block);
+        }
+
+        self.builder().switch_to(end_block);
+        Ok(())
+    }
+
+    // Lowered to a `switch` on the discriminant. Without a `_` arm the match
+    // is exhaustive, so the default destination is unreachable.
+    fn lower_match(&mut self, match_block : &MatchBlock) -> Result<bool, String> {
+        let value = self.lower_operation(&match_block.value)?;
+        let enum_type = match &value.type_ {
+            Type::Enum(enum_type) => enum_type.clone(),
+            _ => unreachable!("only enums are matched"),
+        };
+
+        let default_block = self.builder().create_block("match.default");
+        let arm_blocks : Vec<BlockId> = match_block.arms
+            .iter()
+            .map(|_| self.builder().create_block("match.arm"))
+            .collect();
+
+        let mut cases : Vec<(i64, BlockId)> = vec![];
+        for (arm, block) in match_block.arms.iter().zip(&arm_blocks) {
+            for variant_id in &arm.variants {
+                let variant = enum_type.variant(variant_id).expect("arms are checked by the parser");
+                cases.push((variant.value, *block));
+            }
+        }
+        self.builder().switch(mir::Type::I32, value.value, default_block, cases);
+
+        let mut end_block : Option<BlockId> = None;
+        for (arm, block) in match_block.arms.iter().zip(&arm_blocks) {
+            self.builder().switch_to(*block);
+            if !self.lower_scoped_body(&arm.body)? {
+                let end = *end_block.get_or_insert_with(|| self.builder().create_block("match.end"));
+                self.builder().branch(end);
+            }
+        }
+
+        self.builder().switch_to(default_block);
+        match &match_block.default {
+            Some(body) => {
+                if !self.lower_scoped_body(body)? {
+                    let end = *end_block.get_or_insert_with(|| self.builder().create_block("match.end"));
+                    self.builder().branch(end);
+                }
+            },
+            None => self.builder().unreachable(),
+        }
+
+        match end_block {
+            Some(end) => {
+                self.builder().switch_to(end);
+                Ok(false)
+            },
+            None => Ok(true),
+        }
+    }
+
+    fn lower_store(&mut self, value : &Operand, pointer : Value, type_ : &Type) {
+        let value = self.lower_conversion(value, type_);
+        let type_mir = self.lower_type(type_);
+        self.builder().store(type_mir, value.value, pointer);
+    }
+
+    // Conversions are checked by `check_types`
+    fn convert_constant(value : &Operand, type_ : &Type) -> Constant {
+        let constant = match &value.value {
+            Value::Constant(constant) => constant,
+            Value::Register(_) => unreachable!("constants are never in registers"),
+        };
+
+        match (constant, type_) {
+            (Constant::Int(v), Type::BuiltIn(to)) if to.is_float() => Constant::Float(Lowerer::round_float(*v as f64, to)),
+            (Constant::Float(v), Type::BuiltIn(to)) => Constant::Float(Lowerer::round_float(*v, to)),
+            _ => constant.clone(),
+        }
+    }
+
+    fn round_float(value : f64, type_ : &BuiltInType) -> f64 {
+        if *type_ == BuiltInType::F32 { value as f32 as f64 } else { value }
+    }
+
+    fn lower_conversion(&mut self, value : &Operand, type_ : &Type) -> Operand {
+        if value.type_ == *type_ {
+            return value.clone();
+        }
+
+        if value.is_constant {
+            return Operand {
+                value : Value::Constant(Lowerer::convert_constant(value, type_)),
+                type_ : type_.clone(),
+                is_constant : true,
+            };
+        }
+
+        let (from, to) = match (&value.type_, type_) {
+            (Type::BuiltIn(from), Type::BuiltIn(to)) => (from, to),
+            _ => unreachable!("only built-in values are converted"),
+        };
+
+        let from_width = self.int_width(from);
+        let to_width = self.int_width(to);
+
+        let op = match (from.is_float(), to.is_float()) {
+            (true, true) if from_width < to_width => CastOp::FPExt,
+            (true, true) => CastOp::FPTrunc,
+            (true, false) => if to.is_signed() {CastOp::FPToSI} else {CastOp::FPToUI},
+            (false, true) => if from.is_signed() {CastOp::SIToFP} else {CastOp::UIToFP},
+            (false, false) if from_width == to_width => return Operand { type_ : type_.clone(), ..value.clone() },
+            (false, false) if from_width > to_width => CastOp::Trunc,
+            (false, false) => if from.is_signed() {CastOp::SExt} else {CastOp::ZExt},
+        };
+
+        let from_mir = self.lower_type(&value.type_);
+        let to_mir = self.lower_type(type_);
+        Operand {
+            value : self.builder().cast(op, from_mir, value.value.clone(), to_mir),
+            type_ : type_.clone(),
+            is_constant : false,
+        }
+    }
+
+    fn lower_load(&mut self, pointer : Value, type_ : &Type) -> Operand {
+        let type_mir = self.lower_type(type_);
+        Operand {
+            value : self.builder().load(type_mir, pointer),
+            type_ : type_.clone(),
+            is_constant : false,
+        }
+    }
+
+    // Evaluates an operation as a place in memory. Returns a pointer to it
+    // as the value, with the type of the pointee.
+    fn lower_address(&mut self, op : &OperationResult) -> Result<Option<Operand>, String> {
+        match op {
+            OperationResult::Identifier(id) => {
+                let var = self.find_variable(id);
+                Ok(Some(Operand {
+                    value : var.pointer,
+                    type_ : var.type_,
+                    is_constant : false,
+                }))
+            },
+            OperationResult::MemberAccess(access) => {
+                let object = match self.lower_address(&access.object)? {
+                    Some(object) => object,
+                    None => return Ok(None),
+                };
+                let (index, field_type) = field_of(&object.type_, &access.field)?;
+                let object_type = self.lower_type(&object.type_);
+
+                Ok(Some(Operand {
+                    value : self.builder().field_address(object_type, object.value, index),
+                    type_ : field_type.clone(),
+                    is_constant : false,
+                }))
+            },
+            OperationResult::UnaryOpResult(UnaryOperation{operator : UnaryOperator::Deref, operand, ..}) => {
+                let pointer = self.lower_operation(operand)?;
+                Ok(Some(Operand {
+                    value : pointer.value,
+                    type_ : self.types.type_of(op).clone(),
+                    is_constant : false,
+                }))
+            },
+            OperationResult::Index(index) => {
+                let array = match self.lower_address(&index.object)? {
+                    Some(array) => array,
+                    None => return Ok(None),
+                };
+                let element_type = self.types.type_of(op).clone();
+                self.lower_element_address(&array, &index.index, element_type).map(Some)
+            },
+            _ => Ok(None),
+        }
+    }
+
+    // Like `lower_address`, but values that do not live in memory are
+    // copied to a temporary slot.
+    fn lower_object_address(&mut self, op : &OperationResult) -> Result<Operand, String> {
+        if let Some(address) = self.lower_address(op)? {
+            return Ok(address);
+        }
+
+        let value = self.lower_operation(op)?;
+        let type_mir = self.lower_type(&value.type_);
+        let pointer = self.builder().alloca(type_mir, None);
+        self.lower_store(&value, pointer.clone(), &value.type_);
+
+        Ok(Operand {
+            value : pointer,
+            type_ : value.type_,
+            is_constant : false,
+        })
+    }
+
+    // Returns a pointer to the element `index` of the array or string
+    // `array` points to.
+    fn lower_element_address(&mut self, array : &Operand, index : &OperationResult, element_type : Type) -> Result<Operand, String> {
+        let (len, bytes) = match &array.type_ {
+            Type::Array(_, len) => (Value::int(*len as i64), None),
+            Type::BuiltIn(BuiltInType::String) => {
+                let string = self.lower_load(array.value.clone(), &array.type_);
+                let (bytes, len) = self.lower_string_parts(&string);
+                (len, Some(bytes))
+            },
+            _ => unreachable!("only arrays and strings are indexed"),
+        };
+
+        let index = self.lower_value(index, &Type::BuiltIn(BuiltInType::I64))?;
+
+        if self.options.bounds_check {
+            // Negative indices wrap around to huge unsigned ones
+            let out_of_bounds = self.builder().compare(Comparison::UGe, mir::Type::I64, index.value.clone(), len);
+            let trap_block = self.builder().create_block("oob");
+            let continue_block = self.builder().create_block("inbounds");
+            self.builder().cond_branch(out_of_bounds, trap_block, continue_block);
+            self.builder().switch_to(trap_block);
+            self.builder().trap();
+            self.builder().switch_to(continue_block);
+        }
+
+        let address = match bytes {
+            Some(bytes) => self.builder().offset(mir::Type::I8, bytes, index.value),
+            None => {
+                let array_type = self.lower_type(&array.type_);
+                self.builder().element_address(array_type, array.value.clone(), index.value)
+            },
+        };
+
+        Ok(Operand {
+            value : address,
+            type_ : element_type,
+            is_constant : false,
+        })
+    }
+
+    // Builds an array of type `type_` from its elements.
+    fn lower_array_literal(&mut self, literal : &ArrayLiteral, type_ : &Type) -> Result<Operand, String> {
+        let element_type = match type_ {
+            Type::Array(element_type, _) => element_type,
+            _ => unreachable!("array literals have an array type"),
+        };
+        let type_mir = self.lower_type(type_);
+
+        let mut aggregate = Value::Constant(Constant::Zero);
+        for (i, element) in literal.elements.iter().enumerate() {
+            let element = self.lower_value(element, element_type)?;
+            aggregate = self.builder().insert_value(type_mir.clone(), aggregate, element.value, i);
+        }
+
+        Ok(Operand {
+            value : aggregate,
+            type_ : type_.clone(),
+            is_constant : false,
+        })
+    }
+
+    // Evaluates an operation as a value of type `type_`.
+    fn lower_value(&mut self, op : &OperationResult, type_ : &Type) -> Result<Operand, String> {
+        let value = self.lower_operation(op)?;
+        Ok(self.lower_conversion(&value, type_))
+    }
+
+    // Returns the pointer to the bytes and the length of a string.
+    fn lower_string_parts(&mut self, string : &Operand) -> (Value, Value) {
+        let bytes = self.builder().extract_value(mir::Type::String, string.value.clone(), 0, mir::Type::Ptr);
+        let len = self.builder().extract_value(mir::Type::String, string.value.clone(), 1, mir::Type::I64);
+        (bytes, len)
+    }
+
+    // Widens an `i1` to a `bool`.
+    fn lower_bool(&mut self, condition : Value) -> Operand {
+        Operand {
+            value : self.builder().cast(CastOp::ZExt, mir::Type::I1, condition, mir::Type::I8),
+            type_ : Type::BuiltIn(BuiltInType::Bool),
+            is_constant : false,
+        }
+    }
+
+    // Concatenation and comparison of strings are done by the runtime
+    // (see `runtime/string.c`).
+    fn lower_string_operation(&mut self, op : &BinaryOperator, left : &Operand, right : &Operand) -> Result<Operand, String> {
+        let (left_bytes, left_len) = self.lower_string_parts(left);
+        let (right_bytes, right_len) = self.lower_string_parts(right);
+        let parameters = vec![mir::Type::Ptr, mir::Type::I64, mir::Type::Ptr, mir::Type::I64];
+        let args : Vec<(mir::Type, Value)> = parameters
+            .iter()
+            .cloned()
+            .zip([left_bytes, left_len.clone(), right_bytes, right_len.clone()])
+            .collect();
+
+        match op {
+            BinaryOperator::Plus => {
+                self.module.declare_extern("toy_string_concat", parameters, Some(mir::Type::Ptr));
+                let bytes = self.builder().call(Callee::Extern("toy_string_concat".to_string()), Some(mir::Type::Ptr), args).unwrap();
+                let len = self.builder().binary(BinaryOp::Add, mir::Type::I64, left_len, right_len);
+
+                let partial = self.builder().insert_value(mir::Type::String, Value::Constant(Constant::Undef), bytes, 0);
+                let string = self.builder().insert_value(mir::Type::String, partial, len, 1);
+
+                Ok(Operand {
+                    value : string,
+                    type_ : Type::BuiltIn(BuiltInType::String),
+                    is_constant : false,
+                })
+            },
+            op if op.is_comparison() => {
+                self.module.declare_extern("toy_string_compare", parameters, Some(mir::Type::I32));
+                let order = self.builder().call(Callee::Extern("toy_string_compare".to_string()), Some(mir::Type::I32), args).unwrap();
+
+                let comparison = Lowerer::comparison(op, &BuiltInType::I32);
+                let condition = self.builder().compare(comparison, mir::Type::I32, order, Value::int(0));
+                Ok(self.lower_bool(condition))
+            },
+            _ => unreachable!("unsupported binary operator for strings"),
+        }
+    }
+
+    // The right operand is only evaluated when the left one does not decide
+    // the value, which otherwise flows into the end block unchanged
+    fn lower_short_circuit(&mut self, bin_op : &BinaryOperation) -> Result<Operand, String> {
+        let bool_type = Type::BuiltIn(BuiltInType::Bool);
+        let left = self.lower_value(&bin_op.left, &bool_type)?;
+        let left_block = self.builder().current_block();
+        let condition = self.builder().compare(Comparison::Ne, mir::Type::I8, left.value.clone(), Value::int(0));
+
+        let right_block = self.builder().create_block("logic.rhs");
+        let end_block = self.builder().create_block("logic.end");
+        match bin_op.operator {
+            BinaryOperator::And => self.builder().cond_branch(condition, right_block, end_block),
+            _ => self.builder().cond_branch(condition, end_block, right_block),
+        }
+
+        self.builder().switch_to(right_block);
+        let right = self.lower_value(&bin_op.right, &bool_type)?;
+        let right_end = self.builder().current_block();
+        self.builder().branch(end_block);
+
+        self.builder().switch_to(end_block);
+        Ok(Operand {
+            value : self.builder().phi(mir::Type::I8, vec![(left.value, left_block), (right.value, right_end)]),
+            type_ : bool_type,
+            is_constant : false,
+        })
+    }
+
+    fn lower_unary_operation(&mut self, unary : &UnaryOperation) -> Result<Operand, String> {
+        if let UnaryOperator::AddressOf = unary.operator {
+            let address = self.lower_address(&unary.operand)?.expect("only places have an address");
+            return Ok(Operand {
+                value : address.value,
+                type_ : Type::Pointer(Box::new(address.type_)),
+                is_constant : false,
+            });
+        }
+
+        let value = self.lower_operation(&unary.operand)?;
+        let type_mir = self.lower_type(&value.type_);
+        let result = match (&unary.operator, &value.type_) {
+            (UnaryOperator::Negate, Type::BuiltIn(t)) if t.is_float() => self.builder().negate(type_mir, value.value),
+            (UnaryOperator::Negate, _) => self.builder().binary(BinaryOp::Sub, type_mir, Value::int(0), value.value),
+            (UnaryOperator::Not, Type::BuiltIn(BuiltInType::Bool)) => self.builder().binary(BinaryOp::Xor, type_mir, value.value, Value::int(1)),
+            (UnaryOperator::Not, _) => self.builder().binary(BinaryOp::Xor, type_mir, value.value, Value::int(-1)),
+            _ => unreachable!("unary operators are checked by `check_types`"),
+        };
+
+        Ok(Operand {
+            value : result,
+            type_ : value.type_,
+            is_constant : false,
+        })
+    }
+
+    fn lower_call(&mut self, fun : &ApplyFunction) -> Result<Option<Operand>, String> {
+        if let (Some(receiver), true) = (&fun.receiver, fun.function_id == method_name("string", "len")) {
+            let string = self.lower_operation(receiver)?;
+            let (_, len) = self.lower_string_parts(&string);
+            return Ok(Some(Operand {
+                value : len,
+                type_ : Type::BuiltIn(BuiltInType::I64),
+                is_constant : false,
+            }));
+        }
+
+        let (arg_types, return_type) = self.functions[&fun.function_id].clone();
+
+        let mut args : Vec<(mir::Type, Value)> = vec![];
+        if let Some(receiver) = &fun.receiver {
+            let object = self.lower_object_address(receiver)?;
+            args.push((mir::Type::Ptr, object.value));
+        }
+        for (arg, type_) in fun.arguments.iter().zip(arg_types.iter()) {
+            let value = self.lower_value(arg, type_)?;
+            args.push((self.lower_type(type_), value.value));
+        }
+
+        let return_type_mir = return_type.as_ref().map(|type_| self.lower_type(type_));
+        let result = self.builder().call(Callee::Function(fun.function_id.clone()), return_type_mir, args);
+
+        Ok(result.zip(return_type).map(|(value, type_)| Operand {
+            value,
+            type_,
+            is_constant : false,
+        }))
+    }
+
+    fn lower_operation(&mut self, op : &OperationResult) -> Result<Operand, String> {
+        if let Some(address) = self.lower_address(op)? {
+            return Ok(self.lower_load(address.value, &address.type_));
+        }
+
+        match op {
+            OperationResult::BinOpResult(bin_op) => {
+                if let BinaryOperator::Assign = bin_op.operator {
+                    let address = self.lower_address(&bin_op.left)?.expect("only places are assigned");
+                    let value = self.lower_value(&bin_op.right, &address.type_)?;
+                    self.lower_store(&value, address.value, &address.type_);
+                    return Ok(Operand {
+                        is_constant : false,
+                        ..value
+                    });
+                }
+                if let BinaryOperator::And | BinaryOperator::Or = bin_op.operator {
+                    return self.lower_short_circuit(bin_op);
+                }
+
+                let type_ = self.types.operand_type(bin_op).clone();
+                let left = self.lower_value(&bin_op.left, &type_)?;
+                let right = self.lower_value(&bin_op.right, &type_)?;
+                let built_in_type = match &type_ {
+                    Type::BuiltIn(t) => t.clone(),
+                    // Pointers compare like unsigned integers
+                    Type::Pointer(_) => BuiltInType::USize,
+                    Type::Enum(_) => BuiltInType::I32,
+                    _ => unreachable!("binary operators are checked by `check_types`"),
+                };
+
+                if built_in_type == BuiltInType::String {
+                    return self.lower_string_operation(&bin_op.operator, &left, &right);
+                }
+
+                let type_mir = self.lower_type(&type_);
+                if bin_op.operator.is_comparison() {
+                    let comparison = Lowerer::comparison(&bin_op.operator, &built_in_type);
+                    let condition = self.builder().compare(comparison, type_mir, left.value, right.value);
+                    return Ok(self.lower_bool(condition));
+                }
+
+                let operator = Lowerer::operator(&bin_op.operator, &built_in_type);
+                Ok(Operand {
+                    value : self.builder().binary(operator, type_mir, left.value, right.value),
+                    type_,
+                    is_constant : false,
+                })
+            },
+            OperationResult::UnaryOpResult(unary) => self.lower_unary_operation(unary),
+            OperationResult::Literal(Literal::String(text)) => Ok(Operand {
+                value : Value::Constant(Constant::String(self.module.string(text))),
+                type_ : Type::BuiltIn(BuiltInType::String),
+                is_constant : false,
+            }),
+            OperationResult::Literal(lit) => Lowerer::literal_to_operand(lit),
+            OperationResult::EnumValue(value) => Ok(Lowerer::enum_value_to_operand(value)),
+            OperationResult::FuncResult(fun) => Ok(self.lower_call(fun)?.expect("calls used as values return one")),
+            OperationResult::MemberAccess(access) => {
+                // The object is not in memory (e.g. a returned struct)
+                let object = self.lower_operation(&access.object)?;
+                let (index, field_type) = field_of(&object.type_, &access.field)?;
+                let object_type = self.lower_type(&object.type_);
+                let field_type_mir = self.lower_type(field_type);
+
+                Ok(Operand {
+                    value : self.builder().extract_value(object_type, object.value, index, field_type_mir),
+                    type_ : field_type.clone(),
+                    is_constant : false,
+                })
+            },
+            OperationResult::StructLiteral(literal) => {
+                let type_ = self.types.type_of(op).clone();
+                let type_mir = self.lower_type(&type_);
+
+                // Fields are evaluated in source order and inserted one by one
+                let mut aggregate = Value::Constant(Constant::Zero);
+                for init in &literal.fields {
+                    let (index, field_type) = field_of(&type_, &init.field)?;
+                    let value = self.lower_value(&init.value, field_type)?;
+                    aggregate = self.builder().insert_value(type_mir.clone(), aggregate, value.value, index);
+                }
+
+                Ok(Operand {
+                    value : aggregate,
+                    type_,
+                    is_constant : false,
+                })
+            },
+            OperationResult::Index(index) => {
+                // The array is not in memory (e.g. a returned array)
+                let array = self.lower_object_address(&index.object)?;
+                let element_type = self.types.type_of(op).clone();
+                let address = self.lower_element_address(&array, &index.index, element_type)?;
+                Ok(self.lower_load(address.value, &address.type_))
+            },
+            OperationResult::ArrayLiteral(literal) => {
+                let type_ = self.types.type_of(op).clone();
+                self.lower_array_literal(literal, &type_)
+            },
+            OperationResult::Identifier(_) => unreachable!("identifiers are always addressable"),
+        }
+    }
+}
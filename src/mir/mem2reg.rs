@@ -0,0 +1,163 @@
+use std::collections::HashMap;
+
+use super::mir::*;
+use super::dominators::Dominators;
+use super::dead_code::remove_unreachable_blocks;
+
+// Promotes allocas that are only loaded from and stored to, as a whole, to
+// registers. Phis are placed on the iterated dominance frontiers of the
+// stores, then loads are replaced by the value stored last on the path
+// through the dominator tree. Loads before any store read `undef`.
+pub fn promote_allocas(function : &mut Function) -> bool {
+    let changed = remove_unreachable_blocks(function);
+
+    let allocas = promotable_allocas(function);
+    if allocas.is_empty() {
+        return changed;
+    }
+    let indices : HashMap<Register, usize> = allocas
+        .iter()
+        .enumerate()
+        .map(|(i, (alloca, _))| (*alloca, i))
+        .collect();
+
+    let dominators = Dominators::new(function);
+    let frontiers = dominators.frontiers(function);
+
+    // Blocks where each alloca gets a value
+    let mut definitions = vec![vec![]; allocas.len()];
+    for (i, block) in function.blocks.iter().enumerate() {
+        for instruction in &block.instructions {
+            let alloca = match &instruction.kind {
+                InstructionKind::Alloca(_) => instruction.result,
+                InstructionKind::Store{pointer, ..} => pointer.as_register(),
+                _ => None,
+            };
+            if let Some(&index) = alloca.as_ref().and_then(|alloca| indices.get(alloca)) {
+                if !definitions[index].contains(&BlockId(i)) {
+                    definitions[index].push(BlockId(i));
+                }
+            }
+        }
+    }
+
+    // `phis[b]` are the allocas with a phi at the start of `b`, in order
+    let mut phis : Vec<Vec<usize>> = vec![vec![]; function.blocks.len()];
+    for (index, blocks) in definitions.into_iter().enumerate() {
+        let mut worklist = blocks;
+        while let Some(block) = worklist.pop() {
+            for frontier in &frontiers[block.0] {
+                if !phis[frontier.0].contains(&index) {
+                    phis[frontier.0].push(index);
+                    worklist.push(*frontier);
+                }
+            }
+        }
+    }
+
+    for (block, allocas_with_phi) in phis.iter().enumerate() {
+        let mut instructions = vec![];
+        for index in allocas_with_phi {
+            let (alloca, type_) = &allocas[*index];
+            let name = function.register(*alloca).name.clone();
+            let result = function.new_register(type_.clone(), &name);
+            instructions.push(Instruction {
+                result : Some(result),
+                kind : InstructionKind::Phi {
+                    type_ : type_.clone(),
+                    incoming : vec![],
+                },
+                location : None,
+            });
+        }
+        instructions.append(&mut function.blocks[block].instructions);
+        function.blocks[block].instructions = instructions;
+    }
+
+    let mut replacements : HashMap<Register, Value> = HashMap::new();
+    let children = dominators.children();
+    let mut stack = vec![(BlockId(0), vec![Value::Constant(Constant::Undef); allocas.len()])];
+    while let Some((block, mut values)) = stack.pop() {
+        for (i, index) in phis[block.0].iter().enumerate() {
+            values[*index] = Value::Register(function.block(block).instructions[i].result.unwrap());
+        }
+
+        let instructions = std::mem::take(&mut function.blocks[block.0].instructions);
+        for instruction in instructions {
+            match &instruction.kind {
+                InstructionKind::Alloca(_) if indices.contains_key(&instruction.result.unwrap()) => {
+                    values[indices[&instruction.result.unwrap()]] = Value::Constant(Constant::Undef);
+                },
+                InstructionKind::Store{value, pointer : Value::Register(pointer), ..} if indices.contains_key(pointer) => {
+                    let mut value = value.clone();
+                    while let Some(replacement) = value.as_register().and_then(|register| replacements.get(&register)) {
+                        value = replacement.clone();
+                    }
+                    values[indices[pointer]] = value;
+                },
+                InstructionKind::Load{pointer : Value::Register(pointer), ..} if indices.contains_key(pointer) => {
+                    replacements.insert(instruction.result.unwrap(), values[indices[pointer]].clone());
+                },
+                _ => function.blocks[block.0].instructions.push(instruction),
+            }
+        }
+
+        let mut successors = vec![];
+        for successor in function.block(block).successors() {
+            if !successors.contains(&successor) {
+                successors.push(successor);
+            }
+        }
+        for successor in successors {
+            for (i, index) in phis[successor.0].iter().enumerate() {
+                if let InstructionKind::Phi{incoming, ..} = &mut function.blocks[successor.0].instructions[i].kind {
+                    incoming.push((values[*index].clone(), block));
+                }
+            }
+        }
+
+        for child in children[block.0].iter().rev() {
+            stack.push((*child, values.clone()));
+        }
+    }
+
+    function.replace_uses(&replacements);
+    function.remove_dead_variables();
+    true
+}
+
+// Allocas whose address is only used by loads and stores of their type
+fn promotable_allocas(function : &Function) -> Vec<(Register, Type)> {
+    let mut allocas = vec![];
+    for block in &function.blocks {
+        for instruction in &block.instructions {
+            if let InstructionKind::Alloca(type_) = &instruction.kind {
+                allocas.push((instruction.result.unwrap(), type_.clone()));
+            }
+        }
+    }
+
+    let mut escaping = vec![];
+    for block in &function.blocks {
+        for instruction in &block.instructions {
+            let (pointer, type_, others) = match &instruction.kind {
+                InstructionKind::Load{type_, pointer} => (Some(pointer), Some(type_), vec![]),
+                InstructionKind::Store{type_, value, pointer} => (Some(pointer), Some(type_), vec![value]),
+                kind => (None, None, kind.operands()),
+            };
+
+            if let (Some(Value::Register(pointer)), Some(type_)) = (pointer, type_) {
+                if !allocas.iter().any(|(alloca, alloca_type)| alloca == pointer && alloca_type == type_) {
+                    escaping.push(*pointer);
+                }
+            }
+            escaping.extend(others.into_iter().filter_map(Value::as_register));
+        }
+        if let Some(terminator) = &block.terminator {
+            escaping.extend(terminator.operands().into_iter().filter_map(Value::as_register));
+        }
+    }
+
+    allocas.retain(|(alloca, _)| !escaping.contains(alloca));
+    allocas
+}
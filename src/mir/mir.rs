@@ -0,0 +1,714 @@
+use std::collections::HashMap;
+
+use crate::target::Target;
+use super::debug_info::{Location, DebugInfo, FunctionDebugInfo};
+
+// Integers are signless, operations that care about the sign (division,
+// comparisons, extensions...) say how they interpret their operands.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    I1,
+    I8,
+    I16,
+    I32,
+    I64,
+    F32,
+    F64,
+    Ptr,
+    // Pointer to the bytes and length
+    String,
+    Struct(String),
+    Array(Box<Type>, usize),
+}
+
+impl Type {
+    pub fn int(width : usize) -> Option<Type> {
+        match width {
+            1 => Some(Type::I1),
+            8 => Some(Type::I8),
+            16 => Some(Type::I16),
+            32 => Some(Type::I32),
+            64 => Some(Type::I64),
+            _ => None,
+        }
+    }
+
+    pub fn is_int(&self) -> bool {
+        matches!(self, Type::I1 | Type::I8 | Type::I16 | Type::I32 | Type::I64)
+    }
+
+    pub fn is_float(&self) -> bool {
+        matches!(self, Type::F32 | Type::F64)
+    }
+
+    pub fn is_aggregate(&self) -> bool {
+        matches!(self, Type::String | Type::Struct(_) | Type::Array(..))
+    }
+
+    // Width in bits of integers and floats
+    pub fn width(&self) -> usize {
+        match self {
+            Type::I1 => 1,
+            Type::I8 => 8,
+            Type::I16 => 16,
+            Type::I32 | Type::F32 => 32,
+            _ => 64,
+        }
+    }
+}
+
+impl std::fmt::Display for Type {
+    fn fmt(&self, f : &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Type::I1 => write!(f, "i1"),
+            Type::I8 => write!(f, "i8"),
+            Type::I16 => write!(f, "i16"),
+            Type::I32 => write!(f, "i32"),
+            Type::I64 => write!(f, "i64"),
+            Type::F32 => write!(f, "f32"),
+            Type::F64 => write!(f, "f64"),
+            Type::Ptr => write!(f, "ptr"),
+            Type::String => write!(f, "string"),
+            Type::Struct(id) => write!(f, "%{}", id),
+            Type::Array(element_type, len) => write!(f, "[{} x {}]", len, element_type),
+        }
+    }
+}
+
+/*******************/
+/*******************/
+/*******************/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Register(pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockId(pub usize);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Constant {
+    Int(i64),
+    Float(f64),
+    Null,
+    // Every bit zero, for any type
+    Zero,
+    Undef,
+    // String literal of the module
+    String(usize),
+    // Address of a global variable
+    Global(String),
+    // Fields of a struct or elements of an array
+    Aggregate(Vec<Constant>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Register(Register),
+    Constant(Constant),
+}
+
+impl Value {
+    pub fn int(value : i64) -> Value {
+        Value::Constant(Constant::Int(value))
+    }
+
+    pub fn as_register(&self) -> Option<Register> {
+        match self {
+            Value::Register(register) => Some(*register),
+            Value::Constant(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    SDiv,
+    UDiv,
+    SRem,
+    URem,
+    And,
+    Or,
+    Xor,
+    Shl,
+    AShr,
+    LShr,
+    FAdd,
+    FSub,
+    FMul,
+    FDiv,
+    FRem,
+}
+
+impl BinaryOp {
+    pub fn to_string<'b>(&self) -> &'b str {
+        match self {
+            BinaryOp::Add => "add",
+            BinaryOp::Sub => "sub",
+            BinaryOp::Mul => "mul",
+            BinaryOp::SDiv => "sdiv",
+            BinaryOp::UDiv => "udiv",
+            BinaryOp::SRem => "srem",
+            BinaryOp::URem => "urem",
+            BinaryOp::And => "and",
+            BinaryOp::Or => "or",
+            BinaryOp::Xor => "xor",
+            BinaryOp::Shl => "shl",
+            BinaryOp::AShr => "ashr",
+            BinaryOp::LShr => "lshr",
+            BinaryOp::FAdd => "fadd",
+            BinaryOp::FSub => "fsub",
+            BinaryOp::FMul => "fmul",
+            BinaryOp::FDiv => "fdiv",
+            BinaryOp::FRem => "frem",
+        }
+    }
+
+    pub fn is_float(&self) -> bool {
+        matches!(self, BinaryOp::FAdd | BinaryOp::FSub | BinaryOp::FMul | BinaryOp::FDiv | BinaryOp::FRem)
+    }
+}
+
+// Float comparisons are ordered (false if either operand is NaN) except
+// `FUne`, so that `x != x` holds for NaN.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Eq,
+    Ne,
+    SLt,
+    SLe,
+    SGt,
+    SGe,
+    ULt,
+    ULe,
+    UGt,
+    UGe,
+    FOeq,
+    FUne,
+    FOlt,
+    FOle,
+    FOgt,
+    FOge,
+}
+
+impl Comparison {
+    pub fn to_string<'b>(&self) -> &'b str {
+        match self {
+            Comparison::Eq => "eq",
+            Comparison::Ne => "ne",
+            Comparison::SLt => "slt",
+            Comparison::SLe => "sle",
+            Comparison::SGt => "sgt",
+            Comparison::SGe => "sge",
+            Comparison::ULt => "ult",
+            Comparison::ULe => "ule",
+            Comparison::UGt => "ugt",
+            Comparison::UGe => "uge",
+            Comparison::FOeq => "oeq",
+            Comparison::FUne => "une",
+            Comparison::FOlt => "olt",
+            Comparison::FOle => "ole",
+            Comparison::FOgt => "ogt",
+            Comparison::FOge => "oge",
+        }
+    }
+
+    pub fn is_float(&self) -> bool {
+        matches!(self,
+            Comparison::FOeq | Comparison::FUne | Comparison::FOlt |
+            Comparison::FOle | Comparison::FOgt | Comparison::FOge
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CastOp {
+    Trunc,
+    ZExt,
+    SExt,
+    FPTrunc,
+    FPExt,
+    FPToSI,
+    FPToUI,
+    SIToFP,
+    UIToFP,
+}
+
+impl CastOp {
+    pub fn to_string<'b>(&self) -> &'b str {
+        match self {
+            CastOp::Trunc => "trunc",
+            CastOp::ZExt => "zext",
+            CastOp::SExt => "sext",
+            CastOp::FPTrunc => "fptrunc",
+            CastOp::FPExt => "fpext",
+            CastOp::FPToSI => "fptosi",
+            CastOp::FPToUI => "fptoui",
+            CastOp::SIToFP => "sitofp",
+            CastOp::UIToFP => "uitofp",
+        }
+    }
+}
+
+// Functions of the module and extern functions have separate names, so a
+// program may define a function named like one of the runtime
+#[derive(Debug, Clone, PartialEq)]
+pub enum Callee {
+    Function(String),
+    Extern(String),
+}
+
+impl Callee {
+    pub fn name(&self) -> &str {
+        match self {
+            Callee::Function(name) | Callee::Extern(name) => name,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum InstructionKind {
+    Alloca(Type),
+    Load {
+        type_ : Type,
+        pointer : Value,
+    },
+    Store {
+        type_ : Type,
+        value : Value,
+        pointer : Value,
+    },
+    Binary {
+        op : BinaryOp,
+        type_ : Type,
+        left : Value,
+        right : Value,
+    },
+    // Floats only, integers are subtracted from 0
+    Negate {
+        type_ : Type,
+        value : Value,
+    },
+    // Results in an `i1`
+    Compare {
+        comparison : Comparison,
+        type_ : Type,
+        left : Value,
+        right : Value,
+    },
+    Cast {
+        op : CastOp,
+        from : Type,
+        value : Value,
+        to : Type,
+    },
+    // Address of a field of the struct `type_` at `pointer`
+    FieldAddress {
+        type_ : Type,
+        pointer : Value,
+        index : usize,
+    },
+    // Address of an element of the array `type_` at `pointer`, with an
+    // `i64` index
+    ElementAddress {
+        type_ : Type,
+        pointer : Value,
+        index : Value,
+    },
+    // `pointer` advanced by `index` (`i64`) values of `type_`
+    Offset {
+        type_ : Type,
+        pointer : Value,
+        index : Value,
+    },
+    ExtractValue {
+        type_ : Type,
+        aggregate : Value,
+        index : usize,
+    },
+    InsertValue {
+        type_ : Type,
+        aggregate : Value,
+        value : Value,
+        index : usize,
+    },
+    Call {
+        callee : Callee,
+        return_type : Option<Type>,
+        arguments : Vec<(Type, Value)>,
+    },
+    // Only at the start of a block, with a value for each predecessor
+    Phi {
+        type_ : Type,
+        incoming : Vec<(Value, BlockId)>,
+    },
+}
+
+impl InstructionKind {
+    pub fn operands(&self) -> Vec<&Value> {
+        match self {
+            InstructionKind::Alloca(_) => vec![],
+            InstructionKind::Load{pointer, ..} => vec![pointer],
+            InstructionKind::Store{value, pointer, ..} => vec![value, pointer],
+            InstructionKind::Binary{left, right, ..} => vec![left, right],
+            InstructionKind::Negate{value, ..} => vec![value],
+            InstructionKind::Compare{left, right, ..} => vec![left, right],
+            InstructionKind::Cast{value, ..} => vec![value],
+            InstructionKind::FieldAddress{pointer, ..} => vec![pointer],
+            InstructionKind::ElementAddress{pointer, index, ..} => vec![pointer, index],
+            InstructionKind::Offset{pointer, index, ..} => vec![pointer, index],
+            InstructionKind::ExtractValue{aggregate, ..} => vec![aggregate],
+            InstructionKind::InsertValue{aggregate, value, ..} => vec![aggregate, value],
+            InstructionKind::Call{arguments, ..} => arguments.iter().map(|(_, arg)| arg).collect(),
+            InstructionKind::Phi{incoming, ..} => incoming.iter().map(|(value, _)| value).collect(),
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut Value> {
+        match self {
+            InstructionKind::Alloca(_) => vec![],
+            InstructionKind::Load{pointer, ..} => vec![pointer],
+            InstructionKind::Store{value, pointer, ..} => vec![value, pointer],
+            InstructionKind::Binary{left, right, ..} => vec![left, right],
+            InstructionKind::Negate{value, ..} => vec![value],
+            InstructionKind::Compare{left, right, ..} => vec![left, right],
+            InstructionKind::Cast{value, ..} => vec![value],
+            InstructionKind::FieldAddress{pointer, ..} => vec![pointer],
+            InstructionKind::ElementAddress{pointer, index, ..} => vec![pointer, index],
+            InstructionKind::Offset{pointer, index, ..} => vec![pointer, index],
+            InstructionKind::ExtractValue{aggregate, ..} => vec![aggregate],
+            InstructionKind::InsertValue{aggregate, value, ..} => vec![aggregate, value],
+            InstructionKind::Call{arguments, ..} => arguments.iter_mut().map(|(_, arg)| arg).collect(),
+            InstructionKind::Phi{incoming, ..} => incoming.iter_mut().map(|(value, _)| value).collect(),
+        }
+    }
+
+    // Whether removing the instruction when its result is unused changes
+    // the behavior of the program.
+    pub fn has_side_effects(&self) -> bool {
+        matches!(self, InstructionKind::Store{..} | InstructionKind::Call{..})
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    pub result : Option<Register>,
+    pub kind : InstructionKind,
+    // Source of the instruction, if it comes from one
+    pub location : Option<Location>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Terminator {
+    Return(Option<(Type, Value)>),
+    Branch(BlockId),
+    CondBranch {
+        condition : Value,
+        then_block : BlockId,
+        else_block : BlockId,
+    },
+    Switch {
+        type_ : Type,
+        value : Value,
+        default : BlockId,
+        cases : Vec<(i64, BlockId)>,
+    },
+    // Aborts the program
+    Trap,
+    Unreachable,
+}
+
+impl Terminator {
+    pub fn successors(&self) -> Vec<BlockId> {
+        match self {
+            Terminator::Branch(target) => vec![*target],
+            Terminator::CondBranch{then_block, else_block, ..} => vec![*then_block, *else_block],
+            Terminator::Switch{default, cases, ..} => {
+                let mut successors = vec![*default];
+                successors.extend(cases.iter().map(|(_, target)| *target));
+                successors
+            },
+            Terminator::Return(_) | Terminator::Trap | Terminator::Unreachable => vec![],
+        }
+    }
+
+    pub fn operands(&self) -> Vec<&Value> {
+        match self {
+            Terminator::Return(Some((_, value))) => vec![value],
+            Terminator::CondBranch{condition, ..} => vec![condition],
+            Terminator::Switch{value, ..} => vec![value],
+            _ => vec![],
+        }
+    }
+
+    pub fn operands_mut(&mut self) -> Vec<&mut Value> {
+        match self {
+            Terminator::Return(Some((_, value))) => vec![value],
+            Terminator::CondBranch{condition, ..} => vec![condition],
+            Terminator::Switch{value, ..} => vec![value],
+            _ => vec![],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub name : String,
+    pub instructions : Vec<Instruction>,
+    // Only missing while the block is being built
+    pub terminator : Option<Terminator>,
+    pub terminator_location : Option<Location>,
+}
+
+impl Block {
+    pub fn successors(&self) -> Vec<BlockId> {
+        self.terminator
+            .as_ref()
+            .map_or(vec![], |terminator| terminator.successors())
+    }
+
+    // Renames the blocks the terminator branches to and the phis come from
+    pub fn map_blocks(&mut self, map : impl Fn(BlockId) -> BlockId) {
+        for instruction in &mut self.instructions {
+            if let InstructionKind::Phi{incoming, ..} = &mut instruction.kind {
+                for (_, from) in incoming {
+                    *from = map(*from);
+                }
+            }
+        }
+
+        match &mut self.terminator {
+            Some(Terminator::Branch(target)) => *target = map(*target),
+            Some(Terminator::CondBranch{then_block, else_block, ..}) => {
+                *then_block = map(*then_block);
+                *else_block = map(*else_block);
+            },
+            Some(Terminator::Switch{default, cases, ..}) => {
+                *default = map(*default);
+                for (_, target) in cases {
+                    *target = map(*target);
+                }
+            },
+            _ => {},
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RegisterInfo {
+    pub name : String,
+    pub type_ : Type,
+}
+
+// The first block is the entry point and cannot be branched to.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name : String,
+    pub parameters : Vec<Register>,
+    pub return_type : Option<Type>,
+    pub registers : Vec<RegisterInfo>,
+    pub blocks : Vec<Block>,
+    pub debug_info : Option<FunctionDebugInfo>,
+}
+
+impl Function {
+    pub fn register(&self, register : Register) -> &RegisterInfo {
+        &self.registers[register.0]
+    }
+
+    pub fn block(&self, block : BlockId) -> &Block {
+        &self.blocks[block.0]
+    }
+
+    pub fn parameter_types(&self) -> Vec<Type> {
+        self.parameters
+            .iter()
+            .map(|parameter| self.register(*parameter).type_.clone())
+            .collect()
+    }
+
+    // Constants have no type of their own
+    pub fn value_type(&self, value : &Value) -> Option<&Type> {
+        match value {
+            Value::Register(register) => Some(&self.register(*register).type_),
+            Value::Constant(_) => None,
+        }
+    }
+
+    // Adds a register for a pass, with a numbered suffix if the name is
+    // taken by another register or a block.
+    pub fn new_register(&mut self, type_ : Type, name : &str) -> Register {
+        let taken = |name : &str| {
+            self.registers.iter().any(|register| register.name == name) ||
+                self.blocks.iter().any(|block| block.name == name)
+        };
+
+        let mut unique = name.to_string();
+        let mut count = 0;
+        while taken(&unique) {
+            count += 1;
+            unique = format!("{}.{}", name, count);
+        }
+
+        self.registers.push(RegisterInfo {
+            name : unique,
+            type_,
+        });
+        Register(self.registers.len()-1)
+    }
+
+    // Replaces the uses of registers, following chains of replacements.
+    pub fn replace_uses(&mut self, replacements : &HashMap<Register, Value>) {
+        if replacements.is_empty() {
+            return;
+        }
+
+        let replace = |value : &mut Value| {
+            while let Some(replacement) = value.as_register().and_then(|register| replacements.get(&register)) {
+                *value = replacement.clone();
+            }
+        };
+
+        for block in &mut self.blocks {
+            for instruction in &mut block.instructions {
+                instruction.kind.operands_mut().into_iter().for_each(replace);
+            }
+            if let Some(terminator) = &mut block.terminator {
+                terminator.operands_mut().into_iter().for_each(replace);
+            }
+        }
+    }
+
+    // Forgets the variables whose alloca was removed by a pass
+    pub fn remove_dead_variables(&mut self) {
+        let debug_info = match &mut self.debug_info {
+            Some(debug_info) => debug_info,
+            None => return,
+        };
+
+        let allocas : Vec<Register> = self.blocks
+            .iter()
+            .flat_map(|block| &block.instructions)
+            .filter(|instruction| matches!(instruction.kind, InstructionKind::Alloca(_)))
+            .filter_map(|instruction| instruction.result)
+            .collect();
+        debug_info.variables.retain(|variable| {
+            allocas.contains(&variable.register) || self.parameters.contains(&variable.register)
+        });
+    }
+
+    pub fn predecessors(&self) -> Vec<Vec<BlockId>> {
+        let mut predecessors = vec![vec![]; self.blocks.len()];
+        for (i, block) in self.blocks.iter().enumerate() {
+            for successor in block.successors() {
+                if !predecessors[successor.0].contains(&BlockId(i)) {
+                    predecessors[successor.0].push(BlockId(i));
+                }
+            }
+        }
+        predecessors
+    }
+}
+
+// Functions defined outside of the module, e.g. by the runtime
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExternFunction {
+    pub name : String,
+    pub parameters : Vec<Type>,
+    pub return_type : Option<Type>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StructType {
+    pub name : String,
+    pub fields : Vec<Type>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Global {
+    pub name : String,
+    pub type_ : Type,
+    pub init_value : Constant,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Module {
+    pub target : Target,
+    pub structs : Vec<StructType>,
+    pub globals : Vec<Global>,
+    pub strings : Vec<String>,
+    pub functions : Vec<Function>,
+    pub externs : Vec<ExternFunction>,
+    pub debug_info : Option<DebugInfo>,
+}
+
+impl Module {
+    pub fn new(target : Target) -> Module {
+        Module {
+            target,
+            structs : vec![],
+            globals : vec![],
+            strings : vec![],
+            functions : vec![],
+            externs : vec![],
+            debug_info : None,
+        }
+    }
+
+    pub fn struct_type(&self, name : &str) -> Option<&StructType> {
+        self.structs.iter().find(|struct_type| struct_type.name == name)
+    }
+
+    pub fn global(&self, name : &str) -> Option<&Global> {
+        self.globals.iter().find(|global| global.name == name)
+    }
+
+    pub fn function(&self, name : &str) -> Option<&Function> {
+        self.functions.iter().find(|function| function.name == name)
+    }
+
+    pub fn extern_function(&self, name : &str) -> Option<&ExternFunction> {
+        self.externs.iter().find(|function| function.name == name)
+    }
+
+    // Parameters and return type of a function or extern function
+    pub fn signature(&self, callee : &Callee) -> Option<(Vec<Type>, Option<Type>)> {
+        match callee {
+            Callee::Function(name) => self.function(name).map(|function| (function.parameter_types(), function.return_type.clone())),
+            Callee::Extern(name) => self.extern_function(name).map(|function| (function.parameters.clone(), function.return_type.clone())),
+        }
+    }
+
+    // Type of the field or element `index` of an aggregate
+    pub fn field_type(&self, type_ : &Type, index : usize) -> Option<Type> {
+        match type_ {
+            Type::String => [Type::Ptr, Type::I64].get(index).cloned(),
+            Type::Struct(name) => self.struct_type(name)?.fields.get(index).cloned(),
+            Type::Array(element_type, len) if index < *len => Some(element_type.as_ref().clone()),
+            _ => None,
+        }
+    }
+
+    // Adds a string literal, returns its index.
+    pub fn string(&mut self, text : &str) -> usize {
+        match self.strings.iter().position(|s| s == text) {
+            Some(index) => index,
+            None => {
+                self.strings.push(text.to_string());
+                self.strings.len()-1
+            },
+        }
+    }
+
+    pub fn declare_extern(&mut self, name : &str, parameters : Vec<Type>, return_type : Option<Type>) {
+        if !self.externs.iter().any(|function| function.name == name) {
+            self.externs.push(ExternFunction {
+                name : name.to_string(),
+                parameters,
+                return_type,
+            });
+        }
+    }
+}
@@ -0,0 +1,14 @@
+pub mod mir;
+pub mod debug_info;
+pub mod builder;
+pub mod printer;
+pub mod verifier;
+pub mod lower;
+pub mod dominators;
+pub mod mem2reg;
+pub mod constant_fold;
+pub mod copy_propagation;
+pub mod dead_code;
+pub mod optimize;
+pub mod layout;
+pub mod prelude;
\ No newline at end of file
@@ -0,0 +1,23 @@
+use crate::codegen::OptLevel;
+use super::mir::*;
+use super::mem2reg::promote_allocas;
+use super::constant_fold::fold_constants;
+use super::copy_propagation::propagate_copies;
+use super::dead_code::eliminate_dead_code;
+
+pub fn optimize_module(module : &mut Module, level : OptLevel) {
+    if level == OptLevel::O0 {
+        return;
+    }
+
+    for function in &mut module.functions {
+        promote_allocas(function);
+
+        let mut changed = true;
+        while changed {
+            changed = fold_constants(function);
+            changed |= propagate_copies(function);
+            changed |= eliminate_dead_code(function);
+        }
+    }
+}
@@ -0,0 +1,3 @@
+pub use super::lower::lower_to_mir;
+pub use super::verifier::verify_module;
+pub use super::optimize::optimize_module;
\ No newline at end of file
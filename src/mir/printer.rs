@@ -0,0 +1,247 @@
+use std::fmt::{Display, Formatter, Result};
+
+use super::mir::*;
+
+// Textual form of the MIR, for debugging and tests. Registers are `%name`,
+// globals `@name` and string literals `str.N`.
+
+fn write_constant(f : &mut Formatter, constant : &Constant) -> Result {
+    match constant {
+        Constant::Int(value) => write!(f, "{}", value),
+        Constant::Float(value) => write!(f, "{:?}", value),
+        Constant::Null => write!(f, "null"),
+        Constant::Zero => write!(f, "zero"),
+        Constant::Undef => write!(f, "undef"),
+        Constant::String(index) => write!(f, "str.{}", index),
+        Constant::Global(name) => write!(f, "@{}", name),
+        Constant::Aggregate(elements) => {
+            write!(f, "{{")?;
+            for (i, element) in elements.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write_constant(f, element)?;
+            }
+            write!(f, "}}")
+        },
+    }
+}
+
+impl Display for Constant {
+    fn fmt(&self, f : &mut Formatter) -> Result {
+        write_constant(f, self)
+    }
+}
+
+fn write_value(f : &mut Formatter, function : &Function, value : &Value) -> Result {
+    match value {
+        Value::Register(register) => write!(f, "%{}", function.register(*register).name),
+        Value::Constant(constant) => write_constant(f, constant),
+    }
+}
+
+fn write_instruction(f : &mut Formatter, function : &Function, instruction : &Instruction) -> Result {
+    write!(f, "    ")?;
+    if let Some(result) = instruction.result {
+        write!(f, "%{} = ", function.register(result).name)?;
+    }
+
+    match &instruction.kind {
+        InstructionKind::Alloca(type_) => write!(f, "alloca {}", type_),
+        InstructionKind::Load{type_, pointer} => {
+            write!(f, "load {}, ", type_)?;
+            write_value(f, function, pointer)
+        },
+        InstructionKind::Store{type_, value, pointer} => {
+            write!(f, "store {} ", type_)?;
+            write_value(f, function, value)?;
+            write!(f, ", ")?;
+            write_value(f, function, pointer)
+        },
+        InstructionKind::Binary{op, type_, left, right} => {
+            write!(f, "{} {} ", op.to_string(), type_)?;
+            write_value(f, function, left)?;
+            write!(f, ", ")?;
+            write_value(f, function, right)
+        },
+        InstructionKind::Negate{type_, value} => {
+            write!(f, "neg {} ", type_)?;
+            write_value(f, function, value)
+        },
+        InstructionKind::Compare{comparison, type_, left, right} => {
+            write!(f, "cmp {} {} ", comparison.to_string(), type_)?;
+            write_value(f, function, left)?;
+            write!(f, ", ")?;
+            write_value(f, function, right)
+        },
+        InstructionKind::Cast{op, from, value, to} => {
+            write!(f, "{} {} ", op.to_string(), from)?;
+            write_value(f, function, value)?;
+            write!(f, " to {}", to)
+        },
+        InstructionKind::FieldAddress{type_, pointer, index} => {
+            write!(f, "field_addr {}, ", type_)?;
+            write_value(f, function, pointer)?;
+            write!(f, ", {}", index)
+        },
+        InstructionKind::ElementAddress{type_, pointer, index} => {
+            write!(f, "element_addr {}, ", type_)?;
+            write_value(f, function, pointer)?;
+            write!(f, ", ")?;
+            write_value(f, function, index)
+        },
+        InstructionKind::Offset{type_, pointer, index} => {
+            write!(f, "offset {}, ", type_)?;
+            write_value(f, function, pointer)?;
+            write!(f, ", ")?;
+            write_value(f, function, index)
+        },
+        InstructionKind::ExtractValue{type_, aggregate, index} => {
+            write!(f, "extract {} ", type_)?;
+            write_value(f, function, aggregate)?;
+            write!(f, ", {}", index)
+        },
+        InstructionKind::InsertValue{type_, aggregate, value, index} => {
+            write!(f, "insert {} ", type_)?;
+            write_value(f, function, aggregate)?;
+            write!(f, ", ")?;
+            write_value(f, function, value)?;
+            write!(f, ", {}", index)
+        },
+        InstructionKind::Call{callee, return_type, arguments} => {
+            let callee = match callee {
+                Callee::Function(name) => format!("@{}", name),
+                Callee::Extern(name) => format!("extern @{}", name),
+            };
+            match return_type {
+                Some(type_) => write!(f, "call {} {}(", type_, callee)?,
+                None => write!(f, "call void {}(", callee)?,
+            }
+            for (i, (type_, argument)) in arguments.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{} ", type_)?;
+                write_value(f, function, argument)?;
+            }
+            write!(f, ")")
+        },
+        InstructionKind::Phi{type_, incoming} => {
+            write!(f, "phi {} ", type_)?;
+            for (i, (value, block)) in incoming.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "[")?;
+                write_value(f, function, value)?;
+                write!(f, ", {}]", function.block(*block).name)?;
+            }
+            Ok(())
+        },
+    }?;
+
+    writeln!(f)
+}
+
+fn write_terminator(f : &mut Formatter, function : &Function, terminator : &Terminator) -> Result {
+    write!(f, "    ")?;
+    match terminator {
+        Terminator::Return(None) => write!(f, "ret void"),
+        Terminator::Return(Some((type_, value))) => {
+            write!(f, "ret {} ", type_)?;
+            write_value(f, function, value)
+        },
+        Terminator::Branch(target) => write!(f, "br {}", function.block(*target).name),
+        Terminator::CondBranch{condition, then_block, else_block} => {
+            write!(f, "br ")?;
+            write_value(f, function, condition)?;
+            write!(f, ", {}, {}", function.block(*then_block).name, function.block(*else_block).name)
+        },
+        Terminator::Switch{type_, value, default, cases} => {
+            write!(f, "switch {} ", type_)?;
+            write_value(f, function, value)?;
+            write!(f, ", {} [", function.block(*default).name)?;
+            for (i, (case, target)) in cases.iter().enumerate() {
+                if i > 0 {
+                    write!(f, ", ")?;
+                }
+                write!(f, "{}: {}", case, function.block(*target).name)?;
+            }
+            write!(f, "]")
+        },
+        Terminator::Trap => write!(f, "trap"),
+        Terminator::Unreachable => write!(f, "unreachable"),
+    }?;
+
+    writeln!(f)
+}
+
+impl Display for Function {
+    fn fmt(&self, f : &mut Formatter) -> Result {
+        write!(f, "fn @{}(", self.name)?;
+        for (i, parameter) in self.parameters.iter().enumerate() {
+            if i > 0 {
+                write!(f, ", ")?;
+            }
+            let parameter = self.register(*parameter);
+            write!(f, "{} %{}", parameter.type_, parameter.name)?;
+        }
+        write!(f, ")")?;
+        if let Some(type_) = &self.return_type {
+            write!(f, " -> {}", type_)?;
+        }
+        writeln!(f, " {{")?;
+
+        for block in &self.blocks {
+            writeln!(f, "{}:", block.name)?;
+            for instruction in &block.instructions {
+                write_instruction(f, self, instruction)?;
+            }
+            match &block.terminator {
+                Some(terminator) => write_terminator(f, self, terminator)?,
+                None => writeln!(f, "    <no terminator>")?,
+            }
+        }
+
+        writeln!(f, "}}")
+    }
+}
+
+impl Display for Module {
+    fn fmt(&self, f : &mut Formatter) -> Result {
+        for struct_type in &self.structs {
+            let fields : Vec<String> = struct_type.fields.iter().map(|field| field.to_string()).collect();
+            writeln!(f, "struct %{} = {{ {} }}", struct_type.name, fields.join(", "))?;
+        }
+
+        for global in &self.globals {
+            write!(f, "global @{} : {} = ", global.name, global.type_)?;
+            write_constant(f, &global.init_value)?;
+            writeln!(f)?;
+        }
+
+        for (i, text) in self.strings.iter().enumerate() {
+            writeln!(f, "str.{} = {:?}", i, text)?;
+        }
+
+        for function in &self.externs {
+            let parameters : Vec<String> = function.parameters.iter().map(|parameter| parameter.to_string()).collect();
+            write!(f, "extern fn @{}({})", function.name, parameters.join(", "))?;
+            if let Some(type_) = &function.return_type {
+                write!(f, " -> {}", type_)?;
+            }
+            writeln!(f)?;
+        }
+
+        let mut separate = !(self.structs.is_empty() && self.globals.is_empty() && self.strings.is_empty() && self.externs.is_empty());
+        for function in &self.functions {
+            if separate {
+                writeln!(f)?;
+            }
+            write!(f, "{}", function)?;
+            separate = true;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,416 @@
+use super::mir::*;
+use super::dominators::Dominators;
+
+// Checks that a module is well formed: every block is terminated, branches
+// go to existing blocks, registers are defined once and before they are
+// used, operands have the types the instructions expect, and variables of
+// the debug info are held by allocas or parameters.
+pub fn verify_module(module : &Module) -> Result<(), String> {
+    for struct_type in &module.structs {
+        for field in &struct_type.fields {
+            check_type(module, field).map_err(|e| format!("In struct {}: {}", struct_type.name, e))?;
+        }
+    }
+
+    for global in &module.globals {
+        check_type(module, &global.type_)
+            .and_then(|_| check_constant(module, &global.init_value, &global.type_))
+            .map_err(|e| format!("In global {}: {}", global.name, e))?;
+    }
+
+    for (i, function) in module.functions.iter().enumerate() {
+        if module.functions[..i].iter().any(|other| other.name == function.name) {
+            return Err(format!("Function {} defined twice", function.name));
+        }
+
+        FunctionVerifier::new(module, function)
+            .verify()
+            .map_err(|e| format!("In function {}: {}", function.name, e))?;
+    }
+
+    Ok(())
+}
+
+fn check_type(module : &Module, type_ : &Type) -> Result<(), String> {
+    match type_ {
+        Type::Struct(name) if module.struct_type(name).is_none() => Err(format!("Struct {} not defined", name)),
+        Type::Array(element_type, _) => check_type(module, element_type),
+        _ => Ok(()),
+    }
+}
+
+fn check_constant(module : &Module, constant : &Constant, type_ : &Type) -> Result<(), String> {
+    let valid = match constant {
+        Constant::Int(_) => type_.is_int(),
+        Constant::Float(_) => type_.is_float(),
+        Constant::Null => *type_ == Type::Ptr,
+        Constant::Zero | Constant::Undef => true,
+        Constant::String(index) => {
+            if *index >= module.strings.len() {
+                return Err(format!("String constant {} not defined", index));
+            }
+            *type_ == Type::String
+        },
+        Constant::Global(name) => {
+            if module.global(name).is_none() {
+                return Err(format!("Global {} not defined", name));
+            }
+            *type_ == Type::Ptr
+        },
+        Constant::Aggregate(elements) => {
+            let len = match type_ {
+                Type::Struct(name) => module.struct_type(name).map_or(0, |struct_type| struct_type.fields.len()),
+                Type::Array(_, len) => *len,
+                _ => return Err(format!("Aggregate constant of type {}", type_)),
+            };
+            if elements.len() != len {
+                return Err(format!("Aggregate constant of type {} has {} elements", type_, elements.len()));
+            }
+            for (i, element) in elements.iter().enumerate() {
+                check_constant(module, element, &module.field_type(type_, i).unwrap())?;
+            }
+            true
+        },
+    };
+
+    if !valid {
+        return Err(format!("Constant {:?} is not a valid {}", constant, type_));
+    }
+    Ok(())
+}
+
+/****************************/
+/****************************/
+/****************************/
+
+// Where a register is defined: a parameter or the instruction `index` of
+// a block.
+#[derive(Clone, Copy)]
+enum Definition {
+    Parameter,
+    Instruction(BlockId, usize),
+}
+
+struct FunctionVerifier<'a> {
+    module : &'a Module,
+    function : &'a Function,
+    definitions : Vec<Option<Definition>>,
+    predecessors : Vec<Vec<BlockId>>,
+    dominators : Option<Dominators>,
+}
+
+impl<'a> FunctionVerifier<'a> {
+    fn new(module : &'a Module, function : &'a Function) -> FunctionVerifier<'a> {
+        FunctionVerifier {
+            module,
+            function,
+            definitions : vec![None; function.registers.len()],
+            predecessors : vec![],
+            dominators : None,
+        }
+    }
+
+    fn verify(&mut self) -> Result<(), String> {
+        if self.function.blocks.is_empty() {
+            return Err("No entry block".to_string());
+        }
+
+        for block in &self.function.blocks {
+            let terminator = block.terminator
+                .as_ref()
+                .ok_or_else(|| format!("Block {} is not terminated", block.name))?;
+            for successor in terminator.successors() {
+                if successor.0 >= self.function.blocks.len() {
+                    return Err(format!("Block {} branches to an undefined block", block.name));
+                }
+                if successor.0 == 0 {
+                    return Err(format!("Block {} branches to the entry block", block.name));
+                }
+            }
+        }
+
+        self.predecessors = self.function.predecessors();
+        self.dominators = Some(Dominators::new(self.function));
+        self.collect_definitions()?;
+
+        if let Some(type_) = &self.function.return_type {
+            check_type(self.module, type_)?;
+        }
+        for (i, block) in self.function.blocks.iter().enumerate() {
+            let block_id = BlockId(i);
+            for (index, instruction) in block.instructions.iter().enumerate() {
+                self.verify_instruction(block_id, index, instruction)
+                    .map_err(|e| format!("In block {}, instruction {}: {}", block.name, index, e))?;
+            }
+            self.verify_terminator(block_id, block.terminator.as_ref().unwrap())
+                .map_err(|e| format!("In block {}, terminator: {}", block.name, e))?;
+        }
+
+        self.verify_debug_variables()
+    }
+
+    // Variables live in an alloca or a parameter
+    fn verify_debug_variables(&self) -> Result<(), String> {
+        let variables = self.function.debug_info.iter().flat_map(|debug_info| &debug_info.variables);
+        for variable in variables {
+            let valid = match self.definitions.get(variable.register.0) {
+                Some(Some(Definition::Parameter)) => true,
+                Some(Some(Definition::Instruction(block, index))) => matches!(
+                    self.function.block(*block).instructions[*index].kind,
+                    InstructionKind::Alloca(_)
+                ),
+                _ => false,
+            };
+            if !valid {
+                return Err(format!("Variable {} is not held by an alloca or a parameter", variable.name));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn collect_definitions(&mut self) -> Result<(), String> {
+        let function = self.function;
+        let definitions = &mut self.definitions;
+        let mut define = |register : Register, definition : Definition| {
+            match definitions.get(register.0) {
+                None => Err(format!("Register {} not declared", register.0)),
+                Some(Some(_)) => Err(format!("Register %{} defined twice", function.register(register).name)),
+                Some(None) => {
+                    definitions[register.0] = Some(definition);
+                    Ok(())
+                },
+            }
+        };
+
+        for parameter in &function.parameters {
+            define(*parameter, Definition::Parameter)?;
+        }
+        for (i, block) in function.blocks.iter().enumerate() {
+            for (index, instruction) in block.instructions.iter().enumerate() {
+                if let Some(result) = instruction.result {
+                    define(result, Definition::Instruction(BlockId(i), index))?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    // Whether the value of `register` is available before the instruction
+    // `index` of `block`. Unreachable blocks may use anything.
+    fn is_available(&self, register : Register, block : BlockId, index : usize) -> bool {
+        let dominators = self.dominators.as_ref().unwrap();
+        if !dominators.is_reachable(block) {
+            return true;
+        }
+
+        match self.definitions[register.0] {
+            None => false,
+            Some(Definition::Parameter) => true,
+            Some(Definition::Instruction(def_block, def_index)) if def_block == block => def_index < index,
+            Some(Definition::Instruction(def_block, _)) => dominators.dominates(def_block, block),
+        }
+    }
+
+    fn check_value(&self, value : &Value, type_ : &Type, block : BlockId, index : usize) -> Result<(), String> {
+        match value {
+            Value::Register(register) => {
+                let info = self.function.registers
+                    .get(register.0)
+                    .ok_or_else(|| format!("Register {} not declared", register.0))?;
+                if !self.is_available(*register, block, index) {
+                    return Err(format!("%{} used before its definition", info.name));
+                }
+                if info.type_ != *type_ {
+                    return Err(format!("%{} has type {}, expected {}", info.name, info.type_, type_));
+                }
+                Ok(())
+            },
+            Value::Constant(constant) => check_constant(self.module, constant, type_),
+        }
+    }
+
+    fn field_type(&self, type_ : &Type, index : usize) -> Result<Type, String> {
+        self.module
+            .field_type(type_, index)
+            .ok_or_else(|| format!("{} has no field {}", type_, index))
+    }
+
+    // Type of the result of an instruction, `None` if it has none.
+    fn result_type(&self, kind : &InstructionKind) -> Result<Option<Type>, String> {
+        Ok(match kind {
+            InstructionKind::Alloca(_) => Some(Type::Ptr),
+            InstructionKind::Load{type_, ..} => Some(type_.clone()),
+            InstructionKind::Store{..} => None,
+            InstructionKind::Binary{type_, ..} => Some(type_.clone()),
+            InstructionKind::Negate{type_, ..} => Some(type_.clone()),
+            InstructionKind::Compare{..} => Some(Type::I1),
+            InstructionKind::Cast{to, ..} => Some(to.clone()),
+            InstructionKind::FieldAddress{..} => Some(Type::Ptr),
+            InstructionKind::ElementAddress{..} => Some(Type::Ptr),
+            InstructionKind::Offset{..} => Some(Type::Ptr),
+            InstructionKind::ExtractValue{type_, index, ..} => Some(self.field_type(type_, *index)?),
+            InstructionKind::InsertValue{type_, ..} => Some(type_.clone()),
+            InstructionKind::Call{return_type, ..} => return_type.clone(),
+            InstructionKind::Phi{type_, ..} => Some(type_.clone()),
+        })
+    }
+
+    fn verify_instruction(&self, block : BlockId, index : usize, instruction : &Instruction) -> Result<(), String> {
+        match (self.result_type(&instruction.kind)?, instruction.result) {
+            (Some(type_), Some(result)) => {
+                let info = self.function.register(result);
+                if info.type_ != type_ {
+                    return Err(format!("Result %{} has type {}, expected {}", info.name, info.type_, type_));
+                }
+            },
+            (None, None) => {},
+            (Some(_), None) => return Err("Missing result register".to_string()),
+            (None, Some(_)) => return Err("Instruction has no result".to_string()),
+        }
+
+        match &instruction.kind {
+            // Slots are allocated once per call
+            InstructionKind::Alloca(_) if block.0 != 0 => return Err("Alloca outside the entry block".to_string()),
+            InstructionKind::Alloca(type_) => check_type(self.module, type_)?,
+            InstructionKind::Load{type_, pointer} => {
+                check_type(self.module, type_)?;
+                self.check_value(pointer, &Type::Ptr, block, index)?;
+            },
+            InstructionKind::Store{type_, value, pointer} => {
+                self.check_value(value, type_, block, index)?;
+                self.check_value(pointer, &Type::Ptr, block, index)?;
+            },
+            InstructionKind::Binary{op, type_, left, right} => {
+                if op.is_float() != type_.is_float() || !(type_.is_int() || type_.is_float()) {
+                    return Err(format!("Cannot apply {} to {}", op.to_string(), type_));
+                }
+                self.check_value(left, type_, block, index)?;
+                self.check_value(right, type_, block, index)?;
+            },
+            InstructionKind::Negate{type_, value} => {
+                if !type_.is_float() {
+                    return Err(format!("Cannot negate {}", type_));
+                }
+                self.check_value(value, type_, block, index)?;
+            },
+            InstructionKind::Compare{comparison, type_, left, right} => {
+                let valid = match type_ {
+                    Type::Ptr => !comparison.is_float(),
+                    type_ if type_.is_float() => comparison.is_float(),
+                    type_ if type_.is_int() => !comparison.is_float(),
+                    _ => false,
+                };
+                if !valid {
+                    return Err(format!("Cannot compare {} with {}", type_, comparison.to_string()));
+                }
+                self.check_value(left, type_, block, index)?;
+                self.check_value(right, type_, block, index)?;
+            },
+            InstructionKind::Cast{op, from, value, to} => {
+                let valid = match op {
+                    CastOp::Trunc => from.is_int() && to.is_int() && from.width() > to.width(),
+                    CastOp::ZExt | CastOp::SExt => from.is_int() && to.is_int() && from.width() < to.width(),
+                    CastOp::FPTrunc => *from == Type::F64 && *to == Type::F32,
+                    CastOp::FPExt => *from == Type::F32 && *to == Type::F64,
+                    CastOp::FPToSI | CastOp::FPToUI => from.is_float() && to.is_int(),
+                    CastOp::SIToFP | CastOp::UIToFP => from.is_int() && to.is_float(),
+                };
+                if !valid {
+                    return Err(format!("Cannot {} {} to {}", op.to_string(), from, to));
+                }
+                self.check_value(value, from, block, index)?;
+            },
+            InstructionKind::FieldAddress{type_, pointer, index : field} => {
+                if !matches!(type_, Type::Struct(_)) {
+                    return Err(format!("{} is not a struct", type_));
+                }
+                self.field_type(type_, *field)?;
+                self.check_value(pointer, &Type::Ptr, block, index)?;
+            },
+            InstructionKind::ElementAddress{type_, pointer, index : element} => {
+                if !matches!(type_, Type::Array(..)) {
+                    return Err(format!("{} is not an array", type_));
+                }
+                check_type(self.module, type_)?;
+                self.check_value(pointer, &Type::Ptr, block, index)?;
+                self.check_value(element, &Type::I64, block, index)?;
+            },
+            InstructionKind::Offset{type_, pointer, index : offset} => {
+                check_type(self.module, type_)?;
+                self.check_value(pointer, &Type::Ptr, block, index)?;
+                self.check_value(offset, &Type::I64, block, index)?;
+            },
+            InstructionKind::ExtractValue{type_, aggregate, ..} => {
+                self.check_value(aggregate, type_, block, index)?;
+            },
+            InstructionKind::InsertValue{type_, aggregate, value, index : field} => {
+                self.check_value(aggregate, type_, block, index)?;
+                self.check_value(value, &self.field_type(type_, *field)?, block, index)?;
+            },
+            InstructionKind::Call{callee, return_type, arguments} => {
+                let function = callee.name();
+                let (parameters, expected_return_type) = self.module
+                    .signature(callee)
+                    .ok_or_else(|| format!("Function {} not defined", function))?;
+                if *return_type != expected_return_type {
+                    return Err(format!("Wrong return type for {}", function));
+                }
+                if parameters.len() != arguments.len() {
+                    return Err(format!("Function {} expects {} arguments", function, parameters.len()));
+                }
+                for ((type_, argument), parameter) in arguments.iter().zip(&parameters) {
+                    if type_ != parameter {
+                        return Err(format!("Argument of type {} passed to {} for a {}", type_, function, parameter));
+                    }
+                    self.check_value(argument, type_, block, index)?;
+                }
+            },
+            InstructionKind::Phi{type_, incoming} => {
+                let instructions = &self.function.block(block).instructions;
+                if instructions[..index].iter().any(|other| !matches!(other.kind, InstructionKind::Phi{..})) {
+                    return Err("Phi after a non phi instruction".to_string());
+                }
+
+                let predecessors = &self.predecessors[block.0];
+                if incoming.len() != predecessors.len() ||
+                    !predecessors.iter().all(|predecessor| incoming.iter().any(|(_, from)| from == predecessor)) {
+                    return Err("Phi does not have one value per predecessor".to_string());
+                }
+
+                // The values are used at the end of the incoming blocks
+                for (value, from) in incoming {
+                    let end = self.function.block(*from).instructions.len();
+                    self.check_value(value, type_, *from, end)?;
+                }
+            },
+        }
+
+        Ok(())
+    }
+
+    fn verify_terminator(&self, block : BlockId, terminator : &Terminator) -> Result<(), String> {
+        let end = self.function.block(block).instructions.len();
+
+        match terminator {
+            Terminator::Return(value) => match (value, &self.function.return_type) {
+                (None, None) => {},
+                (Some((type_, value)), Some(return_type)) if type_ == return_type => {
+                    self.check_value(value, type_, block, end)?;
+                },
+                _ => return Err("Return does not match the return type".to_string()),
+            },
+            Terminator::CondBranch{condition, ..} => self.check_value(condition, &Type::I1, block, end)?,
+            Terminator::Switch{type_, value, ..} => {
+                if !type_.is_int() {
+                    return Err(format!("Cannot switch on {}", type_));
+                }
+                self.check_value(value, type_, block, end)?;
+            },
+            Terminator::Branch(_) | Terminator::Trap | Terminator::Unreachable => {},
+        }
+
+        Ok(())
+    }
+}
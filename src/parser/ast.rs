@@ -1,4 +1,4 @@
-use crate::*;
+use crate::{*, lexer::lexeme::Range};
 use super::parser::parser;
 
 #[derive(Debug)]
@@ -17,6 +17,12 @@ pub enum BinaryOperator {
     BitXor,
     LShift,
     RShift,
+    Equals,
+    NotEquals,
+    Less,
+    LessEq,
+    Greater,
+    GreaterEq,
 }
 
 impl BinaryOperator {
@@ -36,9 +42,43 @@ impl BinaryOperator {
             Operator::BitwiseXor => BinaryOperator::BitXor,
             Operator::ShiftLeft => BinaryOperator::LShift,
             Operator::ShiftRight => BinaryOperator::RShift,
+            Operator::Equals => BinaryOperator::Equals,
+            Operator::NotEquals => BinaryOperator::NotEquals,
+            Operator::LessThan => BinaryOperator::Less,
+            Operator::LessEqThan => BinaryOperator::LessEq,
+            Operator::GreaterThan => BinaryOperator::Greater,
+            Operator::GreaterEqThan => BinaryOperator::GreaterEq,
             _ => panic!("Invalid operator for BinaryOperator"),
         }
     }
+
+    pub fn is_comparison(&self) -> bool {
+        matches!(self,
+            BinaryOperator::Equals | BinaryOperator::NotEquals |
+            BinaryOperator::Less | BinaryOperator::LessEq |
+            BinaryOperator::Greater | BinaryOperator::GreaterEq
+        )
+    }
+}
+
+#[derive(Debug)]
+pub enum UnaryOperator {
+    AddressOf,
+    Deref,
+    Negate,
+    Not,
+}
+
+impl UnaryOperator {
+    pub fn from(op : &Operator) -> Option<UnaryOperator> {
+        match op {
+            Operator::BitwiseAnd => Some(UnaryOperator::AddressOf),
+            Operator::Times => Some(UnaryOperator::Deref),
+            Operator::Minus => Some(UnaryOperator::Negate),
+            Operator::Not => Some(UnaryOperator::Not),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -47,6 +87,7 @@ pub enum Literal {
     Float(String),
     String(String),
     Boolean(bool),
+    Null,
 }
 
 #[derive(Debug)]
@@ -54,7 +95,29 @@ pub enum OperationResult {
     Literal(Literal),
     Identifier(String),
     BinOpResult(BinaryOperation),
+    UnaryOpResult(UnaryOperation),
     FuncResult(ApplyFunction),
+    MemberAccess(MemberAccess),
+    StructLiteral(StructLiteral),
+    Index(IndexAccess),
+    ArrayLiteral(ArrayLiteral),
+    EnumValue(EnumValue),
+}
+
+impl OperationResult {
+    // Literals, identifiers and enum values have no range
+    pub fn range(&self) -> Option<Range> {
+        match self {
+            OperationResult::BinOpResult(operation) => Some(operation.range),
+            OperationResult::UnaryOpResult(operation) => Some(operation.range),
+            OperationResult::FuncResult(function) => Some(function.range),
+            OperationResult::MemberAccess(access) => Some(access.range),
+            OperationResult::StructLiteral(literal) => Some(literal.range),
+            OperationResult::Index(access) => Some(access.range),
+            OperationResult::ArrayLiteral(literal) => Some(literal.range),
+            OperationResult::Literal(_) | OperationResult::Identifier(_) | OperationResult::EnumValue(_) => None,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -62,59 +125,144 @@ pub struct BinaryOperation {
     pub operator : BinaryOperator,
     pub left : Box<OperationResult>,
     pub right : Box<OperationResult>,
+    pub range : Range,
+}
+
+#[derive(Debug)]
+pub struct UnaryOperation {
+    pub operator : UnaryOperator,
+    pub operand : Box<OperationResult>,
+    pub range : Range,
+}
+
+#[derive(Debug)]
+pub struct MemberAccess {
+    pub object : Box<OperationResult>,
+    pub field : String,
+    pub range : Range,
 }
 
+#[derive(Debug)]
+pub struct FieldInit {
+    pub field : String,
+    pub value : OperationResult,
+    pub range : Range,
+}
+
+#[derive(Debug)]
+pub struct StructLiteral {
+    pub type_id : String,
+    pub fields : Vec<FieldInit>,
+    pub range : Range,
+}
+
+#[derive(Debug)]
+pub struct IndexAccess {
+    pub object : Box<OperationResult>,
+    pub index : Box<OperationResult>,
+    pub range : Range,
+}
+
+#[derive(Debug)]
+pub struct ArrayLiteral {
+    pub elements : Vec<OperationResult>,
+    pub range : Range,
+}
+
+// `Color.Red`, read as a member access and resolved by the parser
+#[derive(Debug)]
+pub struct EnumValue {
+    pub enum_type : std::rc::Rc<EnumType>,
+    pub variant : String,
+}
+
+// For method calls `receiver` is the object the method is called on and,
+// once resolved by the parser, `function_id` is the mangled method name.
 #[derive(Debug)]
 pub struct ApplyFunction {
     pub function_id : String,
-    pub arguments : Vec<Box<OperationResult>>
+    pub arguments : Vec<OperationResult>,
+    pub receiver : Option<Box<OperationResult>>,
+    pub range : Range,
 }
 
+// `else if` chains are nested in `else_body`. The range of control flow
+// statements is the one of their keyword.
 #[derive(Debug)]
 pub struct IfBlock {
     pub condition : OperationResult,
     pub body : Body,
+    pub else_body : Option<Body>,
+    pub range : Range,
 }
 
+// `init_statement` is a declaration or an operation, scoped to the loop
 #[derive(Debug)]
 pub struct ForBlock {
-    pub init_statement : OperationResult,
+    pub init_statement : Option<Box<Statement>>,
     pub condition : OperationResult,
-    pub end_statement : OperationResult,
+    pub end_statement : Option<OperationResult>,
     pub body : Body,
+    pub range : Range,
 }
 
 #[derive(Debug)]
 pub struct WhileBlock {
     pub condition : OperationResult,
     pub body : Body,
+    pub range : Range,
+}
+
+#[derive(Debug)]
+pub struct MatchArm {
+    pub variants : Vec<String>,
+    pub body : Body,
 }
 
+// `default` is the body of the `_` arm, if any
+#[derive(Debug)]
+pub struct MatchBlock {
+    pub value : OperationResult,
+    pub arms : Vec<MatchArm>,
+    pub default : Option<Body>,
+    pub range : Range,
+}
+
+// Methods have the class they belong to as `receiver`, which is passed
+// implicitly as `self`, and a mangled `identifier` (see `method_name`).
+// `range` is the one of the `def` keyword.
 #[derive(Debug)]
 pub struct FunctionBlock {
     pub identifier : String,
     pub arguments : Vec<Variable>,
     pub return_type : Option<Type>,
-    pub body : Body
+    pub body : Body,
+    pub receiver : Option<std::rc::Rc<CustomType>>,
+    pub range : Range,
 }
 
+// `range` goes from the type to the identifier
 #[derive(Debug)]
 pub struct VariableDeclaration {
     pub identifier : String,
     pub type_ : Type,
     pub init_value : Option<OperationResult>,
+    pub range : Range,
 }
 
 #[derive(Debug)]
 pub enum Statement {
     Declaration(VariableDeclaration),
     Operation(OperationResult),
-    Return(OperationResult),
+    // The range is the one of the `return` keyword
+    Return(Option<OperationResult>, Range),
     If(IfBlock),
     For(ForBlock),
     While(WhileBlock),
+    Match(MatchBlock),
     Function(FunctionBlock),
     Struct(std::rc::Rc<CustomType>),
+    Enum(std::rc::Rc<EnumType>),
     Body(Body),
 }
 
@@ -133,6 +281,31 @@ pub struct Variable {
 pub struct CustomType {
     pub identifier : String,
     pub attributes : Vec<Variable>,
+    pub methods : Vec<String>,
+}
+
+#[derive(Debug, Hash, PartialEq, Eq)]
+pub struct EnumVariant {
+    pub identifier : String,
+    pub value : i64,
+}
+
+#[derive(Debug, Hash, PartialEq, Eq)]
+pub struct EnumType {
+    pub identifier : String,
+    pub variants : Vec<EnumVariant>,
+}
+
+impl EnumType {
+    pub fn variant(&self, id : &str) -> Option<&EnumVariant> {
+        self.variants
+            .iter()
+            .find(|variant| variant.identifier == id)
+    }
+}
+
+pub fn method_name(class_id : &str, method_id : &str) -> String {
+    format!("{}.{}", class_id, method_id)
 }
 
 #[derive(Debug, Hash, Clone, PartialEq, Eq)]
@@ -173,26 +346,104 @@ impl BuiltInType {
             _ => None
         }
     }
+
+    pub fn to_string<'b>(&self) -> &'b str {
+        match self {
+            BuiltInType::I8 => "int8",
+            BuiltInType::I16 => "int16",
+            BuiltInType::I32 => "int32",
+            BuiltInType::I64 => "int64",
+            BuiltInType::U8 => "uint8",
+            BuiltInType::U16 => "uint16",
+            BuiltInType::U32 => "uint32",
+            BuiltInType::U64 => "uint64",
+            BuiltInType::F32 => "fint32",
+            BuiltInType::F64 => "fint64",
+            BuiltInType::ISize => "isize",
+            BuiltInType::USize => "usize",
+            BuiltInType::Bool => "bool",
+            BuiltInType::String => "string",
+        }
+    }
+
+    pub fn is_signed(&self) -> bool {
+        matches!(self, BuiltInType::I8 | BuiltInType::I16 | BuiltInType::I32 | BuiltInType::I64 | BuiltInType::ISize)
+    }
+
+    pub fn is_integer(&self) -> bool {
+        matches!(self,
+            BuiltInType::I8 | BuiltInType::I16 | BuiltInType::I32 | BuiltInType::I64 | BuiltInType::ISize |
+            BuiltInType::U8 | BuiltInType::U16 | BuiltInType::U32 | BuiltInType::U64 | BuiltInType::USize
+        )
+    }
+
+    pub fn is_float(&self) -> bool {
+        matches!(self, BuiltInType::F32 | BuiltInType::F64)
+    }
 }
 
 #[derive(Debug, Hash, Clone, PartialEq, Eq)]
 pub enum Type {
     Custom(std::rc::Rc<CustomType>),
-    BuiltIn(BuiltInType)
+    BuiltIn(BuiltInType),
+    // `int32[4][2]` is an array of 4 arrays of 2 `int32`
+    Array(Box<Type>, usize),
+    Pointer(Box<Type>),
+    Enum(std::rc::Rc<EnumType>),
+}
+
+impl std::fmt::Display for Type {
+    fn fmt(&self, f : &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Type::Custom(custom_type) => write!(f, "{}", custom_type.identifier),
+            Type::BuiltIn(built_in_type) => write!(f, "{}", built_in_type.to_string()),
+            Type::Array(element_type, len) => {
+                let mut lengths = format!("[{}]", len);
+                let mut base_type = element_type;
+                while let Type::Array(element_type, len) = base_type.as_ref() {
+                    lengths += &format!("[{}]", len);
+                    base_type = element_type;
+                }
+                write!(f, "{}{}", base_type, lengths)
+            },
+            Type::Pointer(pointee_type) => write!(f, "{}*", pointee_type),
+            Type::Enum(enum_type) => write!(f, "{}", enum_type.identifier),
+        }
+    }
+}
+
+impl CustomType {
+    pub fn attribute_index(&self, id : &str) -> Option<usize> {
+        self.attributes
+            .iter()
+            .position(|attr| attr.identifier == id)
+    }
+
+    pub fn has_method(&self, id : &str) -> bool {
+        self.methods.iter().any(|method| method == id)
+    }
 }
 
 #[derive(Debug)]
 pub struct TranslationUnit {
     pub custom_types : Vec<std::rc::Rc<CustomType>>,
+    pub enum_types : Vec<std::rc::Rc<EnumType>>,
     pub global_variables : Vec<VariableDeclaration>,
     pub functions : Vec<FunctionBlock>
 }
 
+impl Default for TranslationUnit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl TranslationUnit {
     pub fn new() -> TranslationUnit {
         TranslationUnit {
             global_variables : Vec::new(),
             custom_types : Vec::new(),
+            enum_types : Vec::new(),
             functions : Vec::new()
         }
     }
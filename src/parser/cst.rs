@@ -0,0 +1,257 @@
+use crate::*;
+
+// Concrete syntax tree over a lossless lexeme. Every token of the lexeme,
+// including the final `EndOfFile`, appears exactly once in the tree and in
+// source order, so printing the leading trivia and text of each token
+// rebuilds the original input.
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum SyntaxKind {
+    Root,
+    Statement,
+    Block,
+    Parenthesized,
+    Bracketed,
+}
+
+#[derive(Debug)]
+pub enum SyntaxElement {
+    Node(SyntaxNode),
+    Token(usize),
+}
+
+#[derive(Debug)]
+pub struct SyntaxNode {
+    pub kind : SyntaxKind,
+    pub children : Vec<SyntaxElement>,
+}
+
+#[derive(Debug)]
+pub struct SyntaxTree<'a> {
+    pub lexeme : &'a Lexeme,
+    pub root : SyntaxNode,
+}
+
+impl<'a> SyntaxTree<'a> {
+    pub fn from_lexeme(lexeme : &'a Lexeme) -> SyntaxTree<'a> {
+        let mut builder = CSTBuilder {
+            lexeme,
+            current_token : 0,
+        };
+        let mut root = builder.read_statements(SyntaxKind::Root);
+        if builder.current_token < lexeme.len() {
+            root.children.push(builder.take_token());
+        }
+
+        SyntaxTree {
+            lexeme,
+            root,
+        }
+    }
+
+    pub fn to_source(&self) -> String {
+        let mut source = String::new();
+        self.write_node(&self.root, &mut source);
+        source
+    }
+
+    fn write_node(&self, node : &SyntaxNode, source : &mut String) {
+        for child in node.children.iter() {
+            match child {
+                SyntaxElement::Node(node) => self.write_node(node, source),
+                SyntaxElement::Token(index) => {
+                    let token = self.lexeme.token_at(*index);
+                    for trivia in token.leading_trivia.iter() {
+                        source.push_str(trivia.text());
+                    }
+                    source.push_str(&token.token.to_source());
+                },
+            }
+        }
+    }
+}
+
+/*******************/
+/*******************/
+/*******************/
+
+fn closing_delimiter(kind : SyntaxKind) -> Option<Delimiter> {
+    match kind {
+        SyntaxKind::Block => Some(Delimiter::BracketsClose),
+        SyntaxKind::Parenthesized => Some(Delimiter::ParenthesisClose),
+        SyntaxKind::Bracketed => Some(Delimiter::SquareBracketsClose),
+        _ => None,
+    }
+}
+
+// Statements introduced by these keywords end with their block instead of
+// with a semicolon.
+fn is_block_statement_keyword(kw : Keyword) -> bool {
+    matches!(kw, Keyword::Def | Keyword::Class | Keyword::Enum | Keyword::Match | Keyword::If | Keyword::Else | Keyword::For | Keyword::While)
+}
+
+struct CSTBuilder<'a> {
+    lexeme : &'a Lexeme,
+    current_token : usize,
+}
+
+impl<'a> CSTBuilder<'a> {
+    fn peek(&self) -> Option<&'a Token> {
+        if self.current_token >= self.lexeme.len() {
+            return None;
+        }
+        match &self.lexeme.token_at(self.current_token).token {
+            Token::EndOfFile => None,
+            token => Some(token),
+        }
+    }
+
+    fn is_closing(&self, kind : SyntaxKind) -> bool {
+        match (self.peek(), closing_delimiter(kind)) {
+            (Some(Token::Delimiter(d)), Some(close)) => *d == close,
+            _ => false,
+        }
+    }
+
+    fn take_token(&mut self) -> SyntaxElement {
+        self.current_token += 1;
+        SyntaxElement::Token(self.current_token-1)
+    }
+
+    // Reads the delimiter at the current token and everything up to its
+    // matching closing delimiter (or the end of the lexeme).
+    fn read_group(&mut self, kind : SyntaxKind) -> SyntaxNode {
+        let open = self.take_token();
+        let mut node = match kind {
+            SyntaxKind::Block => self.read_statements(kind),
+            _ => self.read_tokens(kind),
+        };
+        node.children.insert(0, open);
+        if self.is_closing(kind) {
+            node.children.push(self.take_token());
+        }
+        node
+    }
+
+    fn read_element(&mut self) -> SyntaxElement {
+        match self.peek() {
+            Some(Token::Delimiter(Delimiter::BracketsOpen)) => SyntaxElement::Node(self.read_group(SyntaxKind::Block)),
+            Some(Token::Delimiter(Delimiter::ParenthesisOpen)) => SyntaxElement::Node(self.read_group(SyntaxKind::Parenthesized)),
+            Some(Token::Delimiter(Delimiter::SquareBracketsOpen)) => SyntaxElement::Node(self.read_group(SyntaxKind::Bracketed)),
+            _ => self.take_token(),
+        }
+    }
+
+    fn read_tokens(&mut self, kind : SyntaxKind) -> SyntaxNode {
+        let mut children : Vec<SyntaxElement> = vec![];
+        while self.peek().is_some() && !self.is_closing(kind) {
+            children.push(self.read_element());
+        }
+
+        SyntaxNode {
+            kind,
+            children,
+        }
+    }
+
+    fn read_statements(&mut self, kind : SyntaxKind) -> SyntaxNode {
+        let mut children : Vec<SyntaxElement> = vec![];
+        while self.peek().is_some() && !self.is_closing(kind) {
+            children.push(SyntaxElement::Node(self.read_statement(kind)));
+        }
+
+        SyntaxNode {
+            kind,
+            children,
+        }
+    }
+
+    fn read_statement(&mut self, parent : SyntaxKind) -> SyntaxNode {
+        let ends_with_block = match self.peek() {
+            Some(Token::Keyword(kw)) => is_block_statement_keyword(*kw),
+            Some(Token::Delimiter(Delimiter::BracketsOpen)) => true,
+            _ => false,
+        };
+
+        let mut children : Vec<SyntaxElement> = vec![];
+        while let Some(token) = self.peek() {
+            if self.is_closing(parent) {
+                break;
+            }
+
+            let is_block = *token == Token::Delimiter(Delimiter::BracketsOpen);
+            let is_end = *token == Token::EndOfStatement;
+            children.push(self.read_element());
+
+            if is_end {
+                break;
+            }
+            if is_block && ends_with_block && self.peek() != Some(&Token::Keyword(Keyword::Else)) {
+                break;
+            }
+        }
+
+        SyntaxNode {
+            kind : SyntaxKind::Statement,
+            children,
+        }
+    }
+}
+
+/*******************/
+/*******************/
+/*******************/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(source : &str) {
+        let lexeme = Lexeme::from_source_lossless(source);
+        assert_eq!(SyntaxTree::from_lexeme(&lexeme).to_source(), source);
+    }
+
+    #[test]
+    fn prints_the_source_back() {
+        round_trip("");
+        round_trip("  \n// only a comment\n");
+        round_trip("def int32 main() {\r\n\treturn 0; // done\r\n}\r\n");
+        round_trip("class P { int32 x; }\n/* a\n * block */ int32[2] a = [1, 2];\nstring s = \"é\\\"😀\";");
+        round_trip("def int32 f(int32 x) { if x > 0 { return 1; } else { return (x + 2) * 3; } }");
+    }
+
+    #[test]
+    fn prints_unbalanced_source_back() {
+        round_trip("def int32 main() { return (1 + 2;");
+        round_trip("} ) ] x;");
+        round_trip("int32 x");
+    }
+
+    #[test]
+    fn prints_every_program_back() {
+        let directory = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/programs");
+        for entry in std::fs::read_dir(directory).unwrap() {
+            let path = entry.unwrap().path();
+            let source = std::fs::read_to_string(&path).unwrap();
+            let lexeme = Lexeme::from_file_lossless(path.to_str().unwrap());
+            assert_eq!(SyntaxTree::from_lexeme(&lexeme).to_source(), source, "{}", path.display());
+        }
+    }
+
+    #[test]
+    fn groups_statements_and_blocks() {
+        let lexeme = Lexeme::from_source_lossless("int32 x = (1); def int32 f() { return x; }");
+        let tree = SyntaxTree::from_lexeme(&lexeme);
+
+        let kinds : Vec<SyntaxKind> = tree.root.children.iter().map(|child| match child {
+            SyntaxElement::Node(node) => node.kind,
+            SyntaxElement::Token(_) => SyntaxKind::Root,
+        }).collect();
+        assert_eq!(kinds, [SyntaxKind::Statement, SyntaxKind::Statement, SyntaxKind::Root]);
+
+        let SyntaxElement::Node(function) = &tree.root.children[1] else { panic!("Expected a statement") };
+        let Some(SyntaxElement::Node(body)) = function.children.last() else { panic!("Expected a block") };
+        assert_eq!(body.kind, SyntaxKind::Block);
+        assert!(matches!(body.children[1], SyntaxElement::Node(SyntaxNode{kind : SyntaxKind::Statement, ..})));
+    }
+}
@@ -1,4 +1,6 @@
 pub mod operation_parser;
 pub mod parser;
+pub mod types;
 pub mod ast;
+pub mod cst;
 pub mod prelude;
\ No newline at end of file
@@ -1,70 +1,100 @@
-use crate::*;
+use crate::{*, lexer::lexeme::{ContextedToken, Position}};
 use super::ast::*;
 
-const PRIORITY : &'static [&'static [Operator]] = &[
+// From the loosest to the tightest binding operators
+const PRIORITY : &[&[Operator]] = &[
     &[Operator::Comma],
     &[Operator::Assign, Operator::PlusEq, Operator::MinusEq, Operator::TimesEq, Operator::DivEq, Operator::ModEq, Operator::AndEq, Operator::OrEq, Operator::XorEq, Operator::ShiftLeftEq, Operator::ShiftRightEq, Operator::BitwiseAndEq, Operator::BitwiseOrEq, Operator::BitwiseXorEq],
-    &[Operator::Plus, Operator::Minus],
-    &[Operator::Times, Operator::Div, Operator::Mod],
-    &[Operator::Equals, Operator::NotEquals],
     &[Operator::Or],
-    &[Operator::And],
     &[Operator::Xor],
-    &[Operator::LessThan, Operator::LessEqThan, Operator::GreaterThan, Operator::GreaterEqThan],
+    &[Operator::And],
     &[Operator::BitwiseOr],
-    &[Operator::BitwiseAnd],
     &[Operator::BitwiseXor],
+    &[Operator::BitwiseAnd],
+    &[Operator::Equals, Operator::NotEquals],
+    &[Operator::LessThan, Operator::LessEqThan, Operator::GreaterThan, Operator::GreaterEqThan],
     &[Operator::ShiftLeft, Operator::ShiftRight],
+    &[Operator::Plus, Operator::Minus],
+    &[Operator::Times, Operator::Div, Operator::Mod],
     &[Operator::Point]
 ];
 
+// +1 for tokens that open a nested group, -1 for those that close it.
+fn nesting_change(token : &Token) -> i32 {
+    match token {
+        Token::Delimiter(Delimiter::ParenthesisOpen | Delimiter::BracketsOpen | Delimiter::SquareBracketsOpen) => 1,
+        Token::Delimiter(Delimiter::ParenthesisClose | Delimiter::BracketsClose | Delimiter::SquareBracketsClose) => -1,
+        _ => 0,
+    }
+}
+
+// Replaces the escape sequences of a string literal by the characters they
+// stand for.
+fn unescape(raw : &str, position : Position) -> String {
+    let mut string = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    while let Some(ch) = chars.next() {
+        if ch != '\\' {
+            string.push(ch);
+            continue;
+        }
+
+        string.push(match chars.next() {
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            Some('0') => '\0',
+            Some('\\') => '\\',
+            Some('"') => '"',
+            Some(ch) => panic!("Invalid escape sequence `\\{}` in string literal at {}", ch, position),
+            None => panic!("Invalid escape sequence at the end of string literal at {}", position),
+        });
+    }
+    string
+}
+
 pub struct OperationParser<'a> {
-    lexeme: &'a Lexeme,
-    token_begin : usize,
-    token_end : usize
+    tokens : &'a [ContextedToken],
 }
 
 impl<'a> OperationParser<'a> {
 
-pub fn new(lexeme : &'a Lexeme, token_begin : usize, token_end : usize) -> OperationParser<'a> {
+pub fn new(tokens : &'a [ContextedToken]) -> OperationParser<'a> {
     OperationParser{
-        lexeme,
-        token_begin,
-        token_end
+        tokens,
     }
 }
 
 pub fn parse(&mut self) -> OperationResult {
-    return self.read_operation_in(self.token_begin, self.token_end);
+    return self.read_operation_in(0, self.tokens.len());
+}
+
+fn position_at(&self, index : usize) -> Position {
+    TokenCursor::new(&self.tokens[index.min(self.tokens.len())..]).position()
+}
+
+// Operators at the start of an operation or right after another operator
+// are unary.
+fn is_unary_position(&self, tok_begin : usize, index : usize) -> bool {
+    index == tok_begin || matches!(self.tokens[index-1].token, Token::Operator(_))
 }
 
 fn find_any_token_in(&self, tok_begin : usize, tok_end : usize, tokens : &[Token], reverse : bool) -> Option<usize> {
     let range = tok_begin..tok_end;
-    let delim_open : Token;
-    let delim_close : Token;
     let range : Vec<usize> =
         if reverse {
-            delim_open  = Token::Delimiter(Delimiter::ParenthesisClose);
-            delim_close = Token::Delimiter(Delimiter::ParenthesisOpen);
             range.rev().collect()
         } else {
-            delim_open  = Token::Delimiter(Delimiter::ParenthesisOpen);
-            delim_close = Token::Delimiter(Delimiter::ParenthesisClose);
             range.collect()
         };
     
-    let mut n_parenthesis = 0;
+    let mut n_nesting = 0;
     for i in range {
-        let token_at_i = &self.lexeme.token_at(i).token;
+        let token_at_i = &self.tokens[i].token;
 
-        if *token_at_i == delim_open {
-            n_parenthesis += 1;
-        }
-        else if *token_at_i == delim_close {
-            n_parenthesis -= 1;
-        }
+        n_nesting += if reverse { -nesting_change(token_at_i) } else { nesting_change(token_at_i) };
 
-        if n_parenthesis > 0 {
+        if n_nesting > 0 || self.is_unary_position(tok_begin, i) {
             continue;
         }
         
@@ -95,100 +125,274 @@ fn find_highest_priority_op_in(&mut self, tok_begin : usize, tok_end : usize) ->
     None
 }
 
-fn read_operations_sep_by_commas_in(&mut self, tok_begin : usize, tok_end : usize) -> Vec<Box<OperationResult>> {
-    let mut operations : Vec<Box<OperationResult>> = Vec::new();
-    let mut n_parenthesis = 0;
+// Index of the delimiter that opens the group closed at `tok_close`.
+fn find_matching_open_in(&self, tok_begin : usize, tok_close : usize) -> Option<usize> {
+    let mut n_nesting = 0;
+    for i in (tok_begin..=tok_close).rev() {
+        n_nesting -= nesting_change(&self.tokens[i].token);
+        if n_nesting == 0 {
+            return Some(i);
+        }
+    }
+    None
+}
+
+// Splits [tok_begin, tok_end) at the commas that are not nested in any
+// delimiter.
+fn split_by_commas_in(&self, tok_begin : usize, tok_end : usize) -> Vec<(usize, usize)> {
+    let mut ranges : Vec<(usize, usize)> = Vec::new();
+    if tok_begin == tok_end {
+        return ranges;
+    }
+
+    let mut n_nesting = 0;
     let mut last_comma = tok_begin;
     for i in tok_begin..tok_end {
-        let token_at_i = &self.lexeme.token_at(i).token;
+        let token_at_i = &self.tokens[i].token;
 
-        if let Token::Delimiter(Delimiter::ParenthesisOpen) = token_at_i {
-            n_parenthesis += 1;
-        }
-        else if let Token::Delimiter(Delimiter::ParenthesisClose) = token_at_i {
-            n_parenthesis -= 1;
-        }
+        n_nesting += nesting_change(token_at_i);
 
-        if n_parenthesis > 0 {
+        if n_nesting > 0 {
             continue;
         }
 
         if let Token::Operator(Operator::Comma) = token_at_i {
-            operations.push(Box::new(self.read_operation_in(last_comma, i)));
+            ranges.push((last_comma, i));
             last_comma = i+1;
         }
     }
-    operations.push(Box::new(self.read_operation_in(last_comma, tok_end)));
+    ranges.push((last_comma, tok_end));
 
-    operations
+    ranges
 }
 
-fn read_operation_in(&mut self, tok_begin : usize, tok_end : usize) -> OperationResult {
-    println!("Reading operation in [{:#?}, {:#?})", self.lexeme.token_at(tok_begin), self.lexeme.token_at(tok_end));
+fn read_operations_sep_by_commas_in(&mut self, tok_begin : usize, tok_end : usize) -> Vec<OperationResult> {
+    self.split_by_commas_in(tok_begin, tok_end)
+        .into_iter()
+        .map(|(begin, end)| self.read_operation_in(begin, end))
+        .collect()
+}
 
-    // Read highest priority operator
-    if let Some(i) = self.find_highest_priority_op_in(tok_begin, tok_end) {
-        let op = match &self.lexeme.token_at(i).token {
-            Token::Operator(op) => op,
-            _ => panic!("This should never happen")
+fn read_struct_literal(&mut self, tok_begin : usize, tok_end : usize) -> OperationResult {
+    let type_id = match &self.tokens[tok_begin].token {
+        Token::Identifier(id) => id.clone(),
+        _ => panic!("Expected class identifier at {}", self.position_at(tok_begin)),
+    };
+
+    let mut fields : Vec<FieldInit> = vec![];
+    for (begin, end) in self.split_by_commas_in(tok_begin+2, tok_end-1) {
+        // Allow a trailing comma
+        if begin == end && end == tok_end-1 && !fields.is_empty() {
+            continue;
+        }
+
+        let mut tokens = TokenCursor::new(&self.tokens[begin..end]);
+        let field_token = tokens.advance();
+        let field = match field_token.map(|tok| &tok.token) {
+            Some(Token::Identifier(id)) => id.clone(),
+            _ => panic!("Expected field name at {}", self.position_at(begin)),
         };
+        tokens.expect(Token::Delimiter(Delimiter::Colon));
 
-        return OperationResult::BinOpResult(BinaryOperation{
-            left  : Box::new(self.read_operation_in(tok_begin, i)),
-            right : Box::new(self.read_operation_in(i+1, tok_end)),
-            operator : BinaryOperator::from(&op)
+        fields.push(FieldInit {
+            field,
+            value : self.read_operation_in(begin+2, end),
+            range : field_token.unwrap().range,
         });
     }
 
+    OperationResult::StructLiteral(StructLiteral {
+        type_id,
+        fields,
+        range : self.tokens[tok_begin].range,
+    })
+}
+
+fn read_array_literal(&mut self, tok_begin : usize, tok_end : usize) -> OperationResult {
+    let mut ranges = self.split_by_commas_in(tok_begin+1, tok_end-1);
+
+    // Allow a trailing comma
+    if ranges.len() > 1 && ranges.last().is_some_and(|(begin, end)| begin == end) {
+        ranges.pop();
+    }
+
+    OperationResult::ArrayLiteral(ArrayLiteral {
+        elements : ranges
+            .into_iter()
+            .map(|(begin, end)| self.read_operation_in(begin, end))
+            .collect(),
+        range : self.tokens[tok_begin].range,
+    })
+}
+
+// Reads `object[index]` if the operation ends with an index.
+fn read_index_in(&mut self, tok_begin : usize, tok_end : usize) -> Option<OperationResult> {
+    if self.tokens[tok_end-1].token != Token::Delimiter(Delimiter::SquareBracketsClose) {
+        return None;
+    }
+
+    let tok_open = self.find_matching_open_in(tok_begin, tok_end-1)?;
+    if tok_open == tok_begin {
+        return None;
+    }
+
+    Some(OperationResult::Index(IndexAccess {
+        object : Box::new(self.read_operation_in(tok_begin, tok_open)),
+        index : Box::new(self.read_operation_in(tok_open+1, tok_end-1)),
+        range : self.tokens[tok_open].range,
+    }))
+}
+
+fn read_unary_operation_in(&mut self, tok_begin : usize, tok_end : usize) -> Option<OperationResult> {
+    let operator = match &self.tokens[tok_begin].token {
+        Token::Operator(op) => UnaryOperator::from(op).unwrap_or_else(|| {
+            panic!("Unexpected `{}` at {}", op.to_string(), self.tokens[tok_begin].range.start)
+        }),
+        _ => return None,
+    };
+
+    // Negative numeric literals are read as a single literal
+    if let (UnaryOperator::Negate, true) = (&operator, tok_begin+2 == tok_end) {
+        match &self.tokens[tok_begin+1].token {
+            Token::Integer(value) => return Some(OperationResult::Literal(Literal::Integer(format!("-{}", value)))),
+            Token::Float(value) => return Some(OperationResult::Literal(Literal::Float(format!("-{}", value)))),
+            _ => {},
+        }
+    }
+
+    Some(OperationResult::UnaryOpResult(UnaryOperation {
+        operator,
+        operand : Box::new(self.read_operation_in(tok_begin+1, tok_end)),
+        range : self.tokens[tok_begin].range,
+    }))
+}
+
+fn is_function_call_in(&self, tok_begin : usize, tok_end : usize) -> bool {
+    let tokens = TokenCursor::new(&self.tokens[tok_begin..tok_end]);
+    matches!(
+        (tokens.peek(), tokens.peek_nth(1), &self.tokens[tok_end-1].token),
+        (Some(Token::Identifier(_)), Some(Token::Delimiter(Delimiter::ParenthesisOpen)), Token::Delimiter(Delimiter::ParenthesisClose))
+    )
+}
+
+fn read_member_access(&mut self, tok_begin : usize, tok_point : usize, tok_end : usize) -> OperationResult {
+    // Method call
+    if self.is_function_call_in(tok_point+1, tok_end) {
+        if let OperationResult::FuncResult(mut fun) = self.read_operation_in(tok_point+1, tok_end) {
+            fun.receiver = Some(Box::new(self.read_operation_in(tok_begin, tok_point)));
+            return OperationResult::FuncResult(fun);
+        }
+    }
+
+    let field_token = match self.tokens.get(tok_point+1) {
+        Some(tok) if tok_point+2 == tok_end => tok,
+        _ => panic!("Expected field name after `.` at {}", self.position_at(tok_point)),
+    };
+
+    let field = match &field_token.token {
+        Token::Identifier(id) => id.clone(),
+        _ => panic!("Expected field name after `.` at {}", field_token.range.start),
+    };
+
+    OperationResult::MemberAccess(MemberAccess{
+        object : Box::new(self.read_operation_in(tok_begin, tok_point)),
+        field,
+        range : field_token.range,
+    })
+}
+
+fn read_operation_in(&mut self, tok_begin : usize, tok_end : usize) -> OperationResult {
+    if tok_begin >= tok_end {
+        panic!("Expected expression at {}", self.position_at(tok_begin));
+    }
+
+    // Read highest priority operator
+    let op_index = self.find_highest_priority_op_in(tok_begin, tok_end);
+    let op = op_index.map(|i| match &self.tokens[i].token {
+        Token::Operator(op) => *op,
+        _ => panic!("This should never happen")
+    });
+
+    // Binary operators other than `.` bind looser than unary ones, and
+    // member access and indexing bind tighter.
+    if let (Some(i), Some(op)) = (op_index, op) {
+        if op != Operator::Point {
+            return OperationResult::BinOpResult(BinaryOperation{
+                left  : Box::new(self.read_operation_in(tok_begin, i)),
+                right : Box::new(self.read_operation_in(i+1, tok_end)),
+                operator : BinaryOperator::from(&op),
+                range : self.tokens[i].range,
+            });
+        }
+    }
+
+    if let Some(unary) = self.read_unary_operation_in(tok_begin, tok_end) {
+        return unary;
+    }
+
+    if let Some(index) = self.read_index_in(tok_begin, tok_end) {
+        return index;
+    }
+
+    if let Some(i) = op_index {
+        return self.read_member_access(tok_begin, i, tok_end);
+    }
+
+    let mut tokens = TokenCursor::new(&self.tokens[tok_begin..tok_end]);
+    let token_at_end = &self.tokens[tok_end-1].token;
+
     // Read function call
-    if tok_end - tok_begin >= 3 {
-        let tok_id : &Token = &self.lexeme.token_at(tok_begin).token;
-        let tok_par_open : &Token = &self.lexeme.token_at(tok_begin+1).token;
-        let tok_par_close : &Token = &self.lexeme.token_at(tok_end-1).token;
-
-        if let Token::Identifier(id) = tok_id {
-        if let Token::Delimiter(Delimiter::ParenthesisOpen) = tok_par_open {
-        if let Token::Delimiter(Delimiter::ParenthesisClose) = tok_par_close {
+    if self.is_function_call_in(tok_begin, tok_end) {
+        if let Some(Token::Identifier(id)) = tokens.peek() {
             return OperationResult::FuncResult(ApplyFunction{
                 function_id : id.clone(),
-                arguments : self.read_operations_sep_by_commas_in(tok_begin+2, tok_end-1)
+                arguments : self.read_operations_sep_by_commas_in(tok_begin+2, tok_end-1),
+                receiver : None,
+                range : self.tokens[tok_begin].range,
             });
-        }}}
+        }
     }
-    let token_at_begin = &self.lexeme.token_at(tok_begin).token;
 
-    // Read parenthesis
-    let token_at_end = &self.lexeme.token_at(tok_end-1).token;
+    // Read struct literal
+    if let (Some(Token::Identifier(_)), Some(Token::Delimiter(Delimiter::BracketsOpen))) = (tokens.peek(), tokens.peek_nth(1)) {
+        if let Token::Delimiter(Delimiter::BracketsClose) = token_at_end {
+            return self.read_struct_literal(tok_begin, tok_end);
+        }
+    }
+
+    // Read array literal
+    if tokens.check(&Token::Delimiter(Delimiter::SquareBracketsOpen)) {
+        if let Token::Delimiter(Delimiter::SquareBracketsClose) = token_at_end {
+            return self.read_array_literal(tok_begin, tok_end);
+        }
+    }
 
-    if let Token::Delimiter(Delimiter::ParenthesisOpen) = token_at_begin {
+    // Read parenthesis
+    if tokens.check(&Token::Delimiter(Delimiter::ParenthesisOpen)) {
         if let Token::Delimiter(Delimiter::ParenthesisClose) = token_at_end {
             return self.read_operation_in(tok_begin+1, tok_end-1);
         }
     }
 
-    // Read declaration
-
     // Read identifier or literal;
-    if tok_end == tok_begin+1 {
-        let token_at_begin = &self.lexeme.token_at(tok_begin).token;
-        if let Token::Identifier(id) = token_at_begin {
-            return OperationResult::Identifier(id.clone());
-        }
-        else if let Token::Integer(str) = token_at_begin {
-            return OperationResult::Literal(Literal::Integer(str.clone()));
-        }
-        else if let Token::Float(str) = token_at_begin {
-            return OperationResult::Literal(Literal::Float(str.clone()));
-        }
-        else if let Token::String(str) = token_at_begin {
-            return OperationResult::Literal(Literal::String(str.clone()));
-        }
-        else if let Token::Boolean(b) = token_at_begin {
-            return OperationResult::Literal(Literal::Boolean(b.clone()));
+    let position = self.position_at(tok_begin);
+    let operation = match tokens.advance().map(|tok| &tok.token) {
+        Some(Token::Identifier(id)) => Some(OperationResult::Identifier(id.clone())),
+        Some(Token::Integer(str)) => Some(OperationResult::Literal(Literal::Integer(str.clone()))),
+        Some(Token::Float(str)) => Some(OperationResult::Literal(Literal::Float(str.clone()))),
+        Some(Token::String(str)) => Some(OperationResult::Literal(Literal::String(unescape(str, position)))),
+        Some(Token::Boolean(b)) => Some(OperationResult::Literal(Literal::Boolean(*b))),
+        Some(Token::Keyword(Keyword::Null)) => Some(OperationResult::Literal(Literal::Null)),
+        _ => None,
+    };
+
+    if let Some(operation) = operation {
+        if tokens.is_at_end() {
+            return operation;
         }
     }
 
-    panic!("Could not read operation");
+    panic!("Could not read operation at {}", self.position_at(tok_begin));
 }
 
 }
\ No newline at end of file
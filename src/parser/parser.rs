@@ -1,6 +1,8 @@
-use crate::{*, lexer::lexeme::ContextedToken};
+use crate::{*, lexer::lexeme::{ContextedToken, Range}};
 use super::ast::*;
 use super::operation_parser::*;
+use super::types::literal_type;
+use std::collections::HashMap;
 
 pub fn parser(lexeme : &Lexeme) -> TranslationUnit {
     let mut ast_builder = TUBuilder::new(lexeme);
@@ -13,17 +15,17 @@ pub fn parser(lexeme : &Lexeme) -> TranslationUnit {
 /*******************/
 
 struct TUBuilder<'a> {
-    lexeme: &'a Lexeme,
-    current_token : usize,
+    tokens : TokenCursor<'a>,
     tu : TranslationUnit,
+    scopes : Vec<HashMap<String, Type>>,
 }
 
 impl<'a> TUBuilder<'a> {
-    fn new(lexeme : &Lexeme) -> TUBuilder {
+    fn new(lexeme : &Lexeme) -> TUBuilder<'_> {
         TUBuilder{
-            lexeme,
-            current_token : 0,
+            tokens : lexeme.cursor(),
             tu : TranslationUnit::new(),
+            scopes : vec![],
         }
     }
 
@@ -33,6 +35,12 @@ impl<'a> TUBuilder<'a> {
             .find(|ct| ct.identifier == id)
     }
 
+    fn get_enum_type_from_id(&self, id : &str) -> Option<&std::rc::Rc<EnumType>> {
+        self.tu.enum_types
+            .iter()
+            .find(|et| et.identifier == id)
+    }
+
     fn get_type_from_id(&self, id : &str) -> Option<Type> {
         if let Some(type_) = BuiltInType::from(id) {
             return Some(Type::BuiltIn(type_));
@@ -42,50 +50,281 @@ impl<'a> TUBuilder<'a> {
             return Some(Type::Custom(type_.clone()));
         }
 
+        if let Some(type_) = self.get_enum_type_from_id(id) {
+            return Some(Type::Enum(type_.clone()));
+        }
+
         return None;
     }
 
-    fn last_token(&self) -> &ContextedToken {
-        self.lexeme.token_at(self.current_token)
+    fn get_variable_type(&self, id : &str) -> Option<Type> {
+        for scope in self.scopes.iter().rev() {
+            if let Some(type_) = scope.get(id) {
+                return Some(type_.clone());
+            }
+        }
+
+        self.tu.global_variables
+            .iter()
+            .find(|var| var.identifier == id)
+            .map(|var| var.type_.clone())
+    }
+
+    fn declare_variable(&mut self, id : &str, type_ : &Type) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(id.to_string(), type_.clone());
+        }
+    }
+
+    // Reads a type identifier followed by any number of array lengths and
+    // pointer suffixes, e.g. `int32[4][2]` or `int32*[4]`, and leaves the
+    // cursor after it.
+    fn read_type(&mut self) -> Type {
+        let type_tok = self.last_token();
+        let base_type = match &type_tok.token {
+            Token::Identifier(id) => self.get_type_from_id(id).unwrap_or_else(|| {
+                panic!("Unknown type `{}` at {}", id, type_tok.range.start)
+            }),
+            _ => panic!("Expected type at {}", type_tok.range.start),
+        };
+        self.incr_token();
+
+        let mut type_ = base_type;
+        loop {
+            if self.tokens.eat(&Token::Operator(Operator::Times)) {
+                type_ = Type::Pointer(Box::new(type_));
+                continue;
+            }
+
+            let mut lengths : Vec<usize> = vec![];
+            while self.tokens.eat(&Token::Delimiter(Delimiter::SquareBracketsOpen)) {
+                let len_tok = self.last_token();
+                let len = match &len_tok.token {
+                    Token::Integer(len) => len.parse::<usize>().ok(),
+                    _ => None,
+                }.unwrap_or_else(|| panic!("Expected array length at {}", len_tok.range.start));
+
+                self.incr_token();
+                self.tokens.expect(Token::Delimiter(Delimiter::SquareBracketsClose));
+                lengths.push(len);
+            }
+
+            if lengths.is_empty() {
+                return type_;
+            }
+            type_ = lengths
+                .into_iter()
+                .rev()
+                .fold(type_, |type_, len| Type::Array(Box::new(type_), len));
+        }
+    }
+
+    // Member accesses on an enum name, e.g. `Color.Red`, are enum values.
+    fn resolve_enum_value(&self, op : &OperationResult) -> Option<OperationResult> {
+        let (enum_id, access) = match op {
+            OperationResult::MemberAccess(access) => match access.object.as_ref() {
+                OperationResult::Identifier(id) if self.get_variable_type(id).is_none() => (id, access),
+                _ => return None,
+            },
+            _ => return None,
+        };
+
+        let enum_type = self.get_enum_type_from_id(enum_id)?;
+        if enum_type.variant(&access.field).is_none() {
+            panic!("Enum `{}` has no variant `{}` at {}", enum_type.identifier, access.field, access.range.start);
+        }
+
+        Some(OperationResult::EnumValue(EnumValue {
+            enum_type : enum_type.clone(),
+            variant : access.field.clone(),
+        }))
+    }
+
+    // Resolves enum values, methods and fields, using the type of the
+    // operation where it can be known while parsing. Types are checked by
+    // `check_types` once the whole unit is parsed.
+    fn resolve_operation(&self, op : &mut OperationResult) -> Option<Type> {
+        if let Some(value) = self.resolve_enum_value(op) {
+            *op = value;
+        }
+
+        match op {
+            OperationResult::Literal(Literal::Null) => None,
+            OperationResult::Literal(lit) => Some(literal_type(lit)),
+            OperationResult::EnumValue(value) => Some(Type::Enum(value.enum_type.clone())),
+            OperationResult::Identifier(id) => self.get_variable_type(id),
+            OperationResult::BinOpResult(bin_op) => {
+                let left = self.resolve_operation(&mut bin_op.left);
+                let right = self.resolve_operation(&mut bin_op.right);
+
+                if bin_op.operator.is_comparison() {
+                    return Some(Type::BuiltIn(BuiltInType::Bool));
+                }
+                left.or(right)
+            },
+            OperationResult::UnaryOpResult(unary) => {
+                match unary.operator {
+                    UnaryOperator::AddressOf => self.resolve_operation(&mut unary.operand).map(|type_| Type::Pointer(Box::new(type_))),
+                    UnaryOperator::Deref => match self.resolve_operation(&mut unary.operand)? {
+                        Type::Pointer(pointee_type) => Some(*pointee_type),
+                        _ => None,
+                    },
+                    UnaryOperator::Negate | UnaryOperator::Not => self.resolve_operation(&mut unary.operand),
+                }
+            },
+            OperationResult::FuncResult(fun) => {
+                for arg in fun.arguments.iter_mut() {
+                    self.resolve_operation(arg);
+                }
+
+                if let Some(receiver) = &mut fun.receiver {
+                    let custom_type = match self.resolve_operation(receiver) {
+                        Some(Type::Custom(custom_type)) => custom_type,
+                        Some(Type::BuiltIn(BuiltInType::String)) => {
+                            if fun.function_id != "len" || !fun.arguments.is_empty() {
+                                panic!("Type `string` has no method `{}` at {}", fun.function_id, fun.range.start);
+                            }
+                            fun.function_id = method_name("string", &fun.function_id);
+                            return Some(Type::BuiltIn(BuiltInType::I64));
+                        },
+                        Some(type_) => panic!("Cannot call method `{}` on non-class type `{}` at {}", fun.function_id, type_, fun.range.start),
+                        None => panic!("Cannot resolve method `{}` at {}", fun.function_id, fun.range.start),
+                    };
+
+                    if !custom_type.has_method(&fun.function_id) {
+                        panic!("Class `{}` has no method `{}` at {}", custom_type.identifier, fun.function_id, fun.range.start);
+                    }
+
+                    fun.function_id = method_name(&custom_type.identifier, &fun.function_id);
+                }
+
+                // Functions defined later are not known yet
+                let function = self.tu.functions.iter().find(|f| f.identifier == fun.function_id)?;
+                function.return_type.clone()
+            },
+            OperationResult::StructLiteral(literal) => {
+                let custom_type = self.get_custom_type_from_id(&literal.type_id).cloned().unwrap_or_else(|| {
+                    panic!("Unknown class `{}` at {}", literal.type_id, literal.range.start)
+                });
+
+                let mut initialized : Vec<bool> = vec![false; custom_type.attributes.len()];
+                for init in literal.fields.iter_mut() {
+                    self.resolve_operation(&mut init.value);
+
+                    let index = custom_type.attribute_index(&init.field).unwrap_or_else(|| {
+                        panic!("Class `{}` has no field `{}` at {}", custom_type.identifier, init.field, init.range.start)
+                    });
+                    if initialized[index] {
+                        panic!("Field `{}` initialized more than once at {}", init.field, init.range.start);
+                    }
+                    initialized[index] = true;
+                }
+
+                if let Some(index) = initialized.iter().position(|init| !init) {
+                    panic!(
+                        "Field `{}` of class `{}` not initialized at {}",
+                        custom_type.attributes[index].identifier,
+                        custom_type.identifier,
+                        literal.range.start
+                    );
+                }
+
+                Some(Type::Custom(custom_type))
+            },
+            OperationResult::ArrayLiteral(literal) => {
+                let mut element_type : Option<Type> = None;
+                for element in literal.elements.iter_mut() {
+                    let type_ = self.resolve_operation(element);
+                    element_type = element_type.or(type_);
+                }
+
+                element_type.map(|type_| Type::Array(Box::new(type_), literal.elements.len()))
+            },
+            OperationResult::Index(index) => {
+                let object_type = self.resolve_operation(&mut index.object);
+                self.resolve_operation(&mut index.index);
+                match object_type? {
+                    Type::Array(element_type, _) => Some(*element_type),
+                    // Strings are indexed by byte
+                    Type::BuiltIn(BuiltInType::String) => Some(Type::BuiltIn(BuiltInType::U8)),
+                    _ => None,
+                }
+            },
+            OperationResult::MemberAccess(access) => {
+                let custom_type = match self.resolve_operation(&mut access.object)? {
+                    Type::Custom(custom_type) => custom_type,
+                    type_ => panic!("Cannot access field `{}` of non-class type `{}` at {}", access.field, type_, access.range.start),
+                };
+
+                match custom_type.attribute_index(&access.field) {
+                    Some(index) => Some(custom_type.attributes[index].type_.clone()),
+                    None => panic!("Class `{}` has no field `{}` at {}", custom_type.identifier, access.field, access.range.start),
+                }
+            },
+        }
+    }
+
+    // The lexeme always ends with `Token::EndOfFile` and the cursor never
+    // moves past it.
+    fn last_token(&self) -> &'a ContextedToken {
+        self.tokens.current().unwrap_or_else(|| panic!("Token stream does not end with EndOfFile"))
     }
 
-    fn next_token(&mut self) -> &ContextedToken {
-        self.current_token += 1;
-        self.lexeme.token_at(self.current_token)
+    fn next_token(&mut self) -> &'a ContextedToken {
+        self.incr_token();
+        self.last_token()
     }
 
     fn incr_token(&mut self) {
-        self.current_token += 1;
+        self.tokens.advance();
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.tokens.is_at_end()
+    }
+
+    fn unexpected_end_of_file(&self, message : &str, range : &Range) -> ! {
+        panic!("Unexpected end of file: {} at {}", message, range.start);
     }
 
     fn build(&mut self) {
-        while self.current_token < self.lexeme.len() {
+        while !self.is_at_end() {
             match self.read_statement() {
-                Some(Statement::Declaration(dec)) => self.tu.global_variables.push(dec),
+                Some(Statement::Declaration(dec)) => {
+                    if dec.init_value.as_ref().is_some_and(|value| !is_constant(value)) {
+                        panic!("Global variable `{}` must be initialized with a constant at {}", dec.identifier, dec.range.start);
+                    }
+                    self.tu.global_variables.push(dec)
+                },
                 Some(Statement::Function(fun)) => self.tu.functions.push(fun),
-                Some(Statement::Struct(type_)) => self.tu.custom_types.push(type_),
+                Some(Statement::Struct(_)) => {},
+                Some(Statement::Enum(_)) => {},
                 _ => panic!("Unexpected statement"),
             }
         }
     }
 
     fn read_statement(&mut self) -> Option<Statement> {
-        while let Token::EndOfStatement = self.last_token().token {
-            self.incr_token();
-        }
+        while self.tokens.eat(&Token::EndOfStatement) {}
 
         match self.last_token().token {
-            Token::EndOfStatement => None,
             Token::EndOfFile => None,
             Token::Delimiter(Delimiter::BracketsOpen) => Some(Statement::Body(self.read_body())),
-            Token::Keyword(Keyword::Def) => Some(Statement::Function(self.read_function())),
-            // Token::Keyword(Keyword::Return) => Some(Statement::Return(self.read_return())),
-            // Token::Keyword(Keyword::If) => Some(Statement::If(self.read_if())),
-            // Token::Keyword(Keyword::For) => Some(Statement::For(self.read_for())),
-            // Token::Keyword(Keyword::While) => Some(Statement::While(self.read_while())),
+            Token::Keyword(Keyword::Def) => Some(Statement::Function(self.read_function(None))),
+            Token::Keyword(Keyword::Class) => Some(Statement::Struct(self.read_class())),
+            Token::Keyword(Keyword::Enum) => Some(Statement::Enum(self.read_enum())),
+            Token::Keyword(Keyword::Match) => Some(Statement::Match(self.read_match())),
+            Token::Keyword(Keyword::Return) => {
+                let (value, range) = self.read_return();
+                Some(Statement::Return(value, range))
+            },
+            Token::Keyword(Keyword::If) => Some(Statement::If(self.read_if())),
+            Token::Keyword(Keyword::For) => Some(Statement::For(self.read_for())),
+            Token::Keyword(Keyword::While) => Some(Statement::While(self.read_while())),
+            Token::Keyword(Keyword::Else) => panic!("`else` without `if` at {}", self.tokens.position()),
             _ => {
                 if let Token::Identifier(id) = &self.last_token().token {
-                    if let Some(_) = self.get_type_from_id(id) {
+                    if self.get_type_from_id(id).is_some() {
                         return Some(Statement::Declaration(self.read_declaration()));
                     }
                 }
@@ -96,124 +335,603 @@ impl<'a> TUBuilder<'a> {
     }
 
     fn read_body(&mut self) -> Body {
+        let open_range = self.tokens.expect(Token::Delimiter(Delimiter::BracketsOpen)).range;
         let mut statements : Vec<Statement> = vec![];
+        self.scopes.push(HashMap::new());
         loop {
-            match self.next_token().token {
+            while self.tokens.eat(&Token::EndOfStatement) {}
+
+            match self.last_token().token {
                 Token::Delimiter(Delimiter::BracketsClose) => break,
+                Token::EndOfFile => self.unexpected_end_of_file("unclosed `{`", &open_range),
                 _ => {},
-            };
-            if let Some(s) = self.read_statement() {
-                statements.push(s);
+            }
+            match self.read_statement() {
+                Some(Statement::Struct(type_)) => panic!("Class `{}` must be defined at top level", type_.identifier),
+                Some(Statement::Enum(type_)) => panic!("Enum `{}` must be defined at top level", type_.identifier),
+                Some(s) => statements.push(s),
+                None => {},
             }
         }
 
         self.incr_token();
+        self.scopes.pop();
 
         Body {
             statements
         }
     }
 
-    fn read_function(&mut self) -> FunctionBlock {
+    fn read_function(&mut self, receiver : Option<std::rc::Rc<CustomType>>) -> FunctionBlock {
+        let def_range = self.tokens.expect(Token::Keyword(Keyword::Def)).range;
+
         // Read type
-        self.incr_token();
-        let ret_id_tok = self.last_token();
-        let return_type : Option<Type> = match &ret_id_tok.token {
-            Token::Keyword(Keyword::Void) => None,
-            Token::Identifier(id) => Some(
-                self
-                    .get_type_from_id(id)
-                    .unwrap_or_else(|| panic!("Unknown return type"))
-            ),
+        let return_type : Option<Type> = match &self.last_token().token {
+            Token::Keyword(Keyword::Void) => {self.incr_token(); None},
+            Token::Identifier(_) => Some(self.read_type()),
             _ => panic!("Expected function identifier"),
         };
 
         // Read identifier
-        let fun_id_tok = self.next_token();
+        let fun_id_tok = self.last_token();
         let fun_id : String = match &fun_id_tok.token {
             Token::Identifier(id) => id.clone(),
             _ => panic!("Expected function identifier"),
         };
 
-        let token = self.next_token();
-        match token.token {
-            Token::Delimiter(Delimiter::ParenthesisOpen) => {},
-            _ => panic!("Expected argument list"),
-        };
+        self.incr_token();
+        self.tokens.expect(Token::Delimiter(Delimiter::ParenthesisOpen));
 
         // Read argument list
         let mut arguments : Vec<Variable> = vec![];
 
         loop {
-            let type_id: String = match &self.next_token().token {
-                Token::Identifier(id) => id.clone(),
+            match &self.last_token().token {
+                Token::Identifier(_) => {},
                 Token::Delimiter(Delimiter::ParenthesisClose) => break,
-                _ => panic!("Expected identifier or end of argument list"),
+                Token::EndOfFile => self.unexpected_end_of_file("unclosed argument list of function", &def_range),
+                _ => panic!("Expected identifier or end of argument list at {}", self.tokens.position()),
             };
-            let arg_id: String = match &self.next_token().token {
+            let type_ = self.read_type();
+            let arg_id: String = match &self.last_token().token {
                 Token::Identifier(id) => id.clone(),
-                _ => panic!("Expected identifier"),
+                _ => panic!("Expected identifier at {}", self.tokens.position()),
             };
 
-            let type_ = self.get_type_from_id(&type_id).unwrap_or_else(|| panic!("Unknown type"));
-
             arguments.push(Variable {
                 identifier: arg_id,
                 type_
             });
+
+            self.incr_token();
+            if !self.tokens.check(&Token::Delimiter(Delimiter::ParenthesisClose)) && !self.is_at_end() {
+                self.tokens.expect(Token::Operator(Operator::Comma));
+            }
         }
 
         self.incr_token();
+        if self.is_at_end() {
+            self.unexpected_end_of_file("expected body of function", &def_range);
+        }
 
+        self.scopes.push(HashMap::new());
+        if let Some(custom_type) = &receiver {
+            self.declare_variable("self", &Type::Custom(custom_type.clone()));
+        }
+        for arg in arguments.iter() {
+            self.declare_variable(&arg.identifier, &arg.type_);
+        }
         let body = self.read_body();
+        self.scopes.pop();
+
+        let identifier = match &receiver {
+            Some(custom_type) => method_name(&custom_type.identifier, &fun_id),
+            None => fun_id,
+        };
 
         FunctionBlock {
-            identifier : fun_id,
+            identifier,
             arguments,
             return_type,
             body,
+            receiver,
+            range : def_range,
+        }
+    }
+
+    // Classes are read in two passes. The first one collects the fields
+    // and method names, so that method bodies, read in the second pass, can
+    // use all of them regardless of the order of declaration.
+    fn read_class(&mut self) -> std::rc::Rc<CustomType> {
+        let class_range = self.tokens.expect(Token::Keyword(Keyword::Class)).range;
+
+        let class_id : String = match &self.last_token().token {
+            Token::Identifier(id) => id.clone(),
+            _ => panic!("Expected class identifier at {}", self.tokens.position()),
+        };
+
+        if self.get_type_from_id(&class_id).is_some() {
+            panic!("Type `{}` already defined at {}", class_id, self.tokens.position());
+        }
+
+        self.incr_token();
+        self.tokens.expect(Token::Delimiter(Delimiter::BracketsOpen));
+        let body_begin = self.tokens.checkpoint();
+
+        // Read fields and method names
+        let mut attributes : Vec<Variable> = vec![];
+        let mut methods : Vec<String> = vec![];
+
+        loop {
+            while self.tokens.eat(&Token::EndOfStatement) {}
+
+            let member_range = self.last_token().range;
+            let member_id = match &self.last_token().token {
+                Token::Delimiter(Delimiter::BracketsClose) => break,
+                Token::EndOfFile => self.unexpected_end_of_file("unclosed class body", &class_range),
+                Token::Keyword(Keyword::Def) => {
+                    let method_id = self.skip_function();
+                    methods.push(method_id.clone());
+                    method_id
+                },
+                Token::Identifier(_) => {
+                    let type_ = self.read_type();
+
+                    let field_id = match &self.last_token().token {
+                        Token::Identifier(id) => id.clone(),
+                        _ => panic!("Expected field identifier at {}", self.tokens.position()),
+                    };
+
+                    self.incr_token();
+                    self.tokens.expect(Token::EndOfStatement);
+
+                    attributes.push(Variable {
+                        identifier : field_id.clone(),
+                        type_,
+                    });
+                    field_id
+                },
+                _ => panic!("Expected field or method declaration at {}", self.tokens.position()),
+            };
+
+            let n_members = attributes.iter().filter(|attr| attr.identifier == member_id).count()
+                + methods.iter().filter(|method| **method == member_id).count();
+            if n_members > 1 {
+                panic!("Duplicate member `{}` in class `{}` at {}", member_id, class_id, member_range.start);
+            }
+        }
+
+        let custom_type = std::rc::Rc::new(CustomType {
+            identifier : class_id,
+            attributes,
+            methods,
+        });
+        self.tu.custom_types.push(custom_type.clone());
+
+        // Read method bodies
+        self.tokens.rewind(body_begin);
+
+        loop {
+            while self.tokens.eat(&Token::EndOfStatement) {}
+
+            match &self.last_token().token {
+                Token::Delimiter(Delimiter::BracketsClose) => break,
+                Token::Keyword(Keyword::Def) => {
+                    let method = self.read_function(Some(custom_type.clone()));
+                    self.tu.functions.push(method);
+                },
+                _ => {
+                    while !self.tokens.eat(&Token::EndOfStatement) {
+                        self.incr_token();
+                    }
+                },
+            }
+        }
+
+        self.incr_token();
+
+        custom_type
+    }
+
+    // Variants without an explicit discriminant take the one after the
+    // previous variant, starting at 0.
+    fn read_enum(&mut self) -> std::rc::Rc<EnumType> {
+        let enum_range = self.tokens.expect(Token::Keyword(Keyword::Enum)).range;
+
+        let enum_id : String = match &self.last_token().token {
+            Token::Identifier(id) => id.clone(),
+            _ => panic!("Expected enum identifier at {}", self.tokens.position()),
+        };
+
+        if self.get_type_from_id(&enum_id).is_some() {
+            panic!("Type `{}` already defined at {}", enum_id, self.tokens.position());
+        }
+
+        self.incr_token();
+        self.tokens.expect(Token::Delimiter(Delimiter::BracketsOpen));
+
+        let mut variants : Vec<EnumVariant> = vec![];
+        let mut value : i64 = 0;
+        loop {
+            let variant_tok = self.last_token();
+            let variant_id = match &variant_tok.token {
+                Token::Delimiter(Delimiter::BracketsClose) => break,
+                Token::EndOfFile => self.unexpected_end_of_file("unclosed enum body", &enum_range),
+                Token::Identifier(id) => id.clone(),
+                _ => panic!("Expected variant identifier at {}", variant_tok.range.start),
+            };
+            self.incr_token();
+
+            if self.tokens.eat(&Token::Operator(Operator::Assign)) {
+                value = self.read_discriminant();
+            }
+
+            if i32::try_from(value).is_err() {
+                panic!("Discriminant {} of variant `{}` does not fit in an int32 at {}", value, variant_id, variant_tok.range.start);
+            }
+            if enum_type_has_variant(&variants, &variant_id) {
+                panic!("Duplicate variant `{}` in enum `{}` at {}", variant_id, enum_id, variant_tok.range.start);
+            }
+            if let Some(other) = variants.iter().find(|variant| variant.value == value) {
+                panic!("Discriminant {} of variant `{}` already used by `{}` at {}", value, variant_id, other.identifier, variant_tok.range.start);
+            }
+
+            variants.push(EnumVariant {
+                identifier : variant_id,
+                value,
+            });
+            value += 1;
+
+            if !self.tokens.check(&Token::Delimiter(Delimiter::BracketsClose)) {
+                self.tokens.expect(Token::Operator(Operator::Comma));
+            }
+        }
+
+        self.incr_token();
+
+        if variants.is_empty() {
+            panic!("Enum `{}` has no variants at {}", enum_id, enum_range.start);
+        }
+
+        let enum_type = std::rc::Rc::new(EnumType {
+            identifier : enum_id,
+            variants,
+        });
+        self.tu.enum_types.push(enum_type.clone());
+
+        enum_type
+    }
+
+    fn read_discriminant(&mut self) -> i64 {
+        let negative = self.tokens.eat(&Token::Operator(Operator::Minus));
+
+        let value_tok = self.last_token();
+        let value = match &value_tok.token {
+            Token::Integer(value) => value.parse::<i64>().ok(),
+            _ => None,
+        }.unwrap_or_else(|| panic!("Expected integer discriminant at {}", value_tok.range.start));
+        self.incr_token();
+
+        if negative { -value } else { value }
+    }
+
+    // Reads an operation up to the `{` opening a body, which is not consumed.
+    fn read_operation_before_body(&mut self, statement_range : &Range) -> (OperationResult, Option<Type>) {
+        let tok_begin = self.tokens.index();
+        let mut lookahead = self.tokens;
+        while !lookahead.check(&Token::Delimiter(Delimiter::BracketsOpen)) {
+            if lookahead.is_at_end() {
+                self.unexpected_end_of_file("expected `{`", statement_range);
+            }
+            lookahead.advance();
+        }
+        let tok_end = lookahead.index();
+
+        if tok_begin == tok_end {
+            panic!("Expected expression at {}", self.tokens.position());
+        }
+
+        let mut operation = OperationParser::new(&self.tokens.tokens()[tok_begin..tok_end]).parse();
+        let type_ = self.resolve_operation(&mut operation);
+        self.tokens = lookahead;
+
+        (operation, type_)
+    }
+
+    fn read_if(&mut self) -> IfBlock {
+        let if_range = self.tokens.expect(Token::Keyword(Keyword::If)).range;
+
+        let condition = self.read_operation_before_body(&if_range).0;
+        let body = self.read_body();
+
+        let else_body = if self.tokens.eat(&Token::Keyword(Keyword::Else)) {
+            match &self.last_token().token {
+                Token::Keyword(Keyword::If) => Some(Body {
+                    statements : vec![Statement::If(self.read_if())],
+                }),
+                _ => Some(self.read_body()),
+            }
+        } else {
+            None
+        };
+
+        IfBlock {
+            condition,
+            body,
+            else_body,
+            range : if_range,
+        }
+    }
+
+    fn read_while(&mut self) -> WhileBlock {
+        let while_range = self.tokens.expect(Token::Keyword(Keyword::While)).range;
+
+        let condition = self.read_operation_before_body(&while_range).0;
+
+        WhileBlock {
+            condition,
+            body : self.read_body(),
+            range : while_range,
+        }
+    }
+
+    // `for init; condition; end { ... }`, where `init` and `end` may be empty
+    fn read_for(&mut self) -> ForBlock {
+        let for_range = self.tokens.expect(Token::Keyword(Keyword::For)).range;
+
+        // Variables declared in `init` live until the end of the loop
+        self.scopes.push(HashMap::new());
+
+        let init_statement = match &self.last_token().token {
+            Token::EndOfStatement => {self.incr_token(); None},
+            Token::Identifier(id) if self.get_type_from_id(id).is_some() => {
+                Some(Box::new(Statement::Declaration(self.read_declaration())))
+            },
+            _ => Some(Box::new(Statement::Operation(self.read_operation()))),
+        };
+
+        let condition = self.read_operation();
+
+        let end_statement = match &self.last_token().token {
+            Token::Delimiter(Delimiter::BracketsOpen) => None,
+            _ => Some(self.read_operation_before_body(&for_range).0),
+        };
+
+        let body = self.read_body();
+        self.scopes.pop();
+
+        ForBlock {
+            init_statement,
+            condition,
+            end_statement,
+            body,
+            range : for_range,
+        }
+    }
+
+    fn read_match(&mut self) -> MatchBlock {
+        let match_range = self.tokens.expect(Token::Keyword(Keyword::Match)).range;
+
+        let (value, value_type) = self.read_operation_before_body(&match_range);
+        let enum_type = match value_type {
+            Some(Type::Enum(enum_type)) => enum_type,
+            Some(type_) => panic!("Cannot match on non-enum type `{}` at {}", type_, match_range.start),
+            None => panic!("Cannot resolve the type of the matched value at {}", match_range.start),
+        };
+
+        self.tokens.expect(Token::Delimiter(Delimiter::BracketsOpen));
+
+        let mut arms : Vec<MatchArm> = vec![];
+        let mut default : Option<Body> = None;
+        let mut covered : Vec<String> = vec![];
+        loop {
+            let arm_tok = self.last_token();
+            match &arm_tok.token {
+                Token::Delimiter(Delimiter::BracketsClose) => break,
+                Token::EndOfFile => self.unexpected_end_of_file("unclosed match body", &match_range),
+                Token::Identifier(id) if id == "_" => {
+                    if default.is_some() {
+                        panic!("Duplicate `_` arm at {}", arm_tok.range.start);
+                    }
+                    self.incr_token();
+                    self.tokens.expect(Token::Operator(Operator::Arrow));
+                    default = Some(self.read_body());
+                },
+                Token::Identifier(_) => {
+                    let mut variants : Vec<String> = vec![];
+                    loop {
+                        let variant_tok = self.last_token();
+                        let variant = match &variant_tok.token {
+                            Token::Identifier(id) => id.clone(),
+                            _ => panic!("Expected variant identifier at {}", variant_tok.range.start),
+                        };
+
+                        if enum_type.variant(&variant).is_none() {
+                            panic!("Enum `{}` has no variant `{}` at {}", enum_type.identifier, variant, variant_tok.range.start);
+                        }
+                        if covered.contains(&variant) {
+                            panic!("Variant `{}` matched more than once at {}", variant, variant_tok.range.start);
+                        }
+                        covered.push(variant.clone());
+                        variants.push(variant);
+
+                        self.incr_token();
+                        if !self.tokens.eat(&Token::Operator(Operator::Comma)) {
+                            break;
+                        }
+                    }
+
+                    self.tokens.expect(Token::Operator(Operator::Arrow));
+                    arms.push(MatchArm {
+                        variants,
+                        body : self.read_body(),
+                    });
+                },
+                _ => panic!("Expected variant or `_` at {}", arm_tok.range.start),
+            }
+        }
+
+        self.incr_token();
+
+        if default.is_none() {
+            let missing : Vec<String> = enum_type.variants
+                .iter()
+                .filter(|variant| !covered.contains(&variant.identifier))
+                .map(|variant| format!("`{}`", variant.identifier))
+                .collect();
+
+            if !missing.is_empty() {
+                panic!("Non-exhaustive match, {} not covered at {}", missing.join(", "), match_range.start);
+            }
+        }
+
+        MatchBlock {
+            value,
+            arms,
+            default,
+            range : match_range,
+        }
+    }
+
+    // Skips a function definition and returns its identifier.
+    fn skip_function(&mut self) -> String {
+        let def_range = self.tokens.expect(Token::Keyword(Keyword::Def)).range;
+
+        // The identifier is the one right before the argument list
+        let mut fun_id : Option<String> = None;
+        while !self.tokens.check(&Token::Delimiter(Delimiter::BracketsOpen)) {
+            if self.is_at_end() {
+                self.unexpected_end_of_file("expected body of function", &def_range);
+            }
+            if let (None, Token::Identifier(id), Some(Token::Delimiter(Delimiter::ParenthesisOpen))) =
+                (&fun_id, &self.last_token().token, self.tokens.peek_nth(1))
+            {
+                fun_id = Some(id.clone());
+            }
+            self.incr_token();
+        }
+        let fun_id = fun_id.unwrap_or_else(|| panic!("Expected function identifier at {}", def_range.start));
+
+        let mut n_brackets = 0;
+        loop {
+            match self.last_token().token {
+                Token::Delimiter(Delimiter::BracketsOpen) => n_brackets += 1,
+                Token::Delimiter(Delimiter::BracketsClose) => n_brackets -= 1,
+                Token::EndOfFile => self.unexpected_end_of_file("unclosed body of function", &def_range),
+                _ => {},
+            }
+            self.incr_token();
+            if n_brackets == 0 {
+                break;
+            }
         }
+
+        fun_id
     }
 
-    fn find_end_of_statement(&mut self) -> Option<usize> {
-        for n_tok in self.current_token..self.lexeme.len() {
-            if let Token::EndOfStatement = self.lexeme.token_at(n_tok).token {
-                return Some(n_tok);
+    fn read_return(&mut self) -> (Option<OperationResult>, Range) {
+        let return_range = self.tokens.expect(Token::Keyword(Keyword::Return)).range;
+
+        if self.tokens.eat(&Token::EndOfStatement) {
+            return (None, return_range);
+        }
+
+        (Some(self.read_operation()), return_range)
+    }
+
+    // Index of the `;` ending the current statement, or `None` at the end of
+    // the file. Braces of struct literals are skipped, but the `}` closing
+    // the enclosing body means the `;` is missing.
+    fn find_end_of_statement(&self) -> Option<usize> {
+        let mut lookahead = self.tokens;
+        let mut depth : usize = 0;
+        while let Some(tok) = lookahead.current() {
+            match tok.token {
+                Token::EndOfStatement => return Some(lookahead.index()),
+                Token::EndOfFile => return None,
+                Token::Delimiter(Delimiter::BracketsOpen) => depth += 1,
+                Token::Delimiter(Delimiter::BracketsClose) if depth == 0 => {
+                    panic!("Expected `;` before `}}` at {}", tok.range.start)
+                },
+                Token::Delimiter(Delimiter::BracketsClose) => depth -= 1,
+                _ => {},
             }
+            lookahead.advance();
         }
         None
     }
 
+    // Reads an operation up to the next `;` and consumes the `;`.
     fn read_operation(&mut self) -> OperationResult {
-        let tok_begin : usize = self.current_token;
-        let tok_end : usize = self.find_end_of_statement().unwrap_or_else(|| panic!("Did not find end of statement"));
+        let tok_begin : usize = self.tokens.index();
+        let tok_end : usize = self.find_end_of_statement().unwrap_or_else(|| {
+            self.unexpected_end_of_file("expected `;` after statement", &self.last_token().range)
+        });
 
-        self.current_token = tok_end;
+        if tok_begin == tok_end {
+            panic!("Expected expression at {}", self.tokens.position());
+        }
+
+        let mut operation = OperationParser::new(&self.tokens.tokens()[tok_begin..tok_end]).parse();
+        self.resolve_operation(&mut operation);
+
+        while self.tokens.index() <= tok_end {
+            self.incr_token();
+        }
 
-        OperationParser::new(self.lexeme, tok_begin, tok_end).parse()
+        operation
     }
 
     fn read_declaration(&mut self) -> VariableDeclaration {
-        let type_ = match &self.last_token().token {
-            Token::Identifier(id) => self.get_type_from_id(id).unwrap(),
-            _ => panic!("Expected identifier"),
-        };
+        let decl_range = self.last_token().range;
+        let type_ = self.read_type();
 
-        let var_id = match &self.next_token().token {
+        let var_tok = self.last_token();
+        let var_id = match &var_tok.token {
             Token::Identifier(id) => id.clone(),
             _ => panic!("Expected identifier"),
         };
+        let range = Range {
+            start : decl_range.start,
+            end : var_tok.range.end,
+        };
 
         let init_value = match &self.next_token().token {
-            Token::EndOfStatement => {None},
-            Token::Operator(Operator::Assign) => {self.incr_token();  Some(self.read_operation())},
+            Token::EndOfStatement => {self.incr_token(); None},
+            Token::Operator(Operator::Assign) => {
+                self.incr_token();
+                Some(self.read_operation())
+            },
+            Token::EndOfFile => self.unexpected_end_of_file("expected `;` after declaration", &decl_range),
             _ => panic!("Expected semicolon or assignment"),
         };
 
+        self.declare_variable(&var_id, &type_);
+
         VariableDeclaration {
             identifier: var_id,
             type_,
-            init_value
+            init_value,
+            range,
         }
     }
 }
+
+
+// Global initializers are written into the program: literals, enum values,
+// addresses of globals, and class and array literals of those
+fn is_constant(op : &OperationResult) -> bool {
+    match op {
+        OperationResult::Literal(_) | OperationResult::EnumValue(_) => true,
+        OperationResult::UnaryOpResult(unary) => {
+            matches!((&unary.operator, unary.operand.as_ref()), (UnaryOperator::AddressOf, OperationResult::Identifier(_)))
+        },
+        OperationResult::StructLiteral(literal) => literal.fields.iter().all(|init| is_constant(&init.value)),
+        OperationResult::ArrayLiteral(literal) => literal.elements.iter().all(is_constant),
+        _ => false,
+    }
+}
+
+fn enum_type_has_variant(variants : &[EnumVariant], id : &str) -> bool {
+    variants.iter().any(|variant| variant.identifier == id)
+}
@@ -1 +1,3 @@
-pub use super::ast::TranslationUnit;
\ No newline at end of file
+pub use super::ast::TranslationUnit;
+pub use super::cst::SyntaxTree;
+pub use super::types::check_types;
\ No newline at end of file
@@ -0,0 +1,571 @@
+use std::collections::HashMap;
+use std::marker::PhantomData;
+
+use super::ast::*;
+
+// Types every operation of `tu` and checks that each value converts to the
+// type it is used as. The parser only resolves names, so every type rule
+// lives here. The interpreter, the VM and the lowering to MIR run on the
+// result and take their types from it.
+pub fn check_types(tu : &TranslationUnit) -> Result<Types<'_>, String> {
+    TypeChecker::new(tu).check()
+}
+
+// `null` converts to a pointer to any type
+pub fn literal_type(lit : &Literal) -> Type {
+    Type::BuiltIn(match lit {
+        Literal::Integer(_) => BuiltInType::I32,
+        Literal::Float(_) => BuiltInType::F64,
+        Literal::String(_) => BuiltInType::String,
+        Literal::Boolean(_) => BuiltInType::Bool,
+        Literal::Null => return Type::Pointer(Box::new(Type::BuiltIn(BuiltInType::U8))),
+    })
+}
+
+pub fn field_of<'b>(type_ : &'b Type, field : &str) -> Result<(usize, &'b Type), String> {
+    let custom_type = match type_ {
+        Type::Custom(custom_type) => custom_type,
+        _ => return Err(format!("Cannot access field {} of a non-class value", field)),
+    };
+
+    let index = custom_type
+        .attribute_index(field)
+        .ok_or_else(|| format!("Class {} has no field {}", custom_type.identifier, field))?;
+
+    Ok((index, &custom_type.attributes[index].type_))
+}
+
+// Whether the operation is a place in memory, which can be assigned and
+// have its address taken. Fields and elements of temporary values are not.
+pub fn is_place(op : &OperationResult) -> bool {
+    match op {
+        OperationResult::Identifier(_) => true,
+        OperationResult::MemberAccess(access) => is_place(&access.object),
+        OperationResult::UnaryOpResult(UnaryOperation{operator : UnaryOperator::Deref, ..}) => true,
+        OperationResult::Index(index) => is_place(&index.object),
+        _ => false,
+    }
+}
+
+/****************************/
+/****************************/
+/****************************/
+
+// Literals and enum values are constants, which take the type of the value
+// they meet.
+#[derive(Debug, Clone, PartialEq)]
+struct Typed {
+    type_ : Type,
+    is_constant : bool,
+}
+
+// Operations are keyed by their address, which does not change while the
+// translation unit is borrowed.
+#[derive(Debug)]
+pub struct Types<'a> {
+    operations : HashMap<*const OperationResult, Typed>,
+    tu : PhantomData<&'a TranslationUnit>,
+}
+
+impl<'a> Types<'a> {
+    fn of(&self, op : &OperationResult) -> &Typed {
+        self.operations
+            .get(&(op as *const OperationResult))
+            .expect("operations are typed before they run")
+    }
+
+    pub fn type_of(&self, op : &OperationResult) -> &Type {
+        &self.of(op).type_
+    }
+
+    // Type both operands of a binary operation are converted to
+    pub fn operand_type(&self, bin_op : &BinaryOperation) -> &Type {
+        operand_type(self.of(&bin_op.left), self.of(&bin_op.right))
+    }
+}
+
+// Literals take the type of the other operand
+fn operand_type<'b>(left : &'b Typed, right : &'b Typed) -> &'b Type {
+    if left.is_constant && !right.is_constant { &right.type_ } else { &left.type_ }
+}
+
+// Integer constants convert to any built-in type but strings and float
+// constants to floats. Other values convert between any built-in types but
+// strings. Pointers only convert to the same type, except `null`.
+fn check_conversion(value : &Typed, type_ : &Type) -> Result<(), String> {
+    if value.type_ == *type_ {
+        return Ok(());
+    }
+
+    let convertible = match (&value.type_, type_) {
+        (Type::BuiltIn(from), Type::BuiltIn(to)) if value.is_constant && to.is_float() => from.is_float() || from.is_integer(),
+        (Type::BuiltIn(from), Type::BuiltIn(to)) if value.is_constant => !from.is_float() && *to != BuiltInType::String,
+        (Type::BuiltIn(from), Type::BuiltIn(to)) => *from != BuiltInType::String && *to != BuiltInType::String,
+        (Type::Pointer(_), Type::Pointer(_)) => value.is_constant,
+        _ => false,
+    };
+
+    match (convertible, value.is_constant) {
+        (true, _) => Ok(()),
+        (false, true) => Err(format!("Cannot convert constant of type {} to {}", value.type_, type_)),
+        (false, false) => Err(format!("Cannot convert {} to {}", value.type_, type_)),
+    }
+}
+
+/****************************/
+/****************************/
+/****************************/
+
+struct TypeChecker<'a> {
+    tu : &'a TranslationUnit,
+    functions : HashMap<&'a str, &'a FunctionBlock>,
+    global_variables : HashMap<&'a str, Type>,
+    scopes : Vec<HashMap<&'a str, Type>>,
+    return_type : Option<Type>,
+    operations : HashMap<*const OperationResult, Typed>,
+}
+
+impl<'a> TypeChecker<'a> {
+    fn new(tu : &'a TranslationUnit) -> TypeChecker<'a> {
+        TypeChecker {
+            tu,
+            functions : HashMap::new(),
+            global_variables : HashMap::new(),
+            scopes : vec![],
+            return_type : None,
+            operations : HashMap::new(),
+        }
+    }
+
+    // Functions can call each other regardless of their order in the
+    // source, globals only use the ones declared before them.
+    fn check(mut self) -> Result<Types<'a>, String> {
+        let tu = self.tu;
+        for fun in &tu.functions {
+            if self.functions.insert(&fun.identifier, fun).is_some() {
+                return Err(format!("Function {} already defined", fun.identifier));
+            }
+        }
+
+        for var in &tu.global_variables {
+            if self.global_variables.contains_key(var.identifier.as_str()) {
+                return Err(format!("Global variable {} already defined", var.identifier));
+            }
+            if let Some(init_value) = &var.init_value {
+                self.check_value(init_value, &var.type_)
+                    .map_err(|e| format!("In global {}: {}", var.identifier, e))?;
+            }
+            self.global_variables.insert(&var.identifier, var.type_.clone());
+        }
+
+        for fun in &tu.functions {
+            self.check_function(fun)
+                .map_err(|e| format!("In function {}: {}", fun.identifier, e))?;
+        }
+
+        Ok(Types {
+            operations : self.operations,
+            tu : PhantomData,
+        })
+    }
+
+    fn check_function(&mut self, fun : &'a FunctionBlock) -> Result<(), String> {
+        self.scopes = vec![HashMap::new()];
+        self.return_type = fun.return_type.clone();
+
+        // Methods get the object they are called on as `self`
+        if let Some(custom_type) = &fun.receiver {
+            self.declare("self", &Type::Custom(custom_type.clone()))?;
+        }
+        for param in &fun.arguments {
+            self.declare(&param.identifier, &param.type_)?;
+        }
+
+        self.check_body(&fun.body)
+    }
+
+    fn declare(&mut self, id : &'a str, type_ : &Type) -> Result<(), String> {
+        let scope = self.scopes.last_mut().expect("locals are declared inside a function");
+        if scope.insert(id, type_.clone()).is_some() {
+            return Err(format!("Variable {} already defined", id));
+        }
+        Ok(())
+    }
+
+    fn find_variable(&self, id : &str) -> Result<Type, String> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(id))
+            .or_else(|| self.global_variables.get(id))
+            .cloned()
+            .ok_or_else(|| format!("Variable {} not defined", id))
+    }
+
+    /*******************/
+    /*******************/
+    /*******************/
+
+    fn check_body(&mut self, body : &'a Body) -> Result<(), String> {
+        for stm in &body.statements {
+            self.check_statement(stm)?;
+        }
+        Ok(())
+    }
+
+    fn check_scoped_body(&mut self, body : &'a Body) -> Result<(), String> {
+        self.scopes.push(HashMap::new());
+        self.check_body(body)?;
+        self.scopes.pop();
+        Ok(())
+    }
+
+    // The initial value of a declaration cannot use the variable it declares
+    fn check_statement(&mut self, stm : &'a Statement) -> Result<(), String> {
+        match stm {
+            Statement::Declaration(decl) => {
+                if let Some(init_value) = &decl.init_value {
+                    self.check_value(init_value, &decl.type_)?;
+                }
+                self.declare(&decl.identifier, &decl.type_)?;
+            },
+            Statement::Operation(op) => self.check_operation_statement(op)?,
+            Statement::Return(ret, _) => {
+                match (ret, self.return_type.clone()) {
+                    (None, None) => {},
+                    (Some(op), Some(type_)) => {self.check_value(op, &type_)?;},
+                    (None, Some(_)) => return Err("Missing return value".to_string()),
+                    (Some(_), None) => return Err("Return value in void function".to_string()),
+                }
+            },
+            Statement::Body(body) => self.check_scoped_body(body)?,
+            Statement::If(if_block) => {
+                self.check_condition(&if_block.condition)?;
+                self.check_scoped_body(&if_block.body)?;
+                if let Some(else_body) = &if_block.else_body {
+                    self.check_scoped_body(else_body)?;
+                }
+            },
+            Statement::While(while_block) => {
+                self.check_condition(&while_block.condition)?;
+                self.check_scoped_body(&while_block.body)?;
+            },
+            Statement::For(for_block) => {
+                self.scopes.push(HashMap::new());
+                if let Some(init_statement) = &for_block.init_statement {
+                    self.check_statement(init_statement)?;
+                }
+                self.check_condition(&for_block.condition)?;
+                if let Some(end_statement) = &for_block.end_statement {
+                    self.check_operation_statement(end_statement)?;
+                }
+                self.check_scoped_body(&for_block.body)?;
+                self.scopes.pop();
+            },
+            Statement::Match(match_block) => {
+                let enum_type = match self.check_operation(&match_block.value)?.type_ {
+                    Type::Enum(enum_type) => enum_type,
+                    type_ => return Err(format!("Cannot match on a value of type {}", type_)),
+                };
+                for arm in &match_block.arms {
+                    if let Some(variant) = arm.variants.iter().find(|variant| enum_type.variant(variant).is_none()) {
+                        return Err(format!("Enum {} has no variant {}", enum_type.identifier, variant));
+                    }
+                    self.check_scoped_body(&arm.body)?;
+                }
+                if let Some(default) = &match_block.default {
+                    self.check_scoped_body(default)?;
+                }
+            },
+            _ => return Err("Unsupported statement type".to_string()),
+        }
+        Ok(())
+    }
+
+    // Calls are the only operations whose value may be `void`
+    fn check_operation_statement(&mut self, op : &'a OperationResult) -> Result<(), String> {
+        match op {
+            OperationResult::FuncResult(fun) => {self.check_call(fun)?;},
+            op => {self.check_operation(op)?;},
+        }
+        Ok(())
+    }
+
+    fn check_condition(&mut self, condition : &'a OperationResult) -> Result<(), String> {
+        match self.check_operation(condition)?.type_ {
+            Type::BuiltIn(BuiltInType::Bool) => Ok(()),
+            type_ => Err(format!("Condition must be a bool, found {}", type_)),
+        }
+    }
+
+    /*******************/
+    /*******************/
+    /*******************/
+
+    // Checks an operation used as a value of type `type_`. Array literals
+    // take that type directly.
+    fn check_value(&mut self, op : &'a OperationResult, type_ : &Type) -> Result<Typed, String> {
+        if let (OperationResult::ArrayLiteral(literal), Type::Array(element_type, len)) = (op, type_) {
+            if literal.elements.len() != *len {
+                return Err(format!("Cannot assign an array literal of length {} to {}", literal.elements.len(), type_));
+            }
+            for element in &literal.elements {
+                self.check_value(element, element_type)?;
+            }
+            return Ok(self.annotate(op, Typed {
+                type_ : type_.clone(),
+                is_constant : false,
+            }));
+        }
+
+        let value = self.check_operation(op)?;
+        check_conversion(&value, type_)?;
+        Ok(value)
+    }
+
+    // A place that is written to or has its address taken. Strings are
+    // immutable, so their characters are not.
+    fn check_place(&mut self, op : &'a OperationResult) -> Result<Typed, String> {
+        let typed = self.check_operation(op)?;
+        if let OperationResult::Index(index) = op {
+            if self.operations[&(index.object.as_ref() as *const OperationResult)].type_ == Type::BuiltIn(BuiltInType::String) {
+                return Err("Cannot modify a character of a string, strings are immutable".to_string());
+            }
+        }
+        Ok(typed)
+    }
+
+    fn annotate(&mut self, op : &OperationResult, typed : Typed) -> Typed {
+        self.operations.insert(op as *const OperationResult, typed.clone());
+        typed
+    }
+
+    fn check_operation(&mut self, op : &'a OperationResult) -> Result<Typed, String> {
+        let typed = match op {
+            OperationResult::Literal(lit) => Typed {
+                type_ : literal_type(lit),
+                is_constant : !matches!(lit, Literal::String(_)),
+            },
+            OperationResult::EnumValue(value) => {
+                if value.enum_type.variant(&value.variant).is_none() {
+                    return Err(format!("Enum {} has no variant {}", value.enum_type.identifier, value.variant));
+                }
+                Typed {
+                    type_ : Type::Enum(value.enum_type.clone()),
+                    is_constant : true,
+                }
+            },
+            OperationResult::Identifier(id) => Typed {
+                type_ : self.find_variable(id)?,
+                is_constant : false,
+            },
+            OperationResult::BinOpResult(bin_op) => self.check_binary_operation(bin_op)?,
+            OperationResult::UnaryOpResult(unary) => self.check_unary_operation(unary)?,
+            OperationResult::FuncResult(fun) => Typed {
+                type_ : self.check_call(fun)?.ok_or_else(|| format!("Function {} does not return a value", fun.function_id))?,
+                is_constant : false,
+            },
+            OperationResult::MemberAccess(access) => {
+                let object = self.check_operation(&access.object)?;
+                Typed {
+                    type_ : field_of(&object.type_, &access.field)?.1.clone(),
+                    is_constant : false,
+                }
+            },
+            OperationResult::StructLiteral(literal) => self.check_struct_literal(literal)?,
+            OperationResult::Index(index) => self.check_index(index)?,
+            OperationResult::ArrayLiteral(literal) => self.check_array_literal(literal)?,
+        };
+
+        Ok(self.annotate(op, typed))
+    }
+
+    fn check_binary_operation(&mut self, bin_op : &'a BinaryOperation) -> Result<Typed, String> {
+        // An assignment has the value stored, of the type of the place
+        if let BinaryOperator::Assign = bin_op.operator {
+            if !is_place(&bin_op.left) {
+                return Err("Left hand in assignment must be assignable".to_string());
+            }
+            let target = self.check_place(&bin_op.left)?;
+            self.check_value(&bin_op.right, &target.type_)?;
+            return Ok(Typed {
+                type_ : target.type_,
+                is_constant : false,
+            });
+        }
+
+        let left = self.check_operation(&bin_op.left)?;
+        let right = self.check_operation(&bin_op.right)?;
+        let operator = &bin_op.operator;
+
+        // `&&`, `||` and `^^` work on bools, and the first two only evaluate
+        // their right operand when the left one does not decide the value
+        if let BinaryOperator::And | BinaryOperator::Or | BinaryOperator::Xor = operator {
+            for operand in [&left, &right] {
+                if operand.type_ != Type::BuiltIn(BuiltInType::Bool) {
+                    return Err(format!("Logical operators need bool operands, found {}", operand.type_));
+                }
+            }
+            return Ok(Typed {
+                type_ : Type::BuiltIn(BuiltInType::Bool),
+                is_constant : false,
+            });
+        }
+
+        let type_ = operand_type(&left, &right).clone();
+        if !operator.is_comparison() && !matches!(type_, Type::BuiltIn(_)) {
+            return Err(format!("Binary operators are not supported on {}", type_));
+        }
+        check_conversion(&left, &type_)?;
+        check_conversion(&right, &type_)?;
+
+        let result_type = match &type_ {
+            Type::BuiltIn(_) | Type::Pointer(_) | Type::Enum(_) if operator.is_comparison() => Type::BuiltIn(BuiltInType::Bool),
+            Type::BuiltIn(BuiltInType::String) => match operator {
+                BinaryOperator::Plus => type_,
+                _ => return Err("Unsupported binary operator for strings".to_string()),
+            },
+            Type::BuiltIn(t) if t.is_float() => match operator {
+                BinaryOperator::Plus | BinaryOperator::Minus | BinaryOperator::Times | BinaryOperator::Div | BinaryOperator::Mod => type_,
+                _ => return Err("Unsupported binary operator for floating point values".to_string()),
+            },
+            Type::BuiltIn(_) => type_,
+            _ => return Err(format!("Binary operators are not supported on {}", type_)),
+        };
+
+        Ok(Typed {
+            type_ : result_type,
+            is_constant : false,
+        })
+    }
+
+    fn check_unary_operation(&mut self, unary : &'a UnaryOperation) -> Result<Typed, String> {
+        let type_ = match unary.operator {
+            UnaryOperator::AddressOf => {
+                if !is_place(&unary.operand) {
+                    return Err("Cannot take the address of a temporary value".to_string());
+                }
+                Type::Pointer(Box::new(self.check_place(&unary.operand)?.type_))
+            },
+            UnaryOperator::Deref => match self.check_operation(&unary.operand)?.type_ {
+                Type::Pointer(pointee_type) => *pointee_type,
+                type_ => return Err(format!("Cannot dereference a value of type {}", type_)),
+            },
+            UnaryOperator::Negate | UnaryOperator::Not => {
+                let type_ = self.check_operation(&unary.operand)?.type_;
+                let built_in_type = match &type_ {
+                    Type::BuiltIn(t) if *t != BuiltInType::String => t,
+                    type_ => return Err(format!("Unary operators are not supported on {}", type_)),
+                };
+                match (&unary.operator, built_in_type) {
+                    (UnaryOperator::Negate, _) | (UnaryOperator::Not, BuiltInType::Bool) => {},
+                    (UnaryOperator::Not, t) if t.is_integer() => {},
+                    _ => return Err(format!("Unsupported unary operator for {}", type_)),
+                }
+                type_
+            },
+        };
+
+        Ok(Typed {
+            type_,
+            is_constant : false,
+        })
+    }
+
+    // Returns the return type of the function called
+    fn check_call(&mut self, fun : &'a ApplyFunction) -> Result<Option<Type>, String> {
+        if let (Some(receiver), true) = (&fun.receiver, fun.function_id == method_name("string", "len")) {
+            return match self.check_operation(receiver)?.type_ {
+                Type::BuiltIn(BuiltInType::String) => Ok(Some(Type::BuiltIn(BuiltInType::I64))),
+                type_ => Err(format!("Type {} has no method len", type_)),
+            };
+        }
+
+        let function = *self.functions
+            .get(fun.function_id.as_str())
+            .ok_or_else(|| format!("Function {} not defined", fun.function_id))?;
+
+        if function.arguments.len() != fun.arguments.len() {
+            return Err(format!("Function {} expects {} arguments", fun.function_id, function.arguments.len()));
+        }
+
+        if let Some(receiver) = &fun.receiver {
+            self.check_operation(receiver)?;
+        }
+        for (arg, param) in fun.arguments.iter().zip(function.arguments.iter()) {
+            self.check_value(arg, &param.type_)?;
+        }
+
+        Ok(function.return_type.clone())
+    }
+
+    fn check_struct_literal(&mut self, literal : &'a StructLiteral) -> Result<Typed, String> {
+        let custom_type = self.tu.custom_types
+            .iter()
+            .find(|custom_type| custom_type.identifier == literal.type_id)
+            .cloned()
+            .ok_or_else(|| format!("Class {} not defined", literal.type_id))?;
+        let type_ = Type::Custom(custom_type);
+
+        for init in &literal.fields {
+            let field_type = field_of(&type_, &init.field)?.1.clone();
+            self.check_value(&init.value, &field_type)?;
+        }
+
+        Ok(Typed {
+            type_,
+            is_constant : false,
+        })
+    }
+
+    // Strings are indexed by byte
+    fn check_index(&mut self, index : &'a IndexAccess) -> Result<Typed, String> {
+        let object = self.check_operation(&index.object)?;
+        let element_type = match &object.type_ {
+            Type::Array(element_type, _) => element_type.as_ref().clone(),
+            Type::BuiltIn(BuiltInType::String) => Type::BuiltIn(BuiltInType::U8),
+            type_ => return Err(format!("Cannot index a value of type {}", type_)),
+        };
+
+        match self.check_operation(&index.index)?.type_ {
+            Type::BuiltIn(type_) if type_.is_integer() => {},
+            type_ => return Err(format!("Array index must be an integer, found {}", type_)),
+        }
+
+        // Constant indices into arrays are checked here, others at run time
+        if let (Type::Array(_, len), OperationResult::Literal(Literal::Integer(value))) = (&object.type_, index.index.as_ref()) {
+            if value.parse::<usize>().map_or(true, |value| value >= *len) {
+                return Err(format!("Index {} out of bounds for array of length {}", value, len));
+            }
+        }
+
+        Ok(Typed {
+            type_ : element_type,
+            is_constant : false,
+        })
+    }
+
+    // Without a type to take, an array literal takes the type of its first
+    // non constant element.
+    fn check_array_literal(&mut self, literal : &'a ArrayLiteral) -> Result<Typed, String> {
+        let elements = literal.elements
+            .iter()
+            .map(|element| self.check_operation(element))
+            .collect::<Result<Vec<Typed>, String>>()?;
+
+        let element_type = elements
+            .iter()
+            .find(|element| !element.is_constant)
+            .or(elements.first())
+            .map(|element| element.type_.clone())
+            .ok_or_else(|| "Cannot infer the type of an empty array literal".to_string())?;
+        for element in &elements {
+            check_conversion(element, &element_type)?;
+        }
+
+        Ok(Typed {
+            type_ : Type::Array(Box::new(element_type), elements.len()),
+            is_constant : false,
+        })
+    }
+}
@@ -0,0 +1,116 @@
+// Description of the machine the generated code runs on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Target {
+    pub triple : String,
+    pub datalayout : String,
+    // Width in bits of pointers, and of `isize` and `usize`
+    pub pointer_width : usize,
+    // ABI alignments in bits of pointers and of the integer and floating
+    // point types of a given width, as given by the datalayout
+    pub pointer_alignment : usize,
+    pub int_alignments : Vec<(usize, usize)>,
+    pub float_alignments : Vec<(usize, usize)>,
+}
+
+impl Target {
+    // Sizes and alignments are read from the datalayout, with the defaults
+    // of LLVM for the types it does not specify.
+    pub fn new(triple : &str, datalayout : &str) -> Result<Target, String> {
+        let mut target = Target {
+            triple : triple.to_string(),
+            datalayout : datalayout.to_string(),
+            pointer_width : 64,
+            pointer_alignment : 64,
+            int_alignments : vec![(1, 8), (8, 8), (16, 16), (32, 32), (64, 32)],
+            float_alignments : vec![(16, 16), (32, 32), (64, 64), (128, 128)],
+        };
+
+        let invalid = |specification : &str| format!("Invalid datalayout specification {} in {}", specification, datalayout);
+        for specification in datalayout.split('-').filter(|specification| !specification.is_empty()) {
+            let (kind, rest) = specification.split_at(1);
+            let mut parts = rest.split(':');
+            let first = parts.next().unwrap();
+            let mut numbers = parts.map(|part| part.parse::<usize>().map_err(|_| invalid(specification)));
+
+            match kind {
+                // Pointers of the address space 0 only
+                "p" if first.is_empty() || first == "0" => {
+                    target.pointer_width = numbers.next().ok_or_else(|| invalid(specification))??;
+                    target.pointer_alignment = numbers.next().ok_or_else(|| invalid(specification))??;
+                },
+                "i" | "f" => {
+                    let width = first.parse::<usize>().map_err(|_| invalid(specification))?;
+                    let alignment = numbers.next().ok_or_else(|| invalid(specification))??;
+                    let alignments = if kind == "i" {&mut target.int_alignments} else {&mut target.float_alignments};
+                    alignments.retain(|(other, _)| *other != width);
+                    alignments.push((width, alignment));
+                    alignments.sort();
+                },
+                _ => (),
+            }
+        }
+
+        if target.pointer_width == 0 || !target.pointer_width.is_multiple_of(8) {
+            return Err(invalid(&format!("p:{}", target.pointer_width)));
+        }
+
+        Ok(target)
+    }
+
+    pub fn x86_64() -> Target {
+        Target::new(
+            "x86_64-unknown-linux-gnu",
+            "e-m:e-p270:32:32-p271:32:32-p272:64:64-i64:64-f80:128-n8:16:32:64-S128",
+        ).unwrap()
+    }
+
+    pub fn aarch64() -> Target {
+        Target::new(
+            "aarch64-unknown-linux-gnu",
+            "e-m:e-i8:8:32-i16:16:32-i64:64-i128:128-n32:64-S128",
+        ).unwrap()
+    }
+
+    pub fn wasm32() -> Target {
+        Target::new(
+            "wasm32-unknown-unknown",
+            "e-m:e-p:32:32-p10:8:8-p20:8:8-i64:64-n32:64-S128-ni:1:10:20",
+        ).unwrap()
+    }
+
+    // `--target=x86_64`, `--target=aarch64` or `--target=wasm32`
+    pub fn from_flag(flag : &str) -> Option<Target> {
+        match flag.strip_prefix("--target=")? {
+            "x86_64" => Some(Target::x86_64()),
+            "aarch64" => Some(Target::aarch64()),
+            "wasm32" => Some(Target::wasm32()),
+            _ => None,
+        }
+    }
+
+    // Alignment in bytes of an integer, the one of the smallest larger
+    // integer in the datalayout, or of the largest one
+    pub fn int_alignment(&self, width : usize) -> usize {
+        let alignment = self.int_alignments
+            .iter()
+            .find(|(other, _)| *other >= width)
+            .or(self.int_alignments.last())
+            .map_or(width, |(_, alignment)| *alignment);
+        (alignment / 8).max(1)
+    }
+
+    // Alignment in bytes of a floating point number
+    pub fn float_alignment(&self, width : usize) -> usize {
+        let alignment = self.float_alignments
+            .iter()
+            .find(|(other, _)| *other == width)
+            .map_or(width, |(_, alignment)| *alignment);
+        (alignment / 8).max(1)
+    }
+}
+
+impl Default for Target {
+    fn default() -> Self {
+        Target::x86_64()
+    }
+}
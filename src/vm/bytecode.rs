@@ -0,0 +1,273 @@
+use std::fmt::Write;
+
+use crate::interp::value::Value;
+
+// Numeric types the arithmetic opcodes work on. `bool` is `U8` and
+// `isize`/`usize` are resolved to the pointer width when compiling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumType {
+    I8,
+    I16,
+    I32,
+    I64,
+    U8,
+    U16,
+    U32,
+    U64,
+    F32,
+    F64,
+}
+
+impl NumType {
+    pub fn to_string<'b>(&self) -> &'b str {
+        match self {
+            NumType::I8 => "i8",
+            NumType::I16 => "i16",
+            NumType::I32 => "i32",
+            NumType::I64 => "i64",
+            NumType::U8 => "u8",
+            NumType::U16 => "u16",
+            NumType::U32 => "u32",
+            NumType::U64 => "u64",
+            NumType::F32 => "f32",
+            NumType::F64 => "f64",
+        }
+    }
+
+    pub fn width(&self) -> u32 {
+        match self {
+            NumType::I8 | NumType::U8 => 8,
+            NumType::I16 | NumType::U16 => 16,
+            NumType::I32 | NumType::U32 | NumType::F32 => 32,
+            NumType::I64 | NumType::U64 | NumType::F64 => 64,
+        }
+    }
+
+    pub fn is_signed(&self) -> bool {
+        matches!(self, NumType::I8 | NumType::I16 | NumType::I32 | NumType::I64)
+    }
+
+    pub fn is_float(&self) -> bool {
+        matches!(self, NumType::F32 | NumType::F64)
+    }
+
+    // Truncates an integer to the width of the type and extends it back
+    // according to its signedness, like `Value::Int` expects.
+    pub fn wrap(&self, value : i64) -> i64 {
+        let shift = 64 - self.width();
+        if shift == 0 {
+            return value;
+        }
+
+        if self.is_signed() {
+            (value << shift) >> shift
+        } else {
+            (((value as u64) << shift) >> shift) as i64
+        }
+    }
+
+    pub fn round(&self, value : f64) -> f64 {
+        if *self == NumType::F32 { value as f32 as f64 } else { value }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+impl CmpOp {
+    pub fn to_string<'b>(&self) -> &'b str {
+        match self {
+            CmpOp::Eq => "eq",
+            CmpOp::Ne => "ne",
+            CmpOp::Lt => "lt",
+            CmpOp::Le => "le",
+            CmpOp::Gt => "gt",
+            CmpOp::Ge => "ge",
+        }
+    }
+}
+
+// Operands are indices into the constant pool (`#`), the locals of the
+// current function, the globals (`@`), the functions of the program or the
+// code of the current function (jump targets).
+//
+// Addresses are `Value::Pointer`s. Opcodes taking several operands from the
+// stack pop them in reverse, e.g. `Store` pops the value and then the
+// address it is stored at.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Const(u32),
+    Pop,
+    Dup,
+    Swap,
+
+    LoadLocal(u32),
+    StoreLocal(u32),
+    LoadGlobal(u32),
+    StoreGlobal(u32),
+
+    LocalAddr(u32),
+    GlobalAddr(u32),
+    // Address of a field of the class the popped address points to
+    FieldAddr(u32),
+    // Pops an `i64` index and an address of an array of the given length
+    ElementAddr(u32),
+    StringElementAddr,
+    Load,
+    // Leaves the stored value on the stack
+    Store,
+
+    ExtractField(u32),
+    InsertField(u32),
+    MakeAggregate(u32),
+
+    Add(NumType),
+    Sub(NumType),
+    Mul(NumType),
+    Div(NumType),
+    Rem(NumType),
+    And(NumType),
+    Or(NumType),
+    Xor(NumType),
+    // The string constant is the name of the source type, for the error
+    // on an out of range shift amount
+    Shl(NumType, u32),
+    Shr(NumType, u32),
+    Neg(NumType),
+    Not(NumType),
+    Cmp(CmpOp, NumType),
+    CmpPointer(CmpOp),
+    CmpString(CmpOp),
+    Convert(NumType, NumType),
+
+    Concat,
+    StringLen,
+
+    Jump(u32),
+    JumpIfFalse(u32),
+    JumpIfTrue(u32),
+    Call(u32),
+    Return,
+    ReturnVoid,
+    // Stops with the string constant as error
+    Trap(u32),
+}
+
+impl std::fmt::Display for Op {
+    fn fmt(&self, f : &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Op::Const(index) => write!(f, "const #{}", index),
+            Op::Pop => write!(f, "pop"),
+            Op::Dup => write!(f, "dup"),
+            Op::Swap => write!(f, "swap"),
+            Op::LoadLocal(index) => write!(f, "load_local {}", index),
+            Op::StoreLocal(index) => write!(f, "store_local {}", index),
+            Op::LoadGlobal(index) => write!(f, "load_global @{}", index),
+            Op::StoreGlobal(index) => write!(f, "store_global @{}", index),
+            Op::LocalAddr(index) => write!(f, "local_addr {}", index),
+            Op::GlobalAddr(index) => write!(f, "global_addr @{}", index),
+            Op::FieldAddr(index) => write!(f, "field_addr {}", index),
+            Op::ElementAddr(len) => write!(f, "element_addr {}", len),
+            Op::StringElementAddr => write!(f, "string_element_addr"),
+            Op::Load => write!(f, "load"),
+            Op::Store => write!(f, "store"),
+            Op::ExtractField(index) => write!(f, "extract_field {}", index),
+            Op::InsertField(index) => write!(f, "insert_field {}", index),
+            Op::MakeAggregate(len) => write!(f, "make_aggregate {}", len),
+            Op::Add(t) => write!(f, "add.{}", t.to_string()),
+            Op::Sub(t) => write!(f, "sub.{}", t.to_string()),
+            Op::Mul(t) => write!(f, "mul.{}", t.to_string()),
+            Op::Div(t) => write!(f, "div.{}", t.to_string()),
+            Op::Rem(t) => write!(f, "rem.{}", t.to_string()),
+            Op::And(t) => write!(f, "and.{}", t.to_string()),
+            Op::Or(t) => write!(f, "or.{}", t.to_string()),
+            Op::Xor(t) => write!(f, "xor.{}", t.to_string()),
+            Op::Shl(t, name) => write!(f, "shl.{} #{}", t.to_string(), name),
+            Op::Shr(t, name) => write!(f, "shr.{} #{}", t.to_string(), name),
+            Op::Neg(t) => write!(f, "neg.{}", t.to_string()),
+            Op::Not(t) => write!(f, "not.{}", t.to_string()),
+            Op::Cmp(op, t) => write!(f, "cmp.{}.{}", op.to_string(), t.to_string()),
+            Op::CmpPointer(op) => write!(f, "cmp_pointer.{}", op.to_string()),
+            Op::CmpString(op) => write!(f, "cmp_string.{}", op.to_string()),
+            Op::Convert(from, to) => write!(f, "convert.{}.{}", from.to_string(), to.to_string()),
+            Op::Concat => write!(f, "concat"),
+            Op::StringLen => write!(f, "string_len"),
+            Op::Jump(target) => write!(f, "jump {:04}", target),
+            Op::JumpIfFalse(target) => write!(f, "jump_if_false {:04}", target),
+            Op::JumpIfTrue(target) => write!(f, "jump_if_true {:04}", target),
+            Op::Call(index) => write!(f, "call {}", index),
+            Op::Return => write!(f, "return"),
+            Op::ReturnVoid => write!(f, "return_void"),
+            Op::Trap(index) => write!(f, "trap #{}", index),
+        }
+    }
+}
+
+/*******************/
+/*******************/
+/*******************/
+
+// The first `arity` locals are the arguments, with the address of the
+// object first for methods.
+#[derive(Debug, Clone)]
+pub struct Function {
+    pub identifier : String,
+    pub arity : u32,
+    pub locals : u32,
+    pub returns_value : bool,
+    pub code : Vec<Op>,
+}
+
+// `init` stores the initial values of the globals and runs before `main`.
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub constants : Vec<Value>,
+    pub globals : Vec<String>,
+    pub functions : Vec<Function>,
+    pub init : u32,
+    pub main : Option<u32>,
+}
+
+impl Program {
+    pub fn disassemble(&self) -> String {
+        let mut out = String::new();
+
+        writeln!(out, "constants:").unwrap();
+        for (i, constant) in self.constants.iter().enumerate() {
+            writeln!(out, "    #{} = {:?}", i, constant).unwrap();
+        }
+
+        writeln!(out, "globals:").unwrap();
+        for (i, global) in self.globals.iter().enumerate() {
+            writeln!(out, "    @{} = {}", i, global).unwrap();
+        }
+
+        for (i, function) in self.functions.iter().enumerate() {
+            writeln!(
+                out,
+                "\nfunction {} {} (arity {}, locals {}):",
+                i,
+                function.identifier,
+                function.arity,
+                function.locals
+            ).unwrap();
+
+            for (address, op) in function.code.iter().enumerate() {
+                match op {
+                    Op::Call(index) => writeln!(out, "    {:04}  {} ; {}", address, op, self.functions[*index as usize].identifier),
+                    Op::Const(index) | Op::Trap(index) => writeln!(out, "    {:04}  {} ; {:?}", address, op, self.constants[*index as usize]),
+                    _ => writeln!(out, "    {:04}  {}", address, op),
+                }.unwrap();
+            }
+        }
+
+        out
+    }
+}
@@ -0,0 +1,840 @@
+use std::collections::HashMap;
+
+use crate::parser::ast::*;
+use crate::parser::types::{check_types, field_of, is_place, Types};
+use crate::target::Target;
+use crate::interp::value::Value;
+use super::bytecode::*;
+
+pub fn compile_bytecode(tu : &TranslationUnit) -> Result<Program, String> {
+    compile_bytecode_with_target(tu, &Target::default())
+}
+
+// `isize` and `usize` take the pointer width of `target`.
+pub fn compile_bytecode_with_target(tu : &TranslationUnit, target : &Target) -> Result<Program, String> {
+    let types = check_types(tu)?;
+    BytecodeCompiler::new(tu, &types, target).compile()
+}
+
+/****************************/
+/****************************/
+/****************************/
+
+#[derive(Debug, Clone)]
+enum VariableKind {
+    Local(u32),
+    Global(u32),
+    // A local holding the address of the variable (`self` in methods)
+    Reference(u32),
+}
+
+#[derive(Debug, Clone)]
+struct Variable {
+    kind : VariableKind,
+    type_ : Type,
+}
+
+// Constants are not emitted until they are converted to the type they take
+// (see `check_types`).
+#[derive(Debug, Clone)]
+struct Compiled {
+    type_ : Type,
+    constant : Option<Value>,
+}
+
+struct BytecodeCompiler<'a> {
+    tu : &'a TranslationUnit,
+    types : &'a Types<'a>,
+    pointer_width : usize,
+    constants : Vec<Value>,
+    function_indices : HashMap<&'a str, u32>,
+    global_variables : HashMap<String, Variable>,
+    code : Vec<Op>,
+    scopes : Vec<HashMap<String, Variable>>,
+    locals : u32,
+    return_type : Option<Type>,
+}
+
+impl<'a> BytecodeCompiler<'a> {
+    fn new(tu : &'a TranslationUnit, types : &'a Types<'a>, target : &Target) -> BytecodeCompiler<'a> {
+        BytecodeCompiler {
+            tu,
+            types,
+            pointer_width : target.pointer_width,
+            constants : vec![],
+            // Function 0 initializes the globals
+            function_indices : tu.functions
+                .iter()
+                .enumerate()
+                .map(|(i, fun)| (fun.identifier.as_str(), i as u32 + 1))
+                .collect(),
+            global_variables : HashMap::new(),
+            code : vec![],
+            scopes : vec![],
+            locals : 0,
+            return_type : None,
+        }
+    }
+
+    fn compile(mut self) -> Result<Program, String> {
+        let init = self.compile_init()?;
+        let mut functions = vec![init];
+        for fun in &self.tu.functions {
+            let function = self.compile_function(fun)
+                .map_err(|e| format!("Error while compiling {}: {}", fun.identifier, e))?;
+            functions.push(function);
+        }
+
+        let main = self.function_indices.get("main").copied();
+        if let Some(main) = main {
+            if functions[main as usize].arity != 0 {
+                return Err("Function main cannot take arguments".to_string());
+            }
+        }
+
+        Ok(Program {
+            constants : self.constants,
+            globals : self.tu.global_variables
+                .iter()
+                .map(|var| var.identifier.clone())
+                .collect(),
+            functions,
+            init : 0,
+            main,
+        })
+    }
+
+    fn compile_init(&mut self) -> Result<Function, String> {
+        self.begin_function(None);
+
+        for (i, var) in self.tu.global_variables.iter().enumerate() {
+            match &var.init_value {
+                Some(init_value) => self.compile_value(init_value, &var.type_)
+                    .map_err(|e| format!("Error while compiling global {}: {}", var.identifier, e))?,
+                None => {
+                    let zero = self.zero_value(&var.type_);
+                    self.emit_constant(zero);
+                },
+            }
+            self.emit(Op::StoreGlobal(i as u32));
+
+            self.global_variables.insert(var.identifier.clone(), Variable {
+                kind : VariableKind::Global(i as u32),
+                type_ : var.type_.clone(),
+            });
+        }
+        self.emit(Op::ReturnVoid);
+
+        Ok(self.end_function("<init>", 0, false))
+    }
+
+    fn compile_function(&mut self, fun : &FunctionBlock) -> Result<Function, String> {
+        self.begin_function(fun.return_type.clone());
+
+        if let Some(custom_type) = &fun.receiver {
+            let slot = self.new_local();
+            self.scopes.last_mut().unwrap().insert("self".to_string(), Variable {
+                kind : VariableKind::Reference(slot),
+                type_ : Type::Custom(custom_type.clone()),
+            });
+        }
+        for param in &fun.arguments {
+            let slot = self.new_local();
+            self.scopes.last_mut().unwrap().insert(param.identifier.clone(), Variable {
+                kind : VariableKind::Local(slot),
+                type_ : param.type_.clone(),
+            });
+        }
+        let arity = self.locals;
+
+        self.compile_body(&fun.body)?;
+
+        // Falling off the end of a function with a return type is an error
+        match fun.return_type {
+            None => self.emit(Op::ReturnVoid),
+            Some(_) => {
+                let message = self.constant(Value::String(format!("Function {} ended without returning a value", fun.identifier).into_bytes()));
+                self.emit(Op::Trap(message));
+            },
+        }
+
+        Ok(self.end_function(&fun.identifier, arity, fun.return_type.is_some()))
+    }
+
+    fn begin_function(&mut self, return_type : Option<Type>) {
+        self.code = vec![];
+        self.scopes = vec![HashMap::new()];
+        self.locals = 0;
+        self.return_type = return_type;
+    }
+
+    fn end_function(&mut self, identifier : &str, arity : u32, returns_value : bool) -> Function {
+        Function {
+            identifier : identifier.to_string(),
+            arity,
+            locals : self.locals,
+            returns_value,
+            code : std::mem::take(&mut self.code),
+        }
+    }
+
+    /*******************/
+    /*******************/
+    /*******************/
+
+    fn emit(&mut self, op : Op) {
+        self.code.push(op);
+    }
+
+    // Emits a jump to be patched once its target is known.
+    fn emit_jump(&mut self, op : fn(u32) -> Op) -> usize {
+        self.code.push(op(0));
+        self.code.len() - 1
+    }
+
+    fn patch_jump(&mut self, at : usize) {
+        let target = self.code.len() as u32;
+        self.code[at] = match self.code[at] {
+            Op::Jump(_) => Op::Jump(target),
+            Op::JumpIfFalse(_) => Op::JumpIfFalse(target),
+            Op::JumpIfTrue(_) => Op::JumpIfTrue(target),
+            op => unreachable!("{} is not a jump", op),
+        };
+    }
+
+    fn constant(&mut self, value : Value) -> u32 {
+        // `-0.0 == 0.0`, so floats are compared bit by bit
+        let same = |c : &Value| match (c, &value) {
+            (Value::Float(c), Value::Float(v)) => c.to_bits() == v.to_bits(),
+            (c, v) => c == v,
+        };
+        match self.constants.iter().position(same) {
+            Some(index) => index as u32,
+            None => {
+                self.constants.push(value);
+                self.constants.len() as u32 - 1
+            },
+        }
+    }
+
+    fn emit_constant(&mut self, value : Value) {
+        let index = self.constant(value);
+        self.emit(Op::Const(index));
+    }
+
+    fn new_local(&mut self) -> u32 {
+        self.locals += 1;
+        self.locals - 1
+    }
+
+    fn find_variable(&self, id : &str) -> Variable {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(id))
+            .or_else(|| self.global_variables.get(id))
+            .cloned()
+            .expect("variables are declared before they are used")
+    }
+
+    fn num_type(&self, type_ : &BuiltInType) -> NumType {
+        match type_ {
+            BuiltInType::I8 => NumType::I8,
+            BuiltInType::I16 => NumType::I16,
+            BuiltInType::I32 => NumType::I32,
+            BuiltInType::I64 => NumType::I64,
+            BuiltInType::U8 | BuiltInType::Bool => NumType::U8,
+            BuiltInType::U16 => NumType::U16,
+            BuiltInType::U32 => NumType::U32,
+            BuiltInType::U64 => NumType::U64,
+            BuiltInType::F32 => NumType::F32,
+            BuiltInType::F64 => NumType::F64,
+            BuiltInType::ISize if self.pointer_width == 32 => NumType::I32,
+            BuiltInType::USize if self.pointer_width == 32 => NumType::U32,
+            BuiltInType::ISize => NumType::I64,
+            BuiltInType::USize => NumType::U64,
+            BuiltInType::String => unreachable!("strings are not numeric"),
+        }
+    }
+
+    fn zero_value(&self, type_ : &Type) -> Value {
+        match type_ {
+            Type::BuiltIn(t) if t.is_float() => Value::Float(0.0),
+            Type::BuiltIn(BuiltInType::String) => Value::String(vec![]),
+            Type::BuiltIn(_) | Type::Enum(_) => Value::Int(0),
+            Type::Pointer(_) => Value::Pointer(None),
+            Type::Custom(custom_type) => Value::Aggregate(
+                custom_type.attributes
+                    .iter()
+                    .map(|attr| self.zero_value(&attr.type_))
+                    .collect()
+            ),
+            Type::Array(element_type, len) => Value::Aggregate(vec![self.zero_value(element_type); *len]),
+        }
+    }
+
+    fn literal_to_value(lit : &Literal) -> Result<Value, String> {
+        match lit {
+            Literal::Integer(v) => {
+                let value = v.parse::<i64>()
+                    .or_else(|_| v.parse::<u64>().map(|v| v as i64))
+                    .map_err(|_| format!("Invalid integer literal {}", v))?;
+                Ok(Value::Int(value))
+            },
+            Literal::Float(v) => {
+                let value = v.parse::<f64>().map_err(|_| format!("Invalid float literal {}", v))?;
+                Ok(Value::Float(value))
+            },
+            Literal::Boolean(v) => Ok(Value::Int(*v as i64)),
+            Literal::String(text) => Ok(Value::String(text.as_bytes().to_vec())),
+            Literal::Null => Ok(Value::Pointer(None)),
+        }
+    }
+
+    /*******************/
+    /*******************/
+    /*******************/
+
+    // Emits the constant of `compiled`, if it still is one.
+    fn materialize(&mut self, compiled : Compiled) -> Type {
+        if let Some(value) = compiled.constant {
+            self.emit_constant(value);
+        }
+        compiled.type_
+    }
+
+    fn convert_constant(&self, value : &Value, to : &Type) -> Value {
+        match (value, to) {
+            (Value::Float(v), Type::BuiltIn(to)) => Value::Float(self.num_type(to).round(*v)),
+            (Value::Int(v), Type::BuiltIn(to)) if to.is_float() => Value::Float(self.num_type(to).round(*v as f64)),
+            (Value::Int(v), Type::BuiltIn(to)) => Value::Int(self.num_type(to).wrap(*v)),
+            _ => value.clone(),
+        }
+    }
+
+    // Same conversions as `write_conversion` in the LLVM backend, checked
+    // by `check_types`. Values that are not constants must be on top of the
+    // stack.
+    fn convert(&mut self, compiled : Compiled, type_ : &Type) -> Compiled {
+        if compiled.type_ == *type_ {
+            return compiled;
+        }
+
+        if let Some(value) = &compiled.constant {
+            return Compiled {
+                constant : Some(self.convert_constant(value, type_)),
+                type_ : type_.clone(),
+            };
+        }
+
+        let (from, to) = match (&compiled.type_, type_) {
+            (Type::BuiltIn(from), Type::BuiltIn(to)) => (self.num_type(from), self.num_type(to)),
+            _ => unreachable!("only built-in values are converted"),
+        };
+        if from != to {
+            self.emit(Op::Convert(from, to));
+        }
+
+        Compiled {
+            type_ : type_.clone(),
+            constant : None,
+        }
+    }
+
+    // Emits the address of a place and returns the type of its value.
+    fn compile_address(&mut self, op : &OperationResult) -> Result<Type, String> {
+        match op {
+            OperationResult::Identifier(id) => {
+                let var = self.find_variable(id);
+                self.emit(match var.kind {
+                    VariableKind::Local(slot) => Op::LocalAddr(slot),
+                    VariableKind::Global(index) => Op::GlobalAddr(index),
+                    VariableKind::Reference(slot) => Op::LoadLocal(slot),
+                });
+                Ok(var.type_)
+            },
+            OperationResult::MemberAccess(access) => {
+                let object_type = self.compile_address(&access.object)?;
+                let (index, field_type) = field_of(&object_type, &access.field)?;
+                self.emit(Op::FieldAddr(index as u32));
+                Ok(field_type.clone())
+            },
+            OperationResult::UnaryOpResult(UnaryOperation{operator : UnaryOperator::Deref, operand, ..}) => {
+                let pointer = self.compile_operation(operand)?;
+                self.materialize(pointer);
+                Ok(self.types.type_of(op).clone())
+            },
+            OperationResult::Index(index) => {
+                let array_type = self.compile_address(&index.object)?;
+                self.compile_element_address(&array_type, &index.index)?;
+                Ok(self.types.type_of(op).clone())
+            },
+            _ => unreachable!("only places have an address"),
+        }
+    }
+
+    // Like `compile_address`, but values that do not live in memory are
+    // copied to a temporary local.
+    fn compile_object_address(&mut self, op : &OperationResult) -> Result<Type, String> {
+        if is_place(op) {
+            return self.compile_address(op);
+        }
+
+        let value = self.compile_operation(op)?;
+        let type_ = self.materialize(value);
+        let slot = self.new_local();
+        self.emit(Op::StoreLocal(slot));
+        self.emit(Op::LocalAddr(slot));
+        Ok(type_)
+    }
+
+    // With the address of an array or string on the stack, emits the
+    // address of its element `index`.
+    fn compile_element_address(&mut self, array_type : &Type, index : &OperationResult) -> Result<(), String> {
+        self.compile_value(index, &Type::BuiltIn(BuiltInType::I64))?;
+        match array_type {
+            Type::Array(_, len) => self.emit(Op::ElementAddr(*len as u32)),
+            Type::BuiltIn(BuiltInType::String) => self.emit(Op::StringElementAddr),
+            _ => unreachable!("only arrays and strings are indexed"),
+        }
+        Ok(())
+    }
+
+    // Emits an operation as a value of type `type_`.
+    fn compile_value(&mut self, op : &OperationResult, type_ : &Type) -> Result<(), String> {
+        let value = self.compile_operation(op)?;
+        let value = self.convert(value, type_);
+        self.materialize(value);
+        Ok(())
+    }
+
+    fn compile_array_literal(&mut self, literal : &ArrayLiteral, type_ : &Type) -> Result<(), String> {
+        let element_type = match type_ {
+            Type::Array(element_type, _) => element_type,
+            _ => unreachable!("array literals have an array type"),
+        };
+        for element in &literal.elements {
+            self.compile_value(element, element_type)?;
+        }
+        self.emit(Op::MakeAggregate(literal.elements.len() as u32));
+        Ok(())
+    }
+
+    /*******************/
+    /*******************/
+    /*******************/
+
+    fn compile_operation(&mut self, op : &OperationResult) -> Result<Compiled, String> {
+        if let OperationResult::Identifier(id) = op {
+            let var = self.find_variable(id);
+            match var.kind {
+                VariableKind::Local(slot) => self.emit(Op::LoadLocal(slot)),
+                VariableKind::Global(index) => self.emit(Op::LoadGlobal(index)),
+                VariableKind::Reference(slot) => {
+                    self.emit(Op::LoadLocal(slot));
+                    self.emit(Op::Load);
+                },
+            }
+            return Ok(Compiled {
+                type_ : var.type_,
+                constant : None,
+            });
+        }
+
+        if is_place(op) {
+            let type_ = self.compile_address(op)?;
+            self.emit(Op::Load);
+            return Ok(Compiled {
+                type_,
+                constant : None,
+            });
+        }
+
+        let type_ = self.types.type_of(op).clone();
+        match op {
+            OperationResult::BinOpResult(bin_op) => self.compile_binary_operation(bin_op)?,
+            OperationResult::UnaryOpResult(unary) => self.compile_unary_operation(unary)?,
+            // Strings are not converted, they are emitted right away
+            OperationResult::Literal(lit @ Literal::String(_)) => {
+                let value = BytecodeCompiler::literal_to_value(lit)?;
+                self.emit_constant(value);
+            },
+            OperationResult::Literal(lit) => return Ok(Compiled {
+                type_,
+                constant : Some(BytecodeCompiler::literal_to_value(lit)?),
+            }),
+            OperationResult::EnumValue(value) => {
+                let variant = value.enum_type
+                    .variant(&value.variant)
+                    .expect("enum values are checked by the parser");
+                return Ok(Compiled {
+                    type_,
+                    constant : Some(Value::Int(variant.value)),
+                });
+            },
+            OperationResult::FuncResult(fun) => {self.compile_call(fun)?;},
+            OperationResult::MemberAccess(access) => {
+                // The object is not in memory (e.g. a returned struct)
+                let object = self.compile_operation(&access.object)?;
+                let object_type = self.materialize(object);
+                let (index, _) = field_of(&object_type, &access.field)?;
+                self.emit(Op::ExtractField(index as u32));
+            },
+            OperationResult::StructLiteral(literal) => {
+                // Fields are evaluated in source order, missing ones are zero
+                let zero = self.zero_value(&type_);
+                self.emit_constant(zero);
+                for init in &literal.fields {
+                    let (index, field_type) = field_of(&type_, &init.field)?;
+                    self.compile_value(&init.value, field_type)?;
+                    self.emit(Op::InsertField(index as u32));
+                }
+            },
+            OperationResult::Index(index) => {
+                // The array is not in memory (e.g. a returned array)
+                let array_type = self.compile_object_address(&index.object)?;
+                self.compile_element_address(&array_type, &index.index)?;
+                self.emit(Op::Load);
+            },
+            OperationResult::ArrayLiteral(literal) => self.compile_array_literal(literal, &type_)?,
+            OperationResult::Identifier(_) => unreachable!("identifiers are always places"),
+        }
+
+        Ok(Compiled {
+            type_,
+            constant : None,
+        })
+    }
+
+    fn compile_assignment(&mut self, bin_op : &BinaryOperation) -> Result<(), String> {
+        let var = match bin_op.left.as_ref() {
+            OperationResult::Identifier(id) => Some(self.find_variable(id)),
+            _ => None,
+        };
+        match var {
+            Some(Variable{kind : VariableKind::Local(slot), type_}) => {
+                self.compile_value(&bin_op.right, &type_)?;
+                self.emit(Op::Dup);
+                self.emit(Op::StoreLocal(slot));
+            },
+            Some(Variable{kind : VariableKind::Global(index), type_}) => {
+                self.compile_value(&bin_op.right, &type_)?;
+                self.emit(Op::Dup);
+                self.emit(Op::StoreGlobal(index));
+            },
+            _ => {
+                let type_ = self.compile_address(&bin_op.left)?;
+                self.compile_value(&bin_op.right, &type_)?;
+                self.emit(Op::Store);
+            },
+        }
+        Ok(())
+    }
+
+    fn compile_binary_operation(&mut self, bin_op : &BinaryOperation) -> Result<(), String> {
+        match bin_op.operator {
+            BinaryOperator::Assign => return self.compile_assignment(bin_op),
+            BinaryOperator::And => return self.compile_short_circuit(bin_op, Op::JumpIfFalse),
+            BinaryOperator::Or => return self.compile_short_circuit(bin_op, Op::JumpIfTrue),
+            _ => {},
+        }
+
+        let left = self.compile_operation(&bin_op.left)?;
+        let right = self.compile_operation(&bin_op.right)?;
+
+        // Only a constant left operand can come after the right one, it is
+        // swapped back then.
+        let type_ = self.types.operand_type(bin_op).clone();
+        let swap = left.constant.is_some() && right.constant.is_none();
+        if left.constant.is_none() || swap {
+            let right = self.convert(right, &type_);
+            self.materialize(right);
+            let left = self.convert(left, &type_);
+            self.materialize(left);
+            if swap {
+                self.emit(Op::Swap);
+            }
+        } else {
+            let left = self.convert(left, &type_);
+            self.materialize(left);
+            let right = self.convert(right, &type_);
+            self.materialize(right);
+        }
+
+        let op = &bin_op.operator;
+        let cmp_op = match op {
+            BinaryOperator::Equals => Some(CmpOp::Eq),
+            BinaryOperator::NotEquals => Some(CmpOp::Ne),
+            BinaryOperator::Less => Some(CmpOp::Lt),
+            BinaryOperator::LessEq => Some(CmpOp::Le),
+            BinaryOperator::Greater => Some(CmpOp::Gt),
+            BinaryOperator::GreaterEq => Some(CmpOp::Ge),
+            _ => None,
+        };
+
+        let built_in_type = match (&type_, cmp_op) {
+            (Type::BuiltIn(BuiltInType::String), Some(cmp_op)) => {
+                self.emit(Op::CmpString(cmp_op));
+                return Ok(());
+            },
+            (Type::BuiltIn(BuiltInType::String), None) => {
+                self.emit(Op::Concat);
+                return Ok(());
+            },
+            (Type::Pointer(_), Some(cmp_op)) => {
+                self.emit(Op::CmpPointer(cmp_op));
+                return Ok(());
+            },
+            (Type::Enum(_), _) => BuiltInType::I32,
+            (Type::BuiltIn(t), _) => t.clone(),
+            _ => unreachable!("binary operators are checked by `check_types`"),
+        };
+        let num_type = self.num_type(&built_in_type);
+
+        if let Some(cmp_op) = cmp_op {
+            self.emit(Op::Cmp(cmp_op, num_type));
+            return Ok(());
+        }
+
+        if let BinaryOperator::LShift | BinaryOperator::RShift = op {
+            let name = self.constant(Value::String(built_in_type.to_string().as_bytes().to_vec()));
+            self.emit(match op {
+                BinaryOperator::LShift => Op::Shl(num_type, name),
+                _ => Op::Shr(num_type, name),
+            });
+            return Ok(());
+        }
+
+        self.emit(match op {
+            BinaryOperator::Plus => Op::Add(num_type),
+            BinaryOperator::Minus => Op::Sub(num_type),
+            BinaryOperator::Times => Op::Mul(num_type),
+            BinaryOperator::Div => Op::Div(num_type),
+            BinaryOperator::Mod => Op::Rem(num_type),
+            BinaryOperator::BitAnd => Op::And(num_type),
+            BinaryOperator::BitOr => Op::Or(num_type),
+            BinaryOperator::Xor | BinaryOperator::BitXor => Op::Xor(num_type),
+            _ => unreachable!("comparisons are compiled apart"),
+        });
+        Ok(())
+    }
+
+    // The left operand is the value when `jump` takes it past the right one
+    fn compile_short_circuit(&mut self, bin_op : &BinaryOperation, jump : fn(u32) -> Op) -> Result<(), String> {
+        let left = self.compile_operation(&bin_op.left)?;
+        self.materialize(left);
+        self.emit(Op::Dup);
+        let to_end = self.emit_jump(jump);
+
+        self.emit(Op::Pop);
+        let right = self.compile_operation(&bin_op.right)?;
+        self.materialize(right);
+        self.patch_jump(to_end);
+        Ok(())
+    }
+
+    fn compile_unary_operation(&mut self, unary : &UnaryOperation) -> Result<(), String> {
+        if let UnaryOperator::AddressOf = unary.operator {
+            self.compile_address(&unary.operand)?;
+            return Ok(());
+        }
+
+        let operand = self.compile_operation(&unary.operand)?;
+        let built_in_type = match self.materialize(operand) {
+            Type::BuiltIn(t) => t,
+            _ => unreachable!("unary operators are checked by `check_types`"),
+        };
+        let num_type = self.num_type(&built_in_type);
+
+        match (&unary.operator, built_in_type) {
+            (UnaryOperator::Negate, _) => self.emit(Op::Neg(num_type)),
+            (UnaryOperator::Not, BuiltInType::Bool) => {
+                self.emit_constant(Value::Int(1));
+                self.emit(Op::Xor(num_type));
+            },
+            (UnaryOperator::Not, _) => self.emit(Op::Not(num_type)),
+            _ => unreachable!("unary operators are checked by `check_types`"),
+        }
+        Ok(())
+    }
+
+    fn compile_call(&mut self, fun : &ApplyFunction) -> Result<Option<Type>, String> {
+        if let (Some(receiver), true) = (&fun.receiver, fun.function_id == method_name("string", "len")) {
+            let string = self.compile_operation(receiver)?;
+            self.materialize(string);
+            self.emit(Op::StringLen);
+            return Ok(Some(Type::BuiltIn(BuiltInType::I64)));
+        }
+
+        let index = self.function_indices[fun.function_id.as_str()];
+        let function = &self.tu.functions[index as usize - 1];
+
+        if let Some(receiver) = &fun.receiver {
+            self.compile_object_address(receiver)?;
+        }
+        for (arg, param) in fun.arguments.iter().zip(function.arguments.iter()) {
+            self.compile_value(arg, &param.type_)?;
+        }
+
+        self.emit(Op::Call(index));
+        Ok(function.return_type.clone())
+    }
+
+    /*******************/
+    /*******************/
+    /*******************/
+
+    fn compile_body(&mut self, body : &Body) -> Result<(), String> {
+        for stm in &body.statements {
+            self.compile_statement(stm)?;
+        }
+        Ok(())
+    }
+
+    fn compile_scoped_body(&mut self, body : &Body) -> Result<(), String> {
+        self.scopes.push(HashMap::new());
+        self.compile_body(body)?;
+        self.scopes.pop();
+        Ok(())
+    }
+
+    fn compile_condition(&mut self, condition : &OperationResult) -> Result<(), String> {
+        let value = self.compile_operation(condition)?;
+        self.materialize(value);
+        Ok(())
+    }
+
+    fn compile_operation_statement(&mut self, op : &OperationResult) -> Result<(), String> {
+        let returns_value = match op {
+            OperationResult::FuncResult(fun) => self.compile_call(fun)?.is_some(),
+            op => self.compile_operation(op)?.constant.is_none(),
+        };
+        if returns_value {
+            self.emit(Op::Pop);
+        }
+        Ok(())
+    }
+
+    fn compile_statement(&mut self, stm : &Statement) -> Result<(), String> {
+        match stm {
+            Statement::Declaration(decl) => {
+                // The initial value cannot use the variable it initializes
+                match &decl.init_value {
+                    Some(init_value) => self.compile_value(init_value, &decl.type_)?,
+                    None => {
+                        let zero = self.zero_value(&decl.type_);
+                        self.emit_constant(zero);
+                    },
+                }
+
+                let slot = self.new_local();
+                self.scopes.last_mut().unwrap().insert(decl.identifier.clone(), Variable {
+                    kind : VariableKind::Local(slot),
+                    type_ : decl.type_.clone(),
+                });
+                self.emit(Op::StoreLocal(slot));
+            },
+            Statement::Operation(op) => self.compile_operation_statement(op)?,
+            Statement::Return(ret, _) => {
+                match (ret, self.return_type.clone()) {
+                    (None, None) => self.emit(Op::ReturnVoid),
+                    (Some(op), Some(type_)) => {
+                        self.compile_value(op, &type_)?;
+                        self.emit(Op::Return);
+                    },
+                    _ => unreachable!("return values are checked against the return type"),
+                }
+            },
+            Statement::Body(body) => self.compile_scoped_body(body)?,
+            Statement::If(if_block) => {
+                self.compile_condition(&if_block.condition)?;
+                let to_else = self.emit_jump(Op::JumpIfFalse);
+                self.compile_scoped_body(&if_block.body)?;
+
+                match &if_block.else_body {
+                    Some(else_body) => {
+                        let to_end = self.emit_jump(Op::Jump);
+                        self.patch_jump(to_else);
+                        self.compile_scoped_body(else_body)?;
+                        self.patch_jump(to_end);
+                    },
+                    None => self.patch_jump(to_else),
+                }
+            },
+            Statement::While(while_block) => self.compile_loop(&while_block.condition, None, &while_block.body)?,
+            Statement::For(for_block) => {
+                self.scopes.push(HashMap::new());
+                if let Some(init_statement) = &for_block.init_statement {
+                    self.compile_statement(init_statement)?;
+                }
+                self.compile_loop(&for_block.condition, for_block.end_statement.as_ref(), &for_block.body)?;
+                self.scopes.pop();
+            },
+            Statement::Match(match_block) => self.compile_match(match_block)?,
+            _ => return Err("Unsupported statement type".to_string()),
+        }
+        Ok(())
+    }
+
+    fn compile_loop(&mut self, condition : &OperationResult, end_statement : Option<&OperationResult>, body : &Body) -> Result<(), String> {
+        let start = self.code.len() as u32;
+        self.compile_condition(condition)?;
+        let to_end = self.emit_jump(Op::JumpIfFalse);
+
+        self.compile_scoped_body(body)?;
+        if let Some(end_statement) = end_statement {
+            self.compile_operation_statement(end_statement)?;
+        }
+        self.emit(Op::Jump(start));
+        self.patch_jump(to_end);
+        Ok(())
+    }
+
+    // The matched value is kept in a local and compared with the values of
+    // each arm in turn. Without a `_` arm, no arm matching is an error.
+    fn compile_match(&mut self, match_block : &MatchBlock) -> Result<(), String> {
+        let value = self.compile_operation(&match_block.value)?;
+        let enum_type = match self.materialize(value) {
+            Type::Enum(enum_type) => enum_type,
+            _ => unreachable!("only enums are matched"),
+        };
+        let slot = self.new_local();
+        self.emit(Op::StoreLocal(slot));
+
+        let mut to_arms : Vec<Vec<usize>> = vec![];
+        for arm in &match_block.arms {
+            let mut jumps = vec![];
+            for variant_id in &arm.variants {
+                let variant = enum_type.variant(variant_id).expect("arms are checked by the parser");
+                self.emit(Op::LoadLocal(slot));
+                self.emit_constant(Value::Int(variant.value));
+                self.emit(Op::Cmp(CmpOp::Eq, NumType::I32));
+                jumps.push(self.emit_jump(Op::JumpIfTrue));
+            }
+            to_arms.push(jumps);
+        }
+
+        let mut to_end = vec![];
+        match &match_block.default {
+            Some(default) => self.compile_scoped_body(default)?,
+            None => {
+                let message = self.constant(Value::String(format!("No arm of match for {} value", enum_type.identifier).into_bytes()));
+                self.emit(Op::Trap(message));
+            },
+        }
+        to_end.push(self.emit_jump(Op::Jump));
+
+        for (arm, jumps) in match_block.arms.iter().zip(to_arms) {
+            for jump in jumps {
+                self.patch_jump(jump);
+            }
+            self.compile_scoped_body(&arm.body)?;
+            to_end.push(self.emit_jump(Op::Jump));
+        }
+
+        for jump in to_end {
+            self.patch_jump(jump);
+        }
+        Ok(())
+    }
+}
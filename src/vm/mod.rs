@@ -0,0 +1,4 @@
+pub mod bytecode;
+pub mod compiler;
+pub mod vm;
+pub mod prelude;
\ No newline at end of file
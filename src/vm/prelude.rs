@@ -0,0 +1,3 @@
+pub use super::bytecode::{Op, NumType, CmpOp, Function, Program};
+pub use super::compiler::{compile_bytecode, compile_bytecode_with_target};
+pub use super::vm::run_bytecode;
\ No newline at end of file
@@ -0,0 +1,385 @@
+use std::cmp::Ordering;
+
+use crate::interp::interp::MAX_CALL_DEPTH;
+use crate::interp::value::{Value, Place};
+use super::bytecode::*;
+
+// Runs the globals initializer and `main`, and returns the result of `main`
+// or `None` if it returns `void`.
+pub fn run_bytecode(program : &Program) -> Result<Option<Value>, String> {
+    let main = program.main.ok_or_else(|| "Function main not defined".to_string())?;
+
+    let mut vm = VM::new(program);
+    vm.call(program.init)?;
+    vm.execute()?;
+
+    vm.call(main)?;
+    vm.execute()
+}
+
+/****************************/
+/****************************/
+/****************************/
+
+struct Frame {
+    function : usize,
+    ip : usize,
+    locals : Vec<Place>,
+}
+
+struct VM<'a> {
+    program : &'a Program,
+    globals : Vec<Place>,
+    stack : Vec<Value>,
+    frames : Vec<Frame>,
+}
+
+impl<'a> VM<'a> {
+    fn new(program : &'a Program) -> VM<'a> {
+        VM {
+            program,
+            globals : program.globals
+                .iter()
+                .map(|_| Place::new(Value::Int(0)))
+                .collect(),
+            stack : vec![],
+            frames : vec![],
+        }
+    }
+
+    fn pop(&mut self) -> Result<Value, String> {
+        self.stack.pop().ok_or_else(|| "Stack underflow".to_string())
+    }
+
+    fn pop_int(&mut self) -> Result<i64, String> {
+        match self.pop()? {
+            Value::Int(value) => Ok(value),
+            value => Err(format!("Expected an integer, found {:?}", value)),
+        }
+    }
+
+    fn pop_place(&mut self) -> Result<Place, String> {
+        match self.pop()? {
+            Value::Pointer(Some(place)) => Ok(place),
+            Value::Pointer(None) => Err("Null pointer dereference".to_string()),
+            value => Err(format!("Expected an address, found {:?}", value)),
+        }
+    }
+
+    fn pop_string(&mut self) -> Result<Vec<u8>, String> {
+        match self.pop()? {
+            Value::String(bytes) => Ok(bytes),
+            value => Err(format!("Expected a string, found {:?}", value)),
+        }
+    }
+
+    fn call(&mut self, function : u32) -> Result<(), String> {
+        if self.frames.len() >= MAX_CALL_DEPTH {
+            return Err("Stack overflow".to_string());
+        }
+
+        let function_index = function as usize;
+        let function = &self.program.functions[function_index];
+        let arity = function.arity as usize;
+        if self.stack.len() < arity {
+            return Err("Stack underflow".to_string());
+        }
+
+        let mut locals : Vec<Place> = self.stack
+            .drain(self.stack.len()-arity..)
+            .map(Place::new)
+            .collect();
+        locals.resize_with(function.locals as usize, || Place::new(Value::Int(0)));
+
+        self.frames.push(Frame {
+            function : function_index,
+            ip : 0,
+            locals,
+        });
+        Ok(())
+    }
+
+    // Runs until the frame on top when called returns.
+    fn execute(&mut self) -> Result<Option<Value>, String> {
+        let depth = self.frames.len();
+
+        loop {
+            let frame = self.frames.last_mut().unwrap();
+            let function = &self.program.functions[frame.function];
+            let op = *function.code
+                .get(frame.ip)
+                .ok_or_else(|| format!("Execution went past the end of {}", function.identifier))?;
+            frame.ip += 1;
+
+            match op {
+                Op::Const(index) => self.stack.push(self.program.constants[index as usize].clone()),
+                Op::Pop => {self.pop()?;},
+                Op::Dup => {
+                    let value = self.stack.last().cloned().ok_or_else(|| "Stack underflow".to_string())?;
+                    self.stack.push(value);
+                },
+                Op::Swap => {
+                    let len = self.stack.len();
+                    if len < 2 {
+                        return Err("Stack underflow".to_string());
+                    }
+                    self.stack.swap(len-1, len-2);
+                },
+
+                Op::LoadLocal(index) => {
+                    let value = self.frames.last().unwrap().locals[index as usize].read()?;
+                    self.stack.push(value);
+                },
+                Op::StoreLocal(index) => {
+                    let value = self.pop()?;
+                    self.frames.last().unwrap().locals[index as usize].write(value)?;
+                },
+                Op::LoadGlobal(index) => {
+                    let value = self.globals[index as usize].read()?;
+                    self.stack.push(value);
+                },
+                Op::StoreGlobal(index) => {
+                    let value = self.pop()?;
+                    self.globals[index as usize].write(value)?;
+                },
+
+                Op::LocalAddr(index) => {
+                    let place = self.frames.last().unwrap().locals[index as usize].clone();
+                    self.stack.push(Value::Pointer(Some(place)));
+                },
+                Op::GlobalAddr(index) => self.stack.push(Value::Pointer(Some(self.globals[index as usize].clone()))),
+                Op::FieldAddr(index) => {
+                    let place = self.pop_place()?;
+                    self.stack.push(Value::Pointer(Some(place.element(index as usize))));
+                },
+                Op::ElementAddr(len) => {
+                    let index = self.pop_int()?;
+                    let place = self.pop_place()?;
+                    let index = VM::check_index(index, len as usize)?;
+                    self.stack.push(Value::Pointer(Some(place.element(index))));
+                },
+                Op::StringElementAddr => {
+                    let index = self.pop_int()?;
+                    let place = self.pop_place()?;
+                    let len = match place.read()? {
+                        Value::String(bytes) => bytes.len(),
+                        value => return Err(format!("Expected a string, found {:?}", value)),
+                    };
+                    let index = VM::check_index(index, len)?;
+                    self.stack.push(Value::Pointer(Some(place.element(index))));
+                },
+                Op::Load => {
+                    let value = self.pop_place()?.read()?;
+                    self.stack.push(value);
+                },
+                Op::Store => {
+                    let value = self.pop()?;
+                    self.pop_place()?.write(value.clone())?;
+                    self.stack.push(value);
+                },
+
+                Op::ExtractField(index) => match self.pop()? {
+                    Value::Aggregate(mut fields) if (index as usize) < fields.len() => {
+                        self.stack.push(fields.swap_remove(index as usize));
+                    },
+                    value => return Err(format!("Expected an aggregate, found {:?}", value)),
+                },
+                Op::InsertField(index) => {
+                    let value = self.pop()?;
+                    match self.stack.last_mut() {
+                        Some(Value::Aggregate(fields)) if (index as usize) < fields.len() => fields[index as usize] = value,
+                        _ => return Err("Expected an aggregate".to_string()),
+                    }
+                },
+                Op::MakeAggregate(len) => {
+                    let len = len as usize;
+                    if self.stack.len() < len {
+                        return Err("Stack underflow".to_string());
+                    }
+                    let elements = self.stack.split_off(self.stack.len()-len);
+                    self.stack.push(Value::Aggregate(elements));
+                },
+
+                Op::Add(t) | Op::Sub(t) | Op::Mul(t) | Op::Div(t) | Op::Rem(t) |
+                Op::And(t) | Op::Or(t) | Op::Xor(t) => {
+                    let right = self.pop()?;
+                    let left = self.pop()?;
+                    let value = VM::arithmetic(&op, t, left, right)?;
+                    self.stack.push(value);
+                },
+                Op::Shl(t, name) | Op::Shr(t, name) => {
+                    let right = self.pop_int()?;
+                    let left = self.pop_int()?;
+                    if right < 0 || right >= t.width() as i64 {
+                        let name = match &self.program.constants[name as usize] {
+                            Value::String(name) => String::from_utf8_lossy(name).into_owned(),
+                            value => format!("{:?}", value),
+                        };
+                        return Err(format!("Shift amount {} out of range for {}", right, name));
+                    }
+                    let value = match op {
+                        Op::Shl(..) => left << right,
+                        _ if t.is_signed() => left >> right,
+                        _ => ((left as u64) >> right) as i64,
+                    };
+                    self.stack.push(Value::Int(t.wrap(value)));
+                },
+                Op::Neg(t) => {
+                    let value = match self.pop()? {
+                        Value::Float(v) => Value::Float(-v),
+                        Value::Int(v) => Value::Int(t.wrap(v.wrapping_neg())),
+                        value => return Err(format!("Expected a number, found {:?}", value)),
+                    };
+                    self.stack.push(value);
+                },
+                Op::Not(t) => {
+                    let value = self.pop_int()?;
+                    self.stack.push(Value::Int(t.wrap(!value)));
+                },
+                Op::Cmp(cmp_op, t) => {
+                    let right = self.pop()?;
+                    let left = self.pop()?;
+                    let ordering = match (left, right) {
+                        (Value::Int(l), Value::Int(r)) if t.is_signed() => l.partial_cmp(&r),
+                        (Value::Int(l), Value::Int(r)) => (l as u64).partial_cmp(&(r as u64)),
+                        (Value::Float(l), Value::Float(r)) => l.partial_cmp(&r),
+                        (l, r) => return Err(format!("Cannot compare {:?} and {:?}", l, r)),
+                    };
+                    self.stack.push(VM::compare(cmp_op, ordering));
+                },
+                Op::CmpPointer(cmp_op) => {
+                    let right = self.pop()?;
+                    let left = self.pop()?;
+                    // Pointers into different variables are unordered
+                    let ordering = match (left, right) {
+                        (Value::Pointer(Some(l)), Value::Pointer(Some(r))) if l.same_object(&r) => l.path().partial_cmp(r.path()),
+                        (Value::Pointer(None), Value::Pointer(None)) => Some(Ordering::Equal),
+                        (Value::Pointer(_), Value::Pointer(_)) => None,
+                        (l, r) => return Err(format!("Cannot compare {:?} and {:?}", l, r)),
+                    };
+                    self.stack.push(VM::compare(cmp_op, ordering));
+                },
+                Op::CmpString(cmp_op) => {
+                    let right = self.pop_string()?;
+                    let left = self.pop_string()?;
+                    self.stack.push(VM::compare(cmp_op, left.partial_cmp(&right)));
+                },
+                Op::Convert(from, to) => {
+                    let value = match self.pop()? {
+                        Value::Float(v) if to.is_float() => Value::Float(to.round(v)),
+                        Value::Float(v) if to.is_signed() => Value::Int(to.wrap(v as i64)),
+                        Value::Float(v) => Value::Int(to.wrap(v as u64 as i64)),
+                        Value::Int(v) if to.is_float() => {
+                            let v = if from.is_signed() { v as f64 } else { v as u64 as f64 };
+                            Value::Float(to.round(v))
+                        },
+                        Value::Int(v) => Value::Int(to.wrap(v)),
+                        value => return Err(format!("Expected a number, found {:?}", value)),
+                    };
+                    self.stack.push(value);
+                },
+
+                Op::Concat => {
+                    let right = self.pop_string()?;
+                    let mut left = self.pop_string()?;
+                    left.extend(right);
+                    self.stack.push(Value::String(left));
+                },
+                Op::StringLen => {
+                    let len = self.pop_string()?.len();
+                    self.stack.push(Value::Int(len as i64));
+                },
+
+                Op::Jump(target) => self.frames.last_mut().unwrap().ip = target as usize,
+                Op::JumpIfFalse(target) => {
+                    if self.pop_int()? == 0 {
+                        self.frames.last_mut().unwrap().ip = target as usize;
+                    }
+                },
+                Op::JumpIfTrue(target) => {
+                    if self.pop_int()? != 0 {
+                        self.frames.last_mut().unwrap().ip = target as usize;
+                    }
+                },
+                Op::Call(index) => self.call(index)?,
+                Op::Return | Op::ReturnVoid => {
+                    let value = match op {
+                        Op::Return => Some(self.pop()?),
+                        _ => None,
+                    };
+                    self.frames.pop();
+
+                    if self.frames.len() < depth {
+                        return Ok(value);
+                    }
+                    if let Some(value) = value {
+                        self.stack.push(value);
+                    }
+                },
+                Op::Trap(index) => {
+                    return match &self.program.constants[index as usize] {
+                        Value::String(message) => Err(String::from_utf8_lossy(message).into_owned()),
+                        value => Err(format!("{:?}", value)),
+                    };
+                },
+            }
+        }
+    }
+
+    fn check_index(index : i64, len : usize) -> Result<usize, String> {
+        if index < 0 || index as usize >= len {
+            return Err(format!("Index {} out of bounds for length {}", index, len));
+        }
+        Ok(index as usize)
+    }
+
+    // Comparisons with NaN are unordered, only `!=` holds
+    fn compare(cmp_op : CmpOp, ordering : Option<Ordering>) -> Value {
+        let result = match (cmp_op, ordering) {
+            (CmpOp::Ne, None) => true,
+            (_, None) => false,
+            (CmpOp::Eq, Some(o)) => o.is_eq(),
+            (CmpOp::Ne, Some(o)) => o.is_ne(),
+            (CmpOp::Lt, Some(o)) => o.is_lt(),
+            (CmpOp::Le, Some(o)) => o.is_le(),
+            (CmpOp::Gt, Some(o)) => o.is_gt(),
+            (CmpOp::Ge, Some(o)) => o.is_ge(),
+        };
+        Value::Int(result as i64)
+    }
+
+    fn arithmetic(op : &Op, t : NumType, left : Value, right : Value) -> Result<Value, String> {
+        let (l, r) = match (left, right) {
+            (Value::Float(l), Value::Float(r)) => {
+                let value = match op {
+                    Op::Add(_) => l + r,
+                    Op::Sub(_) => l - r,
+                    Op::Mul(_) => l * r,
+                    Op::Div(_) => l / r,
+                    Op::Rem(_) => l % r,
+                    _ => return Err(format!("Unsupported operation {} for floating point values", op)),
+                };
+                return Ok(Value::Float(t.round(value)));
+            },
+            (Value::Int(l), Value::Int(r)) => (l, r),
+            (l, r) => return Err(format!("Cannot apply {} to {:?} and {:?}", op, l, r)),
+        };
+
+        let signed = t.is_signed();
+        let value = match op {
+            Op::Add(_) => l.wrapping_add(r),
+            Op::Sub(_) => l.wrapping_sub(r),
+            Op::Mul(_) => l.wrapping_mul(r),
+            Op::Div(_) | Op::Rem(_) if r == 0 => return Err("Division by zero".to_string()),
+            Op::Div(_) if signed => l.wrapping_div(r),
+            Op::Div(_) => ((l as u64) / (r as u64)) as i64,
+            Op::Rem(_) if signed => l.wrapping_rem(r),
+            Op::Rem(_) => ((l as u64) % (r as u64)) as i64,
+            Op::And(_) => l & r,
+            Op::Or(_) => l | r,
+            Op::Xor(_) => l ^ r,
+            _ => return Err(format!("{} is not an arithmetic operation", op)),
+        };
+        Ok(Value::Int(t.wrap(value)))
+    }
+}
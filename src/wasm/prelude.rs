@@ -0,0 +1 @@
+pub use super::wat_gen::{create_wat, create_wat_with_options, generate_wat};
\ No newline at end of file
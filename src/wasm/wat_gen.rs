@@ -0,0 +1,1172 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+
+use crate::parser::ast::TranslationUnit;
+use crate::codegen::{CodegenOptions, CodegenError, lower_and_optimize};
+use crate::target::Target;
+use crate::mir::mir::*;
+use crate::mir::layout::{size_of, align_of, field_offset, align_to};
+use crate::mir::dominators::Dominators;
+
+// Address 0 is left unused so that no object is at the null pointer
+const DATA_START : usize = 16;
+const STACK_SIZE : usize = 64 * 1024;
+const PAGE_SIZE : usize = 64 * 1024;
+
+const RUNTIME : &str = include_str!("../../runtime/string.wat");
+
+pub fn create_wat(tu : &TranslationUnit, file_name : &str) -> Result<(), CodegenError> {
+    create_wat_with_options(tu, file_name, &CodegenOptions::default())
+}
+
+// Writes the file only once the whole text is generated, and removes what
+// was written if writing fails.
+pub fn create_wat_with_options(tu : &TranslationUnit, file_name : &str, options : &CodegenOptions) -> Result<(), CodegenError> {
+    let mut text = vec![];
+    generate_wat(tu, options, &mut text)?;
+    std::fs::write(file_name, text).map_err(|error| {
+        let _ = std::fs::remove_file(file_name);
+        CodegenError::Io(error)
+    })
+}
+
+// WebAssembly has 32 bit pointers whatever the target of the options.
+pub fn generate_wat<W : Write>(tu : &TranslationUnit, options : &CodegenOptions, output : &mut W) -> Result<(), CodegenError> {
+    let options = CodegenOptions {
+        target : Target::wasm32(),
+        ..options.clone()
+    };
+    let module = lower_and_optimize(tu, &options)?;
+
+    output.write_all(WATGenerator::new(&module).generate().as_bytes())?;
+    output.flush()?;
+    Ok(())
+}
+
+/****************************/
+/****************************/
+/****************************/
+
+// Identifiers may use most printable ASCII characters, including `.`.
+// Others are written as `_{code point}_`, and `_` as `__`, so distinct
+// names stay distinct.
+fn identifier(name : &str) -> String {
+    let name : String = name
+        .chars()
+        .map(|c| match c {
+            '_' => "__".to_string(),
+            c if c.is_ascii_alphanumeric() || "!#$%&'*+-./:<=>?@\\^`|~".contains(c) => c.to_string(),
+            c => format!("_{:x}_", c as u32),
+        })
+        .collect();
+    format!("${}", name)
+}
+
+fn escape_bytes(bytes : &[u8]) -> String {
+    bytes.iter()
+        .map(|byte| match byte {
+            b' '..=b'~' if *byte != b'"' && *byte != b'\\' => (*byte as char).to_string(),
+            _ => format!("\\{:02x}", byte),
+        })
+        .collect()
+}
+
+// Aggregates are passed around as the address of their bytes
+fn value_type(type_ : &Type) -> &'static str {
+    match type_ {
+        Type::I64 => "i64",
+        Type::F32 => "f32",
+        Type::F64 => "f64",
+        _ => "i32",
+    }
+}
+
+fn load_instruction(type_ : &Type) -> &'static str {
+    match type_ {
+        Type::I1 | Type::I8 => "i32.load8_u",
+        Type::I16 => "i32.load16_u",
+        Type::I64 => "i64.load",
+        Type::F32 => "f32.load",
+        Type::F64 => "f64.load",
+        _ => "i32.load",
+    }
+}
+
+fn store_instruction(type_ : &Type) -> &'static str {
+    match type_ {
+        Type::I1 | Type::I8 => "i32.store8",
+        Type::I16 => "i32.store16",
+        Type::I64 => "i64.store",
+        Type::F32 => "f32.store",
+        Type::F64 => "f64.store",
+        _ => "i32.store",
+    }
+}
+
+// `offset=N` immediate of loads and stores
+fn with_offset(instruction : &str, offset : usize) -> String {
+    if offset == 0 {
+        instruction.to_string()
+    } else {
+        format!("{} offset={}", instruction, offset)
+    }
+}
+
+fn int_constant(value : i64, type_ : &Type) -> String {
+    match type_ {
+        Type::I64 => format!("i64.const {}", value),
+        Type::I32 | Type::Ptr => format!("i32.const {}", value as i32),
+        // Narrower integers are kept zero extended
+        type_ => format!("i32.const {}", value & ((1 << type_.width()) - 1)),
+    }
+}
+
+fn float_constant(value : f64, type_ : &Type) -> String {
+    let text = match value {
+        value if value.is_nan() => "nan".to_string(),
+        value if value.is_infinite() && value > 0.0 => "inf".to_string(),
+        value if value.is_infinite() => "-inf".to_string(),
+        value if *type_ == Type::F32 => format!("{:?}", value as f32),
+        value => format!("{:?}", value),
+    };
+    format!("{}.const {}", value_type(type_), text)
+}
+
+/****************************/
+/****************************/
+/****************************/
+
+// Where the string literals and the globals live in memory
+struct MemoryLayout {
+    strings : Vec<usize>,
+    globals : HashMap<String, usize>,
+    // Globals whose address is never taken become wasm globals
+    wasm_globals : HashSet<String>,
+    // Initial bytes of the memory from `DATA_START`
+    data : Vec<u8>,
+    stack_top : usize,
+}
+
+impl MemoryLayout {
+    fn new(module : &Module) -> MemoryLayout {
+        let wasm_globals = MemoryLayout::wasm_globals(module);
+
+        let mut end = DATA_START;
+        let mut globals = HashMap::new();
+        for global in &module.globals {
+            if !wasm_globals.contains(&global.name) {
+                end = align_to(end, align_of(module, &global.type_));
+                globals.insert(global.name.clone(), end);
+                end += size_of(module, &global.type_);
+            }
+        }
+        let mut strings = vec![];
+        for text in &module.strings {
+            strings.push(end);
+            end += text.len();
+        }
+
+        let mut layout = MemoryLayout {
+            strings,
+            globals,
+            wasm_globals,
+            data : vec![0; end - DATA_START],
+            stack_top : align_to(end, 16) + STACK_SIZE,
+        };
+
+        for (i, text) in module.strings.iter().enumerate() {
+            let address = layout.strings[i] - DATA_START;
+            layout.data[address..address + text.len()].copy_from_slice(text.as_bytes());
+        }
+        for global in &module.globals {
+            if let Some(address) = layout.globals.get(&global.name) {
+                layout.write_constant(module, *address, &global.init_value, &global.type_);
+            }
+        }
+
+        layout
+    }
+
+    // Scalar globals only ever loaded and stored with their own type
+    fn wasm_globals(module : &Module) -> HashSet<String> {
+        let mut escaped = HashSet::new();
+        fn escape(constant : &Constant, escaped : &mut HashSet<String>) {
+            match constant {
+                Constant::Global(name) => {
+                    escaped.insert(name.clone());
+                },
+                Constant::Aggregate(elements) => elements.iter().for_each(|element| escape(element, escaped)),
+                _ => {},
+            }
+        }
+
+        for global in &module.globals {
+            escape(&global.init_value, &mut escaped);
+        }
+        for block in module.functions.iter().flat_map(|function| &function.blocks) {
+            for instruction in &block.instructions {
+                let operands = match &instruction.kind {
+                    InstructionKind::Load{type_, pointer : Value::Constant(Constant::Global(name))} |
+                    InstructionKind::Store{type_, pointer : Value::Constant(Constant::Global(name)), ..}
+                        if module.global(name).is_some_and(|global| global.type_ == *type_) => {
+                        match &instruction.kind {
+                            InstructionKind::Store{value, ..} => vec![value],
+                            _ => vec![],
+                        }
+                    },
+                    kind => kind.operands(),
+                };
+                for operand in operands {
+                    if let Value::Constant(constant) = operand {
+                        escape(constant, &mut escaped);
+                    }
+                }
+            }
+            for operand in block.terminator.as_ref().unwrap().operands() {
+                if let Value::Constant(constant) = operand {
+                    escape(constant, &mut escaped);
+                }
+            }
+        }
+
+        module.globals
+            .iter()
+            .filter(|global| !global.type_.is_aggregate() && !escaped.contains(&global.name))
+            .map(|global| global.name.clone())
+            .collect()
+    }
+
+    fn write_constant(&mut self, module : &Module, address : usize, constant : &Constant, type_ : &Type) {
+        let offset = address - DATA_START;
+        let size = size_of(module, type_);
+        match constant {
+            Constant::Int(value) => self.data[offset..offset + size].copy_from_slice(&value.to_le_bytes()[..size]),
+            Constant::Float(value) if *type_ == Type::F32 => {
+                self.data[offset..offset + 4].copy_from_slice(&(*value as f32).to_le_bytes());
+            },
+            Constant::Float(value) => self.data[offset..offset + 8].copy_from_slice(&value.to_le_bytes()),
+            Constant::Null | Constant::Zero | Constant::Undef => {},
+            Constant::String(index) => {
+                let pointer = self.strings[*index] as u32;
+                self.data[offset..offset + 4].copy_from_slice(&pointer.to_le_bytes());
+                let len_offset = offset + field_offset(module, &Type::String, 1);
+                self.data[len_offset..len_offset + 8].copy_from_slice(&(module.strings[*index].len() as i64).to_le_bytes());
+            },
+            Constant::Global(name) => {
+                let pointer = self.globals[name] as u32;
+                self.data[offset..offset + 4].copy_from_slice(&pointer.to_le_bytes());
+            },
+            Constant::Aggregate(elements) => {
+                for (i, element) in elements.iter().enumerate() {
+                    let element_type = module.field_type(type_, i).unwrap();
+                    self.write_constant(module, address + field_offset(module, type_, i), element, &element_type);
+                }
+            },
+        }
+    }
+}
+
+/****************************/
+/****************************/
+/****************************/
+
+// Prints a MIR module as WebAssembly text. Values of the registers are
+// kept in locals, except aggregates which live in the stack frame of the
+// function, in memory below `$toy.stack_pointer`, like the allocas whose
+// address is used for more than loading and storing their value.
+struct WATGenerator<'a> {
+    module : &'a Module,
+    memory : MemoryLayout,
+}
+
+impl<'a> WATGenerator<'a> {
+    fn new(module : &'a Module) -> WATGenerator<'a> {
+        WATGenerator {
+            module,
+            memory : MemoryLayout::new(module),
+        }
+    }
+
+    fn generate(&self) -> String {
+        let module = self.module;
+        let uses_runtime = !module.externs.is_empty();
+
+        // The heap, used by the runtime, starts above the stack
+        let pages = self.memory.stack_top / PAGE_SIZE + 1;
+        let mut text = format!("(module\n  (memory (export \"memory\") {})\n", pages);
+        text += &format!("  (global $toy.stack_pointer (mut i32) (i32.const {}))\n", self.memory.stack_top);
+        if uses_runtime {
+            text += &format!("  (global $toy.heap (mut i32) (i32.const {}))\n", self.memory.stack_top);
+        }
+
+        for global in &module.globals {
+            if self.memory.wasm_globals.contains(&global.name) {
+                let init_value = match &global.init_value {
+                    Constant::Int(value) => int_constant(*value, &global.type_),
+                    Constant::Float(value) => float_constant(*value, &global.type_),
+                    Constant::Global(name) => format!("i32.const {}", self.memory.globals[name]),
+                    _ if global.type_.is_float() => float_constant(0.0, &global.type_),
+                    _ => int_constant(0, &global.type_),
+                };
+                text += &format!(
+                    "  (global {} (mut {}) ({}))\n",
+                    identifier(&global.name),
+                    value_type(&global.type_),
+                    init_value
+                );
+            }
+        }
+
+        // Zeros need no data, the memory starts zeroed
+        for global in &module.globals {
+            if let Some(address) = self.memory.globals.get(&global.name) {
+                let offset = address - DATA_START;
+                let bytes = &self.memory.data[offset..offset + size_of(module, &global.type_)];
+                if bytes.iter().any(|byte| *byte != 0) {
+                    text += &format!("  (data (i32.const {}) \"{}\")\n", address, escape_bytes(bytes));
+                }
+            }
+        }
+        for (i, string) in module.strings.iter().enumerate() {
+            if !string.is_empty() {
+                text += &format!("  (data (i32.const {}) \"{}\")\n", self.memory.strings[i], escape_bytes(string.as_bytes()));
+            }
+        }
+
+        for function in &module.functions {
+            text += "\n";
+            text += &FunctionGenerator::new(module, &self.memory, function).generate();
+        }
+
+        if uses_runtime {
+            text += "\n";
+            for line in RUNTIME.lines() {
+                text += &if line.is_empty() {"\n".to_string()} else {format!("  {}\n", line)};
+            }
+        }
+
+        text + ")\n"
+    }
+}
+
+/****************************/
+/****************************/
+/****************************/
+
+// Registers, blocks and stack frame of the function being generated.
+//
+// The control flow graph is turned into nested `block`s and `loop`s
+// following the dominator tree, as described by Norman Ramsey in "Beyond
+// Relooper". A block with several forward edges into it is placed after a
+// `block` that the edges break out of, and a loop header is wrapped in a
+// `loop` that the back edges branch to. Other blocks have a single
+// predecessor and are placed inline.
+struct FunctionGenerator<'a> {
+    module : &'a Module,
+    memory : &'a MemoryLayout,
+    function : &'a Function,
+    // Position of the reachable blocks in reverse postorder
+    positions : Vec<Option<usize>>,
+    children : Vec<Vec<BlockId>>,
+    forward_edges : Vec<usize>,
+    loop_headers : Vec<bool>,
+    alloca_types : HashMap<Register, &'a Type>,
+    // Allocas only loaded and stored with their own type become locals
+    local_allocas : HashSet<Register>,
+    // Distance below the frame pointer of the allocas in memory and of
+    // the aggregate registers, and of the copies of aggregate phis
+    slots : HashMap<Register, usize>,
+    phi_copies : HashMap<Register, usize>,
+    frame_size : usize,
+    needs_frame : bool,
+    body : String,
+    indent : usize,
+}
+
+impl<'a> FunctionGenerator<'a> {
+    fn new(module : &'a Module, memory : &'a MemoryLayout, function : &'a Function) -> FunctionGenerator<'a> {
+        let dominators = Dominators::new(function);
+        let mut positions = vec![None; function.blocks.len()];
+        for (i, block) in dominators.reverse_postorder().iter().enumerate() {
+            positions[block.0] = Some(i);
+        }
+
+        let mut forward_edges = vec![0; function.blocks.len()];
+        let mut loop_headers = vec![false; function.blocks.len()];
+        for block in dominators.reverse_postorder() {
+            for successor in function.block(*block).successors() {
+                if positions[successor.0] > positions[block.0] {
+                    forward_edges[successor.0] += 1;
+                } else {
+                    loop_headers[successor.0] = true;
+                }
+            }
+        }
+
+        let mut alloca_types = HashMap::new();
+        for instruction in function.blocks.iter().flat_map(|block| &block.instructions) {
+            if let (Some(result), InstructionKind::Alloca(type_)) = (instruction.result, &instruction.kind) {
+                alloca_types.insert(result, type_);
+            }
+        }
+
+        let mut generator = FunctionGenerator {
+            module,
+            memory,
+            function,
+            positions,
+            children : dominators.children(),
+            forward_edges,
+            loop_headers,
+            alloca_types,
+            local_allocas : HashSet::new(),
+            slots : HashMap::new(),
+            phi_copies : HashMap::new(),
+            frame_size : 0,
+            needs_frame : false,
+            body : String::new(),
+            indent : 2,
+        };
+        generator.allocate_frame(&dominators);
+        generator
+    }
+
+    fn allocate_frame(&mut self, dominators : &Dominators) {
+        let function = self.function;
+        let mut memory_allocas = HashSet::new();
+        for block in dominators.reverse_postorder() {
+            for instruction in &function.block(*block).instructions {
+                let operands = match &instruction.kind {
+                    InstructionKind::Load{..} => vec![],
+                    InstructionKind::Store{value, ..} => vec![value],
+                    kind => kind.operands(),
+                };
+                for operand in operands {
+                    if let Some(register) = operand.as_register() {
+                        memory_allocas.insert(register);
+                    }
+                }
+
+                // Accessed with another type than their own
+                match &instruction.kind {
+                    InstructionKind::Load{type_, pointer : Value::Register(pointer)} |
+                    InstructionKind::Store{type_, pointer : Value::Register(pointer), ..}
+                        if !self.alloca_type(*pointer).is_some_and(|alloca_type| *alloca_type == *type_) => {
+                        memory_allocas.insert(*pointer);
+                    },
+                    // Aggregate constants passed to calls are copied to the stack
+                    InstructionKind::Call{arguments, ..}
+                        if arguments.iter().any(|(type_, argument)| type_.is_aggregate() && argument.as_register().is_none()) => {
+                        self.needs_frame = true;
+                    },
+                    _ => {},
+                }
+            }
+            for operand in function.block(*block).terminator.as_ref().unwrap().operands() {
+                if let Some(register) = operand.as_register() {
+                    memory_allocas.insert(register);
+                }
+            }
+        }
+
+        for block in dominators.reverse_postorder() {
+            for instruction in &function.block(*block).instructions {
+                let result = match instruction.result {
+                    Some(result) => result,
+                    None => continue,
+                };
+                match &instruction.kind {
+                    InstructionKind::Alloca(type_) if type_.is_aggregate() || memory_allocas.contains(&result) => {
+                        let slot = self.allocate(type_);
+                        self.slots.insert(result, slot);
+                    },
+                    InstructionKind::Alloca(_) => {
+                        self.local_allocas.insert(result);
+                    },
+                    kind => {
+                        let type_ = &function.register(result).type_;
+                        if type_.is_aggregate() {
+                            let slot = self.allocate(type_);
+                            self.slots.insert(result, slot);
+                            if let InstructionKind::Phi{..} = kind {
+                                let copy = self.allocate(type_);
+                                self.phi_copies.insert(result, copy);
+                            }
+                        }
+                    },
+                }
+            }
+        }
+    }
+
+    fn alloca_type(&self, register : Register) -> Option<&'a Type> {
+        self.alloca_types.get(&register).copied()
+    }
+
+    fn allocate(&mut self, type_ : &Type) -> usize {
+        self.needs_frame = true;
+        self.frame_size = align_to(self.frame_size + size_of(self.module, type_), align_of(self.module, type_));
+        self.frame_size
+    }
+
+    fn emit(&mut self, line : &str) {
+        self.body += &"  ".repeat(self.indent);
+        self.body += line;
+        self.body += "\n";
+    }
+
+    fn generate(mut self) -> String {
+        let function = self.function;
+        self.generate_tree(BlockId(0));
+
+        let mut text = format!("  (func {} (export \"{}\")", identifier(&function.name), function.name);
+        if function.return_type.as_ref().is_some_and(|type_| type_.is_aggregate()) {
+            text += " (param $toy.result i32)";
+        }
+        for parameter in &function.parameters {
+            let parameter = function.register(*parameter);
+            text += &format!(" (param {} {})", identifier(&parameter.name), value_type(&parameter.type_));
+        }
+        match &function.return_type {
+            Some(type_) if !type_.is_aggregate() => text += &format!(" (result {})\n", value_type(type_)),
+            _ => text += "\n",
+        }
+
+        let mut locals = vec![];
+        if self.needs_frame {
+            locals.push("(local $toy.frame i32)".to_string());
+        }
+        for (i, register) in function.registers.iter().enumerate() {
+            if function.parameters.contains(&Register(i)) || self.slots.contains_key(&Register(i)) {
+                continue;
+            }
+            let type_ = self.alloca_type(Register(i)).unwrap_or(&register.type_);
+            locals.push(format!("(local {} {})", identifier(&register.name), value_type(type_)));
+        }
+        for local in locals {
+            text += &format!("    {}\n", local);
+        }
+
+        // The frame is below the stack pointer at the entry of the
+        // function, kept 16 bytes aligned.
+        if self.needs_frame {
+            text += "    global.get $toy.stack_pointer\n";
+            text += "    local.tee $toy.frame\n";
+            text += &format!("    i32.const {}\n", align_to(self.frame_size, 16));
+            text += "    i32.sub\n";
+            text += "    global.set $toy.stack_pointer\n";
+        }
+
+        text += &self.body;
+        if function.return_type.as_ref().is_some_and(|type_| !type_.is_aggregate()) && self.body.ends_with("end\n") {
+            text += "    unreachable\n";
+        }
+        text + "  )\n"
+    }
+
+    /*******************/
+    /*******************/
+    /*******************/
+
+    // Pushes the address of a stack slot
+    fn push_slot(&mut self, slot : usize) {
+        self.emit("local.get $toy.frame");
+        self.emit(&format!("i32.const {}", slot));
+        self.emit("i32.sub");
+    }
+
+    fn push(&mut self, value : &Value, type_ : &Type) {
+        match value {
+            Value::Register(register) => match self.slots.get(register) {
+                Some(slot) => self.push_slot(*slot),
+                None => self.emit(&format!("local.get {}", identifier(&self.function.register(*register).name))),
+            },
+            Value::Constant(constant) => self.push_constant(constant, type_),
+        }
+    }
+
+    fn push_constant(&mut self, constant : &Constant, type_ : &Type) {
+        match constant {
+            _ if type_.is_aggregate() => {
+                let slot = self.allocate(type_);
+                self.store_constant(&|generator : &mut FunctionGenerator| generator.push_slot(slot), 0, constant, type_);
+                self.push_slot(slot);
+            },
+            Constant::Int(value) => self.emit(&int_constant(*value, type_)),
+            Constant::Float(value) => self.emit(&float_constant(*value, type_)),
+            Constant::String(_) | Constant::Aggregate(_) => unreachable!(),
+            Constant::Global(name) => self.emit(&format!("i32.const {}", self.memory.globals[name])),
+            Constant::Null | Constant::Zero | Constant::Undef => match type_ {
+                type_ if type_.is_float() => self.emit(&float_constant(0.0, type_)),
+                type_ => self.emit(&int_constant(0, type_)),
+            },
+        }
+    }
+
+    // Writes a constant at `offset` from the address pushed by `address`
+    fn store_constant(&mut self, address : &dyn Fn(&mut FunctionGenerator), offset : usize, constant : &Constant, type_ : &Type) {
+        let module = self.module;
+        match constant {
+            Constant::Aggregate(elements) => {
+                for (i, element) in elements.iter().enumerate() {
+                    let element_type = module.field_type(type_, i).unwrap();
+                    self.store_constant(address, offset + field_offset(module, type_, i), element, &element_type);
+                }
+            },
+            Constant::String(index) => {
+                address(self);
+                self.emit(&format!("i32.const {}", self.memory.strings[*index]));
+                self.emit(&with_offset("i32.store", offset));
+                address(self);
+                self.emit(&format!("i64.const {}", module.strings[*index].len()));
+                self.emit(&with_offset("i64.store", offset + field_offset(module, &Type::String, 1)));
+            },
+            Constant::Zero | Constant::Undef if type_.is_aggregate() => {
+                address(self);
+                if offset > 0 {
+                    self.emit(&format!("i32.const {}", offset));
+                    self.emit("i32.add");
+                }
+                self.emit("i32.const 0");
+                self.emit(&format!("i32.const {}", size_of(module, type_)));
+                self.emit("memory.fill");
+            },
+            constant => {
+                address(self);
+                self.push_constant(constant, type_);
+                self.emit(&with_offset(store_instruction(type_), offset));
+            },
+        }
+    }
+
+    // Copies an aggregate value to the address pushed by `address`
+    fn store_aggregate(&mut self, address : &dyn Fn(&mut FunctionGenerator), value : &Value, type_ : &Type) {
+        match value {
+            Value::Constant(constant) => self.store_constant(address, 0, constant, type_),
+            Value::Register(_) => {
+                address(self);
+                self.push(value, type_);
+                self.emit(&format!("i32.const {}", size_of(self.module, type_)));
+                self.emit("memory.copy");
+            },
+        }
+    }
+
+    fn set(&mut self, register : Register) {
+        self.emit(&format!("local.set {}", identifier(&self.function.register(register).name)));
+    }
+
+    // Narrow integers are zero extended in the `i32` holding them, and
+    // sign extended for signed operations.
+    fn truncate(&mut self, type_ : &Type) {
+        if matches!(type_, Type::I1 | Type::I8 | Type::I16) {
+            self.emit(&format!("i32.const {}", (1 << type_.width()) - 1));
+            self.emit("i32.and");
+        }
+    }
+
+    fn sign_extend(&mut self, type_ : &Type) {
+        match type_ {
+            Type::I8 => self.emit("i32.extend8_s"),
+            Type::I16 => self.emit("i32.extend16_s"),
+            Type::I1 => {
+                self.emit("i32.const 31");
+                self.emit("i32.shl");
+                self.emit("i32.const 31");
+                self.emit("i32.shr_s");
+            },
+            _ => {},
+        }
+    }
+
+    // Pushes `pointer + index * size`, with an `i64` index
+    fn push_element_address(&mut self, pointer : &Value, index : &Value, element_type : &Type) {
+        let size = size_of(self.module, element_type);
+        self.push(pointer, &Type::Ptr);
+        match index {
+            Value::Constant(Constant::Int(index)) => self.emit(&format!("i32.const {}", (*index as i32).wrapping_mul(size as i32))),
+            index => {
+                self.push(index, &Type::I64);
+                self.emit("i32.wrap_i64");
+                self.emit(&format!("i32.const {}", size));
+                self.emit("i32.mul");
+            },
+        }
+        self.emit("i32.add");
+    }
+
+    fn generate_binary(&mut self, op : BinaryOp, type_ : &Type, left : &Value, right : &Value) {
+        let prefix = value_type(type_);
+        if op == BinaryOp::FRem {
+            // There is no remainder of floats, `left - trunc(left / right) * right`
+            self.push(left, type_);
+            self.push(left, type_);
+            self.push(right, type_);
+            self.emit(&format!("{}.div", prefix));
+            self.emit(&format!("{}.trunc", prefix));
+            self.push(right, type_);
+            self.emit(&format!("{}.mul", prefix));
+            self.emit(&format!("{}.sub", prefix));
+            return;
+        }
+
+        let signed = matches!(op, BinaryOp::SDiv | BinaryOp::SRem | BinaryOp::AShr);
+        self.push(left, type_);
+        if signed {
+            self.sign_extend(type_);
+        }
+        self.push(right, type_);
+        if signed && op != BinaryOp::AShr {
+            self.sign_extend(type_);
+        }
+
+        let name = match op {
+            BinaryOp::Add | BinaryOp::FAdd => "add",
+            BinaryOp::Sub | BinaryOp::FSub => "sub",
+            BinaryOp::Mul | BinaryOp::FMul => "mul",
+            BinaryOp::SDiv => "div_s",
+            BinaryOp::UDiv => "div_u",
+            BinaryOp::FDiv => "div",
+            BinaryOp::SRem => "rem_s",
+            BinaryOp::URem | BinaryOp::FRem => "rem_u",
+            BinaryOp::And => "and",
+            BinaryOp::Or => "or",
+            BinaryOp::Xor => "xor",
+            BinaryOp::Shl => "shl",
+            BinaryOp::AShr => "shr_s",
+            BinaryOp::LShr => "shr_u",
+        };
+        self.emit(&format!("{}.{}", prefix, name));
+        if !op.is_float() {
+            self.truncate(type_);
+        }
+    }
+
+    fn generate_compare(&mut self, comparison : Comparison, type_ : &Type, left : &Value, right : &Value) {
+        let signed = matches!(comparison, Comparison::SLt | Comparison::SLe | Comparison::SGt | Comparison::SGe);
+        for value in [left, right] {
+            self.push(value, type_);
+            if signed {
+                self.sign_extend(type_);
+            }
+        }
+
+        let name = match comparison {
+            Comparison::Eq | Comparison::FOeq => "eq",
+            Comparison::Ne | Comparison::FUne => "ne",
+            Comparison::SLt => "lt_s",
+            Comparison::SLe => "le_s",
+            Comparison::SGt => "gt_s",
+            Comparison::SGe => "ge_s",
+            Comparison::ULt => "lt_u",
+            Comparison::ULe => "le_u",
+            Comparison::UGt => "gt_u",
+            Comparison::UGe => "ge_u",
+            Comparison::FOlt => "lt",
+            Comparison::FOle => "le",
+            Comparison::FOgt => "gt",
+            Comparison::FOge => "ge",
+        };
+        self.emit(&format!("{}.{}", value_type(type_), name));
+    }
+
+    // Float to integer conversions saturate instead of trapping
+    fn generate_cast(&mut self, op : CastOp, from : &Type, value : &Value, to : &Type) {
+        self.push(value, from);
+        let (from_type, to_type) = (value_type(from), value_type(to));
+        match op {
+            CastOp::Trunc => {
+                if *from == Type::I64 {
+                    self.emit("i32.wrap_i64");
+                }
+                self.truncate(to);
+            },
+            CastOp::ZExt => {
+                if *to == Type::I64 {
+                    self.emit("i64.extend_i32_u");
+                }
+            },
+            CastOp::SExt => {
+                self.sign_extend(from);
+                if *to == Type::I64 {
+                    self.emit("i64.extend_i32_s");
+                }
+                self.truncate(to);
+            },
+            CastOp::FPTrunc => self.emit("f32.demote_f64"),
+            CastOp::FPExt => self.emit("f64.promote_f32"),
+            CastOp::FPToSI | CastOp::FPToUI => {
+                let sign = if op == CastOp::FPToSI {"s"} else {"u"};
+                self.emit(&format!("{}.trunc_sat_{}_{}", to_type, from_type, sign));
+                self.truncate(to);
+            },
+            CastOp::SIToFP | CastOp::UIToFP => {
+                let sign = if op == CastOp::SIToFP {"s"} else {"u"};
+                if op == CastOp::SIToFP {
+                    self.sign_extend(from);
+                }
+                self.emit(&format!("{}.convert_{}_{}", to_type, from_type, sign));
+            },
+        }
+    }
+
+    fn generate_instruction(&mut self, instruction : &Instruction) {
+        let module = self.module;
+        let result = instruction.result;
+        let result_slot = result.and_then(|result| self.slots.get(&result).copied());
+
+        match &instruction.kind {
+            InstructionKind::Alloca(_) | InstructionKind::Phi{..} => {},
+            InstructionKind::Load{type_, pointer} => match pointer {
+                Value::Register(pointer) if self.local_allocas.contains(pointer) => {
+                    self.emit(&format!("local.get {}", identifier(&self.function.register(*pointer).name)));
+                    self.set(result.unwrap());
+                },
+                Value::Constant(Constant::Global(name)) if self.memory.wasm_globals.contains(name) => {
+                    self.emit(&format!("global.get {}", identifier(name)));
+                    self.set(result.unwrap());
+                },
+                pointer if type_.is_aggregate() => {
+                    self.push_slot(result_slot.unwrap());
+                    self.push(pointer, &Type::Ptr);
+                    self.emit(&format!("i32.const {}", size_of(module, type_)));
+                    self.emit("memory.copy");
+                },
+                pointer => {
+                    self.push(pointer, &Type::Ptr);
+                    self.emit(load_instruction(type_));
+                    self.set(result.unwrap());
+                },
+            },
+            InstructionKind::Store{type_, value, pointer} => match pointer {
+                Value::Register(pointer) if self.local_allocas.contains(pointer) => {
+                    self.push(value, type_);
+                    self.emit(&format!("local.set {}", identifier(&self.function.register(*pointer).name)));
+                },
+                Value::Constant(Constant::Global(name)) if self.memory.wasm_globals.contains(name) => {
+                    self.push(value, type_);
+                    self.emit(&format!("global.set {}", identifier(name)));
+                },
+                pointer if type_.is_aggregate() => {
+                    let pointer = pointer.clone();
+                    self.store_aggregate(&|generator : &mut FunctionGenerator| generator.push(&pointer, &Type::Ptr), value, type_);
+                },
+                pointer => {
+                    self.push(pointer, &Type::Ptr);
+                    self.push(value, type_);
+                    self.emit(store_instruction(type_));
+                },
+            },
+            InstructionKind::Binary{op, type_, left, right} => {
+                self.generate_binary(*op, type_, left, right);
+                self.set(result.unwrap());
+            },
+            InstructionKind::Negate{type_, value} => {
+                self.push(value, type_);
+                self.emit(&format!("{}.neg", value_type(type_)));
+                self.set(result.unwrap());
+            },
+            InstructionKind::Compare{comparison, type_, left, right} => {
+                self.generate_compare(*comparison, type_, left, right);
+                self.set(result.unwrap());
+            },
+            InstructionKind::Cast{op, from, value, to} => {
+                self.generate_cast(*op, from, value, to);
+                self.set(result.unwrap());
+            },
+            InstructionKind::FieldAddress{type_, pointer, index} => {
+                self.push(pointer, &Type::Ptr);
+                let offset = field_offset(module, type_, *index);
+                if offset > 0 {
+                    self.emit(&format!("i32.const {}", offset));
+                    self.emit("i32.add");
+                }
+                self.set(result.unwrap());
+            },
+            InstructionKind::ElementAddress{type_, pointer, index} => {
+                self.push_element_address(pointer, index, &module.field_type(type_, 0).unwrap());
+                self.set(result.unwrap());
+            },
+            InstructionKind::Offset{type_, pointer, index} => {
+                self.push_element_address(pointer, index, type_);
+                self.set(result.unwrap());
+            },
+            InstructionKind::ExtractValue{type_, aggregate, index} => {
+                let field_type = module.field_type(type_, *index).unwrap();
+                let offset = field_offset(module, type_, *index);
+                match aggregate {
+                    Value::Constant(constant) => {
+                        let field = match constant {
+                            Constant::Aggregate(elements) => elements[*index].clone(),
+                            Constant::String(string) if *index == 0 => Constant::Int(self.memory.strings[*string] as i64),
+                            Constant::String(string) => Constant::Int(module.strings[*string].len() as i64),
+                            _ => Constant::Zero,
+                        };
+                        match result_slot {
+                            Some(slot) => self.store_constant(&|generator : &mut FunctionGenerator| generator.push_slot(slot), 0, &field, &field_type),
+                            None => {
+                                self.push_constant(&field, &field_type);
+                                self.set(result.unwrap());
+                            },
+                        }
+                    },
+                    aggregate => match result_slot {
+                        Some(slot) => {
+                            self.push_slot(slot);
+                            self.push(aggregate, type_);
+                            if offset > 0 {
+                                self.emit(&format!("i32.const {}", offset));
+                                self.emit("i32.add");
+                            }
+                            self.emit(&format!("i32.const {}", size_of(module, &field_type)));
+                            self.emit("memory.copy");
+                        },
+                        None => {
+                            self.push(aggregate, type_);
+                            self.emit(&with_offset(load_instruction(&field_type), offset));
+                            self.set(result.unwrap());
+                        },
+                    },
+                }
+            },
+            InstructionKind::InsertValue{type_, aggregate, value, index} => {
+                let slot = result_slot.unwrap();
+                let field_type = module.field_type(type_, *index).unwrap();
+                let offset = field_offset(module, type_, *index);
+                self.store_aggregate(&|generator : &mut FunctionGenerator| generator.push_slot(slot), aggregate, type_);
+                match value {
+                    Value::Constant(constant) => {
+                        self.store_constant(&|generator : &mut FunctionGenerator| generator.push_slot(slot), offset, constant, &field_type);
+                    },
+                    value if field_type.is_aggregate() => {
+                        let value = value.clone();
+                        self.store_aggregate(&|generator : &mut FunctionGenerator| {
+                            generator.push_slot(slot);
+                            generator.emit(&format!("i32.const {}", offset));
+                            generator.emit("i32.add");
+                        }, &value, &field_type);
+                    },
+                    value => {
+                        self.push_slot(slot);
+                        self.push(value, &field_type);
+                        self.emit(&with_offset(store_instruction(&field_type), offset));
+                    },
+                }
+            },
+            InstructionKind::Call{callee, return_type, arguments} => {
+                let returns_aggregate = return_type.as_ref().is_some_and(|type_| type_.is_aggregate());
+                if returns_aggregate {
+                    let slot = match result_slot {
+                        Some(slot) => slot,
+                        None => self.allocate(return_type.as_ref().unwrap()),
+                    };
+                    self.push_slot(slot);
+                }
+                for (type_, argument) in arguments {
+                    self.push(argument, type_);
+                }
+                // The runtime functions keep their own names
+                match callee {
+                    Callee::Function(name) => self.emit(&format!("call {}", identifier(name))),
+                    Callee::Extern(name) => self.emit(&format!("call ${}", name)),
+                }
+                match (result, return_type) {
+                    (_, None) => {},
+                    _ if returns_aggregate => {},
+                    (Some(result), Some(_)) => self.set(result),
+                    (None, Some(_)) => self.emit("drop"),
+                }
+            },
+        }
+    }
+
+    /*******************/
+    /*******************/
+    /*******************/
+
+    fn position(&self, block : BlockId) -> usize {
+        self.positions[block.0].unwrap()
+    }
+
+    fn is_merge(&self, block : BlockId) -> bool {
+        self.forward_edges[block.0] >= 2
+    }
+
+    fn block_label(&self, block : BlockId) -> String {
+        identifier(&self.function.block(block).name)
+    }
+
+    fn loop_label(&self, block : BlockId) -> String {
+        identifier(&format!("{}.loop", self.function.block(block).name))
+    }
+
+    fn generate_tree(&mut self, block : BlockId) {
+        let mut merges : Vec<BlockId> = self.children[block.0]
+            .iter()
+            .copied()
+            .filter(|child| self.is_merge(*child))
+            .collect();
+        merges.sort_by_key(|merge| std::cmp::Reverse(self.position(*merge)));
+
+        if self.loop_headers[block.0] {
+            self.emit(&format!("loop {}", self.loop_label(block)));
+            self.indent += 1;
+            self.generate_within(block, &merges);
+            self.indent -= 1;
+            self.emit("end");
+        } else {
+            self.generate_within(block, &merges);
+        }
+    }
+
+    // The merge blocks dominated by `block` follow it, the one placed last
+    // is the outermost `block`.
+    fn generate_within(&mut self, block : BlockId, merges : &[BlockId]) {
+        match merges.split_first() {
+            Some((merge, rest)) => {
+                self.emit(&format!("block {}", self.block_label(*merge)));
+                self.indent += 1;
+                self.generate_within(block, rest);
+                self.indent -= 1;
+                self.emit("end");
+                self.generate_tree(*merge);
+            },
+            None => {
+                let function = self.function;
+                for instruction in &function.block(block).instructions {
+                    self.generate_instruction(instruction);
+                }
+                self.generate_terminator(block, function.block(block).terminator.as_ref().unwrap());
+            },
+        }
+    }
+
+    fn generate_branch(&mut self, from : BlockId, to : BlockId) {
+        self.generate_phi_copies(from, to);
+        if self.position(to) <= self.position(from) {
+            self.emit(&format!("br {}", self.loop_label(to)));
+        } else if self.is_merge(to) {
+            self.emit(&format!("br {}", self.block_label(to)));
+        } else {
+            self.generate_tree(to);
+        }
+    }
+
+    // All the values are read before any phi is assigned: scalars through
+    // the operand stack, aggregates through the copies of the phis when a
+    // phi reads another phi of the block.
+    fn generate_phi_copies(&mut self, from : BlockId, to : BlockId) {
+        let function = self.function;
+        let mut scalars = vec![];
+        let mut aggregates = vec![];
+        for instruction in &function.block(to).instructions {
+            let (type_, incoming) = match &instruction.kind {
+                InstructionKind::Phi{type_, incoming} => (type_, incoming),
+                _ => continue,
+            };
+            let result = instruction.result.unwrap();
+            let value = match incoming.iter().find(|(_, block)| *block == from) {
+                Some((value, _)) => value,
+                None => continue,
+            };
+            if *value == Value::Constant(Constant::Undef) || value.as_register() == Some(result) {
+                continue;
+            }
+            if type_.is_aggregate() {
+                aggregates.push((result, type_, value));
+            } else {
+                scalars.push((result, type_, value));
+            }
+        }
+
+        for (_, type_, value) in &scalars {
+            self.push(value, type_);
+        }
+
+        let reads_phi = aggregates
+            .iter()
+            .any(|(_, _, value)| value.as_register().is_some_and(|register| self.phi_copies.contains_key(&register)));
+        for (result, type_, value) in &aggregates {
+            let slot = if reads_phi {self.phi_copies[result]} else {self.slots[result]};
+            self.store_aggregate(&|generator : &mut FunctionGenerator| generator.push_slot(slot), value, type_);
+        }
+        if reads_phi {
+            for (result, type_, _) in &aggregates {
+                self.push_slot(self.slots[result]);
+                self.push_slot(self.phi_copies[result]);
+                self.emit(&format!("i32.const {}", size_of(self.module, type_)));
+                self.emit("memory.copy");
+            }
+        }
+
+        for (result, _, _) in scalars.iter().rev() {
+            self.set(*result);
+        }
+    }
+
+    fn generate_epilogue(&mut self) {
+        if self.needs_frame {
+            self.emit("local.get $toy.frame");
+            self.emit("global.set $toy.stack_pointer");
+        }
+    }
+
+    fn generate_terminator(&mut self, block : BlockId, terminator : &Terminator) {
+        match terminator {
+            Terminator::Return(None) => {
+                self.generate_epilogue();
+                self.emit("return");
+            },
+            Terminator::Return(Some((type_, value))) if type_.is_aggregate() => {
+                self.store_aggregate(&|generator : &mut FunctionGenerator| generator.emit("local.get $toy.result"), value, type_);
+                self.generate_epilogue();
+                self.emit("return");
+            },
+            Terminator::Return(Some((type_, value))) => {
+                self.push(value, type_);
+                self.generate_epilogue();
+                self.emit("return");
+            },
+            Terminator::Branch(target) => self.generate_branch(block, *target),
+            Terminator::CondBranch{condition, then_block, else_block} => {
+                self.push(condition, &Type::I1);
+                self.generate_if(block, *then_block, *else_block);
+            },
+            Terminator::Switch{type_, value, default, cases} => self.generate_switch(block, type_, value, *default, cases),
+            Terminator::Trap | Terminator::Unreachable => self.emit("unreachable"),
+        }
+    }
+
+    fn generate_if(&mut self, block : BlockId, then_block : BlockId, else_block : BlockId) {
+        self.emit("if");
+        self.indent += 1;
+        self.generate_branch(block, then_block);
+        self.indent -= 1;
+        self.emit("else");
+        self.indent += 1;
+        self.generate_branch(block, else_block);
+        self.indent -= 1;
+        self.emit("end");
+    }
+
+    // Cases are tested in order, each in the `else` of the previous one
+    fn generate_switch(&mut self, block : BlockId, type_ : &Type, value : &Value, default : BlockId, cases : &[(i64, BlockId)]) {
+        match cases.split_first() {
+            Some(((case, target), rest)) => {
+                self.push(value, type_);
+                self.emit(&int_constant(*case, type_));
+                self.emit(&format!("{}.eq", value_type(type_)));
+                self.emit("if");
+                self.indent += 1;
+                self.generate_branch(block, *target);
+                self.indent -= 1;
+                self.emit("else");
+                self.indent += 1;
+                self.generate_switch(block, type_, value, default, rest);
+                self.indent -= 1;
+                self.emit("end");
+            },
+            None => self.generate_branch(block, default),
+        }
+    }
+}
@@ -0,0 +1,848 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+
+use crate::parser::ast::TranslationUnit;
+use crate::codegen::{CodegenOptions, CodegenError, lower_and_optimize, symbol_name};
+use crate::target::Target;
+use crate::mir::mir::*;
+use crate::mir::layout::{size_of, align_of, field_offset, align_to};
+use crate::mir::dominators::Dominators;
+
+const ARGUMENT_REGISTERS : [&str; 6] = ["%rdi", "%rsi", "%rdx", "%rcx", "%r8", "%r9"];
+const FLOAT_ARGUMENT_REGISTERS : usize = 8;
+
+pub fn create_asm(tu : &TranslationUnit, file_name : &str) -> Result<(), CodegenError> {
+    create_asm_with_options(tu, file_name, &CodegenOptions::default())
+}
+
+// Writes the file only once the whole text is generated, and removes what
+// was written if writing fails.
+pub fn create_asm_with_options(tu : &TranslationUnit, file_name : &str, options : &CodegenOptions) -> Result<(), CodegenError> {
+    let mut text = vec![];
+    generate_asm(tu, options, &mut text)?;
+    std::fs::write(file_name, text).map_err(|error| {
+        let _ = std::fs::remove_file(file_name);
+        CodegenError::Io(error)
+    })
+}
+
+// The output is GNU assembly for x86-64 Linux whatever the target of the
+// options, to be assembled with `as` and linked with the C runtime.
+pub fn generate_asm<W : Write>(tu : &TranslationUnit, options : &CodegenOptions, output : &mut W) -> Result<(), CodegenError> {
+    let options = CodegenOptions {
+        target : Target::x86_64(),
+        ..options.clone()
+    };
+    let module = lower_and_optimize(tu, &options)?;
+
+    output.write_all(AsmGenerator::new(&module).generate().as_bytes())?;
+    output.flush()?;
+    Ok(())
+}
+
+/****************************/
+/****************************/
+/****************************/
+
+// Name of the low `size` bytes of a 64 bit register
+fn register_part(register : &str, size : usize) -> String {
+    let name = &register[1..];
+    if name.starts_with('r') && name[1..].starts_with(|c : char| c.is_ascii_digit()) {
+        let suffix = match size {
+            1 => "b",
+            2 => "w",
+            4 => "d",
+            _ => "",
+        };
+        return format!("%{}{}", name, suffix);
+    }
+
+    let base = &name[1..];
+    match size {
+        1 if base.ends_with('x') => format!("%{}l", &base[..1]),
+        1 => format!("%{}l", base),
+        2 => format!("%{}", base),
+        4 => format!("%e{}", base),
+        _ => register.to_string(),
+    }
+}
+
+// Size of the scalar values of a type in memory
+fn scalar_size(type_ : &Type) -> usize {
+    match type_ {
+        Type::I1 | Type::I8 => 1,
+        Type::I16 => 2,
+        Type::I32 | Type::F32 => 4,
+        _ => 8,
+    }
+}
+
+// Suffix of the SSE instructions on floats of a type
+fn float_suffix(type_ : &Type) -> &'static str {
+    if *type_ == Type::F32 {"ss"} else {"sd"}
+}
+
+fn escape_bytes(bytes : &[u8]) -> String {
+    bytes.iter()
+        .map(|byte| match byte {
+            b' '..=b'~' if *byte != b'"' && *byte != b'\\' => (*byte as char).to_string(),
+            _ => format!("\\{:03o}", byte),
+        })
+        .collect()
+}
+
+/****************************/
+/****************************/
+/****************************/
+
+// Prints a MIR module as assembly. Aggregate constants are placed in
+// read-only data and used through their address.
+struct AsmGenerator<'a> {
+    module : &'a Module,
+    constants : Vec<String>,
+}
+
+impl<'a> AsmGenerator<'a> {
+    fn new(module : &'a Module) -> AsmGenerator<'a> {
+        AsmGenerator {
+            module,
+            constants : vec![],
+        }
+    }
+
+    fn generate(mut self) -> String {
+        let module = self.module;
+        let mut text = "    .text\n".to_string();
+
+        for (i, function) in module.functions.iter().enumerate() {
+            text += "\n";
+            text += &FunctionGenerator::new(&mut self, i, function).generate();
+        }
+
+        // The program returns the result of its `main` as exit code
+        if let Some(main) = module.function("main") {
+            text += "\n    .globl main\n    .type main, @function\nmain:\n";
+            text += &format!("    pushq %rbp\n    movq %rsp, %rbp\n    call {}\n", symbol_name("main"));
+            match &main.return_type {
+                Some(type_) if type_.is_int() => {},
+                _ => text += "    xorl %eax, %eax\n",
+            }
+            text += "    popq %rbp\n    ret\n";
+        }
+
+        if !module.globals.is_empty() {
+            text += "\n    .data\n";
+        }
+        for global in &module.globals {
+            text += &format!("    .p2align {}\n{}:\n", align_of(module, &global.type_).trailing_zeros(), symbol_name(&global.name));
+            for line in self.data(&global.init_value, &global.type_) {
+                text += &format!("    {}\n", line);
+            }
+        }
+
+        if !module.strings.is_empty() {
+            text += "\n    .section .rodata\n";
+        }
+        for (i, string) in module.strings.iter().enumerate() {
+            text += &format!(".Ltoy.string.{}:\n    .ascii \"{}\"\n", i, escape_bytes(string.as_bytes()));
+        }
+
+        // Constants may hold addresses, relocated when the program is loaded
+        if !self.constants.is_empty() {
+            text += "\n    .section .data.rel.ro.local,\"aw\"\n";
+        }
+        for constant in &self.constants {
+            text += constant;
+        }
+
+        text + "\n    .section .note.GNU-stack,\"\",@progbits\n"
+    }
+
+    // Directives writing a constant, with the padding of its fields
+    fn data(&self, constant : &Constant, type_ : &Type) -> Vec<String> {
+        let module = self.module;
+        let size = size_of(module, type_);
+        match constant {
+            Constant::Int(value) => match size {
+                1 => vec![format!(".byte {}", value & 0xff)],
+                2 => vec![format!(".short {}", value & 0xffff)],
+                4 => vec![format!(".long {}", value & 0xffff_ffff)],
+                _ => vec![format!(".quad {}", value)],
+            },
+            Constant::Float(value) if *type_ == Type::F32 => vec![format!(".long {}", (*value as f32).to_bits())],
+            Constant::Float(value) => vec![format!(".quad {}", value.to_bits())],
+            Constant::Null | Constant::Zero | Constant::Undef => vec![format!(".zero {}", size)],
+            Constant::String(index) => vec![
+                format!(".quad .Ltoy.string.{}", index),
+                format!(".quad {}", module.strings[*index].len()),
+            ],
+            Constant::Global(name) => vec![format!(".quad {}", symbol_name(name))],
+            Constant::Aggregate(elements) => {
+                let mut lines = vec![];
+                let mut end = 0;
+                for (i, element) in elements.iter().enumerate() {
+                    let element_type = module.field_type(type_, i).unwrap();
+                    let offset = field_offset(module, type_, i);
+                    if offset > end {
+                        lines.push(format!(".zero {}", offset - end));
+                    }
+                    lines.extend(self.data(element, &element_type));
+                    end = offset + size_of(module, &element_type);
+                }
+                if size > end {
+                    lines.push(format!(".zero {}", size - end));
+                }
+                lines
+            },
+        }
+    }
+
+    // Label of a new read-only copy of an aggregate constant
+    fn constant(&mut self, constant : &Constant, type_ : &Type) -> String {
+        let label = format!(".Ltoy.constant.{}", self.constants.len());
+        let mut text = format!("    .p2align {}\n{}:\n", align_of(self.module, type_).trailing_zeros(), label);
+        for line in self.data(constant, type_) {
+            text += &format!("    {}\n", line);
+        }
+        self.constants.push(text);
+        label
+    }
+}
+
+/****************************/
+/****************************/
+/****************************/
+
+// Every register has its own stack slot below `%rbp`, and instructions
+// load their operands from the slots into `%rax`, `%rcx` or `%xmm0` and
+// `%xmm1`, and store their result back. Allocas get the memory they
+// allocate, their value is its address. Aggregate registers hold the
+// bytes of the aggregate, except aggregate parameters which hold the
+// address of the caller's copy.
+//
+// Calls follow the System V ABI for scalars. Aggregates are passed and
+// returned in memory, as that ABI does for the ones larger than 16 bytes.
+struct FunctionGenerator<'a, 'g> {
+    generator : &'g mut AsmGenerator<'a>,
+    module : &'a Module,
+    function : &'a Function,
+    index : usize,
+    // Distance below `%rbp` of the slot of each register, and of the
+    // copies of the phis used to assign them all at once
+    slots : HashMap<Register, usize>,
+    phi_copies : HashMap<Register, usize>,
+    allocas : HashSet<Register>,
+    // Slot of the address to write an aggregate result to
+    result_slot : Option<usize>,
+    frame_size : usize,
+    text : String,
+}
+
+impl<'a, 'g> FunctionGenerator<'a, 'g> {
+    fn new(generator : &'g mut AsmGenerator<'a>, index : usize, function : &'a Function) -> FunctionGenerator<'a, 'g> {
+        let module = generator.module;
+        let mut function_generator = FunctionGenerator {
+            generator,
+            module,
+            function,
+            index,
+            slots : HashMap::new(),
+            phi_copies : HashMap::new(),
+            allocas : HashSet::new(),
+            result_slot : None,
+            frame_size : 0,
+            text : String::new(),
+        };
+
+        if function.return_type.as_ref().is_some_and(|type_| type_.is_aggregate()) {
+            function_generator.result_slot = Some(function_generator.allocate(&Type::Ptr));
+        }
+        for parameter in &function.parameters {
+            let slot = function_generator.allocate(&Type::Ptr);
+            function_generator.slots.insert(*parameter, slot);
+        }
+        for instruction in function.blocks.iter().flat_map(|block| &block.instructions) {
+            let result = match instruction.result {
+                Some(result) => result,
+                None => continue,
+            };
+            let type_ = match &instruction.kind {
+                InstructionKind::Alloca(type_) => {
+                    function_generator.allocas.insert(result);
+                    type_
+                },
+                _ => &function.register(result).type_,
+            };
+            let slot = function_generator.allocate(type_);
+            function_generator.slots.insert(result, slot);
+            if let InstructionKind::Phi{..} = instruction.kind {
+                let copy = function_generator.allocate(type_);
+                function_generator.phi_copies.insert(result, copy);
+            }
+        }
+
+        function_generator
+    }
+
+    // Scalars take 8 bytes whatever their size
+    fn allocate(&mut self, type_ : &Type) -> usize {
+        let (size, align) = match type_ {
+            type_ if type_.is_aggregate() => (size_of(self.module, type_), align_of(self.module, type_).max(8)),
+            _ => (8, 8),
+        };
+        self.frame_size = align_to(self.frame_size + size, align);
+        self.frame_size
+    }
+
+    fn emit(&mut self, line : &str) {
+        self.text += "    ";
+        self.text += line;
+        self.text += "\n";
+    }
+
+    fn block_label(&self, block : BlockId) -> String {
+        format!(".L{}.{}", self.index, self.function.block(block).name)
+    }
+
+    fn slot(&self, register : Register) -> String {
+        format!("-{}(%rbp)", self.slots[&register])
+    }
+
+    fn generate(mut self) -> String {
+        let function = self.function;
+        let name = symbol_name(&function.name);
+
+        // Parameters are saved to their slots
+        let (mut int_count, mut float_count, mut stack_count) = (0, 0, 0);
+        if let Some(slot) = self.result_slot {
+            self.emit(&format!("movq %rdi, -{}(%rbp)", slot));
+            int_count += 1;
+        }
+        for parameter in &function.parameters {
+            let type_ = &function.register(*parameter).type_;
+            let slot = self.slot(*parameter);
+            if type_.is_float() && float_count < FLOAT_ARGUMENT_REGISTERS {
+                self.emit(&format!("movs{} %xmm{}, {}", &float_suffix(type_)[1..], float_count, slot));
+                float_count += 1;
+            } else if !type_.is_float() && int_count < ARGUMENT_REGISTERS.len() {
+                self.emit(&format!("movq {}, {}", ARGUMENT_REGISTERS[int_count], slot));
+                int_count += 1;
+            } else {
+                self.emit(&format!("movq {}(%rbp), %rax", 16 + 8 * stack_count));
+                self.emit(&format!("movq %rax, {}", slot));
+                stack_count += 1;
+            }
+        }
+
+        let dominators = Dominators::new(function);
+        let order = dominators.reverse_postorder().to_vec();
+        for (i, block) in order.iter().enumerate() {
+            if i > 0 {
+                self.text += &format!("{}:\n", self.block_label(*block));
+            }
+            for instruction in &function.block(*block).instructions {
+                self.generate_instruction(instruction);
+            }
+            self.generate_terminator(*block, function.block(*block).terminator.as_ref().unwrap(), order.get(i + 1).copied());
+        }
+
+        // The stack stays 16 bytes aligned for calls
+        let mut text = format!("    .type {}, @function\n{}:\n    pushq %rbp\n    movq %rsp, %rbp\n", name, name);
+        let frame_size = align_to(self.frame_size, 16);
+        if frame_size > 0 {
+            text += &format!("    subq ${}, %rsp\n", frame_size);
+        }
+        text + &self.text
+    }
+
+    /*******************/
+    /*******************/
+    /*******************/
+
+    fn move_immediate(&mut self, value : i64, register : &str) {
+        if i32::try_from(value).is_ok() {
+            self.emit(&format!("movq ${}, {}", value, register));
+        } else {
+            self.emit(&format!("movabsq ${}, {}", value, register));
+        }
+    }
+
+    // Loads a scalar of memory into a 64 bit register, sign or zero
+    // extending integers. Floats are loaded as bits.
+    fn load_memory(&mut self, memory : &str, type_ : &Type, register : &str, signed : bool) {
+        let line = match (scalar_size(type_), signed) {
+            (1, true) => format!("movsbq {}, {}", memory, register),
+            (1, false) => format!("movzbq {}, {}", memory, register),
+            (2, true) => format!("movswq {}, {}", memory, register),
+            (2, false) => format!("movzwq {}, {}", memory, register),
+            (4, true) if !type_.is_float() => format!("movslq {}, {}", memory, register),
+            (4, _) => format!("movl {}, {}", memory, register_part(register, 4)),
+            _ => format!("movq {}, {}", memory, register),
+        };
+        self.emit(&line);
+        if *type_ == Type::I1 && signed {
+            self.emit(&format!("negq {}", register));
+        }
+    }
+
+    fn store_memory(&mut self, register : &str, type_ : &Type, memory : &str) {
+        let size = scalar_size(type_);
+        let instruction = match size {
+            1 => "movb",
+            2 => "movw",
+            4 => "movl",
+            _ => "movq",
+        };
+        self.emit(&format!("{} {}, {}", instruction, register_part(register, size), memory));
+    }
+
+    fn load(&mut self, value : &Value, type_ : &Type, register : &str, signed : bool) {
+        match value {
+            Value::Register(source) if self.allocas.contains(source) => {
+                self.emit(&format!("leaq {}, {}", self.slot(*source), register));
+            },
+            Value::Register(source) => self.load_memory(&self.slot(*source), type_, register, signed),
+            Value::Constant(constant) => match constant {
+                Constant::Int(value) => {
+                    let value = match type_ {
+                        Type::I1 if signed => -value,
+                        Type::I64 => *value,
+                        type_ if !signed && type_.width() < 64 => value & ((1 << type_.width()) - 1),
+                        _ => *value,
+                    };
+                    self.move_immediate(value, register);
+                },
+                Constant::Float(value) if *type_ == Type::F32 => self.move_immediate((*value as f32).to_bits() as i64, register),
+                Constant::Float(value) => self.move_immediate(value.to_bits() as i64, register),
+                Constant::Global(name) => self.emit(&format!("leaq {}(%rip), {}", symbol_name(name), register)),
+                Constant::Null | Constant::Zero | Constant::Undef => self.move_immediate(0, register),
+                Constant::String(_) | Constant::Aggregate(_) => unreachable!(),
+            },
+        }
+    }
+
+    // Floats whose bits come from `%r11` when constant
+    fn load_float(&mut self, value : &Value, type_ : &Type, register : &str) {
+        match value {
+            Value::Register(source) => {
+                let slot = self.slot(*source);
+                self.emit(&format!("movs{} {}, {}", &float_suffix(type_)[1..], slot, register));
+            },
+            value => {
+                self.load(value, type_, "%r11", false);
+                self.emit(&format!("movq %r11, {}", register));
+            },
+        }
+    }
+
+    fn store_float(&mut self, register : &str, type_ : &Type, result : Register) {
+        let slot = self.slot(result);
+        self.emit(&format!("movs{} {}, {}", &float_suffix(type_)[1..], register, slot));
+    }
+
+    fn store(&mut self, register : &str, result : Register) {
+        let type_ = self.function.register(result).type_.clone();
+        let slot = self.slot(result);
+        self.store_memory(register, &type_, &slot);
+    }
+
+    // Address of the bytes of an aggregate value
+    fn load_address(&mut self, value : &Value, type_ : &Type, register : &str) {
+        match value {
+            Value::Register(source) if self.function.parameters.contains(source) => {
+                self.emit(&format!("movq {}, {}", self.slot(*source), register));
+            },
+            Value::Register(source) => self.emit(&format!("leaq {}, {}", self.slot(*source), register)),
+            Value::Constant(constant) => {
+                let label = self.generator.constant(constant, type_);
+                self.emit(&format!("leaq {}(%rip), {}", label, register));
+            },
+        }
+    }
+
+    // Copies `size` bytes from `%rsi` to `%rdi`
+    fn copy(&mut self, size : usize) {
+        self.emit(&format!("movq ${}, %rcx", size));
+        self.emit("rep movsb");
+    }
+
+    // Copies a scalar or aggregate value to `offset` from the address in `%rdi`
+    fn store_value(&mut self, value : &Value, type_ : &Type, offset : usize) {
+        if type_.is_aggregate() {
+            if offset > 0 {
+                self.emit(&format!("addq ${}, %rdi", offset));
+            }
+            self.load_address(value, type_, "%rsi");
+            self.copy(size_of(self.module, type_));
+        } else {
+            self.load(value, type_, "%rcx", false);
+            self.store_memory("%rcx", type_, &format!("{}(%rdi)", offset));
+        }
+    }
+
+    /*******************/
+    /*******************/
+    /*******************/
+
+    fn generate_binary(&mut self, op : BinaryOp, type_ : &Type, left : &Value, right : &Value, result : Register) {
+        if op.is_float() {
+            let suffix = float_suffix(type_);
+            self.load_float(left, type_, "%xmm0");
+            self.load_float(right, type_, "%xmm1");
+            match op {
+                BinaryOp::FAdd => self.emit(&format!("add{} %xmm1, %xmm0", suffix)),
+                BinaryOp::FSub => self.emit(&format!("sub{} %xmm1, %xmm0", suffix)),
+                BinaryOp::FMul => self.emit(&format!("mul{} %xmm1, %xmm0", suffix)),
+                BinaryOp::FDiv => self.emit(&format!("div{} %xmm1, %xmm0", suffix)),
+                _ => self.emit(if *type_ == Type::F32 {"call fmodf@PLT"} else {"call fmod@PLT"}),
+            }
+            self.store_float("%xmm0", type_, result);
+            return;
+        }
+
+        let signed = matches!(op, BinaryOp::SDiv | BinaryOp::SRem | BinaryOp::AShr);
+        self.load(left, type_, "%rax", signed);
+        self.load(right, type_, "%rcx", signed);
+        match op {
+            BinaryOp::Add => self.emit("addq %rcx, %rax"),
+            BinaryOp::Sub => self.emit("subq %rcx, %rax"),
+            BinaryOp::Mul => self.emit("imulq %rcx, %rax"),
+            BinaryOp::And => self.emit("andq %rcx, %rax"),
+            BinaryOp::Or => self.emit("orq %rcx, %rax"),
+            BinaryOp::Xor => self.emit("xorq %rcx, %rax"),
+            BinaryOp::Shl => self.emit("shlq %cl, %rax"),
+            BinaryOp::AShr => self.emit("sarq %cl, %rax"),
+            BinaryOp::LShr => self.emit("shrq %cl, %rax"),
+            BinaryOp::SDiv | BinaryOp::SRem => {
+                self.emit("cqto");
+                self.emit("idivq %rcx");
+            },
+            _ => {
+                self.emit("xorl %edx, %edx");
+                self.emit("divq %rcx");
+            },
+        }
+        if matches!(op, BinaryOp::SRem | BinaryOp::URem) {
+            self.emit("movq %rdx, %rax");
+        }
+        self.store("%rax", result);
+    }
+
+    fn generate_compare(&mut self, comparison : Comparison, type_ : &Type, left : &Value, right : &Value, result : Register) {
+        if comparison.is_float() {
+            let instruction = format!("ucomi{}", float_suffix(type_));
+            self.load_float(left, type_, "%xmm0");
+            self.load_float(right, type_, "%xmm1");
+            // `a < b` is tested as `b > a`, which is false when unordered
+            match comparison {
+                Comparison::FOlt | Comparison::FOle => self.emit(&format!("{} %xmm0, %xmm1", instruction)),
+                _ => self.emit(&format!("{} %xmm1, %xmm0", instruction)),
+            }
+            match comparison {
+                Comparison::FOeq => {
+                    self.emit("sete %al");
+                    self.emit("setnp %cl");
+                    self.emit("andb %cl, %al");
+                },
+                Comparison::FUne => {
+                    self.emit("setne %al");
+                    self.emit("setp %cl");
+                    self.emit("orb %cl, %al");
+                },
+                Comparison::FOgt | Comparison::FOlt => self.emit("seta %al"),
+                _ => self.emit("setae %al"),
+            }
+        } else {
+            let signed = matches!(comparison, Comparison::SLt | Comparison::SLe | Comparison::SGt | Comparison::SGe);
+            self.load(left, type_, "%rax", signed);
+            self.load(right, type_, "%rcx", signed);
+            self.emit("cmpq %rcx, %rax");
+            let condition = match comparison {
+                Comparison::Eq => "e",
+                Comparison::Ne => "ne",
+                Comparison::SLt => "l",
+                Comparison::SLe => "le",
+                Comparison::SGt => "g",
+                Comparison::SGe => "ge",
+                Comparison::ULt => "b",
+                Comparison::ULe => "be",
+                Comparison::UGt => "a",
+                _ => "ae",
+            };
+            self.emit(&format!("set{} %al", condition));
+        }
+        self.store("%rax", result);
+    }
+
+    fn generate_cast(&mut self, op : CastOp, from : &Type, value : &Value, to : &Type, result : Register) {
+        match op {
+            CastOp::Trunc | CastOp::ZExt | CastOp::SExt => {
+                self.load(value, from, "%rax", op == CastOp::SExt);
+                if *to == Type::I1 {
+                    self.emit("andq $1, %rax");
+                }
+                self.store("%rax", result);
+            },
+            CastOp::FPTrunc | CastOp::FPExt => {
+                self.load_float(value, from, "%xmm0");
+                self.emit(&format!("cvt{}2{} %xmm0, %xmm0", float_suffix(from), float_suffix(to)));
+                self.store_float("%xmm0", to, result);
+            },
+            // Truncated towards zero, values out of the range of `int64` are lost
+            CastOp::FPToSI | CastOp::FPToUI => {
+                self.load_float(value, from, "%xmm0");
+                self.emit(&format!("cvtt{}2si %xmm0, %rax", float_suffix(from)));
+                self.store("%rax", result);
+            },
+            CastOp::SIToFP | CastOp::UIToFP => {
+                let instruction = format!("cvtsi2{}q", float_suffix(to));
+                self.load(value, from, "%rax", op == CastOp::SIToFP);
+                if op == CastOp::UIToFP && *from == Type::I64 {
+                    // Halved, keeping the lowest bit for rounding, and doubled
+                    self.emit("testq %rax, %rax");
+                    self.emit("js 1f");
+                    self.emit(&format!("{} %rax, %xmm0", instruction));
+                    self.emit("jmp 2f");
+                    self.text += "1:\n";
+                    self.emit("movq %rax, %rcx");
+                    self.emit("shrq %rcx");
+                    self.emit("andl $1, %eax");
+                    self.emit("orq %rax, %rcx");
+                    self.emit(&format!("{} %rcx, %xmm0", instruction));
+                    self.emit(&format!("add{} %xmm0, %xmm0", float_suffix(to)));
+                    self.text += "2:\n";
+                } else {
+                    self.emit(&format!("{} %rax, %xmm0", instruction));
+                }
+                self.store_float("%xmm0", to, result);
+            },
+        }
+    }
+
+    fn generate_call(&mut self, callee : &Callee, return_type : &Option<Type>, arguments : &[(Type, Value)], result : Option<Register>) {
+        let mut int_arguments = vec![];
+        let mut float_arguments = vec![];
+        let mut stack_arguments = vec![];
+        let returns_aggregate = return_type.as_ref().is_some_and(|type_| type_.is_aggregate());
+        let mut int_count = if returns_aggregate {1} else {0};
+        for (type_, value) in arguments {
+            if type_.is_float() && float_arguments.len() < FLOAT_ARGUMENT_REGISTERS {
+                float_arguments.push((type_, value));
+            } else if !type_.is_float() && int_count < ARGUMENT_REGISTERS.len() {
+                int_arguments.push((ARGUMENT_REGISTERS[int_count], type_, value));
+                int_count += 1;
+            } else {
+                stack_arguments.push((type_, value));
+            }
+        }
+
+        let padding = stack_arguments.len() % 2 == 1;
+        if padding {
+            self.emit("subq $8, %rsp");
+        }
+        for (type_, value) in stack_arguments.iter().rev() {
+            match type_.is_aggregate() {
+                true => self.load_address(value, type_, "%rax"),
+                false => self.load(value, type_, "%rax", false),
+            }
+            self.emit("pushq %rax");
+        }
+        for (i, (type_, value)) in float_arguments.iter().enumerate() {
+            self.load_float(value, type_, &format!("%xmm{}", i));
+        }
+        for (register, type_, value) in int_arguments {
+            match type_.is_aggregate() {
+                true => self.load_address(value, type_, register),
+                false => self.load(value, type_, register, false),
+            }
+        }
+        if returns_aggregate {
+            let slot = match result {
+                Some(result) => self.slots[&result],
+                None => self.allocate(return_type.as_ref().unwrap()),
+            };
+            self.emit(&format!("leaq -{}(%rbp), %rdi", slot));
+        }
+
+        match callee {
+            Callee::Function(name) => self.emit(&format!("call {}", symbol_name(name))),
+            Callee::Extern(name) => self.emit(&format!("call {}@PLT", name)),
+        }
+        let stack_size = 8 * (stack_arguments.len() + padding as usize);
+        if stack_size > 0 {
+            self.emit(&format!("addq ${}, %rsp", stack_size));
+        }
+
+        match (result, return_type) {
+            (Some(result), Some(type_)) if type_.is_float() => self.store_float("%xmm0", type_, result),
+            (Some(result), Some(type_)) if !type_.is_aggregate() => self.store("%rax", result),
+            _ => {},
+        }
+    }
+
+    fn generate_instruction(&mut self, instruction : &Instruction) {
+        let module = self.module;
+        let result = instruction.result;
+        match &instruction.kind {
+            InstructionKind::Alloca(_) | InstructionKind::Phi{..} => {},
+            InstructionKind::Load{type_, pointer} => {
+                self.load(pointer, &Type::Ptr, "%rsi", false);
+                if type_.is_aggregate() {
+                    self.emit(&format!("leaq {}, %rdi", self.slot(result.unwrap())));
+                    self.copy(size_of(module, type_));
+                } else {
+                    self.load_memory("(%rsi)", type_, "%rax", false);
+                    self.store("%rax", result.unwrap());
+                }
+            },
+            InstructionKind::Store{type_, value, pointer} => {
+                self.load(pointer, &Type::Ptr, "%rdi", false);
+                self.store_value(value, type_, 0);
+            },
+            InstructionKind::Binary{op, type_, left, right} => self.generate_binary(*op, type_, left, right, result.unwrap()),
+            InstructionKind::Negate{type_, value} => {
+                self.load(value, type_, "%rax", false);
+                if type_.is_float() {
+                    self.emit(&format!("btcq ${}, %rax", type_.width() - 1));
+                } else {
+                    self.emit("negq %rax");
+                }
+                self.store("%rax", result.unwrap());
+            },
+            InstructionKind::Compare{comparison, type_, left, right} => {
+                self.generate_compare(*comparison, type_, left, right, result.unwrap());
+            },
+            InstructionKind::Cast{op, from, value, to} => self.generate_cast(*op, from, value, to, result.unwrap()),
+            InstructionKind::FieldAddress{type_, pointer, index} => {
+                self.load(pointer, &Type::Ptr, "%rax", false);
+                let offset = field_offset(module, type_, *index);
+                if offset > 0 {
+                    self.emit(&format!("addq ${}, %rax", offset));
+                }
+                self.store("%rax", result.unwrap());
+            },
+            InstructionKind::ElementAddress{type_, pointer, index} => {
+                let size = size_of(module, &module.field_type(type_, 0).unwrap());
+                self.load(pointer, &Type::Ptr, "%rax", false);
+                self.load(index, &Type::I64, "%rcx", true);
+                self.emit(&format!("imulq ${}, %rcx, %rcx", size));
+                self.emit("addq %rcx, %rax");
+                self.store("%rax", result.unwrap());
+            },
+            InstructionKind::Offset{type_, pointer, index} => {
+                self.load(pointer, &Type::Ptr, "%rax", false);
+                self.load(index, &Type::I64, "%rcx", true);
+                self.emit(&format!("imulq ${}, %rcx, %rcx", size_of(module, type_)));
+                self.emit("addq %rcx, %rax");
+                self.store("%rax", result.unwrap());
+            },
+            InstructionKind::ExtractValue{type_, aggregate, index} => {
+                let field_type = module.field_type(type_, *index).unwrap();
+                let offset = field_offset(module, type_, *index);
+                self.load_address(aggregate, type_, "%rsi");
+                if field_type.is_aggregate() {
+                    if offset > 0 {
+                        self.emit(&format!("addq ${}, %rsi", offset));
+                    }
+                    self.emit(&format!("leaq {}, %rdi", self.slot(result.unwrap())));
+                    self.copy(size_of(module, &field_type));
+                } else {
+                    self.load_memory(&format!("{}(%rsi)", offset), &field_type, "%rax", false);
+                    self.store("%rax", result.unwrap());
+                }
+            },
+            InstructionKind::InsertValue{type_, aggregate, value, index} => {
+                let slot = self.slot(result.unwrap());
+                let field_type = module.field_type(type_, *index).unwrap();
+                self.emit(&format!("leaq {}, %rdi", slot));
+                self.load_address(aggregate, type_, "%rsi");
+                self.copy(size_of(module, type_));
+                self.emit(&format!("leaq {}, %rdi", slot));
+                self.store_value(value, &field_type, field_offset(module, type_, *index));
+            },
+            InstructionKind::Call{callee, return_type, arguments} => self.generate_call(callee, return_type, arguments, result),
+        }
+    }
+
+    /*******************/
+    /*******************/
+    /*******************/
+
+    // Phis are assigned their copies once all of them are read
+    fn generate_phi_copies(&mut self, from : BlockId, to : BlockId) {
+        let function = self.function;
+        let mut phis = vec![];
+        for instruction in &function.block(to).instructions {
+            if let InstructionKind::Phi{type_, incoming} = &instruction.kind {
+                if let Some((value, _)) = incoming.iter().find(|(_, block)| *block == from) {
+                    phis.push((instruction.result.unwrap(), type_, value));
+                }
+            }
+        }
+
+        for (phi, type_, value) in &phis {
+            self.emit(&format!("leaq -{}(%rbp), %rdi", self.phi_copies[phi]));
+            self.store_value(value, type_, 0);
+        }
+        for (phi, type_, _) in &phis {
+            self.emit(&format!("leaq -{}(%rbp), %rsi", self.phi_copies[phi]));
+            self.emit(&format!("leaq {}, %rdi", self.slot(*phi)));
+            match type_.is_aggregate() {
+                true => self.copy(size_of(self.module, type_)),
+                false => {
+                    self.emit("movq (%rsi), %rax");
+                    self.emit("movq %rax, (%rdi)");
+                },
+            }
+        }
+    }
+
+    fn generate_jump(&mut self, from : BlockId, to : BlockId, next : Option<BlockId>) {
+        self.generate_phi_copies(from, to);
+        if next != Some(to) {
+            self.emit(&format!("jmp {}", self.block_label(to)));
+        }
+    }
+
+    fn generate_terminator(&mut self, block : BlockId, terminator : &Terminator, next : Option<BlockId>) {
+        match terminator {
+            Terminator::Return(value) => {
+                match value {
+                    Some((type_, value)) if type_.is_aggregate() => {
+                        self.emit(&format!("movq -{}(%rbp), %rdi", self.result_slot.unwrap()));
+                        self.store_value(value, type_, 0);
+                        self.emit(&format!("movq -{}(%rbp), %rax", self.result_slot.unwrap()));
+                    },
+                    Some((type_, value)) if type_.is_float() => self.load_float(value, type_, "%xmm0"),
+                    Some((type_, value)) => self.load(value, type_, "%rax", false),
+                    None => {},
+                }
+                self.emit("leave");
+                self.emit("ret");
+            },
+            Terminator::Branch(target) => self.generate_jump(block, *target, next),
+            Terminator::CondBranch{condition, then_block, else_block} => {
+                let else_label = format!(".L{}.{}.else", self.index, block.0);
+                self.load(condition, &Type::I1, "%rax", false);
+                self.emit("testq %rax, %rax");
+                self.emit(&format!("jz {}", else_label));
+                self.generate_jump(block, *then_block, None);
+                self.text += &format!("{}:\n", else_label);
+                self.generate_jump(block, *else_block, next);
+            },
+            Terminator::Switch{type_, value, default, cases} => {
+                for (i, (case, target)) in cases.iter().enumerate() {
+                    let next_label = format!(".L{}.{}.case.{}", self.index, block.0, i);
+                    self.load(value, type_, "%rax", false);
+                    self.load(&Value::Constant(Constant::Int(*case)), type_, "%rcx", false);
+                    self.emit("cmpq %rcx, %rax");
+                    self.emit(&format!("jne {}", next_label));
+                    self.generate_jump(block, *target, None);
+                    self.text += &format!("{}:\n", next_label);
+                }
+                self.generate_jump(block, *default, next);
+            },
+            Terminator::Trap | Terminator::Unreachable => self.emit("ud2"),
+        }
+    }
+}
@@ -0,0 +1,2 @@
+pub mod asm_gen;
+pub mod prelude;
\ No newline at end of file
@@ -0,0 +1 @@
+pub use super::asm_gen::{create_asm, create_asm_with_options, generate_asm};
\ No newline at end of file
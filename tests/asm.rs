@@ -0,0 +1,40 @@
+use std::path::Path;
+
+use toy_lang::*;
+
+fn parse(source : &str) -> TranslationUnit {
+    TranslationUnit::from_lexeme(&Lexeme::from_source(source))
+}
+
+#[test]
+fn errors_leave_no_file() {
+    let file = std::env::temp_dir().join(format!("toy_asm_{}_lowering.s", std::process::id()));
+    let tu = parse("def int32 main() { string s = \"x\"; int32 b = s; return b; }");
+
+    let mut output = vec![];
+    let error = generate_asm(&tu, &CodegenOptions::default(), &mut output).unwrap_err();
+    assert!(matches!(error, CodegenError::Lowering(_)), "{:?}", error);
+    assert!(output.is_empty());
+
+    let error = create_asm(&tu, file.to_str().unwrap()).unwrap_err();
+    assert!(matches!(error, CodegenError::Lowering(_)), "{:?}", error);
+    assert!(!file.exists());
+
+    let tu = parse("def int32 main() { return 0; }");
+    let file = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/missing/example.s");
+    let error = create_asm(&tu, file.to_str().unwrap()).unwrap_err();
+    assert!(matches!(error, CodegenError::Io(_)), "{:?}", error);
+    assert!(!file.exists());
+}
+
+#[test]
+fn user_names_never_clash_with_the_entry_point() {
+    let tu = parse("int32 toy_main = 1; def int32 toy__main() { return toy_main; } def int32 main() { return toy__main(); }");
+    let mut output = vec![];
+    generate_asm(&tu, &CodegenOptions::default(), &mut output).unwrap();
+    let text = String::from_utf8(output).unwrap();
+
+    for expected in ["\ntoy_toy____main:\n", "\ntoy_main:\n", "\nmain:\n", "call toy_main\n", "toy_toy__main(%rip)"] {
+        assert!(text.contains(expected), "{:?} not in\n{}", expected, text);
+    }
+}
@@ -0,0 +1,73 @@
+use std::path::{Path, PathBuf};
+
+use toy_lang::*;
+
+fn parse(source : &str) -> TranslationUnit {
+    TranslationUnit::from_lexeme(&Lexeme::from_source(source))
+}
+
+fn temporary_path(name : &str) -> PathBuf {
+    std::env::temp_dir().join(format!("toy_c_{}_{}", std::process::id(), name))
+}
+
+#[test]
+fn files_match_the_generated_c() {
+    let tu = parse("string s = \"hello\"; def int32 main() { string t = s + s; return 3; }");
+    let source = temporary_path("file.c");
+    let header = temporary_path("file.h");
+
+    create_c(&tu, source.to_str().unwrap(), header.to_str().unwrap()).unwrap();
+    let written = (std::fs::read_to_string(&header).unwrap(), std::fs::read_to_string(&source).unwrap());
+    std::fs::remove_file(&header).unwrap();
+    std::fs::remove_file(&source).unwrap();
+
+    let name = header.file_name().unwrap().to_str().unwrap();
+    let (mut header_output, mut source_output) = (vec![], vec![]);
+    generate_c(&tu, &CodegenOptions::default(), name, &mut header_output, &mut source_output).unwrap();
+    assert_eq!(written, (String::from_utf8(header_output).unwrap(), String::from_utf8(source_output).unwrap()));
+    assert!(written.1.contains(&format!("#include \"{}\"", name)), "{}", written.1);
+}
+
+#[test]
+fn errors_leave_no_file() {
+    let tu = parse("def int32 main() { string s = \"x\"; int32 b = s; return b; }");
+    let source = temporary_path("lowering.c");
+    let header = temporary_path("lowering.h");
+
+    let error = create_c(&tu, source.to_str().unwrap(), header.to_str().unwrap()).unwrap_err();
+    assert!(matches!(error, CodegenError::Lowering(_)), "{:?}", error);
+    assert!(!source.exists() && !header.exists());
+
+    let tu = parse("def int32 main() { return 0; }");
+    let source = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/missing/example.c");
+    let error = create_c(&tu, source.to_str().unwrap(), header.to_str().unwrap()).unwrap_err();
+    assert!(matches!(error, CodegenError::Io(_)), "{:?}", error);
+    assert!(!source.exists() && !header.exists());
+}
+
+#[test]
+fn user_names_never_clash_with_c_names() {
+    let tu = parse("int32 g = 1; def int32 abs(int32 x) { return 42; } def int32 toy_main() { return 1; } def int32 main() { return abs(g) + toy_main(); }");
+    let (mut header, mut source) = (vec![], vec![]);
+    generate_c(&tu, &CodegenOptions::default(), "names.h", &mut header, &mut source).unwrap();
+    let header = String::from_utf8(header).unwrap();
+    let source = String::from_utf8(source).unwrap();
+
+    for expected in ["extern int32_t toy_g;", "int32_t toy_abs(int32_t x);", "int32_t toy_toy__main(void);", "int32_t toy_main(void);"] {
+        assert!(header.contains(expected), "{} not in\n{}", expected, header);
+    }
+    assert!(source.contains("return (int)toy_main();"), "{}", source);
+}
+
+// Names that only differ by characters C does not accept stay distinct
+#[test]
+fn non_ascii_names_stay_distinct() {
+    let tu = parse("class é { int32 à; int32 è; } class ü { int32 x; } def int32 f(int32 à, int32 è) { é v = é { à : à, è : è }; return v.à + v.è; } def int32 main() { ü u = ü { x : 1 }; return f(1, 2) + u.x; }");
+    let (mut header, mut source) = (vec![], vec![]);
+    generate_c(&tu, &CodegenOptions::default(), "names.h", &mut header, &mut source).unwrap();
+    let header = String::from_utf8(header).unwrap();
+
+    for expected in ["struct _e9_ {", "struct _fc_ {", "int32_t _e0_;", "int32_t _e8_;", "int32_t toy_f(int32_t _, int32_t __1);"] {
+        assert!(header.contains(expected), "{} not in\n{}", expected, header);
+    }
+}
@@ -0,0 +1,103 @@
+// Helpers shared by the integration tests, each of which uses some of them
+#![allow(dead_code)]
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+
+use toy_lang::*;
+
+pub fn programs() -> Vec<PathBuf> {
+    let directory = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/programs");
+    let mut programs : Vec<PathBuf> = std::fs::read_dir(directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "toy"))
+        .collect();
+    programs.sort();
+    programs
+}
+
+pub fn has_tool(tool : &str) -> bool {
+    Command::new(tool).arg("--version").output().is_ok_and(|output| output.status.success())
+}
+
+/****************************/
+/****************************/
+/****************************/
+
+// Backends whose output is built into an executable with the C runtime
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    // Compiled with `llc`
+    Llvm,
+    // Compiled with `cc -std=c99 -Wall -Werror`
+    C,
+    // Assembled with `as`, on x86-64 Linux only
+    Asm,
+}
+
+impl Backend {
+    pub fn is_available(&self) -> bool {
+        match self {
+            Backend::Llvm => has_tool("llc") && has_tool("cc"),
+            Backend::C => has_tool("cc"),
+            Backend::Asm => cfg!(all(target_arch = "x86_64", target_os = "linux")) && has_tool("as") && has_tool("cc"),
+        }
+    }
+
+    // Builds the program with the backend and returns its exit code
+    pub fn run(&self, tu : &TranslationUnit, name : &str, opt_level : OptLevel) -> i32 {
+        let options = CodegenOptions {
+            opt_level,
+            ..CodegenOptions::default()
+        };
+        self.run_with_options(tu, name, &options).code().expect("program killed by a signal")
+    }
+
+    pub fn run_with_options(&self, tu : &TranslationUnit, name : &str, options : &CodegenOptions) -> ExitStatus {
+        let opt_level = options.opt_level;
+        let directory = std::env::temp_dir().join(format!("toy_{:?}_{}_{}_{:?}", self, std::process::id(), name, opt_level));
+        std::fs::create_dir_all(&directory).unwrap();
+        let file = |extension : &str| directory.join(format!("{}.{}", name, extension));
+        let executable = directory.join(name);
+        let runtime = Path::new(env!("CARGO_MANIFEST_DIR")).join("runtime/string.c");
+
+        let mut steps = vec![];
+        let object = match self {
+            Backend::Llvm => {
+                create_llvm_ir_with_options(tu, file("ll").to_str().unwrap(), options).unwrap();
+                let mut llc = Command::new("llc");
+                llc.args(["-opaque-pointers", "-relocation-model=pic"]).arg(file("ll")).arg("-o").arg(file("s"));
+                steps.push(llc);
+                file("s")
+            },
+            Backend::C => {
+                create_c_with_options(tu, file("c").to_str().unwrap(), file("h").to_str().unwrap(), options).unwrap();
+                file("c")
+            },
+            Backend::Asm => {
+                create_asm_with_options(tu, file("s").to_str().unwrap(), options).unwrap();
+                let mut as_ = Command::new("as");
+                as_.arg(file("s")).arg("-o").arg(file("o"));
+                steps.push(as_);
+                file("o")
+            },
+        };
+
+        let mut cc = Command::new("cc");
+        if *self == Backend::C {
+            cc.args(["-std=c99", "-Wall", "-Werror"]);
+        }
+        cc.arg(&object).arg(&runtime).arg("-lm").arg("-o").arg(&executable);
+        steps.push(cc);
+
+        for mut step in steps {
+            let status = step.status().unwrap();
+            assert!(status.success(), "{:?} failed on {} at {:?}", step.get_program(), name, opt_level);
+        }
+
+        let status = Command::new(&executable).status().unwrap();
+        std::fs::remove_dir_all(&directory).unwrap();
+        status
+    }
+}
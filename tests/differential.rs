@@ -0,0 +1,157 @@
+use std::path::Path;
+
+use toy_lang::*;
+
+mod common;
+use common::{programs, Backend};
+
+fn parse(path : &Path) -> TranslationUnit {
+    let lexeme = Lexeme::from_file(path.to_str().unwrap());
+    TranslationUnit::from_lexeme(&lexeme)
+}
+
+fn run_vm(tu : &TranslationUnit) -> Result<Option<Value>, String> {
+    run_bytecode(&compile_bytecode(tu)?)
+}
+
+fn expected_exit_code(tu : &TranslationUnit, name : &str) -> i64 {
+    match run_vm(tu) {
+        Ok(Some(Value::Int(value))) => value & 255,
+        Ok(Some(value)) => panic!("{}: main returned {:?}", name, value),
+        Ok(None) => 0,
+        Err(error) => panic!("{}: {}", name, error),
+    }
+}
+
+#[test]
+fn vm_matches_interpreter() {
+    for path in programs() {
+        let tu = parse(&path);
+        assert_eq!(run_vm(&tu), interpret(&tu), "{}", path.display());
+    }
+}
+
+// Every program exits with the value `main` returns in the VM, with each
+// backend and at each optimization level
+#[test]
+fn vm_matches_backends() {
+    for backend in [Backend::Llvm, Backend::C, Backend::Asm] {
+        if !backend.is_available() {
+            eprintln!("Tools for {:?} not found, skipping", backend);
+            continue;
+        }
+
+        for path in programs() {
+            let name = path.file_stem().unwrap().to_str().unwrap();
+            let tu = parse(&path);
+
+            let expected = expected_exit_code(&tu, name);
+            for opt_level in [OptLevel::O0, OptLevel::O1] {
+                assert_eq!(backend.run(&tu, name, opt_level) as i64, expected, "{} with {:?} at {:?}", name, backend, opt_level);
+            }
+        }
+    }
+}
+
+#[test]
+fn vm_reports_runtime_errors() {
+    let cases = [
+        ("int32[4] g; def int32 main() { int32 i = 4; return g[i]; }", "Index 4 out of bounds for length 4"),
+        ("def int32 main() { int32 z = 0; return 1 / z; }", "Division by zero"),
+        ("def int32 main() { int32* p = null; return *p; }", "Null pointer dereference"),
+        ("def int32 f() { } def int32 main() { return f(); }", "Function f ended without returning a value"),
+        ("def int32 f(int32 n) { return f(n + 1); } def int32 main() { return f(0); }", "Stack overflow"),
+        ("def int32 main() { int32 s = 40; return 1 << s; }", "Shift amount 40 out of range for int32"),
+        ("def int32 main() { usize s = 64; usize x = 1; x = x >> s; return 0; }", "Shift amount 64 out of range for usize"),
+        ("def int32 main() { uint8 s = 8; uint8 y = 1; y = y << s; return 0; }", "Shift amount 8 out of range for uint8"),
+    ];
+
+    for (source, error) in cases {
+        let tu = TranslationUnit::from_lexeme(&Lexeme::from_source(source));
+        assert_eq!(run_vm(&tu), Err(error.to_string()), "{}", source);
+        assert_eq!(interpret(&tu), Err(error.to_string()), "{}", source);
+    }
+}
+
+// With `bounds_check`, the backends stop the program on an out of range
+// index like the VM does
+#[test]
+fn bounds_checks_trap() {
+    let cases = [
+        ("in_bounds", "int32[4] g; def int32 main() { int32 i = 3; g[i] = 5; return g[i]; }", Some(5)),
+        ("past_the_end", "int32[4] g; def int32 main() { int32 i = 4; return g[i]; }", None),
+        ("negative", "def int32 main() { int32[4] a; int32 i = -1; a[i] = 1; return 0; }", None),
+    ];
+
+    for backend in [Backend::Llvm, Backend::C, Backend::Asm] {
+        if !backend.is_available() {
+            eprintln!("Tools for {:?} not found, skipping", backend);
+            continue;
+        }
+
+        for (name, source, expected) in cases {
+            let tu = TranslationUnit::from_lexeme(&Lexeme::from_source(source));
+            for opt_level in [OptLevel::O0, OptLevel::O1] {
+                let options = CodegenOptions {
+                    bounds_check : true,
+                    opt_level,
+                    ..CodegenOptions::default()
+                };
+                let status = backend.run_with_options(&tu, name, &options);
+                assert_eq!(status.code(), expected, "{} with {:?} at {:?}", name, backend, opt_level);
+            }
+        }
+    }
+}
+
+#[test]
+fn logical_operators_short_circuit() {
+    let cases = [
+        ("def int32 main() { int32* p = null; if p != null && *p == 1 { return 1; } return 2; }", 2),
+        ("def int32 main() { int32* p = null; if p == null || *p == 1 { return 1; } return 2; }", 1),
+        ("int32 calls = 0; def bool f() { calls = calls + 1; return true; } def int32 main() { bool a = false && f(); bool b = true || f(); bool c = true && f(); return calls; }", 1),
+    ];
+
+    for (source, expected) in cases {
+        let tu = TranslationUnit::from_lexeme(&Lexeme::from_source(source));
+        assert_eq!(run_vm(&tu), Ok(Some(Value::Int(expected))), "{}", source);
+        assert_eq!(interpret(&tu), Ok(Some(Value::Int(expected))), "{}", source);
+    }
+}
+
+// Types are checked once by `check_types`, before any engine runs
+#[test]
+fn engines_report_the_same_type_errors() {
+    let cases = [
+        ("def int32 main() { int32 x = 1; int32 x = 2; return x; }", "In function main: Variable x already defined"),
+        ("def int32 main() { int32 x = x; return 0; }", "In function main: Variable x not defined"),
+        ("def int32 main() { return f(1); } def int32 f() { return 0; }", "In function main: Function f expects 0 arguments"),
+        ("def int32 main() { string s = \"a\"; int32 x = s; return x; }", "In function main: Cannot convert string to int32"),
+        ("def int32 main() { bool b = 1 && 2; return 0; }", "In function main: Logical operators need bool operands, found int32"),
+    ];
+
+    for (source, error) in cases {
+        let tu = TranslationUnit::from_lexeme(&Lexeme::from_source(source));
+        assert_eq!(run_vm(&tu), Err(error.to_string()), "{}", source);
+        assert_eq!(interpret(&tu), Err(error.to_string()), "{}", source);
+        assert_eq!(lower_to_mir(&tu, &CodegenOptions::default()).err(), Some(error.to_string()), "{}", source);
+    }
+}
+
+// The backends write global initializers into the program, so every
+// engine rejects the ones that are not constants
+#[test]
+fn globals_need_constant_initializers() {
+    let cases = [
+        ("int32 g = 2 + 3;", "Global variable `g` must be initialized with a constant at 1:1"),
+        ("def int32 f() { return 1; } int32 g = f();", "Global variable `g` must be initialized with a constant at 1:29"),
+        ("int32 a = 1; int32 g = a;", "Global variable `g` must be initialized with a constant at 1:14"),
+        ("int32 a = 1; int32[2] g = [a, 2];", "Global variable `g` must be initialized with a constant at 1:14"),
+    ];
+
+    for (source, error) in cases {
+        let source = format!("{} def int32 main() {{ return 0; }}", source);
+        let panic = std::panic::catch_unwind(|| TranslationUnit::from_lexeme(&Lexeme::from_source(&source))).unwrap_err();
+        assert_eq!(panic.downcast_ref::<String>().map(String::as_str), Some(error), "{}", source);
+    }
+}
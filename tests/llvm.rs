@@ -0,0 +1,253 @@
+use std::path::{Path, PathBuf};
+
+use toy_lang::*;
+use toy_lang::mir::layout;
+
+mod common;
+use common::{programs, has_tool};
+
+fn parse(source : &str) -> TranslationUnit {
+    TranslationUnit::from_lexeme(&Lexeme::from_source(source))
+}
+
+fn temporary_path(name : &str) -> PathBuf {
+    std::env::temp_dir().join(format!("toy_llvm_{}_{}", std::process::id(), name))
+}
+
+const PROGRAM : &str = "string s = \"hello\"; def int32 main() { string t = s + s; return 3; }";
+
+#[test]
+fn writer_matches_to_string() {
+    let tu = parse(PROGRAM);
+    let options = CodegenOptions::default();
+    let module = lower_to_mir(&tu, &options).unwrap();
+    let generator = LLVMIRGenerator::new(&module);
+
+    let mut output = vec![];
+    generator.write(&mut output).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), generator.to_string());
+
+    let mut output = vec![];
+    generate_llvm_ir(&tu, &options, &mut output).unwrap();
+    assert_eq!(String::from_utf8(output).unwrap(), generator.to_string());
+}
+
+#[test]
+fn file_matches_to_string() {
+    let tu = parse(PROGRAM);
+    let module = lower_to_mir(&tu, &CodegenOptions::default()).unwrap();
+    let path = temporary_path("file.ll");
+
+    create_llvm_ir(&tu, path.to_str().unwrap()).unwrap();
+    let ir = std::fs::read_to_string(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(ir, LLVMIRGenerator::new(&module).to_string());
+}
+
+#[test]
+fn lowering_errors_leave_no_file() {
+    let tu = parse("def int32 main() { string s = \"x\"; int32 b = s; return b; }");
+    let path = temporary_path("lowering.ll");
+
+    let mut output = vec![];
+    let error = generate_llvm_ir(&tu, &CodegenOptions::default(), &mut output).unwrap_err();
+    assert!(matches!(error, CodegenError::Lowering(_)), "{:?}", error);
+    assert!(output.is_empty());
+
+    let error = create_llvm_ir(&tu, path.to_str().unwrap()).unwrap_err();
+    assert!(matches!(error, CodegenError::Lowering(_)), "{:?}", error);
+    assert!(!path.exists());
+}
+
+#[test]
+fn io_errors_are_returned() {
+    let tu = parse(PROGRAM);
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/missing/example.ll");
+
+    let error = create_llvm_ir(&tu, path.to_str().unwrap()).unwrap_err();
+    assert!(matches!(error, CodegenError::Io(_)), "{:?}", error);
+    assert!(!path.exists());
+}
+
+#[test]
+fn header_and_attributes_follow_the_options() {
+    let tu = parse(PROGRAM);
+    let options = CodegenOptions {
+        target : Target::aarch64(),
+        source_filename : Some("main.toy".to_string()),
+        attributes : Attributes {
+            function : vec!["nounwind".to_string(), "noinline".to_string()],
+            visibility : Visibility::Hidden,
+            module_flags : vec![ModuleFlag::new(8, "PIC Level", 2)],
+        },
+        ..CodegenOptions::default()
+    };
+
+    let mut output = vec![];
+    generate_llvm_ir(&tu, &options, &mut output).unwrap();
+    let ir = String::from_utf8(output).unwrap();
+
+    let header : Vec<&str> = ir.lines().take(3).collect();
+    assert_eq!(header, [
+        "source_filename = \"main.toy\"",
+        "target datalayout = \"e-m:e-i8:8:32-i16:16:32-i64:64-i128:128-n32:64-S128\"",
+        "target triple = \"aarch64-unknown-linux-gnu\"",
+    ]);
+    assert!(ir.contains("define hidden i32 @toy_main() #0 {"), "{}", ir);
+    assert!(ir.contains("attributes #0 = { nounwind noinline }"), "{}", ir);
+    assert!(ir.contains("!llvm.module.flags = !{!0}\n!0 = !{i32 8, !\"PIC Level\", i32 2}"), "{}", ir);
+
+    let options = CodegenOptions {
+        attributes : Attributes {
+            function : vec![],
+            ..Attributes::default()
+        },
+        ..CodegenOptions::default()
+    };
+    let module = lower_to_mir(&tu, &options).unwrap();
+    let ir = LLVMIRGenerator::with_options(&module, &options).to_string();
+    assert!(!ir.contains("source_filename") && !ir.contains("attributes"), "{}", ir);
+    assert!(ir.contains("define i32 @toy_main() {"), "{}", ir);
+    assert!(ir.contains("define i32 @main() {\n    %result = call i32 @toy_main()\n    ret i32 %result\n}"), "{}", ir);
+}
+
+#[test]
+fn names_are_quoted_and_never_clash_with_the_runtime() {
+    let tu = parse("class é { int32 x; } int32 à = 1; def int32 malloc(int32 n) { return n; } def int32 main() { é ü = é{x: à}; return malloc(ü.x); }");
+    let ir = LLVMIRGenerator::new(&lower_to_mir(&tu, &CodegenOptions::default()).unwrap()).to_string();
+
+    for expected in [r#"%"\C3\A9" = type { i32 }"#, "@toy__e0_ = global i32 1", "define i32 @toy_malloc(", r#"%"\C3\BC" = alloca %"\C3\A9""#, "define i32 @main()"] {
+        assert!(ir.contains(expected), "{} not in\n{}", expected, ir);
+    }
+    assert!(!ir.contains("@malloc"), "{}", ir);
+}
+
+#[test]
+fn datalayout_sizes_pointers_and_structs() {
+    let target = Target::new("i686-unknown-linux-gnu", "e-m:e-p:32:32-i64:32-f64:32-n8:16:32-S128").unwrap();
+    assert_eq!(target.pointer_width, 32);
+    assert_eq!(target.int_alignment(64), 4);
+    assert_eq!(target.float_alignment(64), 4);
+    assert_eq!(Target::x86_64().pointer_width, 64);
+    assert_eq!(Target::wasm32().pointer_width, 32);
+    assert!(Target::new("x", "e-p:abc:32").is_err());
+    assert_eq!(Target::from_flag("--target=aarch64"), Some(Target::aarch64()));
+    assert_eq!(Target::from_flag("--target=sparc"), None);
+
+    let tu = parse("class Pair { int8 a; int64 b; isize c; } Pair p; def int32 main() { return 0; }");
+    let sizes : Vec<(usize, usize)> = [Target::x86_64(), target]
+        .into_iter()
+        .map(|target| {
+            let options = CodegenOptions {
+                target,
+                ..CodegenOptions::default()
+            };
+            let module = lower_to_mir(&tu, &options).unwrap();
+            let pair = toy_lang::mir::mir::Type::Struct("Pair".to_string());
+            (layout::size_of(&module, &pair), layout::field_offset(&module, &pair, 1))
+        })
+        .collect();
+    assert_eq!(sizes, [(24, 8), (16, 4)]);
+}
+
+#[test]
+fn debug_info_describes_the_source() {
+    let source = "int32 g = 1;\ndef int32 twice(int32 x) {\n    int32 y = x * 2;\n    return y;\n}\ndef int32 main() {\n    return twice(g);\n}\n";
+    let tu = parse(source);
+    let options = CodegenOptions {
+        source_filename : Some("/work/twice.toy".to_string()),
+        debug_info : true,
+        ..CodegenOptions::default()
+    };
+    let module = lower_to_mir(&tu, &options).unwrap();
+    let ir = LLVMIRGenerator::with_options(&module, &options).to_string();
+
+    for expected in [
+        "!DIFile(filename: \"twice.toy\", directory: \"/work\")",
+        "distinct !DICompileUnit(language: DW_LANG_C99",
+        "@toy_g = global i32 1, !dbg",
+        "distinct !DIGlobalVariable(name: \"g\", linkageName: \"toy_g\"",
+        "distinct !DISubprogram(name: \"twice\", linkageName: \"toy_twice\", scope: !2, file: !2, line: 2",
+        "define i32 @toy_main() #0 !dbg",
+        "!DILocalVariable(name: \"x\", arg: 1,",
+        "!DILocalVariable(name: \"y\", scope:",
+        "!DILocation(line: 3, column: 5,",
+        "!DILocation(line: 7, column: 5,",
+        "call void @llvm.dbg.declare(metadata ptr %x, metadata",
+        "declare void @llvm.dbg.declare(metadata, metadata, metadata)",
+        "!{i32 2, !\"Debug Info Version\", i32 3}",
+    ] {
+        assert!(ir.contains(expected), "{} not in\n{}", expected, ir);
+    }
+
+    let without = LLVMIRGenerator::new(&lower_to_mir(&tu, &CodegenOptions::default()).unwrap()).to_string();
+    assert!(!without.contains("!dbg") && !without.contains("llvm.dbg"), "{}", without);
+
+    let options = CodegenOptions {
+        debug_info : true,
+        ..CodegenOptions::default()
+    };
+    assert!(matches!(generate_llvm_ir(&tu, &options, &mut vec![]), Err(CodegenError::Lowering(_))));
+}
+
+// llvm-dwarfdump prints non-ASCII bytes as octal escapes
+fn dwarf_escape(name : &str) -> String {
+    name.bytes()
+        .map(|byte| match byte.is_ascii() {
+            true => (byte as char).to_string(),
+            false => format!("\\{:03o}", byte),
+        })
+        .collect()
+}
+
+// Every program compiles with debug info, and the DWARF names its
+// functions and variables
+#[test]
+fn debug_info_is_valid_dwarf() {
+    if !has_tool("llc") || !has_tool("llvm-dwarfdump") {
+        eprintln!("llc or llvm-dwarfdump not found, skipping");
+        return;
+    }
+
+    for path in programs() {
+        let name = path.file_stem().unwrap().to_str().unwrap();
+        let lexeme = Lexeme::from_file(path.to_str().unwrap());
+        let tu = TranslationUnit::from_lexeme(&lexeme);
+
+        for opt_level in [OptLevel::O0, OptLevel::O1] {
+            let ir = temporary_path(&format!("{}_{:?}.ll", name, opt_level));
+            let object = temporary_path(&format!("{}_{:?}.o", name, opt_level));
+            let options = CodegenOptions {
+                opt_level,
+                source_filename : Some(path.to_str().unwrap().to_string()),
+                debug_info : true,
+                ..CodegenOptions::default()
+            };
+            create_llvm_ir_with_options(&tu, ir.to_str().unwrap(), &options).unwrap();
+
+            let status = std::process::Command::new("llc")
+                .args(["-opaque-pointers", "-filetype=obj"])
+                .arg(&ir)
+                .arg("-o")
+                .arg(&object)
+                .status()
+                .unwrap();
+            assert!(status.success(), "llc failed on {} at {:?}", name, opt_level);
+
+            let output = std::process::Command::new("llvm-dwarfdump").arg(&object).output().unwrap();
+            let dwarf = String::from_utf8_lossy(&output.stdout);
+            std::fs::remove_file(&ir).unwrap();
+            std::fs::remove_file(&object).unwrap();
+
+            assert!(dwarf.contains(&format!("DW_AT_name\t(\"{}.toy\")", name)), "{}", dwarf);
+            for function in &tu.functions {
+                assert!(dwarf.contains(&format!("DW_AT_name\t(\"{}\")", dwarf_escape(&function.identifier))), "{} in {}", function.identifier, name);
+            }
+            if opt_level == OptLevel::O0 {
+                for argument in tu.functions.iter().flat_map(|function| &function.arguments) {
+                    assert!(dwarf.contains(&format!("DW_AT_name\t(\"{}\")", dwarf_escape(&argument.identifier))), "{} in {}", argument.identifier, name);
+                }
+            }
+        }
+    }
+}
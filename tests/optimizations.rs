@@ -0,0 +1,103 @@
+use std::path::{Path, PathBuf};
+
+use toy_lang::*;
+use toy_lang::mir::mir::{Function, Module};
+use toy_lang::mir::mem2reg::promote_allocas;
+use toy_lang::mir::constant_fold::fold_constants;
+use toy_lang::mir::copy_propagation::propagate_copies;
+use toy_lang::mir::dead_code::eliminate_dead_code;
+
+type Pass = fn(&mut Function) -> bool;
+
+fn golden_path(file_name : &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden").join(file_name)
+}
+
+fn lower(path : &Path) -> Module {
+    let lexeme = Lexeme::from_file(path.to_str().unwrap());
+    let tu = TranslationUnit::from_lexeme(&lexeme);
+    lower_to_mir(&tu, &CodegenOptions::default()).unwrap()
+}
+
+fn run_passes(module : &mut Module, passes : &[Pass]) {
+    for function in &mut module.functions {
+        for pass in passes {
+            pass(function);
+        }
+    }
+    verify_module(module).unwrap();
+}
+
+// Compares the printed module with the golden file, or overwrites the
+// golden file when UPDATE_GOLDEN is set.
+fn check_golden(file_name : &str, module : &Module) {
+    let path = golden_path(file_name);
+    let actual = module.to_string();
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(&path, actual).unwrap();
+        return;
+    }
+
+    let expected = std::fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+    assert_eq!(actual, expected, "{} differs, run with UPDATE_GOLDEN=1 to update it", file_name);
+}
+
+// The module of `name.toy` after the `setup` passes is `name.before.mir`,
+// and after `pass` it is `name.after.mir`.
+fn check_pass(name : &str, setup : &[Pass], pass : Pass) {
+    let mut module = lower(&golden_path(&format!("{}.toy", name)));
+    run_passes(&mut module, setup);
+    check_golden(&format!("{}.before.mir", name), &module);
+
+    run_passes(&mut module, &[pass]);
+    check_golden(&format!("{}.after.mir", name), &module);
+}
+
+#[test]
+fn mem2reg() {
+    check_pass("mem2reg", &[], promote_allocas);
+}
+
+#[test]
+fn constant_fold() {
+    check_pass("constant_fold", &[promote_allocas], fold_constants);
+}
+
+#[test]
+fn copy_propagation() {
+    check_pass("copy_propagation", &[promote_allocas], propagate_copies);
+}
+
+#[test]
+fn dead_code() {
+    check_pass("dead_code", &[fold_constants], eliminate_dead_code);
+}
+
+#[test]
+fn example_folds_to_constants() {
+    let mut module = lower(&Path::new(env!("CARGO_MANIFEST_DIR")).join("example.toy"));
+    check_golden("example.before.mir", &module);
+
+    optimize_module(&mut module, OptLevel::O1);
+    verify_module(&module).unwrap();
+    check_golden("example.after.mir", &module);
+}
+
+#[test]
+fn passes_reach_a_fixed_point() {
+    let passes : [Pass; 4] = [promote_allocas, fold_constants, propagate_copies, eliminate_dead_code];
+    let sources = std::fs::read_dir(golden_path(""))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|extension| extension == "toy"));
+
+    for path in sources {
+        let mut module = lower(&path);
+        optimize_module(&mut module, OptLevel::O1);
+        for function in &mut module.functions {
+            for pass in passes {
+                assert!(!pass(function), "{} changed {} after -O1", path.display(), function.name);
+            }
+        }
+    }
+}
@@ -0,0 +1,209 @@
+use toy_lang::*;
+
+// The parser reports errors by panicking with the message and its position
+fn parse_error(source : &str) -> String {
+    let panic = std::panic::catch_unwind(|| TranslationUnit::from_lexeme(&Lexeme::from_source(source))).unwrap_err();
+    match panic.downcast::<String>() {
+        Ok(message) => *message,
+        Err(panic) => panic.downcast_ref::<&str>().unwrap().to_string(),
+    }
+}
+
+fn assert_errors(cases : &[(&str, &str)]) {
+    for (source, error) in cases {
+        assert_eq!(parse_error(source), *error, "{}", source);
+    }
+}
+
+// Errors found by `check_types` once the whole unit is parsed
+fn assert_type_errors(cases : &[(&str, &str)]) {
+    for (source, error) in cases {
+        let tu = TranslationUnit::from_lexeme(&Lexeme::from_source(source));
+        assert_eq!(check_types(&tu).err().as_deref(), Some(*error), "{}", source);
+    }
+}
+
+#[test]
+fn unexpected_end_of_file() {
+    assert_errors(&[
+        ("def int32 main() { return 0;", "Unexpected end of file: unclosed `{` at 1:18"),
+        ("def int32 main() {\n    while true {\n", "Unexpected end of file: unclosed `{` at 2:16"),
+        ("def int32 main(int32 a", "Unexpected end of file: unclosed argument list of function at 1:1"),
+        ("def int32 main", "Unexpected end of file: expected `(` at 1:15"),
+        ("def int32 main()", "Unexpected end of file: expected body of function at 1:1"),
+        ("int32 x", "Unexpected end of file: expected `;` after declaration at 1:1"),
+        ("int32 x = 1", "Unexpected end of file: expected `;` after statement at 1:11"),
+        ("def int32 main() { int32 x = 1; x = 2", "Unexpected end of file: expected `;` after statement at 1:33"),
+    ]);
+}
+
+#[test]
+fn missing_semicolon_before_closing_brace() {
+    assert_errors(&[
+        ("def int32 main() { return 0 }", "Expected `;` before `}` at 1:29"),
+        ("def int32 main() { int32 x = 1 }", "Expected `;` before `}` at 1:32"),
+    ]);
+}
+
+#[test]
+fn class_definition_errors() {
+    assert_errors(&[
+        ("class P { int32 x; } class P { int32 y; }", "Type `P` already defined at 1:28"),
+        ("class P { int32 x; fint64 x; }", "Duplicate member `x` in class `P` at 1:20"),
+        ("class P { foo x; }", "Unknown type `foo` at 1:11"),
+        ("class P { int32 x; } class Q { P p; Q q; }", "Unknown type `Q` at 1:37"),
+        ("class P { int32; }", "Expected field identifier at 1:16"),
+        ("class { int32 x; }", "Expected class identifier at 1:7"),
+        ("def int32 main() { class P { int32 x; } return 0; }", "Class `P` must be defined at top level"),
+        ("class P { int32 x;", "Unexpected end of file: unclosed class body at 1:1"),
+    ]);
+}
+
+#[test]
+fn class_values_only_convert_to_their_class() {
+    assert_type_errors(&[
+        ("class P { int32 x; } def int32 main() { P p; int32 x = p; return x; }", "In function main: Cannot convert P to int32"),
+        ("class P { int32 x; } class Q { int32 x; } def int32 main() { P p; Q q = p; return 0; }", "In function main: Cannot convert P to Q"),
+        ("class P { int32 x; } def int32 f(P p) { return p.x; } def int32 main() { return f(1); }", "In function main: Cannot convert constant of type int32 to P"),
+        ("class P { int32 x; } P g; def P f() { return 1; }", "In function f: Cannot convert constant of type int32 to P"),
+    ]);
+}
+
+#[test]
+fn member_access_errors() {
+    assert_errors(&[
+        ("class P { int32 x; } def int32 main() { P p; return p.y; }", "Class `P` has no field `y` at 1:55"),
+        ("class P { int32 x; } class Q { P p; } def int32 main() { Q q; return q.p.y; }", "Class `P` has no field `y` at 1:74"),
+        ("class P { int32 x; } def int32 main() { P p; p.y = 1; return 0; }", "Class `P` has no field `y` at 1:48"),
+        ("def int32 main() { int32 a = 1; return a.x; }", "Cannot access field `x` of non-class type `int32` at 1:42"),
+        ("class P { int32 x; } def int32 main() { P p; return p.; }", "Expected field name after `.` at 1:54"),
+    ]);
+}
+
+#[test]
+fn method_errors() {
+    assert_errors(&[
+        ("class P { int32 x; def int32 get() { return 0; } def int32 get() { return 1; } }", "Duplicate member `get` in class `P` at 1:50"),
+        ("class P { int32 x; def int32 x() { return 1; } }", "Duplicate member `x` in class `P` at 1:20"),
+        ("class P { int32 x; def int32 get() { return self.y; } }", "Class `P` has no field `y` at 1:50"),
+        ("class P { int32 x; } def int32 main() { P p; return p.len(); }", "Class `P` has no method `len` at 1:55"),
+        ("def int32 main() { int32 a = 1; return a.len(); }", "Cannot call method `len` on non-class type `int32` at 1:42"),
+    ]);
+    assert_type_errors(&[
+        ("class P { int32 x; def int32 get() { return self.x; } } def int32 main() { P p; return p.get(1); }", "In function main: Function P.get expects 0 arguments"),
+    ]);
+}
+
+#[test]
+fn struct_literal_errors() {
+    assert_errors(&[
+        ("class P { int32 x; } def int32 main() { P p = Q{x: 1}; return 0; }", "Unknown class `Q` at 1:47"),
+        ("class P { int32 x; } def int32 main() { P p = P{y: 1}; return 0; }", "Class `P` has no field `y` at 1:49"),
+        ("class P { int32 x; } def int32 main() { P p = P{x: 1, x: 2}; return 0; }", "Field `x` initialized more than once at 1:55"),
+        ("class P { int32 x; int32 y; } def int32 main() { P p = P{x: 1}; return 0; }", "Field `y` of class `P` not initialized at 1:56"),
+        ("class P { int32 x; } def int32 main() { P p = P{1}; return 0; }", "Expected field name at 1:49"),
+        ("class P { int32 x; } int32 a = 1; P g = P{x: a};", "Global variable `g` must be initialized with a constant at 1:35"),
+    ]);
+    assert_type_errors(&[
+        ("class P { int32 x; } def int32 main() { P p = P{x: 1.5}; return 0; }", "In function main: Cannot convert constant of type fint64 to int32"),
+        ("class P { int32 x; } def int32 main() { string s = \"a\"; P p = P{x: s}; return 0; }", "In function main: Cannot convert string to int32"),
+    ]);
+}
+
+#[test]
+fn array_errors() {
+    assert_errors(&[
+        ("def int32 main() { int32[x] a; return 0; }", "Expected array length at 1:26"),
+    ]);
+    assert_type_errors(&[
+        ("def int32 main() { int32[2] a; fint64 i = 0.0; return a[i]; }", "In function main: Array index must be an integer, found fint64"),
+        ("def int32 main() { int32 a = 1; return a[0]; }", "In function main: Cannot index a value of type int32"),
+        ("def int32 main() { int32[2] a; return a[2]; }", "In function main: Index 2 out of bounds for array of length 2"),
+        ("def int32 main() { int32[2] a = [1, 2, 3]; return 0; }", "In function main: Cannot assign an array literal of length 3 to int32[2]"),
+        ("def int32 main() { int32[2] a = [1, 2]; int32[3] b = a; return 0; }", "In function main: Cannot convert int32[2] to int32[3]"),
+        ("def int32 main() { int32[2] a = [1, 2.5]; return 0; }", "In function main: Cannot convert constant of type fint64 to int32"),
+    ]);
+}
+
+#[test]
+fn string_errors() {
+    assert_errors(&[
+        ("def int32 main() { string s = \"ab\"; return s.size(); }", "Type `string` has no method `size` at 1:46"),
+        ("def int32 main() { string s = \"a\\q\"; return 0; }", "Invalid escape sequence `\\q` in string literal at 1:31"),
+    ]);
+    assert_type_errors(&[
+        ("def int32 main() { string s = \"ab\"; s[0] = 1; return 0; }", "In function main: Cannot modify a character of a string, strings are immutable"),
+        ("def int32 main() { string s = \"ab\"; uint8* p = &s[0]; return 0; }", "In function main: Cannot modify a character of a string, strings are immutable"),
+        ("def int32 main() { string s = \"a\" - \"b\"; return 0; }", "In function main: Unsupported binary operator for strings"),
+        ("def int32 main() { string s = 1; return 0; }", "In function main: Cannot convert constant of type int32 to string"),
+        ("def int32 main() { string s = \"a\"; return -s; }", "In function main: Unary operators are not supported on string"),
+    ]);
+}
+
+#[test]
+fn pointer_errors() {
+    assert_type_errors(&[
+        ("def int32 main() { int32 x = 1; int64* p = &x; return 0; }", "In function main: Cannot convert int32* to int64*"),
+        ("def int32 main() { int32 x = 1; int32* p = &x; int64* q = p; return 0; }", "In function main: Cannot convert int32* to int64*"),
+        ("def int32 main() { int32 x = 1; int32* p = &x; int32 y = p; return y; }", "In function main: Cannot convert int32* to int32"),
+        ("def int32 main() { int32 x = 1; int32* p = x; return 0; }", "In function main: Cannot convert int32 to int32*"),
+        ("def int32 f(int32* p) { return *p; } def int32 main() { int64 x = 1; return f(&x); }", "In function main: Cannot convert int64* to int32*"),
+        ("def int32 main() { int32 x = 1; int32* p = &x; int64 y = 1; return p == &y; }", "In function main: Cannot convert int64* to int32*"),
+        ("def int32 main() { int32* p = &1; return 0; }", "In function main: Cannot take the address of a temporary value"),
+        ("def int32 main() { int32 x = 1; return *x; }", "In function main: Cannot dereference a value of type int32"),
+        ("def int32 main() { int32 x = 1; int32* p = &x; int32* q = p + 1; return 0; }", "In function main: Binary operators are not supported on int32*"),
+    ]);
+}
+
+#[test]
+fn enum_errors() {
+    assert_errors(&[
+        ("enum C { A } enum C { B }", "Type `C` already defined at 1:19"),
+        ("enum C { A, A }", "Duplicate variant `A` in enum `C` at 1:13"),
+        ("enum C { A = 1, B = 1 }", "Discriminant 1 of variant `B` already used by `A` at 1:17"),
+        ("enum C { A = 3000000000 }", "Discriminant 3000000000 of variant `A` does not fit in an int32 at 1:10"),
+        ("enum C { A = x }", "Expected integer discriminant at 1:14"),
+        ("enum C { }", "Enum `C` has no variants at 1:1"),
+        ("def int32 main() { enum C { A } return 0; }", "Enum `C` must be defined at top level"),
+        ("enum C { A, B } def int32 main() { C c = C.D; return 0; }", "Enum `C` has no variant `D` at 1:44"),
+    ]);
+    assert_type_errors(&[
+        ("enum C { A, B } enum D { A } def int32 main() { C c = D.A; return 0; }", "In function main: Cannot convert constant of type D to C"),
+        ("enum C { A, B } def int32 main() { C c = 1; return 0; }", "In function main: Cannot convert constant of type int32 to C"),
+        ("enum C { A, B } def int32 main() { C c = C.A; int32 x = c; return x; }", "In function main: Cannot convert C to int32"),
+        ("enum C { A, B } def int32 main() { C c = C.A; if c == 1 { return 1; } return 0; }", "In function main: Cannot convert constant of type int32 to C"),
+    ]);
+}
+
+#[test]
+fn match_errors() {
+    assert_errors(&[
+        ("enum C { A, B } def int32 main() { C c = C.A; match c { A => { return 1; } } return 0; }", "Non-exhaustive match, `B` not covered at 1:47"),
+        ("enum C { A, B } def int32 main() { C c = C.A; match c { A => { return 1; } A => { return 2; } _ => { return 3; } } }", "Variant `A` matched more than once at 1:76"),
+        ("enum C { A, B } def int32 main() { C c = C.A; match c { _ => { return 1; } _ => { return 2; } } }", "Duplicate `_` arm at 1:76"),
+        ("enum C { A, B } def int32 main() { C c = C.A; match c { D => { return 1; } _ => { return 3; } } }", "Enum `C` has no variant `D` at 1:57"),
+        ("enum C { A, B } def int32 main() { int32 c = 1; match c { _ => { return 1; } } }", "Cannot match on non-enum type `int32` at 1:49"),
+        ("enum C { A, B } def int32 main() { C c = C.A; match c { A => { return 1; }", "Unexpected end of file: unclosed match body at 1:47"),
+    ]);
+}
+
+#[test]
+fn control_flow_errors() {
+    assert_errors(&[
+        ("def int32 main() { else { } return 0; }", "`else` without `if` at 1:20"),
+        ("def int32 main() { if { } return 0; }", "Expected expression at 1:23"),
+        ("def int32 main() { while true", "Unexpected end of file: expected `{` at 1:20"),
+    ]);
+    assert_type_errors(&[
+        ("def int32 main() { if 1 { } return 0; }", "In function main: Condition must be a bool, found int32"),
+        ("def int32 main() { int32 x = 1; while x { } return 0; }", "In function main: Condition must be a bool, found int32"),
+        ("def int32 main() { for ; 2; { } return 0; }", "In function main: Condition must be a bool, found int32"),
+    ]);
+}
+
+#[test]
+fn for_variables_live_until_the_end_of_the_loop() {
+    let tu = TranslationUnit::from_lexeme(&Lexeme::from_source("def int32 main() { for int32 i = 0; i < 2; i = i + 1 { } return i; }"));
+    let error = interpret(&tu).unwrap_err();
+    assert!(error.ends_with("Variable i not defined"), "{}", error);
+}
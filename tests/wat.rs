@@ -0,0 +1,708 @@
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::process::Command;
+
+use toy_lang::*;
+
+mod common;
+use common::{programs, has_tool};
+
+// The output of the WebAssembly backend is checked by a small validator of
+// the text format below, which knows the instructions the backend and the
+// runtime use. The `wat` crate is not available offline, so the output is
+// also given to `wat2wasm` when it is installed.
+
+fn generate(path : &Path, opt_level : OptLevel) -> String {
+    let name = path.file_stem().unwrap().to_str().unwrap();
+    let file = std::env::temp_dir().join(format!("toy_wat_{}_{}_{:?}.wat", std::process::id(), name, opt_level));
+    let tu = TranslationUnit::from_lexeme(&Lexeme::from_file(path.to_str().unwrap()));
+    let options = CodegenOptions {
+        opt_level,
+        ..CodegenOptions::default()
+    };
+    create_wat_with_options(&tu, file.to_str().unwrap(), &options).unwrap();
+
+    let text = std::fs::read_to_string(&file).unwrap();
+    if has_tool("wat2wasm") {
+        let status = Command::new("wat2wasm")
+            .args(["--enable-bulk-memory", "--enable-saturating-float-to-int", "--enable-sign-extension"])
+            .arg(&file)
+            .args(["-o", "/dev/null"])
+            .status()
+            .unwrap();
+        assert!(status.success(), "wat2wasm rejected {} at {:?}", name, opt_level);
+    }
+    std::fs::remove_file(&file).unwrap();
+    text
+}
+
+/****************************/
+/****************************/
+/****************************/
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Sexpr {
+    List(Vec<Sexpr>),
+    Atom(String),
+    Str(Vec<u8>),
+}
+
+fn tokenize(text : &str) -> Result<Vec<Sexpr>, String> {
+    let bytes = text.as_bytes();
+    let mut stack = vec![vec![]];
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b' ' | b'\t' | b'\n' | b'\r' => i += 1,
+            b';' if bytes.get(i + 1) == Some(&b';') => {
+                while i < bytes.len() && bytes[i] != b'\n' {
+                    i += 1;
+                }
+            },
+            b'(' => {
+                stack.push(vec![]);
+                i += 1;
+            },
+            b')' => {
+                let list = stack.pop().unwrap();
+                stack.last_mut().ok_or("Unexpected `)`")?.push(Sexpr::List(list));
+                i += 1;
+            },
+            b'"' => {
+                let mut string = vec![];
+                i += 1;
+                loop {
+                    match bytes.get(i) {
+                        None => return Err("Unterminated string".to_string()),
+                        Some(b'"') => break,
+                        Some(b'\\') => {
+                            let escape = text.get(i + 1..i + 3).ok_or("Bad escape")?;
+                            string.push(u8::from_str_radix(escape, 16).map_err(|_| format!("Bad escape \\{}", escape))?);
+                            i += 3;
+                        },
+                        Some(byte) => {
+                            string.push(*byte);
+                            i += 1;
+                        },
+                    }
+                }
+                stack.last_mut().unwrap().push(Sexpr::Str(string));
+                i += 1;
+            },
+            _ => {
+                let start = i;
+                while i < bytes.len() && !b" \t\n\r()\";".contains(&bytes[i]) {
+                    i += 1;
+                }
+                if start == i {
+                    return Err(format!("Unexpected `{}`", bytes[i] as char));
+                }
+                stack.last_mut().unwrap().push(Sexpr::Atom(text[start..i].to_string()));
+            },
+        }
+    }
+    match stack.len() {
+        1 => Ok(stack.pop().unwrap()),
+        _ => Err("Missing `)`".to_string()),
+    }
+}
+
+fn atom(sexpr : &Sexpr) -> Option<&str> {
+    match sexpr {
+        Sexpr::Atom(atom) => Some(atom),
+        _ => None,
+    }
+}
+
+fn keyword(sexpr : &Sexpr) -> Option<&str> {
+    match sexpr {
+        Sexpr::List(items) => items.first().and_then(atom),
+        _ => None,
+    }
+}
+
+fn is_name(atom : &str) -> bool {
+    atom.len() > 1 && atom.starts_with('$')
+}
+
+/****************************/
+/****************************/
+/****************************/
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ValueType {
+    I32,
+    I64,
+    F32,
+    F64,
+}
+
+fn value_type(name : &str) -> Result<ValueType, String> {
+    match name {
+        "i32" => Ok(ValueType::I32),
+        "i64" => Ok(ValueType::I64),
+        "f32" => Ok(ValueType::F32),
+        "f64" => Ok(ValueType::F64),
+        _ => Err(format!("Unknown type {}", name)),
+    }
+}
+
+fn check_literal(type_ : ValueType, literal : &str) -> Result<(), String> {
+    let ok = match type_ {
+        ValueType::I32 => literal.parse::<i32>().is_ok() || literal.parse::<u32>().is_ok(),
+        ValueType::I64 => literal.parse::<i64>().is_ok() || literal.parse::<u64>().is_ok(),
+        ValueType::F32 | ValueType::F64 => {
+            let digits = literal.trim_start_matches(['-', '+']);
+            matches!(digits, "nan" | "inf") || (digits.starts_with(|c : char| c.is_ascii_digit()) && literal.parse::<f64>().is_ok())
+        },
+    };
+    if ok {Ok(())} else {Err(format!("Bad {:?} literal {}", type_, literal))}
+}
+
+struct Signature {
+    parameters : Vec<ValueType>,
+    results : Vec<ValueType>,
+}
+
+struct Frame {
+    label : Option<String>,
+    is_loop : bool,
+    is_if : bool,
+    results : Vec<ValueType>,
+    height : usize,
+    unreachable : bool,
+}
+
+struct ModuleChecker {
+    functions : HashMap<String, Signature>,
+    globals : HashMap<String, (ValueType, bool)>,
+    memory : Option<usize>,
+}
+
+fn check_module(text : &str) -> Result<(), String> {
+    let items = tokenize(text)?;
+    let fields = match items.as_slice() {
+        [Sexpr::List(fields)] if fields.first().and_then(atom) == Some("module") => &fields[1..],
+        _ => return Err("Expected a single module".to_string()),
+    };
+
+    let mut checker = ModuleChecker {
+        functions : HashMap::new(),
+        globals : HashMap::new(),
+        memory : None,
+    };
+    let mut exports = HashSet::new();
+    let mut check_export = |field : &Sexpr| -> Result<(), String> {
+        match field {
+            Sexpr::List(items) if matches!(items.as_slice(), [_, Sexpr::Str(_)]) => {
+                if !exports.insert(items[1].clone()) {
+                    return Err(format!("Duplicate export {:?}", items[1]));
+                }
+                Ok(())
+            },
+            _ => Err("Bad export".to_string()),
+        }
+    };
+
+    // Functions may be called before they are defined
+    for field in fields {
+        let items = match field {
+            Sexpr::List(items) => items,
+            _ => return Err("Expected a module field".to_string()),
+        };
+        match keyword(field) {
+            Some("func") => {
+                let name = items.get(1).and_then(atom).filter(|name| is_name(name)).ok_or("Function without a name")?;
+                let mut signature = Signature {
+                    parameters : vec![],
+                    results : vec![],
+                };
+                for item in &items[2..] {
+                    match keyword(item) {
+                        Some("export") => check_export(item)?,
+                        Some("param") => signature.parameters.push(checker.declaration(item)?.1),
+                        Some("result") => signature.results.push(checker.declaration(item)?.1),
+                        _ => {},
+                    }
+                }
+                if checker.functions.insert(name.to_string(), signature).is_some() {
+                    return Err(format!("Duplicate function {}", name));
+                }
+            },
+            Some("memory") => {
+                if checker.memory.is_some() {
+                    return Err("Several memories".to_string());
+                }
+                for item in &items[1..] {
+                    match item {
+                        Sexpr::List(_) if keyword(item) == Some("export") => check_export(item)?,
+                        Sexpr::Atom(pages) => checker.memory = Some(pages.parse().map_err(|_| format!("Bad memory size {}", pages))?),
+                        _ => return Err("Bad memory".to_string()),
+                    }
+                }
+            },
+            Some("global") => {
+                let (name, type_, mutable, init) = match items.as_slice() {
+                    [_, Sexpr::Atom(name), Sexpr::Atom(type_), init] => (name, value_type(type_)?, false, init),
+                    [_, Sexpr::Atom(name), Sexpr::List(type_), init] if type_.first().and_then(atom) == Some("mut") && type_.len() == 2 => {
+                        (name, value_type(atom(&type_[1]).ok_or("Bad global type")?)?, true, init)
+                    },
+                    _ => return Err("Bad global".to_string()),
+                };
+                match init {
+                    Sexpr::List(init) if init.len() == 2 && init[0] == Sexpr::Atom(format!("{:?}.const", type_).to_lowercase()) => {
+                        check_literal(type_, atom(&init[1]).ok_or("Bad global initializer")?)?;
+                    },
+                    _ => return Err(format!("Bad initializer of global {}", name)),
+                }
+                if !is_name(name) || checker.globals.insert(name.to_string(), (type_, mutable)).is_some() {
+                    return Err(format!("Bad or duplicate global {}", name));
+                }
+            },
+            Some("data") | Some("export") => {},
+            _ => return Err(format!("Unknown module field {:?}", field)),
+        }
+    }
+
+    for field in fields {
+        match (keyword(field), field) {
+            (Some("func"), Sexpr::List(items)) => checker.check_function(items)?,
+            (Some("data"), Sexpr::List(items)) => checker.check_data(items)?,
+            (Some("export"), Sexpr::List(items)) => {
+                check_export(&Sexpr::List(items.iter().take(2).cloned().collect()))?;
+                match items.get(2) {
+                    Some(Sexpr::List(target)) if matches!(target.as_slice(), [Sexpr::Atom(kind), Sexpr::Atom(name)] if kind == "func" && checker.functions.contains_key(name)) => {},
+                    _ => return Err("Bad export".to_string()),
+                }
+            },
+            _ => {},
+        }
+    }
+    Ok(())
+}
+
+impl ModuleChecker {
+    // `(param $name type)`, `(local $name type)` or `(result type)`
+    fn declaration<'a>(&self, sexpr : &'a Sexpr) -> Result<(Option<&'a str>, ValueType), String> {
+        match sexpr {
+            Sexpr::List(items) => match items.as_slice() {
+                [_, Sexpr::Atom(name), Sexpr::Atom(type_)] if is_name(name) => Ok((Some(name), value_type(type_)?)),
+                [_, Sexpr::Atom(type_)] => Ok((None, value_type(type_)?)),
+                _ => Err(format!("Bad declaration {:?}", sexpr)),
+            },
+            _ => Err(format!("Bad declaration {:?}", sexpr)),
+        }
+    }
+
+    fn check_data(&self, items : &[Sexpr]) -> Result<(), String> {
+        let memory = self.memory.ok_or("Data without memory")?;
+        let offset = match items.get(1) {
+            Some(Sexpr::List(offset)) if offset.len() == 2 && offset[0] == Sexpr::Atom("i32.const".to_string()) => {
+                atom(&offset[1]).and_then(|offset| offset.parse::<usize>().ok()).ok_or("Bad data offset")?
+            },
+            _ => return Err("Bad data offset".to_string()),
+        };
+        let mut len = 0;
+        for item in &items[2..] {
+            match item {
+                Sexpr::Str(bytes) => len += bytes.len(),
+                _ => return Err("Bad data".to_string()),
+            }
+        }
+        if offset + len > memory * 65536 {
+            return Err(format!("Data at {} out of memory", offset));
+        }
+        Ok(())
+    }
+
+    fn check_function(&self, items : &[Sexpr]) -> Result<(), String> {
+        let name = atom(&items[1]).unwrap();
+        let mut locals = HashMap::new();
+        let mut body = vec![];
+        for item in &items[2..] {
+            match keyword(item) {
+                Some("export") | Some("result") if body.is_empty() => {},
+                Some("param") | Some("local") if body.is_empty() => {
+                    let (local, type_) = self.declaration(item)?;
+                    let local = local.ok_or(format!("Unnamed local in {}", name))?;
+                    if locals.insert(local.to_string(), type_).is_some() {
+                        return Err(format!("Duplicate local {} in {}", local, name));
+                    }
+                },
+                _ => body.push(item),
+            }
+        }
+
+        let mut validator = Validator {
+            module : self,
+            locals,
+            stack : vec![],
+            frames : vec![Frame {
+                label : None,
+                is_loop : false,
+                is_if : false,
+                results : self.functions[name].results.clone(),
+                height : 0,
+                unreachable : false,
+            }],
+        };
+        validator.check_body(&body).map_err(|error| format!("In {}: {}", name, error))
+    }
+}
+
+/****************************/
+/****************************/
+/****************************/
+
+// Type checks a sequence of plain instructions with the algorithm of the
+// specification: a stack of operand types and a stack of control frames.
+struct Validator<'a> {
+    module : &'a ModuleChecker,
+    locals : HashMap<String, ValueType>,
+    stack : Vec<ValueType>,
+    frames : Vec<Frame>,
+}
+
+impl Validator<'_> {
+    fn push(&mut self, type_ : ValueType) {
+        self.stack.push(type_);
+    }
+
+    fn pop(&mut self, expected : ValueType) -> Result<(), String> {
+        let frame = self.frames.last().unwrap();
+        if self.stack.len() == frame.height {
+            return if frame.unreachable {Ok(())} else {Err(format!("Expected {:?} on an empty stack", expected))};
+        }
+        match self.stack.pop().unwrap() {
+            type_ if type_ == expected => Ok(()),
+            type_ => Err(format!("Expected {:?}, found {:?}", expected, type_)),
+        }
+    }
+
+    fn pop_all(&mut self, types : &[ValueType]) -> Result<(), String> {
+        types.iter().rev().try_for_each(|type_| self.pop(*type_))
+    }
+
+    fn set_unreachable(&mut self) {
+        let frame = self.frames.last_mut().unwrap();
+        self.stack.truncate(frame.height);
+        frame.unreachable = true;
+    }
+
+    // Leaves a frame, whose results must be exactly what is on the stack
+    fn end_frame(&mut self) -> Result<Frame, String> {
+        let results = self.frames.last().unwrap().results.clone();
+        self.pop_all(&results)?;
+        if self.stack.len() != self.frames.last().unwrap().height {
+            return Err("Values left on the stack at the end of a block".to_string());
+        }
+        Ok(self.frames.pop().unwrap())
+    }
+
+    // Types of the values a branch to a label carries
+    fn label(&self, name : &str) -> Result<Vec<ValueType>, String> {
+        let frame = self.frames
+            .iter()
+            .rev()
+            .find(|frame| frame.label.as_deref() == Some(name))
+            .ok_or(format!("Unknown label {}", name))?;
+        Ok(if frame.is_loop {vec![]} else {frame.results.clone()})
+    }
+
+    fn local(&self, name : &str) -> Result<ValueType, String> {
+        self.locals.get(name).copied().ok_or(format!("Unknown local {}", name))
+    }
+
+    fn check_body(&mut self, body : &[&Sexpr]) -> Result<(), String> {
+        let mut i = 0;
+        while i < body.len() {
+            let instruction = atom(body[i]).ok_or(format!("Expected an instruction, found {:?}", body[i]))?;
+            i += 1;
+            let mut immediate = || -> Result<&str, String> {
+                let item = body.get(i).and_then(|item| atom(item)).ok_or(format!("Missing immediate of {}", instruction))?;
+                i += 1;
+                Ok(item)
+            };
+            match instruction {
+                "block" | "loop" | "if" => {
+                    let label = match body.get(i).and_then(|item| atom(item)) {
+                        Some(label) if is_name(label) => {
+                            i += 1;
+                            Some(label.to_string())
+                        },
+                        _ => None,
+                    };
+                    let mut results = vec![];
+                    while let Some(result) = body.get(i).filter(|item| keyword(item) == Some("result")) {
+                        results.push(self.module.declaration(result)?.1);
+                        i += 1;
+                    }
+                    if instruction == "if" {
+                        self.pop(ValueType::I32)?;
+                    }
+                    self.frames.push(Frame {
+                        label,
+                        is_loop : instruction == "loop",
+                        is_if : instruction == "if",
+                        results,
+                        height : self.stack.len(),
+                        unreachable : false,
+                    });
+                },
+                "else" => {
+                    if !self.frames.last().unwrap().is_if || self.frames.len() == 1 {
+                        return Err("`else` outside of an `if`".to_string());
+                    }
+                    let mut frame = self.end_frame()?;
+                    frame.is_if = false;
+                    frame.height = self.stack.len();
+                    frame.unreachable = false;
+                    self.frames.push(frame);
+                },
+                "end" => {
+                    if self.frames.len() == 1 {
+                        return Err("Unbalanced `end`".to_string());
+                    }
+                    let frame = self.end_frame()?;
+                    if frame.is_if && !frame.results.is_empty() {
+                        return Err("`if` with a result and no `else`".to_string());
+                    }
+                    self.stack.extend(frame.results);
+                },
+                "br" => {
+                    let types = self.label(immediate()?)?;
+                    self.pop_all(&types)?;
+                    self.set_unreachable();
+                },
+                "br_if" => {
+                    let types = self.label(immediate()?)?;
+                    self.pop(ValueType::I32)?;
+                    self.pop_all(&types)?;
+                    self.stack.extend(types);
+                },
+                "return" => {
+                    let results = self.frames[0].results.clone();
+                    self.pop_all(&results)?;
+                    self.set_unreachable();
+                },
+                "unreachable" => self.set_unreachable(),
+                "nop" => {},
+                "drop" => {
+                    let frame = self.frames.last().unwrap();
+                    if self.stack.len() > frame.height {
+                        self.stack.pop();
+                    } else if !frame.unreachable {
+                        return Err("`drop` on an empty stack".to_string());
+                    }
+                },
+                "select" => {
+                    self.pop(ValueType::I32)?;
+                    let type_ = *self.stack.last().ok_or("`select` on an empty stack")?;
+                    self.pop(type_)?;
+                    self.pop(type_)?;
+                    self.push(type_);
+                },
+                "call" => {
+                    let name = immediate()?;
+                    let signature = self.module.functions.get(name).ok_or(format!("Unknown function {}", name))?;
+                    self.pop_all(&signature.parameters)?;
+                    self.stack.extend(signature.results.iter().copied());
+                },
+                "local.get" => {
+                    let type_ = self.local(immediate()?)?;
+                    self.push(type_);
+                },
+                "local.set" | "local.tee" => {
+                    let type_ = self.local(immediate()?)?;
+                    self.pop(type_)?;
+                    if instruction == "local.tee" {
+                        self.push(type_);
+                    }
+                },
+                "global.get" | "global.set" => {
+                    let name = immediate()?;
+                    let (type_, mutable) = *self.module.globals.get(name).ok_or(format!("Unknown global {}", name))?;
+                    if instruction == "global.get" {
+                        self.push(type_);
+                    } else if mutable {
+                        self.pop(type_)?;
+                    } else {
+                        return Err(format!("Global {} is immutable", name));
+                    }
+                },
+                "memory.size" | "memory.grow" | "memory.copy" | "memory.fill" => {
+                    self.module.memory.ok_or("No memory")?;
+                    let operands = match instruction {
+                        "memory.size" => 0,
+                        "memory.grow" => 1,
+                        _ => 3,
+                    };
+                    for _ in 0..operands {
+                        self.pop(ValueType::I32)?;
+                    }
+                    if operands < 3 {
+                        self.push(ValueType::I32);
+                    }
+                },
+                _ => {
+                    let (prefix, operation) = instruction.split_once('.').ok_or(format!("Unknown instruction {}", instruction))?;
+                    let type_ = value_type(prefix)?;
+                    if operation == "const" {
+                        check_literal(type_, immediate()?)?;
+                        self.push(type_);
+                        continue;
+                    }
+                    if operation.starts_with("load") || operation.starts_with("store") {
+                        while let Some(argument) = body.get(i).and_then(|item| atom(item)) {
+                            match argument.split_once('=') {
+                                Some(("offset", value)) | Some(("align", value)) if value.parse::<u32>().is_ok() => i += 1,
+                                _ => break,
+                            }
+                        }
+                        self.module.memory.ok_or("No memory")?;
+                    }
+                    let (operands, result) = numeric(type_, operation).ok_or(format!("Unknown instruction {}", instruction))?;
+                    self.pop_all(&operands)?;
+                    self.stack.extend(result);
+                },
+            }
+        }
+
+        if self.frames.len() != 1 {
+            return Err("Missing `end`".to_string());
+        }
+        self.end_frame().map(|_| ())
+    }
+}
+
+// Operand and result types of the numeric and memory instructions of a type
+fn numeric(type_ : ValueType, operation : &str) -> Option<(Vec<ValueType>, Option<ValueType>)> {
+    use ValueType::*;
+    let is_int = matches!(type_, I32 | I64);
+    let conversion = |from : ValueType| Some((vec![from], Some(type_)));
+    match operation {
+        "add" | "sub" | "mul" => Some((vec![type_, type_], Some(type_))),
+        "div_s" | "div_u" | "rem_s" | "rem_u" | "and" | "or" | "xor" | "shl" | "shr_s" | "shr_u" if is_int => Some((vec![type_, type_], Some(type_))),
+        "div" | "min" | "max" | "copysign" if !is_int => Some((vec![type_, type_], Some(type_))),
+        "eq" | "ne" => Some((vec![type_, type_], Some(I32))),
+        "lt_s" | "lt_u" | "gt_s" | "gt_u" | "le_s" | "le_u" | "ge_s" | "ge_u" if is_int => Some((vec![type_, type_], Some(I32))),
+        "lt" | "gt" | "le" | "ge" if !is_int => Some((vec![type_, type_], Some(I32))),
+        "eqz" if is_int => Some((vec![type_], Some(I32))),
+        "clz" | "ctz" | "popcnt" | "extend8_s" | "extend16_s" if is_int => Some((vec![type_], Some(type_))),
+        "extend32_s" if type_ == I64 => Some((vec![type_], Some(type_))),
+        "neg" | "abs" | "sqrt" | "ceil" | "floor" | "trunc" | "nearest" if !is_int => Some((vec![type_], Some(type_))),
+        "wrap_i64" if type_ == I32 => conversion(I64),
+        "extend_i32_s" | "extend_i32_u" if type_ == I64 => conversion(I32),
+        "demote_f64" if type_ == F32 => conversion(F64),
+        "promote_f32" if type_ == F64 => conversion(F32),
+        "trunc_f32_s" | "trunc_f32_u" | "trunc_sat_f32_s" | "trunc_sat_f32_u" if is_int => conversion(F32),
+        "trunc_f64_s" | "trunc_f64_u" | "trunc_sat_f64_s" | "trunc_sat_f64_u" if is_int => conversion(F64),
+        "convert_i32_s" | "convert_i32_u" if !is_int => conversion(I32),
+        "convert_i64_s" | "convert_i64_u" if !is_int => conversion(I64),
+        "load" => Some((vec![I32], Some(type_))),
+        "load8_s" | "load8_u" | "load16_s" | "load16_u" if is_int => Some((vec![I32], Some(type_))),
+        "load32_s" | "load32_u" if type_ == I64 => Some((vec![I32], Some(type_))),
+        "store" => Some((vec![I32, type_], None)),
+        "store8" | "store16" if is_int => Some((vec![I32, type_], None)),
+        "store32" if type_ == I64 => Some((vec![I32, type_], None)),
+        _ => None,
+    }
+}
+
+/****************************/
+/****************************/
+/****************************/
+
+#[test]
+fn programs_are_well_formed() {
+    for path in programs() {
+        for opt_level in [OptLevel::O0, OptLevel::O1] {
+            let text = generate(&path, opt_level);
+            if let Err(error) = check_module(&text) {
+                panic!("{} at {:?}: {}", path.display(), opt_level, error);
+            }
+        }
+    }
+}
+
+#[test]
+fn bounds_checks_are_well_formed() {
+    let file = std::env::temp_dir().join(format!("toy_wat_{}_bounds.wat", std::process::id()));
+    let tu = TranslationUnit::from_lexeme(&Lexeme::from_source("int32[4] g; def int32 main() { int32 i = 4; return g[i]; }"));
+    let options = CodegenOptions {
+        bounds_check : true,
+        ..CodegenOptions::default()
+    };
+    create_wat_with_options(&tu, file.to_str().unwrap(), &options).unwrap();
+    let text = std::fs::read_to_string(&file).unwrap();
+    std::fs::remove_file(&file).unwrap();
+    check_module(&text).unwrap();
+    assert!(text.contains("unreachable"));
+}
+
+#[test]
+fn errors_leave_no_file() {
+    let file = std::env::temp_dir().join(format!("toy_wat_{}_lowering.wat", std::process::id()));
+    let tu = TranslationUnit::from_lexeme(&Lexeme::from_source("def int32 main() { string s = \"x\"; int32 b = s; return b; }"));
+
+    let mut output = vec![];
+    let error = generate_wat(&tu, &CodegenOptions::default(), &mut output).unwrap_err();
+    assert!(matches!(error, CodegenError::Lowering(_)), "{:?}", error);
+    assert!(output.is_empty());
+
+    let error = create_wat(&tu, file.to_str().unwrap()).unwrap_err();
+    assert!(matches!(error, CodegenError::Lowering(_)), "{:?}", error);
+    assert!(!file.exists());
+
+    let tu = TranslationUnit::from_lexeme(&Lexeme::from_source("def int32 main() { return 0; }"));
+    let file = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/missing/example.wat");
+    let error = create_wat(&tu, file.to_str().unwrap()).unwrap_err();
+    assert!(matches!(error, CodegenError::Io(_)), "{:?}", error);
+    assert!(!file.exists());
+}
+
+#[test]
+fn non_ascii_names_stay_distinct() {
+    let source = "def int32 é(int32 à) { return à; } def int32 è(int32 _e0_) { return _e0_; } def int32 _e9_() { return 1; } def int32 main() { string s = \"a\" + \"b\"; return é(1) + è(2) + _e9_(); }";
+    let tu = TranslationUnit::from_lexeme(&Lexeme::from_source(source));
+    let mut output = vec![];
+    generate_wat(&tu, &CodegenOptions::default(), &mut output).unwrap();
+    let text = String::from_utf8(output).unwrap();
+    check_module(&text).unwrap();
+
+    for expected in [
+        "(func $_e9_ (export \"é\") (param $_e0_.arg i32)",
+        "(func $_e8_ (export \"è\") (param $__e0__.arg i32)",
+        "(func $__e9__ (export \"_e9_\")",
+        "call $toy_string_concat\n",
+    ] {
+        assert!(text.contains(expected), "{} not in\n{}", expected, text);
+    }
+}
+
+#[test]
+fn checker_rejects_malformed_text() {
+    let cases = [
+        ("(module (func $f (result i32) i32.const 1)", "Missing `)`"),
+        ("(module (func $f (result i32) i64.const 1))", "In $f: Expected I32, found I64"),
+        ("(module (func $f (result i32) i32.const 1 f32.const 2 i32.add))", "In $f: Expected I32, found F32"),
+        ("(module (func $f i32.const 1))", "In $f: Values left on the stack at the end of a block"),
+        ("(module (func $f block $a br $b end))", "In $f: Unknown label $b"),
+        ("(module (func $f local.get $x drop))", "In $f: Unknown local $x"),
+        ("(module (func $f block end end))", "In $f: Unbalanced `end`"),
+        ("(module (func $f (result i32) block $a i32.const 1 end))", "In $f: Values left on the stack at the end of a block"),
+        ("(module (global $g i32 (i32.const 0)) (func $f i32.const 1 global.set $g))", "In $f: Global $g is immutable"),
+        ("(module (func $f i32.const 0 i32.load drop))", "In $f: No memory"),
+        ("(module (memory 1) (data (i32.const 65535) \"ab\"))", "Data at 65535 out of memory"),
+        ("(module (func $f call $g))", "In $f: Unknown function $g"),
+        ("(module (func $f (result i32) i32.const 4294967296))", "In $f: Bad I32 literal 4294967296"),
+    ];
+
+    for (text, error) in cases {
+        assert_eq!(check_module(text), Err(error.to_string()), "{}", text);
+    }
+
+    // Unreachable code may pop values that are not there
+    check_module("(module (func $f (result i32) unreachable i32.add))").unwrap();
+    check_module("(module (func $f (param $x i32) (result i32) block $a local.get $x br_if $a end local.get $x))").unwrap();
+}